@@ -0,0 +1,415 @@
+//! Library entry points mirroring the `deploy` and `list` commands
+
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    DocverError, GitArgs,
+    commands::{
+        self,
+        deploy::{Deploy, Status},
+        list::Query,
+    },
+    parse_deploy_prefix,
+    versions::Versions,
+};
+
+pub use crate::commands::deploy::DeployReport;
+
+/// Errors of the library entry points
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The options can't be used, e.g. a deploy prefix escaping the branch
+    InvalidOptions(String),
+    /// The publish branch or the versions file couldn't be read
+    Read(DocverError),
+    /// The deploy failed; the report records how far it got
+    Deploy {
+        report: Box<DeployReport>,
+        /// What went wrong, with the context of each step that failed
+        message: String,
+        /// The failure behind it, unless it was e.g. a site over its size budget
+        source: Option<DocverError>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+            Error::Read(err) => write!(f, "failed to read the versions: {}", err),
+            Error::Deploy { message, .. } => write!(f, "deploy failed: {}", message),
+        }
+    }
+}
+
+impl Error {
+    /// The failure behind a read or deploy error, to tell e.g. a missing branch from a corrupt
+    /// versions.json
+    pub fn docver_error(&self) -> Option<&DocverError> {
+        match self {
+            Error::InvalidOptions(_) => None,
+            Error::Read(err) => Some(err),
+            Error::Deploy { source, .. } => source.as_ref(),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.docver_error()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Where the publish branch lives, shared by all entry points
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct BranchOptions {
+    /// Git remote (name or URL) to fetch from and push to
+    pub remote: String,
+    /// Git branch to publish to
+    pub branch: String,
+    /// Prefix directory under which versions are deployed
+    pub deploy_prefix: Option<String>,
+    /// Use the branch as last fetched instead of fetching it
+    pub no_fetch: bool,
+    /// Repository to publish from (defaults to the one containing the current directory)
+    pub repo: Option<PathBuf>,
+    /// Git directory to publish from instead, e.g. a bare mirror
+    pub git_dir: Option<PathBuf>,
+}
+
+impl Default for BranchOptions {
+    fn default() -> Self {
+        let defaults = commands::defaults::<GitArgs>();
+        BranchOptions {
+            remote: defaults.remote().to_string(),
+            branch: defaults.branch,
+            deploy_prefix: None,
+            no_fetch: false,
+            repo: None,
+            git_dir: None,
+        }
+    }
+}
+
+impl BranchOptions {
+    fn git_args(&self) -> Result<GitArgs, Error> {
+        let deploy_prefix = self
+            .deploy_prefix
+            .as_deref()
+            .map(parse_deploy_prefix)
+            .transpose()
+            .map_err(|err| Error::InvalidOptions(err.to_string()))?;
+        Ok(GitArgs {
+            remotes: vec![self.remote.clone()],
+            branch: self.branch.clone(),
+            deploy_prefix,
+            no_fetch: self.no_fetch,
+            repo: self.repo.clone(),
+            git_dir: self.git_dir.clone(),
+            quiet: true,
+            ..commands::defaults()
+        })
+    }
+}
+
+/// Options of [`deploy`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DeployOptions {
+    pub branch: BranchOptions,
+    /// Directory of the built site
+    pub source: PathBuf,
+    /// Version identifier, e.g. "v1.2.3"
+    pub version: String,
+    /// Aliases to point at the version, e.g. "latest"
+    pub aliases: Vec<String>,
+    /// Human-readable title of the version
+    pub title: Option<String>,
+    /// Commit message (defaults to an auto-generated message)
+    pub message: Option<String>,
+    /// Push the branch after committing
+    pub push: bool,
+}
+
+impl DeployOptions {
+    pub fn new(source: impl Into<PathBuf>, version: impl Into<String>) -> Self {
+        DeployOptions {
+            branch: BranchOptions::default(),
+            source: source.into(),
+            version: version.into(),
+            aliases: Vec::new(),
+            title: None,
+            message: None,
+            push: false,
+        }
+    }
+}
+
+/// Options of [`list`]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ListOptions {
+    pub branch: BranchOptions,
+    /// Only list these versions or aliases; "*" and "?" match like shell globs
+    pub identifiers: Vec<String>,
+    /// Read the versions as of this commit or ref instead of the branch tip
+    pub at: Option<String>,
+    /// Read this versions.json instead of the branch, without running git
+    pub file: Option<PathBuf>,
+}
+
+/// Deploy a built site as a version to the publish branch of the repository in the current
+/// directory (or [`BranchOptions::repo`]), like `versite deploy`. Status lines are printed to
+/// stderr.
+pub fn deploy(options: DeployOptions) -> Result<DeployReport, Error> {
+    let mut git_args = options.branch.git_args()?.discover().map_err(Error::Read)?;
+    git_args.message = options.message;
+    git_args.push = options.push;
+
+    let mut deploy = Deploy::new(options.source, options.version);
+    deploy.aliases = options.aliases;
+    deploy.title = options.title;
+    match deploy.report(git_args, Status::new(true)) {
+        (report, Ok(())) => Ok(report),
+        (report, Err(err)) => Err(Error::Deploy {
+            report: Box::new(report),
+            message: format!("{:#}", err),
+            // Found through the context the deploy steps add
+            source: err.downcast().ok(),
+        }),
+    }
+}
+
+/// The deployed versions, like `versite list`
+pub fn list(options: ListOptions) -> Result<Versions, Error> {
+    let mut git_args = options.branch.git_args()?;
+    let query = Query {
+        identifiers: options.identifiers,
+        at: options.at,
+        file: options.file,
+        ..Default::default()
+    };
+    if query.file.is_none() {
+        git_args = git_args.discover().map_err(Error::Read)?;
+        git_args.fetch().map_err(Error::Read)?;
+    }
+    let mut versions = query.read(&git_args).map_err(Error::Read)?;
+    query.filter(&mut versions);
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test_support::{Scratch, bare_publishing_setup, publishing_setup};
+
+    #[test]
+    fn options_default_like_the_cli() {
+        let options = DeployOptions::new("site", "v1.0");
+        assert_eq!(options.branch.remote, "origin");
+        assert_eq!(options.branch.branch, "gh-pages");
+
+        let git_args = options.branch.git_args().unwrap();
+        assert_eq!(git_args.remotes, ["origin"]);
+        assert!(!git_args.push && !git_args.force_push);
+
+        let branch = BranchOptions {
+            deploy_prefix: Some("../docs".into()),
+            ..Default::default()
+        };
+        let err = list(ListOptions {
+            branch,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidOptions(_)));
+        insta::assert_snapshot!(err, @r#"invalid options: invalid deploy prefix "../docs": '..' is not allowed"#);
+    }
+
+    #[test]
+    fn lists_a_versions_file() {
+        let dir = Scratch::dir("api-list");
+        let path = dir.join("versions.json");
+        fs::write(
+            &path,
+            r#"[{"version": "v2.0", "aliases": ["latest"]}, {"version": "v1.0", "aliases": []}]"#,
+        )
+        .unwrap();
+        let options = ListOptions {
+            file: Some(path.clone()),
+            ..Default::default()
+        };
+        let all = list(options.clone()).unwrap();
+        let latest = list(ListOptions {
+            identifiers: vec!["latest".into()],
+            ..options.clone()
+        })
+        .unwrap();
+        fs::write(&path, "[").unwrap();
+        let err = list(options).unwrap_err();
+
+        let tags = |versions: &Versions| {
+            versions
+                .into_iter()
+                .map(|(version, _)| version.tag.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tags(&all), ["v2.0", "v1.0"]);
+        assert_eq!(tags(&latest), ["v2.0"]);
+        assert!(matches!(err, Error::Read(_)));
+        assert!(matches!(
+            err.docver_error(),
+            Some(DocverError::Manifest {
+                source: Some(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn redeploys_reuse_unchanged_files() {
+        let dir = publishing_setup("api-reuse");
+        fs::write(dir.join("site/style.css"), "h1 {}").unwrap();
+
+        // The branch is never pushed, so reuse has to read the local branch
+        let deploy = || {
+            let branch = BranchOptions {
+                repo: Some(dir.join("work")),
+                ..Default::default()
+            };
+            let report = deploy(DeployOptions {
+                branch,
+                ..DeployOptions::new(dir.join("site"), "v1.0")
+            })
+            .unwrap();
+            (report.files_uploaded, report.files_reused)
+        };
+        let first = deploy();
+        let unchanged = deploy();
+        fs::write(dir.join("site/style.css"), "h1 { color: red }").unwrap();
+        let changed = deploy();
+
+        assert_eq!(first, (2, 0));
+        assert_eq!(unchanged, (0, 2));
+        assert_eq!(changed, (1, 1));
+    }
+
+    #[test]
+    fn deploys_and_pushes_from_a_scratch_repository() {
+        let dir = publishing_setup("api-push");
+
+        let branch = BranchOptions {
+            repo: Some(dir.join("work")),
+            ..Default::default()
+        };
+        let report = deploy(DeployOptions {
+            branch: branch.clone(),
+            aliases: vec!["latest".into()],
+            title: Some("Release {version}".into()),
+            message: Some("Publish the docs".into()),
+            push: true,
+            ..DeployOptions::new(dir.join("site"), "v1.0")
+        })
+        .unwrap();
+        let versions = list(ListOptions {
+            branch,
+            identifiers: vec!["latest".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let pushed = dir.git(&[
+            "--git-dir",
+            "origin.git",
+            "log",
+            "-1",
+            "--format=%s",
+            "gh-pages",
+        ]);
+
+        assert_eq!(report.status, "deployed");
+        let listed = versions
+            .into_iter()
+            .map(|(version, aliases)| format!("{} {:?} {aliases:?}", version.tag, version.title))
+            .collect::<Vec<_>>();
+        assert_eq!(listed, [r#"v1.0 Some("Release v1.0") ["latest"]"#]);
+        assert_eq!(pushed.trim(), "Publish the docs");
+    }
+
+    #[test]
+    fn deploy_errors_carry_the_failure_behind_them() {
+        let dir = publishing_setup("api-deploy-error");
+        let work = dir.join("work");
+        fs::write(work.join("versions.json"), "[").unwrap();
+        dir.git(&["-C", "work", "checkout", "-q", "-b", "gh-pages"]);
+        dir.git(&["-C", "work", "add", "versions.json"]);
+        dir.git(&["-C", "work", "commit", "-q", "-m", "corrupt"]);
+
+        let err = deploy(DeployOptions {
+            branch: BranchOptions {
+                repo: Some(work),
+                ..Default::default()
+            },
+            ..DeployOptions::new(dir.join("site"), "v1.0")
+        })
+        .unwrap_err();
+
+        let Error::Deploy { report, .. } = &err else {
+            panic!("not a deploy error: {}", err);
+        };
+        assert_eq!(report.status, "error");
+        assert!(matches!(
+            err.docver_error(),
+            Some(DocverError::Manifest { .. })
+        ));
+        assert!(err.to_string().contains("--reset-versions"), "{}", err);
+    }
+
+    #[test]
+    fn deploys_to_a_bare_repository() {
+        let dir = bare_publishing_setup("api-bare");
+
+        let bare = dir.join("bare.git");
+        let branch = BranchOptions {
+            remote: bare.to_string_lossy().into_owned(),
+            git_dir: Some(bare.clone()),
+            ..Default::default()
+        };
+        let report = deploy(DeployOptions {
+            branch: branch.clone(),
+            aliases: vec!["latest".into()],
+            ..DeployOptions::new(dir.join("site"), "v1.0")
+        })
+        .unwrap();
+        let versions = list(ListOptions {
+            branch,
+            ..Default::default()
+        })
+        .unwrap();
+        let deployed = dir.git(&[
+            "--git-dir",
+            "bare.git",
+            "ls-tree",
+            "-r",
+            "--name-only",
+            "gh-pages",
+        ]);
+
+        assert_eq!(report.version.as_deref(), Some("v1.0"));
+        let listed = versions
+            .into_iter()
+            .map(|(version, aliases)| format!("{} {aliases:?}", version.tag))
+            .collect::<Vec<_>>();
+        assert_eq!(listed, [r#"v1.0 ["latest"]"#]);
+        insta::assert_snapshot!(deployed, @r"
+        .nojekyll
+        _redirects
+        v1.0/index.html
+        versions.json
+        ");
+    }
+}
@@ -0,0 +1,105 @@
+//! How the publish branch is read and written. Everything goes through the git CLI today; the
+//! [`Backend`] trait is where an in-process implementation (such as gitoxide) plugs in.
+
+use std::collections::BTreeMap;
+
+use git_cmd::git_in_dir;
+
+use crate::{
+    error::{DocverError, Result},
+    git::{self, Commit, CommitProgress, ImportReport, TreeEntry},
+};
+
+/// The backend for the repository at `repo_dir`
+pub fn open(repo_dir: &str) -> Box<dyn Backend> {
+    Box::new(Cli {
+        repo_dir: repo_dir.to_string(),
+    })
+}
+
+/// The object reads and writes the commands need from a repository
+pub trait Backend {
+    /// The object id `rev` names, if it exists
+    fn rev_parse(&self, rev: &str) -> Option<String>;
+
+    /// The contents of `path` at `rev`, or `None` when either doesn't exist
+    fn read_file(&self, rev: &str, path: &str) -> Result<Option<Vec<u8>>>;
+
+    /// The blobs under `path` at `rev`, keyed by their full path
+    fn ls_tree(&self, rev: &str, path: &str) -> Result<BTreeMap<String, TreeEntry>>;
+
+    /// Write the commit and update its ref, telling `progress` about the files written, and
+    /// reporting what was written when the backend can
+    fn commit(
+        &self,
+        commit: &Commit,
+        progress: Option<&dyn CommitProgress>,
+    ) -> Result<Option<ImportReport>>;
+}
+
+/// The git binary, run in the repository directory
+struct Cli {
+    repo_dir: String,
+}
+
+impl Backend for Cli {
+    fn rev_parse(&self, rev: &str) -> Option<String> {
+        git_in_dir(
+            self.repo_dir.as_str().into(),
+            &["rev-parse", "--verify", "--quiet", rev],
+        )
+        .ok()
+        .map(|sha| sha.trim().to_string())
+    }
+
+    fn read_file(&self, rev: &str, path: &str) -> Result<Option<Vec<u8>>> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["cat-file", "blob", &format!("{}:{}", rev, path)])
+            .output()
+            .map_err(DocverError::io("failed to run git cat-file"))?;
+        Ok(output.status.success().then_some(output.stdout))
+    }
+
+    fn ls_tree(&self, rev: &str, path: &str) -> Result<BTreeMap<String, TreeEntry>> {
+        git::ls_tree(&self.repo_dir, rev, path)
+    }
+
+    fn commit(
+        &self,
+        commit: &Commit,
+        progress: Option<&dyn CommitProgress>,
+    ) -> Result<Option<ImportReport>> {
+        commit.run_with(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_repo;
+
+    #[test]
+    fn cli_reads_back_what_it_commits() {
+        let dir = scratch_repo("backend-cli");
+        let backend = open(dir.to_str().unwrap());
+        let commit = Commit::new(&*dir, "refs/heads/gh-pages")
+            .message("Deploy")
+            .add_bytes("v1/index.html", 0o100644, "<html>")
+            .add_bytes("versions.json", 0o100644, "[]");
+        backend.commit(&commit, None).unwrap();
+
+        let tip = backend.rev_parse("gh-pages");
+        let versions = backend.read_file("gh-pages", "versions.json").unwrap();
+        let missing = backend.read_file("gh-pages", "v2/index.html").unwrap();
+        let tree = backend.ls_tree("gh-pages", "v1").unwrap();
+        let unknown = backend.rev_parse("no-such-branch");
+
+        assert!(tip.is_some_and(|sha| sha.len() == 40));
+        assert_eq!(versions.as_deref(), Some(&b"[]"[..]));
+        assert_eq!(missing, None);
+        assert_eq!(tree.keys().collect::<Vec<_>>(), ["v1/index.html"]);
+        assert_eq!(unknown, None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Per-version checksum manifests in the `sha256sum` format
+
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+
+use crate::versions::VERSIONS_FILE;
+
+/// Name of the manifest written into each version directory
+pub const MANIFEST_FILE: &str = "SHA256SUMS";
+
+/// Name of the root index mapping each version to the SHA-256 of its manifest
+pub const MANIFESTS_INDEX: &str = "manifests.json";
+
+/// Whether a file at `path` (relative to the version directory) is written by the deploy
+/// itself and so not covered by the manifest: the manifest, and the copy of versions.json when
+/// `versions_copies` says the branch has versions.js
+pub fn is_generated(path: &str, versions_copies: bool) -> bool {
+    path == MANIFEST_FILE || (versions_copies && path == VERSIONS_FILE)
+}
+
+/// The SHA-256 digest of `data` as lowercase hex
+pub fn hex_digest(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// A digest as lowercase hex
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Render a manifest from hex digests keyed by path relative to the version directory
+pub fn render(digests: &BTreeMap<String, String>) -> String {
+    digests
+        .iter()
+        .map(|(path, digest)| format!("{}  {}\n", digest, path))
+        .collect()
+}
+
+/// Parse a manifest into hex digests keyed by path
+pub fn parse(manifest: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut digests = BTreeMap::new();
+    for line in manifest.lines().filter(|line| !line.is_empty()) {
+        // The second separator character is ' ' in text mode and '*' in binary mode
+        let (Some(digest), Some(path)) = (line.get(..64), line.get(66..)) else {
+            bail!("invalid {} line {:?}", MANIFEST_FILE, line);
+        };
+        if !digest.bytes().all(|b| b.is_ascii_hexdigit()) || !line[64..].starts_with(' ') {
+            bail!("invalid {} line {:?}", MANIFEST_FILE, line);
+        }
+        digests.insert(path.to_string(), digest.to_ascii_lowercase());
+    }
+    Ok(digests)
+}
+
+/// Describe every file that is missing, changed or not listed when comparing the digests in a
+/// manifest with the digests of the files actually present
+pub fn compare(
+    manifest: &BTreeMap<String, String>,
+    actual: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (path, digest) in manifest {
+        match actual.get(path) {
+            None => problems.push(format!("missing {}", path)),
+            Some(found) if found != digest => problems.push(format!(
+                "{} has SHA-256 {} instead of {}",
+                path, found, digest
+            )),
+            Some(_) => {}
+        }
+    }
+    for path in actual.keys().filter(|path| !manifest.contains_key(*path)) {
+        problems.push(format!("{} is not listed in {}", path, MANIFEST_FILE));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn round_trips_sha256sum_format() {
+        let digests = BTreeMap::from([
+            ("index.html".to_string(), ABC.to_string()),
+            ("a b/empty.txt".to_string(), EMPTY.to_string()),
+        ]);
+        let manifest = render(&digests);
+        assert_eq!(
+            manifest,
+            format!("{}  a b/empty.txt\n{}  index.html\n", EMPTY, ABC)
+        );
+        assert_eq!(parse(&manifest).unwrap(), digests);
+        assert_eq!(
+            parse(&format!("{} *index.html\n", ABC.to_uppercase())).unwrap(),
+            BTreeMap::from([("index.html".to_string(), ABC.to_string())])
+        );
+        assert!(parse("not a manifest").is_err());
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let manifest = BTreeMap::from([
+            ("same".to_string(), ABC.to_string()),
+            ("changed".to_string(), ABC.to_string()),
+            ("missing".to_string(), ABC.to_string()),
+        ]);
+        let actual = BTreeMap::from([
+            ("same".to_string(), ABC.to_string()),
+            ("changed".to_string(), EMPTY.to_string()),
+            ("extra".to_string(), EMPTY.to_string()),
+        ]);
+        assert_eq!(
+            compare(&manifest, &actual),
+            vec![
+                format!("changed has SHA-256 {} instead of {}", EMPTY, ABC),
+                "missing missing".to_string(),
+                "extra is not listed in SHA256SUMS".to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! The `versite` command line, the only place failures are printed and turned into exit codes
+
+use std::{
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
+
+use clap::{Parser, ValueEnum};
+
+use crate::{DocverError, GitArgs, commands::Command};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+/// Versite: versioned static site deployments to a Git branch
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Global git options applied to all subcommands
+    #[command(flatten)]
+    git_args: GitArgs,
+
+    /// When to color the output ("auto" colors terminals, honoring NO_COLOR and CLICOLOR_FORCE)
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        global = true,
+        help_heading = "Global Options"
+    )]
+    color: ColorChoice,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output, given an environment variable lookup and whether stdout is a
+    /// terminal
+    fn enabled(self, var: impl Fn(&str) -> Option<String>, terminal: bool) -> bool {
+        let set = |name| var(name).is_some_and(|value| !value.is_empty() && value != "0");
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto if var("NO_COLOR").is_some_and(|value| !value.is_empty()) => false,
+            ColorChoice::Auto => set("CLICOLOR_FORCE") || terminal,
+        }
+    }
+}
+
+/// Run the command line the `versite` binary is, with the arguments of the process
+pub fn run() -> ExitCode {
+    let cli = Cli::parse();
+    let color = cli
+        .color
+        .enabled(|name| std::env::var(name).ok(), io::stdout().is_terminal());
+    colored::control::set_override(color);
+    match cli.command.execute(cli.git_args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+/// The exit code for a failed command, told apart by the first [`DocverError`] it wraps
+fn exit_code(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<DocverError>())
+        .map_or(1, DocverError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_choice() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(ColorChoice::Auto.enabled(env(&[]), true));
+        assert!(!ColorChoice::Auto.enabled(env(&[]), false));
+        assert!(!ColorChoice::Auto.enabled(env(&[("NO_COLOR", "1")]), true));
+        assert!(ColorChoice::Auto.enabled(env(&[("NO_COLOR", "")]), true));
+        assert!(ColorChoice::Auto.enabled(env(&[("CLICOLOR_FORCE", "1")]), false));
+        assert!(!ColorChoice::Auto.enabled(env(&[("CLICOLOR_FORCE", "0")]), false));
+        assert!(ColorChoice::Always.enabled(env(&[("NO_COLOR", "1")]), false));
+        assert!(!ColorChoice::Never.enabled(env(&[("CLICOLOR_FORCE", "1")]), true));
+    }
+
+    #[test]
+    fn never_prints_plain_text() {
+        use colored::Colorize;
+
+        colored::control::set_override(ColorChoice::Never.enabled(|_| None, true));
+        assert_eq!("v1.0".green().to_string(), "v1.0");
+        colored::control::unset_override();
+    }
+}
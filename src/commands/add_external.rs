@@ -0,0 +1,89 @@
+use anyhow::bail;
+use clap::Args;
+
+use crate::{
+    GitArgs,
+    versions::{Compat, Version, Versions},
+};
+
+use super::deploy::{
+    Status, add_versions_files, has_schema_copies, has_versions_js, push, push_mirrors,
+};
+
+#[derive(Debug, Args)]
+/// Add a version that links to docs hosted elsewhere, e.g. old versions moved to an archive host,
+/// so it still appears in `list` and version selectors
+pub struct AddExternalArgs {
+    /// Version identifier, optionally with its title (e.g. "v0.9" or "v0.9:0.9 (archived)")
+    version: Version,
+
+    /// Absolute URL of the version's docs (e.g. "https://archive.example.com/v0.9/")
+    url: String,
+
+    /// Title of the version, shown by version selectors (defaults to the title given with the
+    /// version, or else the version)
+    #[arg(short, long)]
+    title: Option<String>,
+
+    /// Write versions.json in another tool's format, as with `deploy --compat`
+    #[arg(long, value_enum, value_name = "TOOL")]
+    compat: Option<Compat>,
+}
+
+impl AddExternalArgs {
+    pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
+        git_args.fetch()?;
+        let remote_rev = git_args.remote_rev();
+        let remote_tip = git_args.backend().rev_parse(&remote_rev);
+        let parent = remote_tip
+            .clone()
+            .or_else(|| git_args.backend().rev_parse(&git_args.branch));
+
+        let mut versions = match &parent {
+            Some(parent) => Versions::from_git(&*git_args.backend(), parent)?,
+            None => Versions::default(),
+        };
+        let tag = &self.version.tag;
+        if versions
+            .by_tag(tag)
+            .is_some_and(|version| !version.is_external())
+        {
+            bail!(
+                "version {} is deployed to {}; it can't also link elsewhere",
+                tag,
+                git_args.branch
+            );
+        }
+        let title = self.title.clone().or_else(|| self.version.title.clone());
+        versions.add_external(tag.clone(), self.url.clone(), title)?;
+
+        let commit = git_args.commit(&format!(
+            "Added external version {} with {} {}",
+            tag,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        let mut commit = add_versions_files(
+            commit,
+            &git_args,
+            &versions,
+            self.compat,
+            has_versions_js(&git_args, parent.as_deref()),
+            has_schema_copies(&git_args, parent.as_deref(), &versions),
+        )?;
+        if let Some(parent) = &parent {
+            commit = commit.parent(parent);
+        }
+        git_args.backend().commit(&commit, None)?;
+        println!(
+            "Added {} to {}, linking to {}.",
+            tag, git_args.branch, self.url
+        );
+
+        if git_args.push {
+            let pushed = push(&git_args, git_args.remote(), remote_tip.as_deref());
+            push_mirrors(&git_args, pushed.map_err(Into::into), Status::new(false))?;
+        }
+        Ok(())
+    }
+}
@@ -1,99 +1,1366 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, IsTerminal},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
 
-use anyhow::Context;
-use clap::Args;
+use anyhow::{Context, bail};
+use clap::{Args, ValueEnum};
+use dialoguer::{Confirm, theme::ColorfulTheme};
 use git_cmd::git_in_dir;
+use serde::Serialize;
 use walkdir::WalkDir;
 
 use crate::{
-    GitArgs,
-    git::Commit,
-    versions::{VERSIONS_FILE, Versions},
+    DocverError, GitArgs, checksums,
+    compress::Encoding,
+    git::{self, Commit, ImportReport},
+    html,
+    progress::{Progress, format_bytes, parse_size},
+    project::VersionSource,
+    reader::{MAX_IN_FLIGHT_BYTES, read_files},
+    search::{self, SearchIndexFormat},
+    sitemap, template,
+    versions::{
+        Channels, Compat, ManifestFormat, NameError, OrderingStrategy, RewriteFormat,
+        RewriteOptions, VERSIONS_FILE, VERSIONS_JS, Versions, channel_of, glob_matches,
+        parse_redirect_status, robots_allow_list, sanitize_name, validate_name,
+    },
 };
 
+/// Where a deploy prints its human-readable status messages: stdout, or stderr when stdout
+/// carries the `--json` report
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Status {
+    to_stderr: bool,
+}
+
+impl Status {
+    pub(crate) fn new(to_stderr: bool) -> Self {
+        Self { to_stderr }
+    }
+
+    /// Print a human-readable status message
+    fn print(self, message: std::fmt::Arguments) {
+        if self.to_stderr {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+/// Version of the `--json` report schema, bumped on incompatible changes
+const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// The outcome of a deploy, printed as the `--json` report. Fields are only ever added within a
+/// schema version.
+#[derive(Debug, Clone, Default, Serialize)]
+#[non_exhaustive]
+pub struct DeployReport {
+    pub schema_version: u32,
+    /// "deployed", "skipped" (with --if-not-exists) or "error"
+    pub status: &'static str,
+    pub branch: String,
+    /// The deployed version, once resolved
+    pub version: Option<String>,
+    /// Aliases pointing at the version after the deploy
+    pub aliases: Vec<String>,
+    /// The new tip of the publish branch
+    pub commit: Option<String>,
+    pub files_uploaded: usize,
+    pub files_reused: usize,
+    /// Bytes streamed into the commit
+    pub bytes: u64,
+    /// Bytes of generated file contents, such as versions.json, held in memory until the commit
+    /// is written; the site's files are streamed from disk or written out as they are read and
+    /// don't count
+    pub bytes_buffered: u64,
+    /// Versions removed by the retention policy
+    pub pruned: Vec<String>,
+    /// The objects git fast-import wrote (absent for signed commits)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import: Option<ImportReport>,
+    /// Whether the branch was pushed to every remote
+    pub pushed: bool,
+    /// The annotated tag created with --tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Args)]
 /// Deploy a built static site version to the target branch
 pub struct DeployArgs {
-    /// Path to the directory containing the built site to deploy
-    path: PathBuf,
+    /// Path to the built site; omitted when using --map
+    #[arg(required_unless_present = "maps")]
+    path: Option<PathBuf>,
 
-    /// Version identifier for this deployment (e.g. "v1.2.3" or "1.0")
-    version: String,
+    /// Version identifier for this deployment (e.g. "v1.2.3"); omitted with --version-from
+    #[arg(required_unless_present_any = ["version_from", "maps"])]
+    version: Option<String>,
 
     /// Additional aliases that should point to this version (e.g. "latest")
     aliases: Vec<String>,
 
-    /// Optional human-readable title for this version
+    /// Map a source directory to a subpath of the version (e.g. "./target/doc=/api"); repeatable
+    #[arg(long = "map", value_name = "SRC=DEST")]
+    maps: Vec<Mapping>,
+
+    /// Read the version identifier from project metadata instead of the positional argument
+    #[arg(long, value_enum)]
+    version_from: Option<VersionSource>,
+
+    /// With --version-from, fail unless the version read is this one
+    #[arg(long, value_name = "VERSION", requires = "version_from")]
+    expect_version: Option<String>,
+
+    /// Optional human-readable title for this version; may use the placeholders {version},
+    /// {date}, {source_sha} and {channel} ("{{" and "}}" for literal braces)
     #[arg(short, long)]
     title: Option<String>,
+
+    /// Leave the version out of `list` and versions.js, e.g. for previews that should only be
+    /// reachable by URL. Redeploying without it shows the version again.
+    #[arg(long)]
+    hide: bool,
+
+    /// Start versions.json over with only this deploy, e.g. when the file on the branch can't be
+    /// parsed. The directories of the versions it listed stay on the branch.
+    #[arg(long)]
+    reset_versions: bool,
+
+    /// Replace slashes in the version and aliases with dashes, e.g. to deploy a preview of the
+    /// branch "feature/login" as "feature-login". Versions are single directories, so names with
+    /// slashes are rejected otherwise.
+    #[arg(long)]
+    sanitize_slashes: bool,
+
+    /// Deploy files whose names aren't valid UTF-8 under a lossy name, with U+FFFD replacing
+    /// the invalid bytes; by default such files abort the deploy
+    #[arg(long, conflicts_with = "raw_paths")]
+    lossy_paths: bool,
+
+    /// Deploy files whose names aren't valid UTF-8 under their exact bytes (Unix only)
+    #[arg(long)]
+    raw_paths: bool,
+
+    /// Order of the versions in versions.json, `list` and versions.js, kept for later deploys
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    ordering: Option<OrderingStrategy>,
+
+    /// Layout of versions.json (default: pretty), kept for later deploys
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    manifest_format: Option<ManifestFormat>,
+
+    /// Position of this version with `--ordering explicit`, heaviest first; kept on redeploys
+    /// without it
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    weight: Option<i64>,
+
+    /// Keep the deploy history of the branch, or replace it with a single commit on every
+    /// deploy so clones stay small
+    #[arg(long, value_enum, default_value_t = History::Append)]
+    history: History,
+
+    /// Create an annotated tag (e.g. "site/v1.2.3") on the deploy commit, pushed with --push
+    #[arg(long)]
+    tag: bool,
+
+    /// Prefix of the tag name created with --tag
+    #[arg(long, value_name = "PREFIX", default_value = "site/", requires = "tag")]
+    tag_prefix: String,
+
+    /// Move the tag when it already exists, e.g. when re-deploying a version
+    #[arg(long, requires = "tag")]
+    force_tag: bool,
+
+    /// Skip the deploy (successfully) when the version is already deployed
+    #[arg(long, conflicts_with = "fail_if_exists")]
+    if_not_exists: bool,
+
+    /// Fail when the version is already deployed
+    #[arg(long)]
+    fail_if_exists: bool,
+
+    /// With --if-not-exists, still point the given aliases at an already deployed version
+    #[arg(long, requires = "if_not_exists")]
+    update_aliases: bool,
+
+    /// Overwrite a diverged publish branch, rebasing onto the remote tip or, failing that,
+    /// recreating the branch from its current files
+    #[arg(long)]
+    force: bool,
+
+    /// Number of times to rebuild and re-push when a concurrent deploy wins the push race
+    #[arg(long, default_value_t = 3)]
+    push_retries: u32,
+
+    /// Point this alias at the highest semver version after deploying (which may leave it unchanged)
+    #[arg(long, value_name = "ALIAS")]
+    auto_latest: Option<String>,
+
+    /// Consider prerelease versions for --auto-latest
+    #[arg(long, requires = "auto_latest")]
+    include_prereleases: bool,
+
+    /// Serve the site root from this alias (instead of "latest") and record it in versions.json
+    /// for version selectors and later deploys
+    #[arg(long, value_name = "ALIAS")]
+    default_alias: Option<String>,
+
+    /// Maintain channel aliases (stable, beta, rc) from the prerelease part of the version
+    #[arg(long)]
+    channels: bool,
+
+    /// Override which alias tracks a channel (e.g. "beta=next"); repeatable
+    #[arg(long = "channel-alias", value_name = "CHANNEL=ALIAS", requires = "channels", value_parser = parse_channel_alias)]
+    channel_aliases: Vec<(String, String)>,
+
+    /// Prune versions whose tag matches this glob (e.g. "pr-*") down to the newest --keep
+    #[arg(long, requires = "keep", value_name = "PATTERN")]
+    keep_matching: Option<String>,
+
+    /// Number of versions matching --keep-matching to retain; aliased versions are never pruned
+    #[arg(long, requires = "keep_matching")]
+    keep: Option<usize>,
+
+    /// Don't prune versions by the retention policy in versions.json (see `retention`)
+    #[arg(long)]
+    no_retention: bool,
+
+    /// After committing (and before pushing), check that every file and versions.json landed
+    /// in the new branch tip as expected
+    #[arg(long)]
+    verify: bool,
+
+    /// Append provenance trailers (Deployed-Version, Source-Commit, CI-Run-URL) to the commit message
+    #[arg(long, overrides_with = "no_trailers")]
+    trailers: bool,
+
+    /// Do not append provenance trailers to the commit message
+    #[arg(long, overrides_with = "trailers")]
+    no_trailers: bool,
+
+    /// Additional trailer to append to the commit message; repeatable
+    #[arg(long = "trailer", value_name = "KEY=VALUE", value_parser = parse_trailer)]
+    extra_trailers: Vec<(String, String)>,
+
+    /// Upload every file instead of reusing unchanged blobs from the previous deploy
+    #[arg(long)]
+    no_reuse: bool,
+
+    /// Write a sitemap.xml listing the HTML pages of the default version; once written it is
+    /// kept up to date by every later deploy
+    #[arg(long, requires = "base_url")]
+    sitemap: bool,
+
+    /// Also list the pages of every other version in the sitemap, with a lower priority
+    #[arg(long, requires = "sitemap")]
+    sitemap_all_versions: bool,
+
+    /// HTTP status of the alias rules in _redirects: 200 serves the version under the alias
+    /// URL, 301 and 302 redirect to the version's own URL
+    #[arg(long, value_name = "CODE", value_parser = parse_redirect_status, default_value_t = 200)]
+    redirect_status: u16,
+
+    /// HTTP status of the alias rules only, overriding --redirect-status
+    #[arg(long, value_name = "CODE", value_parser = parse_redirect_status)]
+    alias_status: Option<u16>,
+
+    /// HTTP status of the site root rule only, overriding --redirect-status (e.g. 301 with
+    /// 200 aliases)
+    #[arg(long, value_name = "CODE", value_parser = parse_redirect_status)]
+    root_status: Option<u16>,
+
+    /// Write a robots.txt that only lets crawlers index the default version; once written it
+    /// is kept up to date by every later deploy
+    #[arg(long)]
+    robots: bool,
+
+    /// Also allow crawlers to index this version in robots.txt; repeatable (default: the
+    /// versions allowed by the existing robots.txt)
+    #[arg(long = "robots-allow", value_name = "VERSION")]
+    robots_allow: Vec<String>,
+
+    /// Add a canonical link pointing at the default version's copy of the page to the HTML
+    /// files of a version that is not the default (e.g. "https://docs.example.com")
+    #[arg(long, value_name = "URL")]
+    canonical_base: Option<String>,
+
+    /// Leave pages that already have a canonical link pointing elsewhere untouched
+    #[arg(long, requires = "canonical_base")]
+    keep_existing_canonical: bool,
+
+    /// Inject a banner linking to the default version into the HTML pages of a version that
+    /// is not the default
+    #[arg(long)]
+    inject_banner: bool,
+
+    /// HTML snippet to use as the banner, with the placeholders {version}, {latest} and
+    /// {latest_url}
+    #[arg(long, value_name = "PATH", requires = "inject_banner")]
+    banner_template: Option<PathBuf>,
+
+    /// Write a versions.js for client-side version switchers and copy versions.json into every
+    /// version directory; once written they are kept up to date by every later deploy
+    #[arg(long)]
+    versions_js: bool,
+
+    /// Write the copies of versions.json in the version directories as the whole file, with its
+    /// schema, instead of the bare list of entries versions.js exposes. Later deploys keep
+    /// writing them this way.
+    #[arg(long)]
+    schema_copies: bool,
+
+    /// Write versions.json in another tool's format, e.g. mike's for themes like
+    /// mkdocs-material that read it. Either format is read back.
+    #[arg(long, value_enum, value_name = "TOOL")]
+    compat: Option<Compat>,
+
+    /// Merge the search indexes of all versions into one at the root, annotating each result
+    /// with its version
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    merge_search_index: Option<SearchIndexFormat>,
+
+    /// Leave documents out of the merged search index once it reaches this many bytes
+    #[arg(long, value_name = "BYTES", requires = "merge_search_index")]
+    search_index_max_size: Option<u64>,
+
+    /// Also commit compressed siblings (e.g. "page.html.gz") of matching files, for hosts
+    /// that serve them; comma-separated
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "ENCODINGS")]
+    precompress: Vec<Encoding>,
+
+    /// Smallest file size, in bytes, to precompress
+    #[arg(long, default_value_t = 1024, requires = "precompress")]
+    min_size: u64,
+
+    /// File extensions to precompress; comma-separated
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "html,css,js,svg,json",
+        requires = "precompress"
+    )]
+    types: Vec<String>,
+
+    /// Write a SHA256SUMS manifest of the version's files and record its hash in
+    /// manifests.json; once written, manifests are kept up to date by every later deploy
+    #[arg(long)]
+    checksums: bool,
+
+    /// Abort the deploy when the site's files add up to more than this (e.g. "500MB")
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_total_size: Option<u64>,
+
+    /// Abort the deploy when a file is larger than this (e.g. "50MB"), listing the offenders
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// How to deploy symbolic links in the site
+    #[arg(long, value_enum, default_value_t = Symlinks::Follow)]
+    symlinks: Symlinks,
+
+    /// Whether to deploy dotfiles and directories (nested .git directories and junk files such as
+    /// .DS_Store are always skipped)
+    #[arg(long, value_enum, default_value_t = Hidden::Include)]
+    hidden: Hidden,
+
+    /// Log every file left out of the deploy and why
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Commit files matching this pattern (relative to the version directory, e.g.
+    /// "scripts/*.sh") as executable, for platforms without an executable bit; repeatable
+    #[arg(long, value_name = "GLOB")]
+    executable: Vec<glob::Pattern>,
+
+    /// Fail on broken symlinks when preserving them instead of warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Ask for confirmation before deploying more files than this; without a terminal to ask
+    /// on, only warn
+    #[arg(long, value_name = "COUNT", default_value_t = 20_000)]
+    large_site_threshold: usize,
+
+    /// Deploy sites over --large-site-threshold without asking for confirmation
+    #[arg(long, short = 'y', alias = "allow-large")]
+    yes: bool,
+
+    /// Leave files over --max-file-size out of the deploy instead of aborting
+    #[arg(long, requires = "max_file_size")]
+    skip_oversize: bool,
+
+    /// Public URL the branch is served from (e.g. "https://docs.example.com")
+    #[arg(long, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Shell command to run after a successful deploy (after pushing, with --push), with
+    /// DOCVER_VERSION, DOCVER_ALIASES, DOCVER_COMMIT and DOCVER_BRANCH set
+    #[arg(long, value_name = "CMD")]
+    post_deploy_hook: Option<String>,
+
+    /// Print a JSON report of the deploy (or the error) to stdout; other messages go to stderr
+    #[arg(long)]
+    json: bool,
+}
+
+/// A deploy as the engine runs it, converted from [`DeployArgs`] or built by
+/// [`crate::api::deploy`]. The fields are the options of the same name.
+#[derive(Debug, Clone)]
+pub(crate) struct Deploy {
+    /// Source directories and where they go under the version directory
+    mappings: Vec<Mapping>,
+    /// The version identifier, unless it is read with `version_from`
+    version: Option<String>,
+    version_from: Option<VersionSource>,
+    expect_version: Option<String>,
+    pub(crate) aliases: Vec<String>,
+    /// Add the provenance trailers to the commit message (off with --no-trailers)
+    trailers: bool,
+    pub(crate) title: Option<String>,
+    hide: bool,
+    reset_versions: bool,
+    sanitize_slashes: bool,
+    lossy_paths: bool,
+    raw_paths: bool,
+    ordering: Option<OrderingStrategy>,
+    manifest_format: Option<ManifestFormat>,
+    weight: Option<i64>,
+    history: History,
+    tag: bool,
+    tag_prefix: String,
+    force_tag: bool,
+    if_not_exists: bool,
+    fail_if_exists: bool,
+    update_aliases: bool,
+    force: bool,
+    push_retries: u32,
+    auto_latest: Option<String>,
+    include_prereleases: bool,
+    default_alias: Option<String>,
+    channels: bool,
+    channel_aliases: Vec<(String, String)>,
+    keep_matching: Option<String>,
+    keep: Option<usize>,
+    no_retention: bool,
+    verify: bool,
+    extra_trailers: Vec<(String, String)>,
+    no_reuse: bool,
+    sitemap: bool,
+    sitemap_all_versions: bool,
+    redirect_status: u16,
+    alias_status: Option<u16>,
+    root_status: Option<u16>,
+    robots: bool,
+    robots_allow: Vec<String>,
+    canonical_base: Option<String>,
+    keep_existing_canonical: bool,
+    inject_banner: bool,
+    banner_template: Option<PathBuf>,
+    versions_js: bool,
+    schema_copies: bool,
+    compat: Option<Compat>,
+    merge_search_index: Option<SearchIndexFormat>,
+    search_index_max_size: Option<u64>,
+    precompress: Vec<Encoding>,
+    min_size: u64,
+    types: Vec<String>,
+    checksums: bool,
+    max_total_size: Option<u64>,
+    max_file_size: Option<u64>,
+    symlinks: Symlinks,
+    hidden: Hidden,
+    verbose: bool,
+    executable: Vec<glob::Pattern>,
+    strict: bool,
+    large_site_threshold: usize,
+    yes: bool,
+    skip_oversize: bool,
+    base_url: Option<String>,
+    post_deploy_hook: Option<String>,
+}
+
+impl Default for Deploy {
+    /// The defaults of the command line options
+    fn default() -> Self {
+        Deploy {
+            mappings: Vec::new(),
+            version: None,
+            version_from: None,
+            expect_version: None,
+            aliases: Vec::new(),
+            trailers: true,
+            title: Default::default(),
+            hide: Default::default(),
+            reset_versions: Default::default(),
+            sanitize_slashes: Default::default(),
+            lossy_paths: Default::default(),
+            raw_paths: Default::default(),
+            ordering: Default::default(),
+            manifest_format: Default::default(),
+            weight: Default::default(),
+            history: History::Append,
+            tag: Default::default(),
+            tag_prefix: "site/".to_string(),
+            force_tag: Default::default(),
+            if_not_exists: Default::default(),
+            fail_if_exists: Default::default(),
+            update_aliases: Default::default(),
+            force: Default::default(),
+            push_retries: 3,
+            auto_latest: Default::default(),
+            include_prereleases: Default::default(),
+            default_alias: Default::default(),
+            channels: Default::default(),
+            channel_aliases: Default::default(),
+            keep_matching: Default::default(),
+            keep: Default::default(),
+            no_retention: Default::default(),
+            verify: Default::default(),
+            extra_trailers: Default::default(),
+            no_reuse: Default::default(),
+            sitemap: Default::default(),
+            sitemap_all_versions: Default::default(),
+            redirect_status: 200,
+            alias_status: Default::default(),
+            root_status: Default::default(),
+            robots: Default::default(),
+            robots_allow: Default::default(),
+            canonical_base: Default::default(),
+            keep_existing_canonical: Default::default(),
+            inject_banner: Default::default(),
+            banner_template: Default::default(),
+            versions_js: Default::default(),
+            schema_copies: Default::default(),
+            compat: Default::default(),
+            merge_search_index: Default::default(),
+            search_index_max_size: Default::default(),
+            precompress: Default::default(),
+            min_size: 1024,
+            types: ["html", "css", "js", "svg", "json"]
+                .map(String::from)
+                .to_vec(),
+            checksums: Default::default(),
+            max_total_size: Default::default(),
+            max_file_size: Default::default(),
+            symlinks: Symlinks::Follow,
+            hidden: Hidden::Include,
+            verbose: Default::default(),
+            executable: Default::default(),
+            strict: Default::default(),
+            large_site_threshold: 20_000,
+            yes: Default::default(),
+            skip_oversize: Default::default(),
+            base_url: Default::default(),
+            post_deploy_hook: Default::default(),
+        }
+    }
+}
+
+impl Deploy {
+    /// A deploy of the site at `source` as `version`, with the defaults for everything else
+    pub(crate) fn new(source: PathBuf, version: String) -> Self {
+        Deploy {
+            mappings: vec![Mapping::root(source)],
+            version: Some(version),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&DeployArgs> for Deploy {
+    /// clap fills the positionals in order, so the values after an omitted path (with --map)
+    /// or version (with --version-from) move back to where they belong
+    fn from(args: &DeployArgs) -> Self {
+        let mut path = args.path.clone();
+        let mut version = args.version.clone();
+        let mut aliases = args.aliases.clone();
+        if !args.maps.is_empty()
+            && let Some(first) = path.take()
+        {
+            aliases.splice(0..0, version.take());
+            version = Some(first.to_string_lossy().into_owned());
+        }
+        if args.version_from.is_some() {
+            aliases.splice(0..0, version.take());
+        }
+
+        let mut mappings = path.map(Mapping::root).into_iter().collect::<Vec<_>>();
+        mappings.extend(args.maps.iter().cloned());
+
+        Deploy {
+            mappings,
+            version,
+            version_from: args.version_from,
+            expect_version: args.expect_version.clone(),
+            aliases,
+            trailers: !args.no_trailers,
+            title: args.title.clone(),
+            hide: args.hide,
+            reset_versions: args.reset_versions,
+            sanitize_slashes: args.sanitize_slashes,
+            lossy_paths: args.lossy_paths,
+            raw_paths: args.raw_paths,
+            ordering: args.ordering,
+            manifest_format: args.manifest_format,
+            weight: args.weight,
+            history: args.history,
+            tag: args.tag,
+            tag_prefix: args.tag_prefix.clone(),
+            force_tag: args.force_tag,
+            if_not_exists: args.if_not_exists,
+            fail_if_exists: args.fail_if_exists,
+            update_aliases: args.update_aliases,
+            force: args.force,
+            push_retries: args.push_retries,
+            auto_latest: args.auto_latest.clone(),
+            include_prereleases: args.include_prereleases,
+            default_alias: args.default_alias.clone(),
+            channels: args.channels,
+            channel_aliases: args.channel_aliases.clone(),
+            keep_matching: args.keep_matching.clone(),
+            keep: args.keep,
+            no_retention: args.no_retention,
+            verify: args.verify,
+            extra_trailers: args.extra_trailers.clone(),
+            no_reuse: args.no_reuse,
+            sitemap: args.sitemap,
+            sitemap_all_versions: args.sitemap_all_versions,
+            redirect_status: args.redirect_status,
+            alias_status: args.alias_status,
+            root_status: args.root_status,
+            robots: args.robots,
+            robots_allow: args.robots_allow.clone(),
+            canonical_base: args.canonical_base.clone(),
+            keep_existing_canonical: args.keep_existing_canonical,
+            inject_banner: args.inject_banner,
+            banner_template: args.banner_template.clone(),
+            versions_js: args.versions_js,
+            schema_copies: args.schema_copies,
+            compat: args.compat,
+            merge_search_index: args.merge_search_index,
+            search_index_max_size: args.search_index_max_size,
+            precompress: args.precompress.clone(),
+            min_size: args.min_size,
+            types: args.types.clone(),
+            checksums: args.checksums,
+            max_total_size: args.max_total_size,
+            max_file_size: args.max_file_size,
+            symlinks: args.symlinks,
+            hidden: args.hidden,
+            verbose: args.verbose,
+            executable: args.executable.clone(),
+            strict: args.strict,
+            large_site_threshold: args.large_site_threshold,
+            yes: args.yes,
+            skip_oversize: args.skip_oversize,
+            base_url: args.base_url.clone(),
+            post_deploy_hook: args.post_deploy_hook.clone(),
+        }
+    }
 }
 
 impl DeployArgs {
     pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
-        let commit_sha = git_in_dir(".".into(), &["show", "-s", "--format=%h"])?;
+        let (report, result) = Deploy::from(self).report(git_args, Status::new(self.json));
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        result
+    }
+}
+
+/// How symbolic links in the site are deployed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Symlinks {
+    /// Commit links as git symlinks pointing at their original target
+    Preserve,
+    /// Deploy the files and directories links point at, skipping loops
+    Follow,
+    /// Leave links out of the deploy
+    Skip,
+}
+
+/// What the publish branch keeps of earlier deploys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum History {
+    /// Add each deploy as a new commit on top of the branch
+    Append,
+    /// Replace the branch with a single commit holding every version (force-pushed)
+    None,
+}
+
+/// Whether dotfiles in the site are deployed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Hidden {
+    Include,
+    Exclude,
+}
+
+/// Editor and OS droppings that are never meant to be published
+const JUNK_FILES: &[&str] = &["*.swp", "*~", ".DS_Store", "Thumbs.db", "desktop.ini"];
+
+/// A source directory and the path under the version directory it is deployed to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    source: PathBuf,
+    dest: PathBuf,
+}
+
+impl Mapping {
+    /// Deploy `source` to the root of the version directory
+    pub(crate) fn root(source: PathBuf) -> Self {
+        Mapping {
+            source,
+            dest: PathBuf::new(),
+        }
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((source, dest)) = s.rsplit_once('=') else {
+            bail!("invalid mapping {:?}: expected SRC=DEST", s);
+        };
+        if source.is_empty() {
+            bail!("invalid mapping {:?}: source path is empty", s);
+        }
+
+        let mut normalized = PathBuf::new();
+        for component in Path::new(dest).components() {
+            match component {
+                Component::Normal(part) => normalized.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    bail!(
+                        "invalid mapping {:?}: destination must stay inside the version",
+                        s
+                    )
+                }
+            }
+        }
+
+        Ok(Self {
+            source: source.into(),
+            dest: normalized,
+        })
+    }
+}
 
-        let message = git_args.message.clone().unwrap_or(format!(
+/// Counts of site files streamed into the commit versus reused from the previous deploy
+#[derive(Debug, Default)]
+struct SiteStats {
+    uploaded: usize,
+    reused: usize,
+    /// Bytes streamed into fast-import
+    bytes: u64,
+    /// Bytes of generated file contents held in memory until the commit is written
+    bytes_buffered: u64,
+    elapsed: Duration,
+    /// Versions removed by the retention policy
+    pruned: Vec<String>,
+    /// Aliases pointing at the deployed version
+    aliases: Vec<String>,
+    /// Number of precompressed siblings written, and bytes saved over the originals
+    precompressed: usize,
+    precompression_saved: u64,
+    /// What fast-import wrote, unless the commit was signed
+    import: Option<ImportReport>,
+}
+
+/// The deployment target resolved from the positional arguments and `--map` options
+#[derive(Debug)]
+struct Target {
+    mappings: Vec<Mapping>,
+    version: String,
+    aliases: Vec<String>,
+    /// The title with its template placeholders expanded
+    title: Option<String>,
+    /// The full SHA of HEAD, unless deploying from outside of a repository
+    source_commit: Option<String>,
+    /// The files found in the mappings, scanned before any git work
+    site: SiteFiles,
+}
+
+/// The files of a site to deploy, keyed by their path on the branch
+#[derive(Debug, Default)]
+struct SiteFiles {
+    /// The source file of each destination
+    sources: BTreeMap<String, PathBuf>,
+    sizes: BTreeMap<String, u64>,
+    /// The targets of symlinks deployed as links
+    links: BTreeMap<String, PathBuf>,
+    /// The exact bytes of names kept with --raw-paths
+    raw_names: BTreeMap<String, Vec<u8>>,
+}
+
+impl Deploy {
+    fn target(&self, git_args: &GitArgs, out: Status) -> anyhow::Result<Target> {
+        if self.mappings.is_empty() {
+            bail!("missing site path");
+        }
+
+        let version = match self.version_from {
+            Some(source) => {
+                let detected = source.detect(git_args.repo_dir())?;
+                if let Some(expected) = &self.expect_version
+                    && *expected != detected
+                {
+                    bail!(
+                        "version {} does not match {} read from {}",
+                        expected,
+                        detected,
+                        source.describe()
+                    );
+                }
+                out.print(format_args!(
+                    "Resolved version {} from {}",
+                    detected,
+                    source.describe()
+                ));
+                detected
+            }
+            None => self.version.clone().context("missing version identifier")?,
+        };
+
+        // Checked before anything is built: the names become directories of the branch
+        let mut names = std::iter::once(version)
+            .chain(self.aliases.iter().cloned())
+            .collect::<Vec<_>>();
+        for name in &mut names {
+            if self.sanitize_slashes {
+                *name = sanitize_name(name);
+            } else if name.contains(['/', '\\']) {
+                return Err(anyhow::Error::new(DocverError::Name(NameError::NotASegment(
+                    name.clone(),
+                )))
+                .context(format!(
+                    "{:?} has a slash; pass --sanitize-slashes to deploy it as {:?}",
+                    name,
+                    sanitize_name(name)
+                )));
+            }
+            validate_name(name)?;
+        }
+        let version = names.remove(0);
+        let aliases = names;
+
+        Ok(Target {
+            mappings: self.mappings.clone(),
+            version,
+            aliases,
+            title: None,
+            source_commit: None,
+            site: SiteFiles::default(),
+        })
+    }
+
+    /// Deploy, returning the report along with the result (which the report also records)
+    pub(crate) fn report(
+        &self,
+        git_args: GitArgs,
+        out: Status,
+    ) -> (DeployReport, anyhow::Result<()>) {
+        let mut report = DeployReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            status: "skipped",
+            branch: git_args.branch.clone(),
+            ..Default::default()
+        };
+        let repo_dir = git_args.repo_dir().to_string();
+        let result = self.run(git_args, &mut report, out);
+        // A new tag on a deploy that never landed would make the re-run refuse it
+        if result.is_err()
+            && !report.pushed
+            && !self.force_tag
+            && let Some(tag) = report.tag.take()
+        {
+            let _ = git_in_dir(repo_dir.as_str().into(), &["tag", "-d", &tag]);
+        }
+        if let Err(err) = &result {
+            report.status = "error";
+            report.error = Some(format!("{:#}", err));
+        }
+        (report, result)
+    }
+
+    /// Send status lines to stderr (as with --json), keeping stdout for the caller
+    fn run(
+        &self,
+        mut git_args: GitArgs,
+        report: &mut DeployReport,
+        out: Status,
+    ) -> anyhow::Result<()> {
+        let mut target = self.target(&git_args, out)?;
+        // A squashed commit never descends from the remote tip; the lease still protects
+        // deploys that landed after the fetch
+        if self.history == History::None {
+            git_args.force_push = true;
+        }
+        report.version = Some(target.version.clone());
+        target.site = self.scan_site(&git_args, &target, out)?;
+
+        // Refuse up front rather than after the deploy commit has been pushed
+        if self.tag
+            && !self.force_tag
+            && git_args
+                .backend()
+                .rev_parse(&format!("refs/tags/{}", self.tag_name(&target)))
+                .is_some()
+        {
+            bail!(
+                "tag {} already exists; pass --force-tag to move it to the new deploy",
+                self.tag_name(&target)
+            );
+        }
+
+        let commit_sha = git_in_dir(git_args.repo_dir().into(), &["show", "-s", "--format=%h"])?;
+        target.source_commit = git_in_dir(git_args.repo_dir().into(), &["rev-parse", "HEAD"])
+            .ok()
+            .map(|sha| sha.trim().to_string());
+
+        let mut message = git_args.message.clone().unwrap_or(format!(
             "Deployed {} to {}{} with {} {}",
             commit_sha,
-            self.version,
+            target.version,
             git_args
                 .deploy_prefix
                 .as_ref()
-                .map(|p| format!("in {}", p.display()))
+                .map(|p| format!(" in {}", p.display()))
                 .unwrap_or_default(),
             env!("CARGO_PKG_NAME"),
             env!("CARGO_PKG_VERSION")
         ));
 
-        git_in_dir(
-            ".".into(),
-            &["fetch", git_args.remote.as_str(), git_args.branch.as_str()],
-        )?;
+        target.title = self
+            .title
+            .as_deref()
+            .map(|template| {
+                let when = git_args
+                    .commit_date
+                    .clone()
+                    .unwrap_or_else(Commit::now_when);
+                template::render(template, |name| match name {
+                    "version" => Some(target.version.clone()),
+                    "date" => git::date_of(&when),
+                    "source_sha" => Some(commit_sha.trim().to_string()),
+                    "channel" => Some(channel_of(&target.version).unwrap_or_default()),
+                    _ => None,
+                })
+            })
+            .transpose()
+            .context("invalid --title")?;
+
+        let mut trailers = Vec::new();
+        if self.trailers {
+            trailers.push(("Deployed-Version".to_string(), target.version.clone()));
+            if let Some(sha) = &target.source_commit {
+                trailers.push(("Source-Commit".to_string(), sha.clone()));
+            }
+            if let Some(url) = ci_run_url() {
+                trailers.push(("CI-Run-URL".to_string(), url));
+            }
+        }
+        trailers.extend(self.extra_trailers.iter().cloned());
+        if !trailers.is_empty() {
+            message = append_trailers(&message, &trailers);
+        }
 
         let remote_rev = git_args.remote_rev();
-        let mut versions: Versions = Versions::from_git(&remote_rev);
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let main_version_path = deploy_prefix.join(&target.version);
 
-        versions.add(
-            self.version.clone(),
-            self.title.clone(),
-            self.aliases.clone().into_iter().collect(),
-        );
+        // Trees of the version directory from the previous attempt: as it was on the remote
+        // and as we deployed it. Used to detect a concurrent deploy of the same version.
+        let mut previous_trees: Option<(Option<String>, Option<String>)> = None;
+        // The versions on the remote when the previous attempt lost the push race
+        let mut raced_versions: Option<Versions> = None;
 
-        let versions_json = serde_json::to_string_pretty(&versions)
-            .context(format!("Failed to serialize {}", VERSIONS_FILE))?;
+        for attempt in 0..=self.push_retries {
+            if attempt > 0 {
+                let delay = Duration::from_secs(1 << (attempt - 1).min(5));
+                eprintln!(
+                    "Push rejected because {} moved; retrying in {}s (attempt {}/{})",
+                    remote_rev,
+                    delay.as_secs(),
+                    attempt,
+                    self.push_retries
+                );
+                thread::sleep(delay);
+            }
 
-        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+            git_args.fetch()?;
+
+            // The remote tip as fetched, used as the lease when force-pushing
+            let remote_tip = git_args.backend().rev_parse(&remote_rev);
+            let remote_tree = git_args
+                .backend()
+                .rev_parse(&tree_rev(&remote_rev, &main_version_path));
 
-        let main_version_path = deploy_prefix.join(self.version.clone());
+            if let Some(before) = raced_versions.take()
+                && let Ok(after) = Versions::from_git(&*git_args.backend(), &remote_rev)
+            {
+                let diff = before.diff(&after);
+                if !diff.is_empty() {
+                    eprintln!("The concurrent deploy changed {}:\n{}", VERSIONS_FILE, diff);
+                }
+            }
 
-        let parent_head = git_in_dir(".".into(), &["rev-parse", remote_rev.as_str()])
-            .or_else(|_| git_in_dir(".".into(), &["rev-parse", git_args.branch.as_str()]))
-            .ok();
+            if let Some((base_tree, ours)) = &previous_trees
+                && remote_tree != *base_tree
+                && remote_tree != *ours
+            {
+                bail!(
+                    "version {} was concurrently deployed to {} with different content; giving up",
+                    target.version,
+                    remote_rev
+                );
+            }
 
-        let mut commit =
-            Commit::new(".", format!("refs/heads/{}", git_args.branch)).message(message.clone());
+            let Some(stats) = self.commit(
+                &target,
+                &git_args,
+                &message,
+                remote_tip.as_deref(),
+                previous_trees.is_some(),
+                out,
+            )?
+            else {
+                return Ok(());
+            };
 
-        if let Some(parent) = parent_head {
-            commit = commit.parent(parent.trim().to_string());
+            report.status = "deployed";
+            report.commit = git_args.backend().rev_parse(&git_args.branch);
+            report.aliases = stats.aliases.clone();
+            report.files_uploaded = stats.uploaded;
+            report.files_reused = stats.reused;
+            report.bytes = stats.bytes;
+            report.bytes_buffered = stats.bytes_buffered;
+            report.pruned = stats.pruned.clone();
+            report.import = stats.import.clone();
+            report.tag = self.tag.then(|| self.tag_name(&target));
+
+            // Print a concise success message for local import
+            out.print(format_args!(
+                "Deployed to {} (local): {} files uploaded, {} unchanged files reused ({} in {:.1}s, {} buffered).",
+                git_args.branch,
+                stats.uploaded,
+                stats.reused,
+                format_bytes(stats.bytes),
+                stats.elapsed.as_secs_f64(),
+                format_bytes(stats.bytes_buffered)
+            ));
+            if stats.precompressed > 0 {
+                out.print(format_args!(
+                    "Precompressed {} files, saving {}.",
+                    stats.precompressed,
+                    format_bytes(stats.precompression_saved)
+                ));
+            }
+            if !stats.pruned.is_empty() {
+                out.print(format_args!("Pruned versions: {}", stats.pruned.join(", ")));
+            }
+            if self.verbose
+                && let Some(import) = &stats.import
+            {
+                out.print(format_args!("{}", import_summary(import)));
+            }
+
+            if let Some(tag) = &report.tag {
+                out.print(format_args!("Tagged {} as {}", git_args.branch, tag));
+            }
+
+            if !git_args.push {
+                return self.post_deploy(report, out);
+            }
+
+            let pushed = match push(&git_args, git_args.remote(), remote_tip.as_deref()) {
+                Err(err) if attempt < self.push_retries && is_push_race(&err) => {
+                    let ours = git_args.backend().rev_parse(&tree_rev(&git_args.branch, &main_version_path));
+                    previous_trees = Some((remote_tree, ours));
+                    raced_versions = match &remote_tip {
+                        Some(tip) => Versions::from_git(&*git_args.backend(), tip).ok(),
+                        None => Some(Versions::default()),
+                    };
+                    continue;
+                }
+                Err(err) if git_args.force_push && err.to_string().contains("stale info") => {
+                    Err(anyhow::Error::new(err).context(format!(
+                        "{} changed since it was fetched, so another deploy probably landed concurrently. Re-run the deploy to rebase onto the new tip.",
+                        remote_rev
+                    )))
+                }
+                pushed => pushed.map_err(anyhow::Error::from),
+            };
+            push_mirrors(&git_args, pushed, out)?;
+            report.pushed = true;
+            self.push_tag(&git_args, report, out)?;
+            return self.post_deploy(report, out);
         }
 
-        commit = commit.add_bytes(VERSIONS_FILE, 0o100644, versions_json.into_bytes());
+        unreachable!("the final attempt always returns")
+    }
 
-        // TODO: make the default alias configurable
-        let rewrites = versions.netlify_rewrites("latest".into());
-        commit = commit.add_bytes("_redirects", 0o100644, rewrites.into_bytes());
+    /// The name of the --tag tag for the deployed version
+    fn tag_name(&self, target: &Target) -> String {
+        format!("{}{}", self.tag_prefix, target.version)
+    }
 
-        if std::path::Path::new(".gitignore").exists() {
-            commit = commit.add_file(".gitignore", ".gitignore")?;
+    /// Push the --tag tag the deploy commit was imported with to every remote
+    fn push_tag(
+        &self,
+        git_args: &GitArgs,
+        report: &DeployReport,
+        out: Status,
+    ) -> anyhow::Result<()> {
+        let Some(name) = &report.tag else {
+            return Ok(());
+        };
+        let refname = format!("refs/tags/{}", name);
+        for remote in &git_args.remotes {
+            let mut push_args = vec!["push", remote.as_str(), refname.as_str()];
+            if self.force_tag {
+                push_args.push("--force");
+            }
+            git_in_dir(git_args.repo_dir().into(), &push_args)
+                .with_context(|| format!("failed to push tag {} to {}", name, remote))?;
+            out.print(format_args!("Pushed tag {} to {}", name, remote));
+        }
+        Ok(())
+    }
+
+    /// Run the `--post-deploy-hook` command, if any, failing with its stderr when it fails
+    fn post_deploy(&self, report: &DeployReport, out: Status) -> anyhow::Result<()> {
+        let Some(hook) = &self.post_deploy_hook else {
+            return Ok(());
+        };
+        out.print(format_args!("Running post-deploy hook: {}", hook));
+
+        let mut command = if cfg!(windows) {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", hook]);
+            command
+        } else {
+            let mut command = std::process::Command::new("sh");
+            command.args(["-c", hook]);
+            command
+        };
+        let output = command
+            .env(
+                "DOCVER_VERSION",
+                report.version.as_deref().unwrap_or_default(),
+            )
+            .env("DOCVER_ALIASES", report.aliases.join(","))
+            .env(
+                "DOCVER_COMMIT",
+                report.commit.as_deref().unwrap_or_default(),
+            )
+            .env("DOCVER_BRANCH", &report.branch)
+            .output()
+            .with_context(|| format!("failed to run post-deploy hook {:?}", hook))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !stdout.trim().is_empty() {
+            out.print(format_args!("{}", stdout.trim_end()));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            bail!(
+                "post-deploy hook {:?} failed ({}):\n{}",
+                hook,
+                output.status,
+                stderr.trim_end()
+            );
+        }
+        if !stderr.trim().is_empty() {
+            eprintln!("{}", stderr.trim_end());
+        }
+        Ok(())
+    }
+
+    /// Build and import the deploy commit on top of `remote_tip` (or the local branch when the
+    /// remote has none). Returns `None` when the deploy was skipped.
+    fn commit(
+        &self,
+        target: &Target,
+        git_args: &GitArgs,
+        message: &str,
+        remote_tip: Option<&str>,
+        replace_local: bool,
+        out: Status,
+    ) -> anyhow::Result<Option<SiteStats>> {
+        let remote_rev = git_args.remote_rev();
+        let mut versions = if self.reset_versions {
+            Versions::default()
+        } else {
+            Versions::from_git(&*git_args.backend(), &git_args.read_rev()).map_err(|err| match err {
+                DocverError::Manifest { .. } => anyhow::Error::new(err).context(format!(
+                    "fix {}, or deploy with --reset-versions to start over without the versions it lists",
+                    VERSIONS_FILE
+                )),
+                err => err.into(),
+            })?
+        };
+        for error in versions.validate() {
+            eprintln!("warning: {} in {}", error, VERSIONS_FILE);
+        }
+
+        let existing_title = versions.by_tag(&target.version).map(|v| v.title.clone());
+        if existing_title.is_some() {
+            if self.fail_if_exists {
+                return Err(DocverError::VersionExists(target.version.clone()).into());
+            }
+            if self.if_not_exists && (!self.update_aliases || target.aliases.is_empty()) {
+                out.print(format_args!(
+                    "Version {} is already deployed, skipping.",
+                    target.version
+                ));
+                return Ok(None);
+            }
+        }
+        // An existing version is only touched to move the requested aliases onto it
+        let aliases_only = existing_title.is_some() && self.if_not_exists;
+
+        let repointed = versions.add(
+            target.version.clone(),
+            match existing_title {
+                Some(title) if aliases_only => title,
+                _ => target.title.clone(),
+            },
+            target.aliases.iter().cloned().collect(),
+        )?;
+        for (alias, previous) in repointed {
+            out.print(format_args!(
+                "Moving {} from {} to {}",
+                alias, previous, target.version
+            ));
+        }
+        let when = git_args
+            .commit_date
+            .clone()
+            .unwrap_or_else(Commit::now_when);
+        if !aliases_only {
+            versions.set_hidden(&target.version, self.hide)?;
+            let at = git::iso_of(&when).context("invalid commit date")?;
+            versions.record_deploy(&target.version, &at, target.source_commit.as_deref())?;
+        }
+        if let Some(weight) = self.weight {
+            versions.set_weight(&target.version, Some(weight))?;
+        }
+        if let Some(ordering) = self.ordering {
+            versions.set_ordering(ordering);
+        }
+        if let Some(format) = self.manifest_format {
+            versions.set_manifest_format(format);
+        }
+
+        if let Some(alias) = &self.auto_latest
+            && let Some(latest) = versions.latest_semver(self.include_prereleases)
+        {
+            let latest = latest.tag.clone();
+            out.print(format_args!("Pointing {} at {}", alias, latest));
+            versions.set_alias(alias.clone(), &latest)?;
+        }
+
+        if self.channels {
+            let mut channels = Channels::default();
+            for (channel, alias) in &self.channel_aliases {
+                channels.set(channel, alias);
+            }
+            if let Some((alias, tag)) = versions.update_channel(&target.version, &channels) {
+                out.print(format_args!("Pointing {} at {}", alias, tag));
+            }
+        }
+
+        if let Some(alias) = &self.default_alias {
+            versions.set_default(alias)?;
+        }
+
+        let mut pruned = Vec::new();
+        if let (Some(pattern), Some(keep)) = (&self.keep_matching, self.keep) {
+            pruned = versions.prune(pattern, keep, &target.version);
+        }
+        if !self.no_retention {
+            let now = git::seconds_of(&when).context("invalid commit date")?;
+            pruned.extend(versions.apply_retention(now, &target.version));
+        }
+
+        let versions_json = versions
+            .to_json(self.compat)
+            .context(format!("Failed to serialize {}", VERSIONS_FILE))?;
+
+        let parent_head = remote_tip
+            .map(str::to_string)
+            .or_else(|| git_args.backend().rev_parse(&git_args.branch));
+
+        let progress = Progress::new("Scanning", git_args.quiet);
+        let mut commit = git_args
+            .commit(message)
+            // A retry replaces our own unpublished commit from the rejected attempt
+            .force(replace_local);
+        if self.tag {
+            // Annotated with the deploy commit's message, in the same import as the commit
+            commit = commit.tag(self.tag_name(target), message);
+        }
+
+        let versions_copies = self.versions_js || has_versions_js(git_args, parent_head.as_deref());
+        let schema_copies =
+            self.schema_copies || has_schema_copies(git_args, parent_head.as_deref(), &versions);
+
+        let rewrite_options = RewriteOptions {
+            prefix: git_args.prefix(),
+            alias_status: self.alias_status.unwrap_or(self.redirect_status),
+            root_status: self.root_status.unwrap_or(self.redirect_status),
+        };
+        for (path, rewrites) in versions.rewrites(RewriteFormat::Netlify, &rewrite_options) {
+            commit = commit.add_bytes(path.to_string_lossy(), 0o100644, rewrites.into_bytes());
+        }
+
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let main_version_path: &Path = &deploy_prefix.join(&target.version);
+        let robots_path = deploy_prefix
+            .join("robots.txt")
+            .to_string_lossy()
+            .to_string();
+        let existing_robots = parent_head.as_ref().and_then(|parent| {
+            git_in_dir(
+                git_args.repo_dir().into(),
+                &["show", &format!("{}:{}", parent, robots_path)],
+            )
+            .ok()
+        });
+        if self.robots || !self.robots_allow.is_empty() || existing_robots.is_some() {
+            let allow = match &existing_robots {
+                Some(existing) if self.robots_allow.is_empty() => robots_allow_list(existing),
+                _ => self.robots_allow.clone(),
+            };
+            let robots = versions.robots_txt(&git_args.prefix(), &allow);
+            commit = commit.add_bytes(&robots_path, 0o100644, robots.into_bytes());
+        }
+
+        if let Some(worktree) = git_args.worktree()
+            && worktree.join(".gitignore").exists()
+        {
+            commit = commit.add_file(".gitignore", worktree.join(".gitignore"))?;
         }
 
         if git_in_dir(
-            ".".into(),
+            git_args.repo_dir().into(),
             &["show", format!("{}:{}", remote_rev, ".nojekyll").as_str()],
         )
         .or_else(|_| {
             git_in_dir(
-                ".".into(),
+                git_args.repo_dir().into(),
                 &[
                     "show",
                     format!("{}:{}", git_args.branch, ".nojekyll").as_str(),
@@ -105,39 +1372,1750 @@ impl DeployArgs {
             commit = commit.add_bytes(".nojekyll", 0o100644, Vec::<u8>::new());
         }
 
-        commit = commit.delete_path(main_version_path.to_string_lossy());
+        for tag in &pruned {
+            commit = commit.delete_path(deploy_prefix.join(tag).to_string_lossy());
+        }
 
-        for entry in WalkDir::new(&self.path)
-            .follow_links(false)
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| e.file_type().is_file())
-        {
-            let path = entry.path();
-            let rel = path.strip_prefix(&self.path).unwrap();
-            let dest = main_version_path.join(rel);
-            let dest_str = dest.to_string_lossy().to_string();
-            commit = commit.add_file(dest_str, path)?;
+        let mut stats = SiteStats::default();
+        if aliases_only {
+            out.print(format_args!(
+                "Version {} is already deployed, updating aliases only.",
+                target.version
+            ));
+        } else {
+            let rewrites = self.rewrites(target, &versions, &deploy_prefix)?;
+            (commit, stats) = self.add_site(commit, git_args, target, &rewrites, &progress)?;
+            let own_versions = main_version_path.join(VERSIONS_FILE);
+            if versions_copies && commit.contains(&own_versions.to_string_lossy()) {
+                bail!(
+                    "the site has its own {}, which would be replaced by the copy of the version list kept next to {}; rename it",
+                    own_versions.display(),
+                    VERSIONS_JS
+                );
+            }
         }
 
-        commit.run()?;
+        // Once written, the manifests are kept up to date by every later deploy
+        let manifests_path = deploy_prefix.join(checksums::MANIFESTS_INDEX);
+        let existing_manifests = parent_head.as_ref().and_then(|parent| {
+            git_in_dir(
+                git_args.repo_dir().into(),
+                &["show", &tree_rev(parent, &manifests_path)],
+            )
+            .ok()
+        });
+        if self.checksums || existing_manifests.is_some() {
+            let mut manifests: BTreeMap<String, String> = match &existing_manifests {
+                Some(json) => serde_json::from_str(json)
+                    .with_context(|| format!("failed to parse {}", checksums::MANIFESTS_INDEX))?,
+                None => BTreeMap::new(),
+            };
+            manifests.retain(|tag, _| versions.by_tag(tag).is_some());
+            if !aliases_only {
+                let manifest = self.manifest(&commit, main_version_path, versions_copies)?;
+                manifests.insert(
+                    target.version.clone(),
+                    checksums::hex_digest(manifest.as_bytes()),
+                );
+                commit = commit.add_bytes(
+                    main_version_path
+                        .join(checksums::MANIFEST_FILE)
+                        .to_string_lossy(),
+                    0o100644,
+                    manifest.into_bytes(),
+                );
+            }
+            commit = commit.add_bytes(
+                manifests_path.to_string_lossy(),
+                0o100644,
+                serde_json::to_string_pretty(&manifests)?.into_bytes(),
+            );
+        }
+
+        commit = add_versions_files(
+            commit,
+            git_args,
+            &versions,
+            self.compat,
+            versions_copies,
+            schema_copies,
+        )?;
 
-        // Print a concise success message for local import
-        println!("Deployed to {} (local).", git_args.branch);
+        if let Some(format) = self.merge_search_index {
+            let parent_tree = match &parent_head {
+                Some(parent) => git_args.backend().ls_tree(parent, &git_args.prefix())?,
+                None => BTreeMap::new(),
+            };
+            // Newest versions first, each read from this commit or else the parent tree
+            let mut indexes = Vec::new();
+            let mut from_parent = Vec::new();
+            for (version, _) in &versions {
+                if version.is_external() {
+                    continue;
+                }
+                let path = deploy_prefix
+                    .join(&version.tag)
+                    .join(format.version_index())
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                if let Some(contents) = commit.read(&path)? {
+                    indexes.push((version.tag.clone(), contents));
+                } else if let Some(entry) = parent_tree.get(&path)
+                    && (aliases_only || version.tag != target.version)
+                {
+                    from_parent.push((indexes.len(), entry.sha.clone()));
+                    indexes.push((version.tag.clone(), Vec::new()));
+                }
+            }
+            let shas = from_parent
+                .iter()
+                .map(|(_, sha)| sha.clone())
+                .collect::<Vec<_>>();
+            for ((index, _), contents) in from_parent
+                .iter()
+                .zip(git::read_blobs(git_args.repo_dir(), &shas)?)
+            {
+                indexes[*index].1 = contents;
+            }
+
+            let merged = search::merge(&indexes, self.search_index_max_size)?;
+            out.print(format_args!(
+                "Merged the search indexes of {} versions{}",
+                indexes.len(),
+                match merged.dropped {
+                    0 => String::new(),
+                    dropped => format!(" ({} documents over the size limit left out)", dropped),
+                }
+            ));
+            commit = commit.add_bytes(
+                deploy_prefix.join(format.merged_index()).to_string_lossy(),
+                0o100644,
+                merged.json.into_bytes(),
+            );
+        }
 
-        if git_args.push {
+        // Generated from the resulting tree so it also covers versions deployed earlier
+        let sitemap_path = deploy_prefix
+            .join("sitemap.xml")
+            .to_string_lossy()
+            .to_string();
+        let existing_sitemap = parent_head.as_ref().and_then(|parent| {
             git_in_dir(
-                ".".into(),
-                &["push", git_args.remote.as_str(), git_args.branch.as_str()],
-            )?;
+                git_args.repo_dir().into(),
+                &["show", &format!("{}:{}", parent, sitemap_path)],
+            )
+            .ok()
+        });
+        let sitemap_settings = if self.sitemap {
+            Some(sitemap::Settings {
+                base_url: self.base_url.clone().unwrap_or_default(),
+                all_versions: self.sitemap_all_versions,
+            })
+        } else if let Some(existing) = &existing_sitemap {
+            let settings =
+                sitemap::settings(existing, &git_args.prefix(), &versions).map(|settings| {
+                    sitemap::Settings {
+                        base_url: self.base_url.clone().unwrap_or(settings.base_url),
+                        ..settings
+                    }
+                });
+            if settings.is_none() {
+                eprintln!(
+                    "warning: can't tell the base URL of the existing {}; pass --sitemap --base-url to update it",
+                    sitemap_path
+                );
+            }
+            settings
+        } else {
+            None
+        };
+        if let Some(settings) = sitemap_settings {
+            let base = match &parent_head {
+                Some(parent) => git_args
+                    .backend()
+                    .ls_tree(parent, "")?
+                    .into_keys()
+                    .collect(),
+                None => Vec::new(),
+            };
+            let sitemap = sitemap::render(
+                &settings.base_url,
+                &git_args.prefix(),
+                &versions,
+                &commit.paths(base),
+                settings.all_versions,
+            );
+            commit = commit.add_bytes(&sitemap_path, 0o100644, sitemap.into_bytes());
+        }
 
-            // Print a concise success message for push
-            println!(
-                "Pushed {} to {}:{}",
-                git_args.branch, git_args.remote, git_args.branch
+        let bytes_buffered = commit.buffered_bytes();
+        if self.history == History::None {
+            // Every other file of the previous tip is carried over by blob
+            let base = match &parent_head {
+                Some(parent) => git_args.backend().ls_tree(parent, "")?,
+                None => BTreeMap::new(),
+            };
+            commit = commit.squash(base).force(true);
+            stats.import = git_args.backend().commit(&commit, Some(&progress))?;
+        } else {
+            let based = match &parent_head {
+                Some(parent) => commit.clone().parent(parent),
+                None => commit.clone(),
+            };
+            stats.import = match git_args.backend().commit(&based, Some(&progress)) {
+                Ok(import) => import,
+                Err(err) if self.force && matches!(err, DocverError::NonFastForward(_)) => {
+                    self.force_update(commit.clone(), git_args, &progress, out)?
+                }
+                Err(err) => return Err(err.into()),
+            };
+        }
+
+        if self.verify {
+            let version_path = (!aliases_only).then_some(main_version_path);
+            verify(git_args, &commit, version_path, &versions_json, out)?;
+        }
+
+        stats.aliases = versions
+            .aliases()
+            .iter()
+            .filter(|(_, tag)| **tag == target.version)
+            .map(|(alias, _)| alias.clone())
+            .collect();
+        stats.aliases.sort();
+        stats.pruned = pruned;
+        stats.bytes = progress.bytes();
+        stats.bytes_buffered = bytes_buffered;
+        stats.elapsed = progress.elapsed();
+        Ok(Some(stats))
+    }
+
+    /// Recover from a non-fast-forward failure by basing the commit on the freshly fetched
+    /// remote tip, or as a last resort recreating the branch without history
+    fn force_update(
+        &self,
+        commit: Commit,
+        git_args: &GitArgs,
+        progress: &Progress,
+        out: Status,
+    ) -> anyhow::Result<Option<ImportReport>> {
+        let remote_rev = git_args.remote_rev();
+        out.print(format_args!(
+            "{} has diverged; --force: resetting it onto {}",
+            git_args.branch, remote_rev
+        ));
+
+        let remote_tip = git_args.fetch().map_err(anyhow::Error::from).and_then(|_| {
+            git_in_dir(
+                git_args.repo_dir().into(),
+                &["rev-parse", remote_rev.as_str()],
+            )
+        });
+        match remote_tip {
+            Ok(tip) => match git_args.backend().commit(
+                &commit.clone().parent(tip.trim()).force(true),
+                Some(progress),
+            ) {
+                Ok(import) => return Ok(import),
+                Err(err) => eprintln!("Failed to reset onto {}: {:#}", remote_rev, err),
+            },
+            Err(err) => eprintln!("Failed to fetch {}: {:#}", remote_rev, err),
+        }
+
+        let backend = git_args.backend();
+        let base = [remote_rev.as_str(), git_args.branch.as_str()]
+            .into_iter()
+            .find(|rev| backend.rev_parse(rev).is_some())
+            .context("no existing branch to recreate from")?;
+        out.print(format_args!(
+            "--force: recreating {} without history, keeping the other files from {}",
+            git_args.branch, base
+        ));
+        let squashed = commit.squash(backend.ls_tree(base, "")?).force(true);
+        Ok(backend.commit(&squashed, Some(progress))?)
+    }
+
+    /// The HTML rewrites for the pages of `target`. Pages of a version other than the default
+    /// point search engines at the default version's copy and can carry an outdated banner.
+    fn rewrites(
+        &self,
+        target: &Target,
+        versions: &Versions,
+        deploy_prefix: &Path,
+    ) -> anyhow::Result<html::Rewrites> {
+        let mut rewrites = html::Rewrites {
+            keep_existing_canonical: self.keep_existing_canonical,
+            ..Default::default()
+        };
+        let Some(latest) = versions.default_version().map(|version| &version.tag) else {
+            return Ok(rewrites);
+        };
+        if *latest == target.version {
+            return Ok(rewrites);
+        }
+
+        let alias_path = deploy_prefix
+            .join(versions.default_alias())
+            .to_string_lossy()
+            .replace('\\', "/");
+        rewrites.canonical_root = self
+            .canonical_base
+            .as_ref()
+            .map(|base| format!("{}/{}", base.trim_end_matches('/'), alias_path));
+        if self.inject_banner {
+            let banner_template = match &self.banner_template {
+                Some(path) => std::fs::read_to_string(path).with_context(|| {
+                    format!("failed to read banner template {}", path.display())
+                })?,
+                None => html::DEFAULT_BANNER.to_string(),
+            };
+            let latest_url = format!("/{}/", alias_path);
+            rewrites.banner = Some(
+                html::render_banner(&banner_template, &target.version, latest, &latest_url)
+                    .context("invalid banner template")?,
             );
         }
+        Ok(rewrites)
+    }
+
+    /// Walk the mapped source directories for the files to deploy, enforcing the file count and
+    /// size limits before any git work
+    fn scan_site(
+        &self,
+        git_args: &GitArgs,
+        target: &Target,
+        out: Status,
+    ) -> anyhow::Result<SiteFiles> {
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let version_path: &Path = &deploy_prefix.join(&target.version);
+        let progress = Progress::new("Scanning", git_args.quiet);
+        check_overlaps(&target.mappings)?;
+
+        // Track which source produced each destination so overlapping mappings are rejected
+        let mut sources: BTreeMap<String, PathBuf> = BTreeMap::new();
+        let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+        let mut links: BTreeMap<String, PathBuf> = BTreeMap::new();
+        let mut raw_names: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        let mut not_utf8 = Vec::new();
+        let mut skipped = 0;
+        let repo_root = fs::canonicalize(git_args.repo_dir()).ok();
+        for mapping in &target.mappings {
+            if !mapping.source.is_dir() {
+                bail!(
+                    "source directory {} does not exist",
+                    mapping.source.display()
+                );
+            }
+            if repo_root.is_some() && fs::canonicalize(&mapping.source).ok() == repo_root {
+                eprintln!(
+                    "warning: {} is the repository root; did you mean to deploy a build output directory?",
+                    mapping.source.display()
+                );
+            }
+
+            for entry in WalkDir::new(&mapping.source)
+                .follow_links(self.symlinks == Symlinks::Follow)
+                .into_iter()
+                .filter_entry(|entry| {
+                    let reason = (entry.depth() > 0)
+                        .then(|| self.skip_reason(entry))
+                        .flatten();
+                    if let Some(reason) = reason {
+                        if self.verbose {
+                            eprintln!("Skipping {}: {}", entry.path().display(), reason);
+                        }
+                        skipped += 1;
+                    }
+                    reason.is_none()
+                })
+            {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        // Loops and dangling targets only surface as errors while following
+                        if let Some(path) = err.path()
+                            && err.loop_ancestor().is_some()
+                        {
+                            eprintln!("warning: skipping symlink loop at {}", path.display());
+                        } else if let Some(path) = err.path()
+                            && path.is_symlink()
+                        {
+                            eprintln!("warning: skipping broken symlink {}", path.display());
+                        }
+                        continue;
+                    }
+                };
+                let path = entry.path();
+                if entry.path_is_symlink() && self.symlinks != Symlinks::Follow {
+                    let broken = fs::metadata(path).is_err();
+                    if self.symlinks == Symlinks::Skip {
+                        if broken {
+                            eprintln!("warning: skipping broken symlink {}", path.display());
+                        }
+                        continue;
+                    }
+                    if broken && self.strict {
+                        bail!("broken symlink {}", path.display());
+                    } else if broken {
+                        eprintln!("warning: preserving broken symlink {}", path.display());
+                    }
+                    let rel = path.strip_prefix(&mapping.source).unwrap();
+                    let dest = version_path.join(&mapping.dest).join(rel);
+                    let Some((dest_str, raw)) = self.dest_name(&dest) else {
+                        not_utf8.push(path.to_path_buf());
+                        continue;
+                    };
+                    raw_names.extend(raw.map(|raw| (dest_str.clone(), raw)));
+                    let target = fs::read_link(path)
+                        .with_context(|| format!("failed to read symlink {}", path.display()))?;
+                    if links.insert(dest_str.clone(), target).is_some() {
+                        bail!("overlapping mappings: two symlinks deploy to {}", dest_str);
+                    }
+                    continue;
+                }
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+
+                let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+                progress.inc(size);
+                let rel = path.strip_prefix(&mapping.source).unwrap();
+                let dest = version_path.join(&mapping.dest).join(rel);
+                let Some((dest_str, raw)) = self.dest_name(&dest) else {
+                    not_utf8.push(path.to_path_buf());
+                    continue;
+                };
+                raw_names.extend(raw.map(|raw| (dest_str.clone(), raw)));
+                sizes.insert(dest_str.clone(), size);
+
+                if let Some(previous) = sources.insert(dest_str.clone(), path.to_path_buf()) {
+                    bail!(
+                        "overlapping mappings: {} and {} both deploy to {}",
+                        previous.display(),
+                        path.display(),
+                        dest_str
+                    );
+                }
+            }
+        }
+
+        if !not_utf8.is_empty() {
+            let list = not_utf8
+                .iter()
+                .map(|path| format!("  {:?}", path))
+                .collect::<Vec<_>>()
+                .join("\n");
+            bail!(
+                "{} files have names that aren't valid UTF-8 and can't be deployed as they are:\n{}\nPass --lossy-paths to deploy them under lossy names, or --raw-paths to keep their exact bytes (Unix only)",
+                not_utf8.len(),
+                list
+            );
+        }
+        if skipped > 0 && !self.verbose {
+            out.print(format_args!(
+                "Skipped {} hidden or junk files and directories (--verbose lists them)",
+                skipped
+            ));
+        }
+        if let Some(dest) = links.keys().find(|dest| sources.contains_key(*dest)) {
+            bail!(
+                "overlapping mappings: a file and a symlink both deploy to {}",
+                dest
+            );
+        }
+
+        progress.clear();
+        self.check_file_count(&sources, version_path)?;
+        if !sources.keys().any(|dest| html::is_html(dest)) {
+            eprintln!("warning: no HTML files found; is this the built site directory?");
+        }
+        for dest in sources.keys().filter(|dest| hard_to_serve(dest)) {
+            eprintln!(
+                "warning: {:?} has quotes, control characters or spaces at the ends of a name, which many web hosts can't serve",
+                dest
+            );
+        }
+        self.check_size_budget(&mut sources, &sizes, out)?;
+
+        Ok(SiteFiles {
+            sources,
+            sizes,
+            links,
+            raw_names,
+        })
+    }
+
+    /// Replace the version directory with the mapped site files, applying `rewrites` to the
+    /// HTML pages
+    fn add_site(
+        &self,
+        mut commit: Commit,
+        git_args: &GitArgs,
+        target: &Target,
+        rewrites: &html::Rewrites,
+        progress: &Progress,
+    ) -> anyhow::Result<(Commit, SiteStats)> {
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let version_path: &Path = &deploy_prefix.join(&target.version);
+        commit = commit.delete_path(version_path.to_string_lossy());
+        let SiteFiles {
+            sources,
+            sizes,
+            links,
+            raw_names,
+        } = &target.site;
+        for (dest, raw) in raw_names {
+            commit = commit.raw_path(dest, raw.clone());
+        }
+        for (dest, link) in links {
+            commit = commit.add_symlink(dest, link);
+        }
+
+        // Files whose content matches the blob already deployed at the same path are
+        // referenced by SHA instead of being streamed through fast-import again, along with
+        // their precompressed siblings. Rewritten pages never match their source, so they are
+        // always uploaded. Reused files missing a sibling are compressed again, but only the
+        // siblings that shrink are uploaded; a file that never shrinks costs no upload.
+        let backend = git_args.backend();
+        let read_rev = git_args.read_rev();
+        let existing = if self.no_reuse || backend.rev_parse(&read_rev).is_none() {
+            BTreeMap::new()
+        } else {
+            backend.ls_tree(&read_rev, &version_path.to_string_lossy())?
+        };
+        let sibling =
+            |dest: &str, encoding: &Encoding| format!("{}.{}", dest, encoding.extension());
+        let candidates = sources
+            .iter()
+            .filter(|(dest, path)| {
+                existing
+                    .get(*dest)
+                    .is_some_and(|e| e.mode == self.file_mode(dest, path, version_path))
+            })
+            .filter(|(dest, _)| !rewrites.is_active() || !html::is_html(dest))
+            .map(|(dest, path)| (dest.clone(), path.clone()))
+            .collect::<Vec<_>>();
+        let paths = candidates
+            .iter()
+            .map(|(_, p)| p.clone())
+            .collect::<Vec<_>>();
+        let mut reused: BTreeMap<String, String> = BTreeMap::new();
+        for ((dest, _), sha) in candidates
+            .into_iter()
+            .zip(git::hash_objects(git_args.repo_dir(), &paths)?)
+        {
+            if existing[&dest].sha == sha {
+                reused.insert(dest, sha);
+            }
+        }
+
+        // Read the remaining files concurrently; the commit orders entries by path, so the
+        // resulting stream does not depend on the order reads complete in
+        let (blobs, uploads): (Vec<_>, Vec<_>) = sources
+            .iter()
+            .partition(|(dest, _)| reused.contains_key(*dest));
+        let mut siblings_only = Vec::new();
+        for (dest, path) in blobs {
+            commit = commit.add_blob(dest, existing[dest].mode, &reused[dest]);
+            let mut missing = false;
+            for encoding in &self.precompress {
+                match existing.get(&sibling(dest, encoding)) {
+                    Some(entry) => {
+                        commit = commit.add_blob(sibling(dest, encoding), entry.mode, &entry.sha)
+                    }
+                    None => missing = true,
+                }
+            }
+            if missing && self.precompresses(dest) && sizes[dest] >= self.min_size {
+                siblings_only.push((dest, path));
+            }
+        }
+
+        // Files left as they are on disk are streamed into fast-import when the commit is
+        // written; pages are rewritten and compressed on the reading threads
+        let (streamed, uploads): (Vec<_>, Vec<_>) = uploads.into_iter().partition(|(dest, _)| {
+            !(rewrites.is_active() && html::is_html(dest))
+                && (!self.precompresses(dest) || sizes[*dest] < self.min_size)
+        });
+        for (dest, path) in streamed {
+            commit =
+                commit.add_file_with_mode(dest, self.file_mode(dest, path, version_path), path)?;
+        }
+        // Files read to be processed, and whether the file itself is uploaded besides its
+        // siblings
+        let processed = uploads
+            .into_iter()
+            .map(|file| (file, true))
+            .chain(siblings_only.into_iter().map(|file| (file, false)))
+            .collect::<Vec<_>>();
+        let processed_paths = processed
+            .iter()
+            .map(|((_, p), _)| (*p).clone())
+            .collect::<Vec<_>>();
+        let mut stats = SiteStats {
+            uploaded: sources.len() - reused.len(),
+            reused: reused.len(),
+            ..Default::default()
+        };
+        if !processed.is_empty() {
+            // The processed files go straight into the object database as they are read, so
+            // only the reads in flight are held in memory
+            let version_dir = format!("{}/", version_path.to_string_lossy());
+            let mut writer = git::BlobWriter::new(git_args.repo_dir())?;
+            let mut written = Vec::new();
+            read_files(
+                &processed_paths,
+                MAX_IN_FLIGHT_BYTES,
+                |index, data| {
+                    let ((dest, _), _) = processed[index];
+                    let page = dest.strip_prefix(&version_dir).unwrap_or(dest);
+                    let data = rewrites.apply(page, data);
+                    let compressed = self.compress(dest, &data);
+                    Ok((data, compressed))
+                },
+                |index, (data, compressed)| {
+                    let ((dest, path), upload) = processed[index];
+                    for (encoding, bytes) in compressed {
+                        if !upload && existing.contains_key(&sibling(dest, &encoding)) {
+                            continue;
+                        }
+                        stats.precompressed += 1;
+                        stats.precompression_saved += (data.len() - bytes.len()) as u64;
+                        written.push((sibling(dest, &encoding), 0o100644, writer.write(&bytes)?));
+                    }
+                    if upload {
+                        let mode = self.file_mode(dest, path, version_path);
+                        written.push((dest.clone(), mode, writer.write(&data)?));
+                    }
+                    Ok(())
+                },
+            )?;
+            let shas = writer.finish()?;
+            for (dest, mode, index) in written {
+                commit = commit.add_blob(dest, mode, &shas[index]);
+            }
+        }
+
+        progress.clear();
+        Ok((commit, stats))
+    }
+
+    /// The name `dest` is deployed under, with its exact bytes when --raw-paths keeps a name
+    /// that isn't UTF-8. None when the name isn't UTF-8 and neither --lossy-paths nor
+    /// --raw-paths allows it.
+    fn dest_name(&self, dest: &Path) -> Option<(String, Option<Vec<u8>>)> {
+        if let Some(name) = dest.to_str() {
+            return Some((name.to_string(), None));
+        }
+        let lossy = dest.to_string_lossy().to_string();
+        if self.raw_paths {
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStrExt;
+                return Some((lossy, Some(dest.as_os_str().as_bytes().to_vec())));
+            }
+            #[cfg(not(unix))]
+            return None;
+        }
+        if !self.lossy_paths {
+            return None;
+        }
+        eprintln!(
+            "warning: {:?} isn't valid UTF-8; deploying it as {}",
+            dest, lossy
+        );
+        Some((lossy, None))
+    }
+
+    /// Why the walk leaves `entry` (and everything below it) out of the deploy, if it does
+    fn skip_reason(&self, entry: &walkdir::DirEntry) -> Option<&'static str> {
+        let name = entry.file_name().to_string_lossy();
+        if name == ".git" {
+            Some("nested git repository")
+        } else if JUNK_FILES
+            .iter()
+            .any(|pattern| glob_matches(pattern, &name))
+        {
+            Some("junk file")
+        } else if name.starts_with('.') && self.hidden == Hidden::Exclude {
+            Some("hidden (--hidden exclude)")
+        } else {
+            None
+        }
+    }
+
+    /// The git mode to commit the source file `path` deployed at `dest` with
+    fn file_mode(&self, dest: &str, path: &Path, version_path: &Path) -> u32 {
+        let rel = Path::new(dest)
+            .strip_prefix(version_path)
+            .unwrap_or(Path::new(dest))
+            .to_string_lossy()
+            .replace('\\', "/");
+        if self.executable.iter().any(|pattern| pattern.matches(&rel)) {
+            0o100755
+        } else {
+            git::file_mode(path)
+        }
+    }
+
+    /// Warn about suspiciously many files (e.g. a stray `target/` directory), listing the
+    /// directories holding most of them, and confirm before continuing
+    fn check_file_count(
+        &self,
+        sources: &BTreeMap<String, PathBuf>,
+        version_path: &Path,
+    ) -> anyhow::Result<()> {
+        if sources.len() <= self.large_site_threshold {
+            return Ok(());
+        }
 
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for dest in sources.keys() {
+            let rel = Path::new(dest)
+                .strip_prefix(version_path)
+                .unwrap_or(Path::new(dest));
+            let mut components = rel.components();
+            let dir = match (components.next(), components.next()) {
+                (Some(dir), Some(_)) => format!("{}/", dir.as_os_str().to_string_lossy()),
+                _ => "./".to_string(),
+            };
+            *counts.entry(dir).or_default() += 1;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        eprintln!(
+            "warning: about to deploy {} files (over --large-site-threshold of {}). Largest directories:",
+            sources.len(),
+            self.large_site_threshold
+        );
+        for (dir, count) in counts.iter().take(5) {
+            eprintln!("  {:>8}  {}", count, dir);
+        }
+        if self.yes {
+            return Ok(());
+        }
+        // Nobody can answer in CI or a script, so the warning has to do
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            eprintln!("warning: not asking for confirmation without a terminal; continuing");
+            return Ok(());
+        }
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Continue?")
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            bail!("deploy cancelled");
+        }
         Ok(())
     }
+
+    /// Enforce --max-file-size (dropping oversized files from `sources` with --skip-oversize)
+    /// and then --max-total-size
+    fn check_size_budget(
+        &self,
+        sources: &mut BTreeMap<String, PathBuf>,
+        sizes: &BTreeMap<String, u64>,
+        out: Status,
+    ) -> anyhow::Result<()> {
+        if self.max_file_size.is_none() && self.max_total_size.is_none() {
+            return Ok(());
+        }
+
+        if let Some(max) = self.max_file_size {
+            let oversized = sizes
+                .iter()
+                .filter(|(_, size)| **size > max)
+                .map(|(dest, size)| format!("{} ({})", dest, format_bytes(*size)))
+                .collect::<Vec<_>>();
+            if !oversized.is_empty() && !self.skip_oversize {
+                bail!(
+                    "{} files exceed --max-file-size of {}:\n  {}",
+                    oversized.len(),
+                    format_bytes(max),
+                    oversized.join("\n  ")
+                );
+            }
+            if !oversized.is_empty() {
+                eprintln!(
+                    "Skipping {} files over {}:\n  {}",
+                    oversized.len(),
+                    format_bytes(max),
+                    oversized.join("\n  ")
+                );
+                sources.retain(|dest, _| sizes[dest] <= max);
+            }
+        }
+
+        let total = sources.keys().map(|dest| sizes[dest]).sum::<u64>();
+        let largest = sources.keys().max_by_key(|dest| sizes[*dest]);
+        out.print(format_args!(
+            "Site size: {} in {} files{}{}",
+            format_bytes(total),
+            sources.len(),
+            self.max_total_size
+                .map(|max| format!(" (budget {})", format_bytes(max)))
+                .unwrap_or_default(),
+            largest
+                .map(|dest| format!("; largest {} ({})", dest, format_bytes(sizes[dest])))
+                .unwrap_or_default()
+        ));
+        if let Some(max) = self.max_total_size
+            && total > max
+        {
+            bail!(
+                "the site is {}, over --max-total-size of {}",
+                format_bytes(total),
+                format_bytes(max)
+            );
+        }
+        Ok(())
+    }
+
+    /// The SHA256SUMS manifest of the files `commit` deploys to `version_path`, leaving out
+    /// the manifest itself and the copy of versions.json written with `versions_copies`
+    fn manifest(
+        &self,
+        commit: &Commit,
+        version_path: &Path,
+        versions_copies: bool,
+    ) -> anyhow::Result<String> {
+        let version_dir = version_path.to_string_lossy();
+        let mut digests = BTreeMap::new();
+        commit.for_each_file(&version_dir, |path, contents| {
+            let relative = &path[version_dir.len() + 1..];
+            if !checksums::is_generated(relative, versions_copies) {
+                digests.insert(relative.to_string(), checksums::hex_digest(contents));
+            }
+            Ok(())
+        })?;
+        Ok(checksums::render(&digests))
+    }
+
+    /// Whether the file deployed at `dest` should get precompressed siblings
+    fn precompresses(&self, dest: &str) -> bool {
+        let extension = Path::new(dest).extension().unwrap_or_default();
+        self.types.iter().any(|t| extension.eq_ignore_ascii_case(t))
+    }
+
+    /// The precompressed siblings of the file deployed at `dest`, for each encoding that
+    /// shrinks it
+    fn compress(&self, dest: &str, data: &[u8]) -> Vec<(Encoding, Vec<u8>)> {
+        if (data.len() as u64) < self.min_size || !self.precompresses(dest) {
+            return Vec::new();
+        }
+        self.precompress
+            .iter()
+            .map(|encoding| (*encoding, encoding.compress(data)))
+            .filter(|(_, compressed)| compressed.len() < data.len())
+            .collect()
+    }
+}
+
+/// Reject mappings that deploy to the same directory, or into a directory where another
+/// mapping already has a file or directory of the same name. Nesting is fine otherwise, e.g.
+/// "./site=/" next to "./target/doc=/api" when the site has no api/ of its own.
+fn check_overlaps(mappings: &[Mapping]) -> anyhow::Result<()> {
+    for (index, first) in mappings.iter().enumerate() {
+        for second in &mappings[index + 1..] {
+            if first.dest == second.dest {
+                bail!(
+                    "overlapping mappings: {} and {} both deploy to /{}",
+                    first.source.display(),
+                    second.source.display(),
+                    first.dest.display()
+                );
+            }
+            let (outer, inner) = if second.dest.starts_with(&first.dest) {
+                (first, second)
+            } else if first.dest.starts_with(&second.dest) {
+                (second, first)
+            } else {
+                continue;
+            };
+            let shadowed = outer
+                .source
+                .join(inner.dest.strip_prefix(&outer.dest).unwrap());
+            if fs::symlink_metadata(&shadowed).is_ok() {
+                bail!(
+                    "overlapping mappings: {} deploys to /{}, where {} already has {}",
+                    inner.source.display(),
+                    inner.dest.display(),
+                    outer.source.display(),
+                    shadowed.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a deployed path has a name web servers and URLs commonly mishandle
+fn hard_to_serve(dest: &str) -> bool {
+    dest.split('/').any(|name| {
+        name.starts_with(' ')
+            || name.ends_with(' ')
+            || name.chars().any(|c| c == '"' || c.is_control())
+    })
+}
+
+/// Parse a `--channel-alias` value in the `CHANNEL=ALIAS` format
+fn parse_channel_alias(s: &str) -> anyhow::Result<(String, String)> {
+    match s.split_once('=') {
+        Some((channel, alias)) if !channel.trim().is_empty() && !alias.trim().is_empty() => Ok((
+            channel.trim().to_ascii_lowercase(),
+            alias.trim().to_string(),
+        )),
+        _ => bail!("invalid channel alias {:?}: expected CHANNEL=ALIAS", s),
+    }
+}
+
+/// Parse a `--trailer` value in the `KEY=VALUE` format
+fn parse_trailer(s: &str) -> anyhow::Result<(String, String)> {
+    let Some((key, value)) = s.split_once('=') else {
+        bail!("invalid trailer {:?}: expected KEY=VALUE", s);
+    };
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        bail!(
+            "invalid trailer {:?}: the key may only contain letters, digits and '-'",
+            s
+        );
+    }
+    if value.trim().is_empty() || value.contains('\n') {
+        bail!(
+            "invalid trailer {:?}: the value must be a single non-empty line",
+            s
+        );
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Append a trailer block, separated from the body by a blank line as `git interpret-trailers` expects
+fn append_trailers(message: &str, trailers: &[(String, String)]) -> String {
+    let mut result = message.trim_end().to_string();
+    result.push_str("\n\n");
+    for (key, value) in trailers {
+        result.push_str(&format!("{}: {}\n", key, value));
+    }
+    result
+}
+
+/// The URL of the CI run performing the deploy, for GitHub Actions and GitLab CI
+fn ci_run_url() -> Option<String> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    if let (Some(server), Some(repository), Some(run_id)) = (
+        var("GITHUB_SERVER_URL"),
+        var("GITHUB_REPOSITORY"),
+        var("GITHUB_RUN_ID"),
+    ) {
+        return Some(format!("{}/{}/actions/runs/{}", server, repository, run_id));
+    }
+    var("CI_PIPELINE_URL").or_else(|| var("CI_JOB_URL"))
+}
+
+/// Compare the committed branch tip against what was deployed: every file `commit` added
+/// under the version must be present with the same content (and nothing else), unless only
+/// aliases changed, and versions.json must deserialize to the same versions as `versions_json`
+fn verify(
+    git_args: &GitArgs,
+    commit: &Commit,
+    version_path: Option<&Path>,
+    versions_json: &str,
+    out: Status,
+) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+
+    if let Some(version_path) = version_path {
+        let version_path = version_path.to_string_lossy();
+        let committed = git_args
+            .backend()
+            .ls_tree(&git_args.branch, &version_path)?;
+        problems.extend(commit.compare_tree(&committed, &version_path)?);
+    }
+
+    let committed_versions = git_in_dir(
+        git_args.repo_dir().into(),
+        &["show", &format!("{}:{}", git_args.branch, VERSIONS_FILE)],
+    )
+    .and_then(|json| Ok(serde_json::from_str::<Versions>(&json)?));
+    match committed_versions {
+        Ok(committed) if committed == serde_json::from_str::<Versions>(versions_json)? => {}
+        Ok(_) => problems.push(format!(
+            "{} does not match the deployed versions",
+            VERSIONS_FILE
+        )),
+        Err(err) => problems.push(format!("{} could not be read: {:#}", VERSIONS_FILE, err)),
+    }
+
+    if !problems.is_empty() {
+        bail!(
+            "verification of {} failed:\n  {}",
+            git_args.branch,
+            problems.join("\n  ")
+        );
+    }
+    out.print(format_args!("Verified {}.", git_args.branch));
+    Ok(())
+}
+
+/// The revision naming `path` inside the tree of `rev`
+pub(super) fn tree_rev(rev: &str, path: &Path) -> String {
+    format!("{}:{}", rev, path.to_string_lossy())
+}
+
+/// Whether the deploys to `parent` wrote versions.js, which every later deploy keeps up to date
+pub(super) fn has_versions_js(git_args: &GitArgs, parent: Option<&str>) -> bool {
+    let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+    parent.is_some_and(|parent| {
+        git_args
+            .backend()
+            .rev_parse(&tree_rev(parent, &deploy_prefix.join(VERSIONS_JS)))
+            .is_some()
+    })
+}
+
+/// Whether the deploys to `parent` copied the whole versions.json into the version directories
+/// (with --schema-copies) rather than the bare list of entries
+pub(super) fn has_schema_copies(
+    git_args: &GitArgs,
+    parent: Option<&str>,
+    versions: &Versions,
+) -> bool {
+    let Some(parent) = parent else {
+        return false;
+    };
+    let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+    let backend = git_args.backend();
+    versions
+        .versions()
+        .values()
+        .filter(|version| !version.is_external())
+        .find_map(|version| {
+            let copy = deploy_prefix.join(&version.tag).join(VERSIONS_FILE);
+            backend
+                .read_file(parent, &copy.to_string_lossy())
+                .ok()
+                .flatten()
+        })
+        .is_some_and(|copy| copy.trim_ascii_start().starts_with(b"{"))
+}
+
+/// Add versions.json to `commit`, along with versions.js and the copies of versions.json in
+/// each version when `versions_js` is set. The copies are the bare list of entries unless
+/// `schema_copies` is set.
+pub(super) fn add_versions_files(
+    mut commit: Commit,
+    git_args: &GitArgs,
+    versions: &Versions,
+    compat: Option<Compat>,
+    versions_js: bool,
+    schema_copies: bool,
+) -> anyhow::Result<Commit> {
+    let versions_json = versions
+        .to_json(compat)
+        .context(format!("Failed to serialize {}", VERSIONS_FILE))?;
+    commit = commit.add_bytes(VERSIONS_FILE, 0o100644, versions_json.clone().into_bytes());
+
+    // Pages fetch the version list relative to themselves, e.g. from ../versions.json
+    let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+    if versions_js {
+        let script = versions
+            .versions_js()
+            .context(format!("Failed to serialize {}", VERSIONS_JS))?;
+        commit = commit.add_bytes(
+            deploy_prefix.join(VERSIONS_JS).to_string_lossy(),
+            0o100644,
+            script.into_bytes(),
+        );
+        let copy_json = match schema_copies {
+            true => versions_json.clone(),
+            false => versions
+                .entries_json()
+                .context(format!("Failed to serialize {}", VERSIONS_FILE))?,
+        };
+        for version in versions.versions().values() {
+            if version.is_external() {
+                continue;
+            }
+            let copy = deploy_prefix.join(&version.tag).join(VERSIONS_FILE);
+            commit = commit.add_bytes(
+                copy.to_string_lossy(),
+                0o100644,
+                copy_json.clone().into_bytes(),
+            );
+        }
+    }
+    Ok(commit)
+}
+
+/// Push the publish branch to `remote`. When force-pushing, the primary remote is leased
+/// against `remote_tip` while mirrors (without a tip) are overwritten.
+pub(super) fn push(
+    git_args: &GitArgs,
+    remote: &str,
+    remote_tip: Option<&str>,
+) -> Result<(), DocverError> {
+    let mut push_args = vec![
+        "push".to_string(),
+        remote.to_string(),
+        git_args.branch.clone(),
+    ];
+    if git_args.force_push {
+        if remote == git_args.remote() {
+            // An empty expected value means the branch must not exist on the remote yet
+            push_args.push(format!(
+                "--force-with-lease={}:{}",
+                git_args.branch,
+                remote_tip.unwrap_or_default()
+            ));
+        } else {
+            push_args.push("--force".to_string());
+        }
+    }
+    let push_args = push_args.iter().map(String::as_str).collect::<Vec<_>>();
+    git_in_dir(git_args.repo_dir().into(), &push_args).map_err(|err| {
+        let message = err.to_string();
+        if message.contains("[rejected]") || message.contains("[remote rejected]") {
+            DocverError::PushRejected {
+                remote: remote.to_string(),
+                message,
+            }
+        } else {
+            DocverError::Git {
+                command: "push".to_string(),
+                message,
+            }
+        }
+    })?;
+    Ok(())
+}
+
+/// Report the primary push, then push to every additional remote, failing if any push failed
+pub(super) fn push_mirrors(
+    git_args: &GitArgs,
+    primary: anyhow::Result<()>,
+    out: Status,
+) -> anyhow::Result<()> {
+    if git_args.remotes.len() == 1 {
+        primary?;
+        // Print a concise success message for push
+        out.print(format_args!(
+            "Pushed {} to {}:{}",
+            git_args.branch,
+            git_args.remote(),
+            git_args.branch
+        ));
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    let results = std::iter::once((git_args.remote(), primary)).chain(
+        git_args.remotes[1..].iter().map(|remote| {
+            (
+                remote.as_str(),
+                push(git_args, remote, None).map_err(Into::into),
+            )
+        }),
+    );
+    for (remote, result) in results {
+        match result {
+            Ok(()) => out.print(format_args!(
+                "Pushed {} to {}:{}",
+                git_args.branch, remote, git_args.branch
+            )),
+            Err(err) => {
+                failed += 1;
+                eprintln!(
+                    "Failed to push {} to {}: {:#}",
+                    git_args.branch, remote, err
+                );
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "failed to push to {} of {} remotes",
+            failed,
+            git_args.remotes.len()
+        );
+    }
+    Ok(())
+}
+
+/// One line on what fast-import wrote, or its statistics as printed when they couldn't be parsed
+fn import_summary(import: &ImportReport) -> String {
+    if let Some(unparsed) = &import.unparsed {
+        return format!("fast-import statistics:\n{}", unparsed);
+    }
+    let mut summary = format!(
+        "fast-import wrote blobs: {} new, {} duplicate; trees: {}; commits: {}",
+        import.blobs.new, import.blobs.duplicates, import.trees.new, import.commits.new
+    );
+    if let Some(bytes) = import.bytes_written {
+        summary.push_str(&format!("; repository grew {}", format_bytes(bytes)));
+    }
+    summary
+}
+
+/// Whether a push failed because the remote branch moved after it was fetched
+fn is_push_race(err: &DocverError) -> bool {
+    let DocverError::PushRejected { message, .. } = err else {
+        return false;
+    };
+    // A ref that moves while the push is being received fails to lock on the remote instead
+    let rejected = message.contains("[rejected]")
+        && ["fetch first", "non-fast-forward", "stale info"]
+            .iter()
+            .any(|reason| message.contains(reason));
+    let moved = message.contains("[remote rejected]")
+        && message.contains("cannot lock ref")
+        && message.contains("but expected");
+    rejected || moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands,
+        test_support::{Scratch, publishing_setup, scratch_repo},
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dest = Path::new(OsStr::from_bytes(b"v1/caf\xe9.html"));
+        let strict = Deploy::default();
+        let lossy = Deploy {
+            lossy_paths: true,
+            ..Default::default()
+        };
+        let raw = Deploy {
+            raw_paths: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            strict.dest_name(Path::new("v1/café.html")),
+            Some(("v1/café.html".to_string(), None))
+        );
+        assert_eq!(strict.dest_name(dest), None);
+        assert_eq!(
+            lossy.dest_name(dest),
+            Some(("v1/caf\u{fffd}.html".to_string(), None))
+        );
+        assert_eq!(
+            raw.dest_name(dest),
+            Some((
+                "v1/caf\u{fffd}.html".to_string(),
+                Some(b"v1/caf\xe9.html".to_vec())
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_mapping_normalizes_destination() {
+        let mapping: Mapping = "./target/doc=/api/".parse().unwrap();
+        assert_eq!(mapping.source, PathBuf::from("./target/doc"));
+        assert_eq!(mapping.dest, PathBuf::from("api"));
+
+        let root: Mapping = "site=/".parse().unwrap();
+        assert_eq!(root.dest, PathBuf::new());
+    }
+
+    #[test]
+    fn trailers_follow_a_blank_line() {
+        let trailers = vec![
+            ("Deployed-Version".to_string(), "v1.2.3".to_string()),
+            ("Reviewed-By".to_string(), "Jane".to_string()),
+        ];
+        assert_eq!(
+            append_trailers("Deployed abc to v1.2.3\n", &trailers),
+            "Deployed abc to v1.2.3\n\nDeployed-Version: v1.2.3\nReviewed-By: Jane\n"
+        );
+        assert!(parse_trailer("Reviewed By=Jane").is_err());
+        assert!(parse_trailer("Reviewed-By").is_err());
+    }
+
+    #[test]
+    fn library_defaults_match_the_command_line() {
+        let cli = Deploy::from(&commands::defaults::<DeployArgs>());
+        assert_eq!(format!("{:?}", Deploy::default()), format!("{:?}", cli));
+    }
+
+    #[test]
+    fn report_schema() {
+        let report = DeployReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            status: "error",
+            branch: "gh-pages".into(),
+            version: Some("v1.2.3".into()),
+            error: Some("version v1.2.3 is already deployed".into()),
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(report, @r#"
+        {
+          "schema_version": 1,
+          "status": "error",
+          "branch": "gh-pages",
+          "version": "v1.2.3",
+          "aliases": [],
+          "commit": null,
+          "files_uploaded": 0,
+          "files_reused": 0,
+          "bytes": 0,
+          "bytes_buffered": 0,
+          "pruned": [],
+          "pushed": false,
+          "error": "version v1.2.3 is already deployed"
+        }
+        "#);
+    }
+
+    #[test]
+    fn overlapping_mappings_are_rejected() {
+        let dir = Scratch::dir("deploy-maps");
+        fs::create_dir_all(dir.join("site/guide")).unwrap();
+        fs::create_dir_all(dir.join("doc")).unwrap();
+        fs::write(dir.join("site/index.html"), "").unwrap();
+        fs::write(dir.join("site/search"), "").unwrap();
+        let mapping = |spec: &str| spec.replace("DIR", dir.to_str().unwrap()).parse().unwrap();
+        let check = |specs: &[&str]| {
+            let mappings = specs.iter().map(|spec| mapping(spec)).collect::<Vec<_>>();
+            match check_overlaps(&mappings) {
+                Ok(()) => "ok".to_string(),
+                Err(err) => err.to_string().replace(dir.to_str().unwrap(), "DIR"),
+            }
+        };
+        let results = [
+            check(&["DIR/site=/", "DIR/doc=/api"]),
+            check(&["DIR/site=/api", "DIR/doc=api/"]),
+            check(&["DIR/doc=/guide", "DIR/site=/"]),
+            check(&["DIR/site=/", "DIR/doc=/search/v1"]),
+            check(&["DIR/site=/", "DIR/doc=/search"]),
+        ];
+        insta::assert_debug_snapshot!(results, @r#"
+        [
+            "ok",
+            "overlapping mappings: DIR/site and DIR/doc both deploy to /api",
+            "overlapping mappings: DIR/doc deploys to /guide, where DIR/site already has DIR/site/guide",
+            "ok",
+            "overlapping mappings: DIR/doc deploys to /search, where DIR/site already has DIR/site/search",
+        ]
+        "#);
+    }
+
+    #[test]
+    fn parse_mapping_rejects_invalid() {
+        assert!("site".parse::<Mapping>().is_err());
+        assert!("=api".parse::<Mapping>().is_err());
+        assert!("site=../api".parse::<Mapping>().is_err());
+    }
+
+    /// The arguments of `versite --quiet deploy <args>` run in `repo`
+    fn parse_deploy(repo: &Path, args: &[&str]) -> (Deploy, GitArgs) {
+        use clap::{FromArgMatches, Subcommand};
+
+        let name = env!("CARGO_PKG_NAME");
+        let cli =
+            commands::Command::augment_subcommands(GitArgs::augment_args(clap::Command::new(name)));
+        let matches = cli.get_matches_from([name, "--quiet", "deploy"].iter().chain(args));
+        let mut git_args = GitArgs::from_arg_matches(&matches).unwrap();
+        git_args.repo = Some(repo.to_path_buf());
+        let commands::Command::Deploy(deploy) =
+            commands::Command::from_arg_matches(&matches).unwrap()
+        else {
+            unreachable!("the deploy subcommand was given");
+        };
+        (Deploy::from(&*deploy), git_args.discover().unwrap())
+    }
+
+    /// Run `versite --quiet deploy <args>` in `repo`
+    fn deploy_in(repo: &Path, args: &[&str]) -> (DeployReport, anyhow::Result<()>) {
+        let (deploy, git_args) = parse_deploy(repo, args);
+        deploy.report(git_args, Status::new(false))
+    }
+
+    #[test]
+    fn detected_versions_leave_the_positionals_to_aliases() {
+        let repo = scratch_repo("deploy-version-from");
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "release"]);
+        repo.git(&["tag", "v1.2.0"]);
+        let target = |args: &[&str]| {
+            let (deploy, git_args) = parse_deploy(&repo, args);
+            match deploy.target(&git_args, Status::new(false)) {
+                Ok(target) => format!("{} {:?}", target.version, target.aliases),
+                Err(err) => err.to_string(),
+            }
+        };
+
+        insta::assert_debug_snapshot!([
+            target(&["site", "latest", "--version-from", "git-tag"]),
+            target(&["site", "2.0", "latest", "--version-from", "git-tag"]),
+            target(&["site", "--version-from", "git-tag", "--expect-version", "v1.2.0"]),
+            target(&["site", "--version-from", "git-tag", "--expect-version", "v1.3.0"]),
+            target(&["--map", "site=/", "v2.0", "latest"]),
+            target(&["--map", "site=/", "--version-from", "git-tag", "latest"]),
+        ], @r#"
+        [
+            "v1.2.0 [\"latest\"]",
+            "v1.2.0 [\"2.0\", \"latest\"]",
+            "v1.2.0 []",
+            "version v1.3.0 does not match v1.2.0 read from the tag at HEAD",
+            "v2.0 [\"latest\"]",
+            "v1.2.0 [\"latest\"]",
+        ]
+        "#);
+    }
+
+    #[test]
+    fn positionals_are_required_unless_replaced() {
+        use clap::Subcommand;
+
+        let name = env!("CARGO_PKG_NAME");
+        let parses = |args: &[&str]| {
+            commands::Command::augment_subcommands(clap::Command::new(name))
+                .try_get_matches_from([name, "deploy"].iter().chain(args))
+                .map_err(|err| err.kind())
+        };
+
+        assert!(parses(&["site", "v1"]).is_ok());
+        assert!(parses(&["--map", "site=/", "v1"]).is_ok());
+        assert!(parses(&["site", "--version-from", "git-tag"]).is_ok());
+        assert_eq!(
+            parses(&["site"]).unwrap_err(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            parses(&["--version-from", "git-tag"]).unwrap_err(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn a_site_versions_json_is_checksummed_unless_versions_js_replaces_it() {
+        let dir = publishing_setup("deploy-own-versions");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        fs::write(site.join("versions.json"), "[\"the site's own\"]").unwrap();
+        let site = site.to_str().unwrap();
+
+        deploy_in(&work, &[site, "v1", "--checksums"]).1.unwrap();
+        let manifest = dir.git(&["-C", "work", "show", "gh-pages:v1/SHA256SUMS"]);
+        assert!(manifest.contains("  versions.json\n"), "{}", manifest);
+
+        let (_, result) = deploy_in(&work, &[site, "v2", "--versions-js"]);
+        insta::assert_snapshot!(result.unwrap_err(), @"the site has its own v2/versions.json, which would be replaced by the copy of the version list kept next to versions.js; rename it");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tags_are_imported_with_the_commit_and_dropped_when_the_push_fails() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = publishing_setup("deploy-tag");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+
+        let (report, result) = deploy_in(&work, &[site, "v1", "--tag", "-m", "Deploy v1"]);
+        result.unwrap();
+        let tag = dir.git(&["-C", "work", "cat-file", "-p", "site/v1"]);
+        let tagged = dir.git(&["-C", "work", "rev-parse", "site/v1^{commit}", "gh-pages"]);
+
+        let hook = dir.join("origin.git/hooks/pre-receive");
+        fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        let (rejected, result) = deploy_in(&work, &[site, "v2", "--tag", "--push"]);
+        result.unwrap_err();
+        let tags = dir.git(&["-C", "work", "tag", "--list"]);
+
+        assert_eq!(report.tag.as_deref(), Some("site/v1"));
+        assert!(tag.contains("\n\nDeploy v1\n"), "{}", tag);
+        let tagged = tagged.lines().collect::<Vec<_>>();
+        assert_eq!(tagged[0], tagged[1]);
+        assert_eq!(rejected.tag, None);
+        assert_eq!(tags, "site/v1\n");
+    }
+
+    #[test]
+    fn oversize_sites_fail_before_fetching() {
+        let dir = publishing_setup("deploy-oversize");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        dir.git(&[
+            "-C",
+            "work",
+            "remote",
+            "set-url",
+            "origin",
+            "../missing.git",
+        ]);
+
+        let (_, result) = deploy_in(
+            &work,
+            &[site.to_str().unwrap(), "v1", "--max-total-size", "10B"],
+        );
+
+        insta::assert_snapshot!(result.unwrap_err(), @"the site is 13 B, over --max-total-size of 10 B");
+        let branches = dir.git(&["-C", "work", "branch", "--list", "gh-pages"]);
+        assert_eq!(branches, "");
+    }
+
+    #[test]
+    fn fail_if_exists_is_a_name_clash() {
+        let dir = publishing_setup("deploy-fail-if-exists");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+
+        deploy_in(&work, &[site, "v1"]).1.unwrap();
+        let err = deploy_in(&work, &[site, "v1", "--fail-if-exists"])
+            .1
+            .unwrap_err();
+
+        insta::assert_snapshot!(err, @"version v1 already exists");
+        let clash = err.downcast_ref::<DocverError>().unwrap();
+        assert_eq!(clash.exit_code(), 5);
+    }
+
+    #[test]
+    fn versions_json_copies_are_bare_lists_unless_schema_copies_is_passed() {
+        let dir = publishing_setup("deploy-versions-copies");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+        let copy = |version: &str| {
+            dir.git(&[
+                "-C",
+                "work",
+                "show",
+                &format!("gh-pages:{}/versions.json", version),
+            ])
+        };
+
+        deploy_in(&work, &[site, "v1", "--versions-js"]).1.unwrap();
+        let bare = copy("v1");
+        deploy_in(&work, &[site, "v2", "--schema-copies"])
+            .1
+            .unwrap();
+        // Later deploys keep writing the whole file without the option
+        deploy_in(&work, &[site, "v3"]).1.unwrap();
+
+        assert!(
+            bare.starts_with(r#"[{"version":"v1","title":"v1","aliases":[],"#),
+            "{}",
+            bare
+        );
+        assert_eq!(copy("v1"), copy("v3"));
+        assert!(
+            copy("v3").starts_with("{\n  \"schema\": 2"),
+            "{}",
+            copy("v3")
+        );
+    }
+
+    #[test]
+    fn precompression_writes_siblings_and_reuses_them() {
+        let dir = publishing_setup("deploy-precompress");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        fs::write(site.join("index.html"), "<p>compressible</p>".repeat(100)).unwrap();
+        fs::write(site.join("small.css"), "p {}").unwrap();
+        fs::write(site.join("notes.txt"), "not a listed type ".repeat(100)).unwrap();
+        // Bytes no compressor can shrink
+        let mut state = 1u32;
+        let noise = (0..2000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        fs::write(site.join("noise.js"), noise).unwrap();
+        let site = site.to_str().unwrap();
+        let deploy = |encodings: &str| {
+            let args = [site, "v1", "--min-size=100", "--types=html,css,js"];
+            let (report, result) =
+                deploy_in(&work, &[&args[..], &["--precompress", encodings]].concat());
+            result.unwrap();
+            let files = dir.git(&["-C", "work", "ls-tree", "--name-only", "gh-pages:v1"]);
+            (report.files_uploaded, report.files_reused, files)
+        };
+
+        let first = deploy("gzip");
+        let unchanged = deploy("gzip");
+        let more = deploy("gzip,br");
+        insta::assert_debug_snapshot!([first, unchanged, more], @r#"
+        [
+            (
+                4,
+                0,
+                "index.html\nindex.html.gz\nnoise.js\nnotes.txt\nsmall.css\n",
+            ),
+            (
+                0,
+                4,
+                "index.html\nindex.html.gz\nnoise.js\nnotes.txt\nsmall.css\n",
+            ),
+            (
+                0,
+                4,
+                "index.html\nindex.html.br\nindex.html.gz\nnoise.js\nnotes.txt\nsmall.css\n",
+            ),
+        ]
+        "#);
+    }
+
+    #[test]
+    fn large_sites_only_warn_without_a_terminal() {
+        let dir = publishing_setup("deploy-large");
+        let site = dir.join("site");
+        let args = [site.to_str().unwrap(), "v1", "--large-site-threshold=0"];
+        let (report, result) = deploy_in(&dir.join("work"), &args);
+        result.unwrap();
+        assert_eq!(report.files_uploaded, 1);
+    }
+
+    #[test]
+    fn sitemaps_are_kept_up_to_date() {
+        let dir = publishing_setup("deploy-sitemap");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+        let base_url = "--base-url=https://docs.example.com";
+        let sitemap = ["--sitemap", "--sitemap-all-versions", base_url];
+        deploy_in(&work, &[&[site, "v1", "latest"], &sitemap[..]].concat())
+            .1
+            .unwrap();
+        deploy_in(&work, &[site, "v2", "latest"]).1.unwrap();
+
+        let sitemap = dir.git(&["-C", "work", "show", "gh-pages:sitemap.xml"]);
+        insta::assert_snapshot!(sitemap, @r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+          <!-- all versions -->
+          <url><loc>https://docs.example.com/latest/</loc><priority>1.0</priority></url>
+          <url><loc>https://docs.example.com/v1/</loc><priority>0.3</priority></url>
+        </urlset>
+        "#);
+    }
+
+    #[test]
+    fn force_resets_a_diverged_branch_onto_the_remote() {
+        let dir = publishing_setup("deploy-force");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+        deploy_in(&work, &[site, "v1", "--push"]).1.unwrap();
+        // An unpublished deploy leaves the local branch ahead of the remote
+        deploy_in(&work, &["--no-fetch", site, "v2"]).1.unwrap();
+
+        let (_, refused) = deploy_in(&work, &[site, "v3"]);
+        let (report, forced) = deploy_in(&work, &[site, "v3", "--force"]);
+        let deployed = dir.git(&["-C", "work", "ls-tree", "--name-only", "gh-pages"]);
+        let parent = dir.git(&["-C", "work", "rev-parse", "gh-pages^"]);
+        let remote = dir.git(&["-C", "work", "rev-parse", "origin/gh-pages"]);
+
+        assert!(matches!(
+            refused.unwrap_err().downcast_ref(),
+            Some(DocverError::NonFastForward(_))
+        ));
+        forced.unwrap();
+        assert_eq!(report.status, "deployed");
+        assert_eq!(parent, remote);
+        insta::assert_snapshot!(deployed, @r"
+        .nojekyll
+        _redirects
+        v1
+        v3
+        versions.json
+        ");
+    }
+
+    /// Make the next push from `work` lose the race against the deploy already committed in
+    /// `../other`, by pushing that first from a pre-push hook
+    #[cfg(unix)]
+    fn lose_next_push_race(work: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let hook = work.join(".git/hooks/pre-push");
+        fs::write(
+            &hook,
+            "#!/bin/sh\n\
+             [ -e ../raced ] && exit 0\n\
+             touch ../raced\n\
+             unset GIT_DIR GIT_WORK_TREE GIT_INDEX_FILE\n\
+             git -C ../other push -q origin gh-pages\n",
+        )
+        .unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lost_push_races_are_retried() {
+        let dir = publishing_setup("deploy-race");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+        deploy_in(&work, &[site, "v1", "--push"]).1.unwrap();
+        dir.git(&["clone", "-q", "origin.git", "other"]);
+        dir.git(&["-C", "other", "commit", "-q", "--allow-empty", "-m", "init"]);
+        deploy_in(&dir.join("other"), &[site, "v2"]).1.unwrap();
+        lose_next_push_race(&work);
+
+        let (report, result) = deploy_in(&work, &[site, "v3", "--push"]);
+        let deployed = dir.git(&["-C", "origin.git", "ls-tree", "--name-only", "gh-pages"]);
+        let origin = GitArgs {
+            git_dir: Some(dir.join("origin.git")),
+            ..commands::defaults()
+        };
+        let versions =
+            Versions::from_git(&*origin.discover().unwrap().backend(), "gh-pages").unwrap();
+
+        result.unwrap();
+        assert!(report.pushed);
+        assert!(dir.join("raced").exists());
+        insta::assert_snapshot!(deployed, @r"
+        .nojekyll
+        _redirects
+        v1
+        v2
+        v3
+        versions.json
+        ");
+        let tags = versions
+            .into_iter()
+            .map(|(version, _)| version.tag.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["v3", "v2", "v1"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn concurrent_deploys_of_the_same_version_give_up() {
+        let dir = publishing_setup("deploy-race-same");
+        let work = dir.join("work");
+        let site = dir.join("site");
+        let site = site.to_str().unwrap();
+        deploy_in(&work, &[site, "v1", "--push"]).1.unwrap();
+        dir.git(&["clone", "-q", "origin.git", "other"]);
+        dir.git(&["-C", "other", "commit", "-q", "--allow-empty", "-m", "init"]);
+        fs::create_dir_all(dir.join("other-site")).unwrap();
+        fs::write(dir.join("other-site/index.html"), "<h1>Other</h1>").unwrap();
+        let other_site = dir.join("other-site");
+        deploy_in(&dir.join("other"), &[other_site.to_str().unwrap(), "v2"])
+            .1
+            .unwrap();
+        lose_next_push_race(&work);
+
+        let (report, result) = deploy_in(&work, &[site, "v2", "--push"]);
+
+        assert!(!report.pushed);
+        insta::assert_snapshot!(result.unwrap_err(), @"version v2 was concurrently deployed to origin/gh-pages with different content; giving up");
+    }
 }
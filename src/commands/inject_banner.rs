@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::Args;
+
+use crate::{DocverError, GitArgs, git, html, versions::Versions};
+
+use super::deploy::{Status, push, push_mirrors};
+
+#[derive(Debug, Args)]
+/// Inject (or update) the outdated-version banner in already deployed versions
+pub struct InjectBannerArgs {
+    /// Versions whose HTML pages get the banner
+    #[arg(required = true)]
+    versions: Vec<String>,
+
+    /// HTML snippet to use as the banner, with the placeholders {version}, {latest} and
+    /// {latest_url}
+    #[arg(long, value_name = "PATH")]
+    banner_template: Option<PathBuf>,
+}
+
+impl InjectBannerArgs {
+    pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
+        git_args.fetch()?;
+        let remote_rev = git_args.remote_rev();
+        let remote_tip = git_args.backend().rev_parse(&remote_rev);
+        let parent = remote_tip
+            .clone()
+            .or_else(|| git_args.backend().rev_parse(&git_args.branch))
+            .context("nothing has been deployed yet")?;
+
+        let versions = Versions::from_git(&*git_args.backend(), &parent)?;
+        let latest = versions
+            .default_version()
+            .map(|version| &version.tag)
+            .with_context(|| format!("no version is aliased as {}", versions.default_alias()))?;
+        let banner_template = match &self.banner_template {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read banner template {}", path.display()))?,
+            None => html::DEFAULT_BANNER.to_string(),
+        };
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let alias_path = deploy_prefix.join(versions.default_alias());
+        let latest_url = format!("/{}/", alias_path.to_string_lossy().replace('\\', "/"));
+
+        let mut commit = git_args.commit(&format!(
+            "Injected banner into {} with {} {}",
+            self.versions.join(", "),
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        let mut pages = 0;
+        for identifier in &self.versions {
+            let Some(version) = versions.resolve(identifier) else {
+                return Err(DocverError::VersionNotFound(identifier.clone()).into());
+            };
+            if let Some(url) = &version.url {
+                bail!(
+                    "version {} links to {}, so it has no pages here",
+                    version.tag,
+                    url
+                );
+            }
+            let version = &version.tag;
+            if version == latest {
+                bail!("version {} is the default version", version);
+            }
+            let rewrites = html::Rewrites {
+                banner: Some(
+                    html::render_banner(&banner_template, version, latest, &latest_url)
+                        .context("invalid banner template")?,
+                ),
+                ..Default::default()
+            };
+
+            let version_path = deploy_prefix
+                .join(version)
+                .to_string_lossy()
+                .replace('\\', "/");
+            let entries = git_args
+                .backend()
+                .ls_tree(&parent, &version_path)?
+                .into_iter()
+                .filter(|(path, _)| html::is_html(path))
+                .collect::<Vec<_>>();
+            let shas = entries
+                .iter()
+                .map(|(_, e)| e.sha.clone())
+                .collect::<Vec<_>>();
+            let version_dir = format!("{}/", version_path);
+            for ((path, entry), data) in entries
+                .iter()
+                .zip(git::read_blobs(git_args.repo_dir(), &shas)?)
+            {
+                let page = path.strip_prefix(&version_dir).unwrap_or(path);
+                commit = commit.add_bytes(path, entry.mode, rewrites.apply(page, data));
+                pages += 1;
+            }
+        }
+
+        git_args.backend().commit(&commit.parent(&parent), None)?;
+        println!(
+            "Injected the banner into {} pages on {}.",
+            pages, git_args.branch
+        );
+
+        if git_args.push {
+            let pushed = push(&git_args, git_args.remote(), remote_tip.as_deref());
+            push_mirrors(&git_args, pushed.map_err(Into::into), Status::new(false))?;
+        }
+        Ok(())
+    }
+}
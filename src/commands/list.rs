@@ -1,43 +1,1589 @@
-use crate::{GitArgs, versions::Versions};
-use clap::Args;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    GitArgs, git,
+    progress::format_bytes,
+    template,
+    versions::{Version, Versions, is_prerelease, major_group, parse_semver_like, release_group},
+};
+use anyhow::Context;
+use clap::{Args, ValueEnum};
 use colored::Colorize;
+use dialoguer::{FuzzySelect, Select, theme::ColorfulTheme};
 use git_cmd::git_in_dir;
+use regex::Regex;
+use semver::VersionReq;
+use serde::Serialize;
+
+/// How `list --verbose` shows deploy dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DateFormat {
+    /// e.g. "3 days ago"
+    Relative,
+    /// Strict ISO 8601, e.g. "2024-05-01T12:00:00+02:00"
+    Iso,
+}
+
+/// Output formats of `list`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Colored text for humans
+    Plain,
+    /// An aligned table with borders
+    Table,
+    /// The versions.json structure (plus any extra columns)
+    Json,
+    /// The same structure as JSON
+    Yaml,
+    /// One row per version with a header row
+    Csv,
+    /// Like CSV, separated by tabs
+    Tsv,
+}
+
+/// Columns of `list --format table`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Column {
+    Tag,
+    Title,
+    Aliases,
+    /// Marks the version the default alias points at
+    Default,
+    Deployed,
+    Source,
+    Files,
+    Size,
+    /// The public URL, with --base-url
+    Url,
+}
+
+impl Column {
+    fn header(self) -> &'static str {
+        match self {
+            Column::Tag => "tag",
+            Column::Title => "title",
+            Column::Aliases => "aliases",
+            Column::Default => "default",
+            Column::Deployed => "deployed",
+            Column::Source => "source",
+            Column::Files => "files",
+            Column::Size => "size",
+            Column::Url => "url",
+        }
+    }
+}
+
+/// Keys `list` can group versions by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// Semver major version ("1.x"), with tags that are not semver-like under "other"
+    Major,
+    /// Release ("2.0.0"), so prereleases like "2.0.0-rc.1" sit with the release they lead up to
+    Release,
+}
+
+/// Orders `list` can print versions in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Sort {
+    /// The order of versions.json (see deploy --ordering), by default non-semver versions first
+    /// and then semver versions newest first
+    #[value(alias = "semver")]
+    Ordering,
+    /// Lexicographically by tag
+    Alpha,
+    /// Most recently deployed first
+    Deployed,
+    /// Largest first
+    Size,
+}
 
 #[derive(Debug, Args)]
 /// List all versions of the site
 pub struct ListArgs {
-    /// Version or alias identifiers to list
+    /// Version or alias identifiers to list; "*" and "?" match like shell globs (e.g. "v1.*"),
+    /// and a trailing "." or "-" matches as a prefix (e.g. "v1.")
     identifiers: Vec<String>,
 
-    /// Output in JSON format
-    #[arg(short, long, default_value = "false")]
+    /// Only list versions whose tag or an alias matches this regular expression
+    #[arg(long, value_name = "PATTERN")]
+    regex: Option<Regex>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Plain)]
+    format: Format,
+
+    /// Columns of --format table (default: tag, title, aliases and default, plus the columns
+    /// enabled by --verbose and --sizes)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    columns: Vec<Column>,
+
+    /// Print each version as this template, with the placeholders {tag}, {title}, {aliases},
+    /// {default}, {deployed}, {source}, {files} and {size} and the escapes \t and \n
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = parse_format_string,
+        conflicts_with_all = ["json", "format", "columns"]
+    )]
+    format_string: Option<String>,
+
+    /// Draw --format table with ASCII instead of box-drawing characters
+    #[arg(long)]
+    ascii: bool,
+
+    /// Read this versions.json instead of the one on the branch, without running git
+    #[arg(long, value_name = "PATH")]
+    file: Option<PathBuf>,
+
+    /// List the versions as of this commit or ref of the branch instead of its tip (e.g.
+    /// "origin/gh-pages~3")
+    #[arg(long, value_name = "REV", conflicts_with = "file")]
+    at: Option<String>,
+
+    /// Output in JSON format (same as --format json)
+    ///
+    /// The output is an object with these fields, whose names and meaning only change along
+    /// with `schema_version`:
+    ///
+    ///   schema_version  1
+    ///   default         tag the default alias ("latest") points at, or null
+    ///   versions        the versions, each with "version", "title" and "aliases", plus
+    ///                   "hidden" for hidden versions with --all, "created_at" and
+    ///                   "updated_at" when recorded (ISO 8601), "source_commit" when
+    ///                   recorded or with --verbose, "deployed" with --verbose, "files" and "bytes"
+    ///                   with --sizes, "url" and "alias_urls" with --base-url, and
+    ///                   "extra" with the versions.json fields versite doesn't know
+    ///   groups          instead of "versions" with --group-by: a list of objects with the
+    ///                   "group" name and its "versions"
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        conflicts_with = "format",
+        verbatim_doc_comment
+    )]
     json: bool,
+
+    /// Print the JSON as the bare list of versions of earlier releases, without the
+    /// schema_version envelope. Deprecated: will be removed in the next release.
+    #[arg(long)]
+    json_compat: bool,
+
+    /// Write the output to this file instead of stdout, without colors. The file is replaced
+    /// atomically, and missing parent directories are created.
+    #[arg(short, long, value_name = "PATH", conflicts_with = "interactive")]
+    output: Option<PathBuf>,
+
+    /// Only list versions in this semver range (e.g. ">=1.0, <2" or "^1.4")
+    #[arg(long, value_name = "RANGE", value_parser = parse_range)]
+    range: Option<VersionReq>,
+
+    /// Order to list the versions in
+    #[arg(long, value_enum, default_value_t = Sort::Ordering)]
+    sort: Sort,
+
+    /// Reverse the order
+    #[arg(long)]
+    reverse: bool,
+
+    /// Only list versions built from this commit or one of its descendants, by the source
+    /// commits recorded in versions.json. Versions without one are left out with a warning.
+    #[arg(long, value_name = "COMMIT")]
+    contains: Option<String>,
+
+    /// Also list the versions deployed with --hide
+    #[arg(long)]
+    all: bool,
+
+    /// Also show when each version was last deployed and from which source commit
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Public URL the branch is served from (e.g. "https://docs.example.com"), to show the URL
+    /// of each version and alias
+    #[arg(long, value_name = "URL")]
+    base_url: Option<String>,
+
+    /// Also show the number of files and total size of each version
+    #[arg(long)]
+    sizes: bool,
+
+    /// Format of the deploy dates shown with --verbose
+    #[arg(long, value_enum, default_value_t = DateFormat::Relative)]
+    date: DateFormat,
+
+    /// Only print the tag of the newest semver version (the full entry with --json), failing
+    /// when there is none
+    #[arg(long, conflicts_with_all = ["sort", "reverse", "format"])]
+    latest: bool,
+
+    /// Only print which tag each alias points at, flagging aliases whose version is missing
+    #[arg(long, conflicts_with_all = ["latest", "sort", "reverse", "format"])]
+    aliases: bool,
+
+    /// Group the versions under headers, nesting them in JSON output
+    #[arg(
+        long,
+        value_enum,
+        value_name = "KEY",
+        conflicts_with_all = ["format", "format_string", "latest", "aliases"]
+    )]
+    group_by: Option<GroupBy>,
+
+    /// Only show the newest version of each group
+    #[arg(long, requires = "group_by")]
+    latest_per_group: bool,
+
+    /// Pick a version from a searchable list and then an action for it
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["json", "format", "format_string", "latest", "aliases", "group_by"]
+    )]
+    interactive: bool,
+
+    /// Consider prereleases for --latest
+    #[arg(long, requires = "latest")]
+    include_prereleases: bool,
+
+    /// Also list versions that are not semver (e.g. "main") when filtering with --range
+    #[arg(long, requires = "range")]
+    include_non_semver: bool,
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it over `path`, so readers
+/// never see a partial file
+fn write_atomically(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let name = path.file_name().context("not a file path")?;
+    let temp = dir.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })?;
+    Ok(())
+}
+
+/// Version of the `list --json` schema, bumped whenever a field is renamed, removed or changes
+/// meaning
+const SCHEMA_VERSION: u32 = 1;
+
+/// The `list --json` output
+#[derive(Serialize)]
+struct JsonListing<'a> {
+    schema_version: u32,
+    default: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    versions: Option<&'a [ListedVersion<'a>]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<serde_json::Value>>,
+}
+
+fn parse_range(s: &str) -> anyhow::Result<VersionReq> {
+    VersionReq::parse(s).map_err(|err| {
+        anyhow::anyhow!(
+            "{}\nA range is a comma-separated list of comparisons, e.g. \">=1.0, <2\", \"^1.4\", \"~1.4.2\" or \"1.*\"",
+            err
+        )
+    })
+}
+
+/// Which versions a listing reads and keeps, converted from [`ListArgs`] or built by
+/// [`crate::api::list`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Query {
+    pub(crate) identifiers: Vec<String>,
+    pub(crate) regex: Option<Regex>,
+    pub(crate) range: Option<VersionReq>,
+    /// Keep hidden versions
+    pub(crate) all: bool,
+    /// Keep versions that aren't semver when filtering by range
+    pub(crate) include_non_semver: bool,
+    /// Read the versions as of this revision instead of the branch tip
+    pub(crate) at: Option<String>,
+    /// Read this versions.json instead of the branch
+    pub(crate) file: Option<PathBuf>,
+}
+
+impl From<&ListArgs> for Query {
+    fn from(args: &ListArgs) -> Self {
+        Query {
+            identifiers: args.identifiers.clone(),
+            regex: args.regex.clone(),
+            range: args.range.clone(),
+            all: args.all,
+            include_non_semver: args.include_non_semver,
+            at: args.at.clone(),
+            file: args.file.clone(),
+        }
+    }
+}
+
+impl Query {
+    /// Read the versions from the file, the revision or the branch tip
+    pub(crate) fn read(&self, git_args: &GitArgs) -> crate::error::Result<Versions> {
+        Ok(match &self.file {
+            Some(file) => Versions::from_file(file)?,
+            None => match &self.at {
+                Some(rev) => Versions::at(&*git_args.backend(), rev)?,
+                None => Versions::from_git(&*git_args.backend(), &git_args.read_rev())?,
+            },
+        })
+    }
+
+    /// Keep the versions matching the identifiers, regex and range
+    pub(crate) fn filter(&self, versions: &mut Versions) {
+        if !self.all {
+            versions.retain(|version| !version.hidden);
+        }
+        if !self.identifiers.is_empty() {
+            let tags = self
+                .identifiers
+                .iter()
+                .flat_map(|identifier| versions.search(identifier))
+                .map(|version| version.tag.clone())
+                .collect::<HashSet<_>>();
+            versions.retain(|version| tags.contains(&version.tag));
+        }
+        if let Some(regex) = &self.regex {
+            let tags = versions
+                .search_regex(regex)
+                .into_iter()
+                .map(|version| version.tag.clone())
+                .collect::<HashSet<_>>();
+            versions.retain(|version| tags.contains(&version.tag));
+        }
+        if let Some(range) = &self.range {
+            versions.retain(|version| match parse_semver_like(&version.tag) {
+                Some(semver) => range.matches(&semver),
+                None => self.include_non_semver,
+            });
+        }
+    }
 }
 
 impl ListArgs {
     pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
-        git_in_dir(
-            ".".into(),
-            &["fetch", git_args.remote.as_str(), git_args.branch.as_str()],
-        )?;
+        if self.file.is_none() {
+            git_args.fetch()?;
+        }
 
-        let versions = Versions::from_git(&git_args.remote_rev());
+        let mut out = String::new();
+        let Some(path) = &self.output else {
+            let result = self.write(git_args, &mut out);
+            print!("{}", out);
+            return result;
+        };
+        colored::control::set_override(false);
+        self.write(git_args, &mut out)?;
+        write_atomically(path, &out).with_context(|| format!("failed to write {}", path.display()))
+    }
 
-        if self.json {
-            println!("{}", serde_json::to_string_pretty(&versions)?);
+    /// Whether the versions are read from --file rather than from git
+    pub(crate) fn reads_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Write the listing to `out`, except for --interactive which prompts on the terminal
+    fn write(&self, git_args: GitArgs, out: &mut String) -> anyhow::Result<()> {
+        let query = Query::from(self);
+        let mut versions = query.read(&git_args)?;
+        if self.aliases {
+            return self.list_aliases(&git_args, &versions, out);
+        }
+        query.filter(&mut versions);
+        if let Some(commit) = &self.contains {
+            retain_containing(&git_args, &mut versions, commit)?;
+        }
+
+        if self.latest {
+            let Some(latest) = versions.latest_semver(self.include_prereleases) else {
+                anyhow::bail!("no semver versions are deployed");
+            };
+            if self.json {
+                let aliases = (&versions)
+                    .into_iter()
+                    .find(|(version, _)| version.tag == latest.tag)
+                    .map(|(_, aliases)| aliases)
+                    .unwrap_or_default();
+                let listed = ListedVersion::new(latest, &aliases);
+                writeln!(out, "{}", serde_json::to_string_pretty(&listed)?)?;
+            } else {
+                writeln!(out, "{}", latest.tag)?;
+            }
+            return Ok(());
+        }
+
+        let columns = self.columns();
+        if self.file.is_some()
+            && (self.verbose
+                || self.sizes
+                || matches!(self.sort, Sort::Deployed | Sort::Size)
+                || columns.iter().any(|column| {
+                    matches!(
+                        column,
+                        Column::Deployed | Column::Source | Column::Files | Column::Size
+                    )
+                }))
+        {
+            anyhow::bail!(
+                "deploy dates and sizes come from the branch, so they can't be listed with --file"
+            );
+        }
+        let deploys = if self.verbose
+            || self.sort == Sort::Deployed
+            || columns.contains(&Column::Deployed)
+            || columns.contains(&Column::Source)
+        {
+            deploys(&git_args, &self.rev(&git_args), &versions)?
+        } else {
+            HashMap::new()
+        };
+
+        let sizes = if self.sizes
+            || self.sort == Sort::Size
+            || columns.contains(&Column::Files)
+            || columns.contains(&Column::Size)
+        {
+            sizes(&git_args, &self.rev(&git_args), &versions)?
         } else {
-            for (version, aliases) in &versions {
-                print!("{}", version.tag.green());
+            HashMap::new()
+        };
+
+        let mut entries = (&versions).into_iter().collect::<Vec<_>>();
+        match self.sort {
+            Sort::Ordering => {}
+            Sort::Alpha => entries.sort_by(|(a, _), (b, _)| a.tag.cmp(&b.tag)),
+            // Stable, so versions without a deploy commit keep their versions.json order at the end
+            Sort::Deployed => entries.sort_by_key(|(version, _)| {
+                std::cmp::Reverse(deploys.get(&version.tag).map(|deploy| deploy.time))
+            }),
+            Sort::Size => entries.sort_by_key(|(version, _)| {
+                std::cmp::Reverse(sizes.get(&version.tag).map(|size| size.bytes))
+            }),
+        }
+        if self.reverse {
+            entries.reverse();
+        }
+
+        // Indices into the entries of each group. Groups are ordered by their first version, and
+        // keep the listing order within.
+        let groups = self.group_by.map(|group_by| {
+            let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+            for (i, (version, _)) in entries.iter().enumerate() {
+                let group = match group_by {
+                    GroupBy::Major => major_group(&version.tag),
+                    GroupBy::Release => release_group(&version.tag),
+                };
+                match groups.iter_mut().find(|(g, _)| *g == group) {
+                    Some((_, members)) => members.push(i),
+                    None => groups.push((group, vec![i])),
+                }
+            }
+            if self.latest_per_group {
+                for (_, members) in &mut groups {
+                    // Ties, like the tags in "other", keep the first one listed
+                    let newest = members
+                        .iter()
+                        .rev()
+                        .max_by_key(|i| parse_semver_like(&entries[**i].0.tag))
+                        .copied();
+                    *members = newest.into_iter().collect();
+                }
+            }
+            groups
+        });
+
+        let default_tag = versions.default_version().map(|version| &version.tag);
+        let cell = |column: Column, version: &Version, aliases: &[&str]| {
+            let deploy = deploys.get(&version.tag);
+            let size = sizes.get(&version.tag).copied().unwrap_or_default();
+            match column {
+                Column::Tag => version.tag.clone(),
+                Column::Title => version.title.clone().unwrap_or_default(),
+                Column::Aliases => aliases.join(", "),
+                Column::Default => match default_tag == Some(&version.tag) {
+                    true => "*".to_string(),
+                    false => String::new(),
+                },
+                Column::Deployed => deploy.map(|d| self.format_date(d)).unwrap_or_default(),
+                Column::Source => deploy
+                    .and_then(|d| d.source.as_deref())
+                    .map(|sha| sha.get(..7).unwrap_or(sha).to_string())
+                    .unwrap_or_default(),
+                Column::Files => size.files.to_string(),
+                Column::Size => format_bytes(size.bytes),
+                Column::Url => self
+                    .url(&git_args, &versions, &version.tag)
+                    .unwrap_or_default(),
+            }
+        };
+
+        let format = if self.json { Format::Json } else { self.format };
+        if self.interactive {
+            let rows = entries
+                .iter()
+                .map(|(version, aliases)| {
+                    [Column::Tag, Column::Title, Column::Aliases]
+                        .map(|column| cell(column, version, aliases))
+                })
+                .collect::<Vec<_>>();
+            return self.pick(&git_args, &versions, &rows);
+        } else if let Some(template) = &self.format_string {
+            let template = unescape(template);
+            for (version, aliases) in &entries {
+                let line = template::render(&template, |name| {
+                    Column::from_str(name, false)
+                        .ok()
+                        .map(|column| cell(column, version, aliases))
+                })?;
+                writeln!(out, "{}", line)?;
+            }
+        } else if format == Format::Table {
+            let rows = entries
+                .iter()
+                .map(|(version, aliases)| {
+                    columns
+                        .iter()
+                        .map(|column| cell(*column, version, aliases))
+                        .collect()
+                })
+                .collect::<Vec<_>>();
+            // Only fit the table to the terminal when there is one
+            let width = (self.output.is_none() && io::stdout().is_terminal()).then(|| {
+                std::env::var("COLUMNS")
+                    .ok()
+                    .and_then(|columns| columns.parse().ok())
+                    .unwrap_or(80)
+            });
+            let headers = columns.iter().map(|c| c.header()).collect::<Vec<_>>();
+            let title = columns.iter().position(|c| *c == Column::Title);
+            write!(out, "{}", to_grid(&headers, rows, title, width, self.ascii))?;
+        } else if format != Format::Plain {
+            let listed = entries
+                .iter()
+                .map(|(version, aliases)| {
+                    let mut listed = ListedVersion::new(version, aliases);
+                    if self.verbose
+                        && let Some(deploy) = deploys.get(&version.tag)
+                    {
+                        listed.deployed = Some(self.format_date(deploy));
+                        listed.source_commit = listed.source_commit.or(deploy.source.as_deref());
+                    }
+                    if self.sizes {
+                        let size = sizes.get(&version.tag).copied().unwrap_or_default();
+                        listed.files = Some(size.files);
+                        listed.bytes = Some(size.bytes);
+                    }
+                    if self.base_url.is_some() {
+                        listed.url = self.url(&git_args, &versions, &version.tag);
+                        listed.alias_urls = Some(
+                            aliases
+                                .iter()
+                                .filter_map(|alias| {
+                                    Some((*alias, self.url(&git_args, &versions, alias)?))
+                                })
+                                .collect(),
+                        );
+                    }
+                    listed
+                })
+                .collect::<Vec<_>>();
+            let groups = groups.map(|groups| {
+                groups
+                    .iter()
+                    .map(|(group, members)| {
+                        serde_json::json!({
+                            "group": group,
+                            "versions": members.iter().map(|i| &listed[*i]).collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let json = match (groups, self.json_compat) {
+                (Some(groups), true) => serde_json::to_string_pretty(&groups)?,
+                (None, true) => serde_json::to_string_pretty(&listed)?,
+                (groups, false) => serde_json::to_string_pretty(&JsonListing {
+                    schema_version: SCHEMA_VERSION,
+                    default: default_tag.map(String::as_str),
+                    versions: groups.is_none().then_some(listed.as_slice()),
+                    groups,
+                })?,
+            };
+            match format {
+                Format::Json => writeln!(out, "{}", json)?,
+                Format::Yaml => write!(out, "{}", to_yaml(&listed))?,
+                Format::Csv => write!(out, "{}", to_table(&listed, ','))?,
+                Format::Tsv => write!(out, "{}", to_table(&listed, '\t'))?,
+                Format::Plain | Format::Table => unreachable!(),
+            }
+        } else {
+            let write_entry = |out: &mut String,
+                               indent: &str,
+                               (version, aliases): &(&Version, Vec<&str>)|
+             -> std::fmt::Result {
+                write!(out, "{}{}", indent, version.tag.green())?;
                 if let Some(title) = &version.title {
-                    print!(" ({})", title.blue());
+                    write!(out, " ({})", title.blue())?;
+                }
+                if !aliases.is_empty() {
+                    // The default alias, which the site root is served from, is starred
+                    let aliases = aliases
+                        .iter()
+                        .map(|alias| match *alias == versions.default_alias() {
+                            true => format!("{}*", alias),
+                            false => alias.to_string(),
+                        })
+                        .collect::<Vec<_>>();
+                    write!(out, " [{}]", aliases.join(", ").yellow())?;
+                }
+                if is_prerelease(&version.tag) {
+                    write!(out, " {}", "prerelease".dimmed())?;
+                }
+                if version.hidden {
+                    write!(out, " {}", "hidden".dimmed())?;
+                }
+                if self.sizes {
+                    let size = sizes.get(&version.tag).copied().unwrap_or_default();
+                    write!(
+                        out,
+                        "  {}",
+                        format!("{} files, {}", size.files, format_bytes(size.bytes)).dimmed()
+                    )?;
+                }
+                if self.verbose
+                    && let Some(deploy) = deploys.get(&version.tag)
+                {
+                    write!(out, "  deployed {}", self.format_date(deploy).dimmed())?;
+                    let source = deploy.source.as_deref().unwrap_or("unknown");
+                    write!(out, " from {}", source.get(..7).unwrap_or(source).dimmed())?;
+                }
+                if let Some(url) = self.url(&git_args, &versions, &version.tag) {
+                    write!(out, "  {}", url.underline())?;
+                }
+                writeln!(out)
+            };
+            match &groups {
+                Some(groups) => {
+                    for (group, members) in groups {
+                        writeln!(out, "{}", group.bold())?;
+                        for i in members {
+                            write_entry(out, "  ", &entries[*i])?;
+                        }
+                    }
+                }
+                None => {
+                    for entry in &entries {
+                        write_entry(out, "", entry)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Let the user pick versions (given as tag, title and aliases) and act on them until they
+    /// quit
+    fn pick(
+        &self,
+        git_args: &GitArgs,
+        versions: &Versions,
+        rows: &[[String; 3]],
+    ) -> anyhow::Result<()> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            anyhow::bail!("--interactive needs a terminal");
+        }
+        if rows.is_empty() {
+            anyhow::bail!("no versions to pick from");
+        }
+        let items = rows
+            .iter()
+            .map(|[tag, title, aliases]| {
+                let mut item = tag.clone();
+                if !title.is_empty() && title != tag {
+                    item.push_str(&format!(" ({})", title));
                 }
                 if !aliases.is_empty() {
-                    print!(" [{}]", aliases.join(", ").yellow());
+                    item.push_str(&format!(" [{}]", aliases));
                 }
-                println!();
+                item
+            })
+            .collect::<Vec<_>>();
+
+        let theme = ColorfulTheme::default();
+        let mut deploys = None;
+        let mut picked = 0;
+        loop {
+            let Some(index) = FuzzySelect::with_theme(&theme)
+                .with_prompt("Version (type to search, Esc to quit)")
+                .items(&items)
+                .default(picked)
+                .interact_opt()?
+            else {
+                return Ok(());
+            };
+            picked = index;
+            let tag = &rows[index][0];
+
+            let actions = ["Show details", "Show URL", "Back", "Quit"];
+            let action = Select::with_theme(&theme)
+                .with_prompt(tag)
+                .items(actions)
+                .default(0)
+                .interact_opt()?;
+            match action {
+                Some(0) => {
+                    if deploys.is_none() {
+                        deploys = Some(match self.file {
+                            Some(_) => HashMap::new(),
+                            None => self::deploys(git_args, &self.rev(git_args), versions)?,
+                        });
+                    }
+                    let [tag, title, aliases] = &rows[index];
+                    println!("{:>9}: {}", "tag".bold(), tag.green());
+                    println!("{:>9}: {}", "title".bold(), title);
+                    println!("{:>9}: {}", "aliases".bold(), aliases.yellow());
+                    if let Some(deploy) = deploys.as_ref().and_then(|d| d.get(tag)) {
+                        println!("{:>9}: {}", "deployed".bold(), self.format_date(deploy));
+                        if let Some(source) = &deploy.source {
+                            println!("{:>9}: {}", "source".bold(), source);
+                        }
+                    }
+                }
+                Some(1) => match self.url(git_args, versions, tag) {
+                    Some(url) => println!("{}", url),
+                    None => eprintln!("Pass --base-url to show the public URL of a version"),
+                },
+                Some(3) => return Ok(()),
+                _ => {}
             }
         }
+    }
 
+    /// Print the aliases sorted by name. An alias dangles when its version is not in
+    /// versions.json or its directory is missing from the branch, which breaks its redirects.
+    fn list_aliases(
+        &self,
+        git_args: &GitArgs,
+        versions: &Versions,
+        out: &mut String,
+    ) -> anyhow::Result<()> {
+        let prefix = match git_args.prefix() {
+            prefix if prefix.is_empty() => prefix,
+            prefix => format!("{}/", prefix),
+        };
+        let rev = self.rev(git_args);
+        let mut aliases = versions.aliases().iter().collect::<Vec<_>>();
+        aliases.sort();
+        // Without the branch, only aliases to versions missing from the file are dangling
+        let dangling = |tag: &str| {
+            let Some(version) = versions.by_tag(tag) else {
+                return true;
+            };
+            !version.is_external()
+                && self.file.is_none()
+                && git_in_dir(
+                    git_args.repo_dir().into(),
+                    &[
+                        "rev-parse",
+                        "--verify",
+                        "--quiet",
+                        &format!("{}:{}{}", rev, prefix, tag),
+                    ],
+                )
+                .is_err()
+        };
+
+        if self.json {
+            for (alias, tag) in &aliases {
+                if dangling(tag) {
+                    eprintln!("warning: alias {} points at missing version {}", alias, tag);
+                }
+            }
+            // With --base-url each alias maps to its version and URL instead of just the tag
+            let json = match &self.base_url {
+                None => serde_json::to_value(aliases.into_iter().collect::<BTreeMap<_, _>>())?,
+                Some(_) => serde_json::to_value(
+                    aliases
+                        .into_iter()
+                        .map(|(alias, tag)| {
+                            let entry = serde_json::json!({
+                                "version": tag,
+                                "url": self.url(git_args, versions, alias),
+                            });
+                            (alias, entry)
+                        })
+                        .collect::<BTreeMap<_, _>>(),
+                )?,
+            };
+            writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+            return Ok(());
+        }
+        for (alias, tag) in aliases {
+            if dangling(tag) {
+                write!(
+                    out,
+                    "{} -> {} {}",
+                    alias.yellow(),
+                    tag.red(),
+                    "(missing)".red()
+                )?;
+            } else {
+                write!(out, "{} -> {}", alias.yellow(), tag.green())?;
+            }
+            if let Some(url) = self.url(git_args, versions, alias) {
+                write!(out, "  {}", url.underline())?;
+            }
+            writeln!(out)?;
+        }
         Ok(())
     }
+
+    /// The columns of --format table
+    fn columns(&self) -> Vec<Column> {
+        if let Some(template) = &self.format_string {
+            return template_columns(template).unwrap_or_default();
+        }
+        if !self.columns.is_empty() {
+            return self.columns.clone();
+        }
+        let mut columns = vec![Column::Tag, Column::Title, Column::Aliases, Column::Default];
+        if self.verbose {
+            columns.extend([Column::Deployed, Column::Source]);
+        }
+        if self.sizes {
+            columns.extend([Column::Files, Column::Size]);
+        }
+        if self.base_url.is_some() {
+            columns.push(Column::Url);
+        }
+        columns
+    }
+
+    /// The public URL of the version or alias `name`, with --base-url
+    /// The commit to read the branch at: --at, or else the branch tip
+    fn rev(&self, git_args: &GitArgs) -> String {
+        self.at.clone().unwrap_or_else(|| git_args.read_rev())
+    }
+
+    /// The public URL of a version or alias: the link of an external version, or else its
+    /// directory under --base-url
+    fn url(&self, git_args: &GitArgs, versions: &Versions, name: &str) -> Option<String> {
+        if let Some(url) = versions
+            .by_tag(name)
+            .or_else(|| versions.by_alias(name))
+            .and_then(|version| version.url.clone())
+        {
+            return Some(url);
+        }
+        let base = self.base_url.as_deref()?.trim_end_matches('/');
+        Some(match git_args.prefix().as_str() {
+            "" => format!("{}/{}/", base, name),
+            prefix => format!("{}/{}/{}/", base, prefix, name),
+        })
+    }
+
+    fn format_date(&self, deploy: &Deploy) -> String {
+        match self.date {
+            DateFormat::Relative => deploy.relative.clone(),
+            DateFormat::Iso => deploy.iso.clone(),
+        }
+    }
+}
+
+/// A version in `--json` output, shaped like its versions.json entry (plus the deploy details
+/// with --verbose)
+#[derive(Serialize)]
+struct ListedVersion<'a> {
+    version: &'a str,
+    title: &'a str,
+    aliases: Vec<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated_at: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deployed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_commit: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// The URL of each alias
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias_urls: Option<BTreeMap<&'a str, String>>,
+    /// The fields of the versions.json entry versite doesn't know
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    extra: &'a serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> ListedVersion<'a> {
+    fn new(version: &'a Version, aliases: &[&'a str]) -> Self {
+        let mut aliases = aliases.to_vec();
+        aliases.sort();
+        Self {
+            version: &version.tag,
+            title: version.title.as_deref().unwrap_or(&version.tag),
+            aliases,
+            hidden: version.hidden,
+            created_at: version.created_at.as_deref(),
+            updated_at: version.updated_at.as_deref(),
+            deployed: None,
+            source_commit: version.source_commit.as_deref(),
+            files: None,
+            bytes: None,
+            url: version.url.clone(),
+            alias_urls: None,
+            extra: &version.extra,
+        }
+    }
+
+    /// The fields in the order they are serialized, skipping the unset ones
+    fn fields(&self) -> Vec<(&'static str, serde_json::Value)> {
+        let mut fields = vec![
+            ("version", self.version.into()),
+            ("title", self.title.into()),
+            ("aliases", self.aliases.clone().into()),
+        ];
+        if self.hidden {
+            fields.push(("hidden", true.into()));
+        }
+        if let Some(created_at) = self.created_at {
+            fields.push(("created_at", created_at.into()));
+        }
+        if let Some(updated_at) = self.updated_at {
+            fields.push(("updated_at", updated_at.into()));
+        }
+        if let Some(deployed) = &self.deployed {
+            fields.push(("deployed", deployed.clone().into()));
+        }
+        if let Some(source_commit) = self.source_commit {
+            fields.push(("source_commit", source_commit.into()));
+        }
+        if let Some(files) = self.files {
+            fields.push(("files", files.into()));
+        }
+        if let Some(bytes) = self.bytes {
+            fields.push(("bytes", bytes.into()));
+        }
+        if let Some(url) = &self.url {
+            fields.push(("url", url.clone().into()));
+        }
+        if let Some(alias_urls) = &self.alias_urls {
+            fields.push(("alias_urls", serde_json::json!(alias_urls)));
+        }
+        if !self.extra.is_empty() {
+            fields.push(("extra", self.extra.clone().into()));
+        }
+        fields
+    }
+}
+
+/// The newest branch commit touching a version's directory
+struct Deploy {
+    /// Committer date as a Unix timestamp
+    time: i64,
+    iso: String,
+    relative: String,
+    /// The source commit from the Source-Commit trailer, or abbreviated from the default
+    /// "Deployed <sha> to <version>" message
+    source: Option<String>,
+}
+
+/// Check every placeholder of a --format-string up front
+fn parse_format_string(s: &str) -> anyhow::Result<String> {
+    template_columns(s)?;
+    Ok(s.to_string())
+}
+
+/// The columns the placeholders of a --format-string refer to
+fn template_columns(template: &str) -> anyhow::Result<Vec<Column>> {
+    let columns = std::cell::RefCell::new(Vec::new());
+    template::render(&unescape(template), |name| {
+        let column = Column::from_str(name, false).ok()?;
+        columns.borrow_mut().push(column);
+        Some(String::new())
+    })?;
+    Ok(columns.into_inner())
+}
+
+/// Expand the `\t`, `\n` and `\\` escapes of a --format-string
+fn unescape(template: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('t')) => result.push('\t'),
+            ('\\', Some('n')) => result.push('\n'),
+            ('\\', Some('\\')) => result.push('\\'),
+            _ => {
+                result.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    result
+}
+
+/// Render rows as an aligned table with borders. When the table is wider than `width`, the
+/// `shrink` column is narrowed (to no less than 10 characters), truncating its cells.
+fn to_grid(
+    headers: &[&str],
+    mut rows: Vec<Vec<String>>,
+    shrink: Option<usize>,
+    width: Option<usize>,
+    ascii: bool,
+) -> String {
+    let len = |s: &str| s.chars().count();
+    let mut widths = headers.iter().map(|h| len(h)).collect::<Vec<_>>();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(len(cell));
+        }
+    }
+
+    // Each column takes its width plus "│ " and " ", and the table a final "│"
+    let total = widths.iter().map(|w| w + 3).sum::<usize>() + 1;
+    if let (Some(column), Some(width)) = (shrink, width)
+        && total > width
+    {
+        let ellipsis = if ascii { "..." } else { "…" };
+        let narrowed = widths[column]
+            .saturating_sub(total - width)
+            .max(10)
+            .min(widths[column]);
+        widths[column] = narrowed;
+        for row in &mut rows {
+            let cell = &mut row[column];
+            if len(cell) > narrowed {
+                let keep = narrowed.saturating_sub(len(ellipsis));
+                *cell = cell.chars().take(keep).collect::<String>() + ellipsis;
+            }
+        }
+    }
+
+    let (h, v, top, middle, bottom) = if ascii {
+        ("-", "|", ["+"; 3], ["+"; 3], ["+"; 3])
+    } else {
+        ("─", "│", ["┌", "┬", "┐"], ["├", "┼", "┤"], ["└", "┴", "┘"])
+    };
+    let rule = |[left, mid, right]: [&str; 3]| {
+        let segments = widths.iter().map(|w| h.repeat(w + 2)).collect::<Vec<_>>();
+        format!("{}{}{}\n", left, segments.join(mid), right)
+    };
+    let line = |cells: &[&str]| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!(" {}{} ", cell, " ".repeat(width - len(cell))))
+            .collect::<Vec<_>>();
+        format!("{}{}{}\n", v, cells.join(v), v)
+    };
+
+    let mut out = rule(top);
+    out.push_str(&line(headers));
+    out.push_str(&rule(middle));
+    for row in &rows {
+        out.push_str(&line(&row.iter().map(String::as_str).collect::<Vec<_>>()));
+    }
+    out.push_str(&rule(bottom));
+    out
+}
+
+/// Render the listed versions as a YAML sequence of mappings with the same structure as the
+/// JSON output. Values use JSON's flow syntax, which is also valid YAML.
+fn to_yaml(listed: &[ListedVersion]) -> String {
+    if listed.is_empty() {
+        return "[]\n".to_string();
+    }
+    let mut out = String::new();
+    for version in listed {
+        for (i, (key, value)) in version.fields().iter().enumerate() {
+            let indent = if i == 0 { "- " } else { "  " };
+            out.push_str(&format!("{}{}: {}\n", indent, key, value));
+        }
+    }
+    out
+}
+
+/// Render the listed versions as CSV (quoted per RFC 4180) or TSV (tabs and newlines in
+/// fields replaced by spaces) with a header row
+fn to_table(listed: &[ListedVersion], separator: char) -> String {
+    let field = |value: &str| {
+        if separator == '\t' {
+            value.replace(['\t', '\n', '\r'], " ")
+        } else if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let row = |fields: Vec<String>| {
+        let mut line = fields
+            .iter()
+            .map(|f| field(f))
+            .collect::<Vec<_>>()
+            .join(&separator.to_string());
+        line.push('\n');
+        line
+    };
+
+    let Some(first) = listed.first() else {
+        return String::new();
+    };
+    let mut header = vec!["tag", "title", "aliases"];
+    if first.deployed.is_some() || first.source_commit.is_some() {
+        header.extend(["deployed", "source_commit"]);
+    }
+    if first.files.is_some() {
+        header.extend(["files", "bytes"]);
+    }
+    if first.url.is_some() {
+        header.push("url");
+    }
+    let mut out = row(header.iter().map(|h| h.to_string()).collect());
+    for version in listed {
+        let mut fields = vec![
+            version.version.to_string(),
+            version.title.to_string(),
+            version.aliases.join(";"),
+        ];
+        if header.contains(&"deployed") {
+            fields.push(version.deployed.clone().unwrap_or_default());
+            fields.push(version.source_commit.unwrap_or_default().to_string());
+        }
+        if header.contains(&"files") {
+            fields.push(version.files.unwrap_or_default().to_string());
+            fields.push(version.bytes.unwrap_or_default().to_string());
+        }
+        if header.contains(&"url") {
+            fields.push(version.url.clone().unwrap_or_default());
+        }
+        out.push_str(&row(fields));
+    }
+    out
+}
+
+/// The files of a version's directory
+#[derive(Debug, Clone, Copy, Default)]
+struct Size {
+    files: usize,
+    bytes: u64,
+}
+
+/// The size of each version's directory, from a single listing of the branch tree
+fn sizes(
+    git_args: &GitArgs,
+    rev: &str,
+    versions: &Versions,
+) -> anyhow::Result<HashMap<String, Size>> {
+    let prefix = git_args.prefix();
+    let mut sizes: HashMap<String, Size> = HashMap::new();
+    for (path, bytes) in git::blob_sizes(git_args.repo_dir(), rev, &prefix)? {
+        let rel = path
+            .strip_prefix(prefix.as_str())
+            .unwrap_or(&path)
+            .trim_start_matches('/');
+        if let Some((tag, _)) = rel.split_once('/')
+            && versions.versions().contains_key(tag)
+        {
+            let size = sizes.entry(tag.to_string()).or_default();
+            size.files += 1;
+            size.bytes += bytes;
+        }
+    }
+    Ok(sizes)
+}
+
+/// Keep the versions whose source commit contains `commit`, warning about those whose source
+/// commit is unknown or missing from this repository
+fn retain_containing(
+    git_args: &GitArgs,
+    versions: &mut Versions,
+    commit: &str,
+) -> anyhow::Result<()> {
+    let commit = git_in_dir(
+        git_args.repo_dir().into(),
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{}^{{commit}}", commit),
+        ],
+    )
+    .map_err(|_| anyhow::anyhow!("unknown commit {:?}", commit))?;
+
+    let mut unknown = Vec::new();
+    let mut containing = HashSet::new();
+    for (version, _) in &*versions {
+        match &version.source_commit {
+            Some(source) => match git::is_ancestor(git_args.repo_dir(), commit.trim(), source) {
+                Ok(true) => {
+                    containing.insert(version.tag.clone());
+                }
+                Ok(false) => {}
+                Err(_) => unknown.push(version.tag.as_str()),
+            },
+            None => unknown.push(version.tag.as_str()),
+        }
+    }
+    if !unknown.is_empty() {
+        eprintln!(
+            "warning: the source commit of {} is unknown, so they were left out",
+            unknown.join(", ")
+        );
+    }
+    versions.retain(|version| containing.contains(&version.tag));
+    Ok(())
+}
+
+/// The last deploy of each version: as recorded in its entry, or else the last commit touching
+/// its directory up to `rev`, read from a single walk of its history
+fn deploys(
+    git_args: &GitArgs,
+    rev: &str,
+    versions: &Versions,
+) -> anyhow::Result<HashMap<String, Deploy>> {
+    let mut deploys = HashMap::new();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    for version in versions.versions().values() {
+        if let (Some(at), Some(source)) = (&version.updated_at, &version.source_commit)
+            && let Some(time) = git::parse_date(at)
+                .ok()
+                .and_then(|when| when.split(' ').next()?.parse().ok())
+        {
+            deploys.insert(
+                version.tag.clone(),
+                Deploy {
+                    time,
+                    iso: at.clone(),
+                    relative: relative_date(now - time),
+                    source: Some(source.clone()),
+                },
+            );
+        }
+    }
+    if deploys.len() == versions.versions().len() {
+        return Ok(deploys);
+    }
+
+    let prefix = match git_args.prefix() {
+        prefix if prefix.is_empty() => prefix,
+        prefix => format!("{}/", prefix),
+    };
+    let mut args = vec![
+        "log",
+        "--format=>%ct%x09%cI%x09%cr%x09%(trailers:key=Source-Commit,valueonly,separator=%x20)%x09%s",
+        "--name-only",
+        rev,
+        "--",
+    ];
+    let dirs = versions
+        .versions()
+        .keys()
+        .filter(|tag| !deploys.contains_key(*tag))
+        .map(|tag| format!("{}{}", prefix, tag))
+        .collect::<Vec<_>>();
+    args.extend(dirs.iter().map(String::as_str));
+    let log = git_in_dir(git_args.repo_dir().into(), &args)?;
+
+    // Newest commits come first, so the first commit seen for a version is its latest deploy
+    let mut current = None;
+    for line in log.lines() {
+        if let Some(header) = line.strip_prefix('>') {
+            let fields = header.splitn(5, '\t').collect::<Vec<_>>();
+            let [time, iso, relative, trailer, subject] = fields[..] else {
+                anyhow::bail!("unexpected git log output: {:?}", line);
+            };
+            let source = Some(trailer.trim())
+                .filter(|sha| !sha.is_empty())
+                .or_else(|| {
+                    subject
+                        .strip_prefix("Deployed ")
+                        .and_then(|rest| rest.split_whitespace().next())
+                        .filter(|sha| sha.chars().all(|c| c.is_ascii_hexdigit()))
+                })
+                .map(str::to_string);
+            current = Some((time.parse()?, iso.to_string(), relative.to_string(), source));
+        } else if let Some(tag) = line
+            .strip_prefix(prefix.as_str())
+            .and_then(|path| path.split('/').next())
+            && versions.versions().contains_key(tag)
+            && let Some((time, iso, relative, source)) = &current
+        {
+            deploys.entry(tag.to_string()).or_insert_with(|| Deploy {
+                time: *time,
+                iso: iso.clone(),
+                relative: relative.clone(),
+                source: source.clone(),
+            });
+        }
+    }
+    Ok(deploys)
+}
+
+/// How long ago something happened `secs` ago, in the words of git's relative dates (e.g.
+/// "3 days ago")
+fn relative_date(secs: i64) -> String {
+    let ago = |n: i64, unit: &str| format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
+    if secs < 0 {
+        return "in the future".to_string();
+    }
+    if secs < 90 {
+        return ago(secs, "second");
+    }
+    let minutes = (secs + 30) / 60;
+    if minutes < 90 {
+        return ago(minutes, "minute");
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return ago(hours, "hour");
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        ago(days, "day")
+    } else if days < 70 {
+        ago((days + 3) / 7, "week")
+    } else if days < 365 {
+        ago((days + 15) / 30, "month")
+    } else if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        let years = format!("{} year{}", years, if years == 1 { "" } else { "s" });
+        match months {
+            0 => format!("{} ago", years),
+            _ => format!("{}, {}", years, ago(months, "month")),
+        }
+    } else {
+        ago((days + 183) / 365, "year")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands, test_support::Scratch};
+
+    fn versions() -> [Version; 2] {
+        [
+            Version::new("v2.0".into(), Some("Two, \"final\"".into())),
+            Version::new("v1.0".into(), None),
+        ]
+    }
+
+    fn listed(versions: &[Version; 2]) -> Vec<ListedVersion<'_>> {
+        let mut first = ListedVersion::new(&versions[0], &["latest", "stable"]);
+        first.files = Some(3);
+        first.bytes = Some(1024);
+        let mut second = ListedVersion::new(&versions[1], &[]);
+        second.files = Some(1);
+        second.bytes = Some(10);
+        vec![first, second]
+    }
+
+    #[test]
+    fn csv_and_tsv() {
+        let versions = versions();
+        insta::assert_snapshot!(to_table(&listed(&versions), ','), @r#"
+        tag,title,aliases,files,bytes
+        v2.0,"Two, ""final""",latest;stable,3,1024
+        v1.0,v1.0,,1,10
+        "#);
+        insta::assert_snapshot!(to_table(&listed(&versions), '\t'), @r#"
+        tag	title	aliases	files	bytes
+        v2.0	Two, "final"	latest;stable	3	1024
+        v1.0	v1.0		1	10
+        "#);
+    }
+
+    #[test]
+    fn grid_truncates_the_title() {
+        let rows = vec![
+            vec!["v2.0".into(), "A very long title indeed".into(), "*".into()],
+            vec!["v1.0".into(), "Short".into(), String::new()],
+        ];
+        let headers = ["tag", "title", "default"];
+        insta::assert_snapshot!(to_grid(&headers, rows.clone(), Some(1), None, false), @r"
+        ┌──────┬──────────────────────────┬─────────┐
+        │ tag  │ title                    │ default │
+        ├──────┼──────────────────────────┼─────────┤
+        │ v2.0 │ A very long title indeed │ *       │
+        │ v1.0 │ Short                    │         │
+        └──────┴──────────────────────────┴─────────┘
+        ");
+        insta::assert_snapshot!(to_grid(&headers, rows, Some(1), Some(36), true), @r"
+        +------+-----------------+---------+
+        | tag  | title           | default |
+        +------+-----------------+---------+
+        | v2.0 | A very long ... | *       |
+        | v1.0 | Short           |         |
+        +------+-----------------+---------+
+        ");
+    }
+
+    #[test]
+    fn format_strings() {
+        assert_eq!(unescape(r"{tag}\t{title}\n\\t"), "{tag}\t{title}\n\\t");
+        assert_eq!(
+            template_columns(r"{tag}\t{aliases} {{literal}}").unwrap(),
+            [Column::Tag, Column::Aliases]
+        );
+        assert!(parse_format_string("{tag} {colour}").is_err());
+    }
+
+    #[test]
+    fn yaml_mirrors_json() {
+        let versions = versions();
+        let listed = listed(&versions);
+        let json = serde_json::to_string(&listed).unwrap();
+        let keys = listed[0].fields().into_iter().map(|(key, _)| key);
+        assert!(keys.is_sorted_by_key(|key| json.find(&format!("\"{}\":", key))));
+
+        insta::assert_snapshot!(to_yaml(&listed), @r#"
+        - version: "v2.0"
+          title: "Two, \"final\""
+          aliases: ["latest","stable"]
+          files: 3
+          bytes: 1024
+        - version: "v1.0"
+          title: "v1.0"
+          aliases: []
+          files: 1
+          bytes: 10
+        "#);
+        assert_eq!(to_yaml(&[]), "[]\n");
+    }
+
+    #[test]
+    fn json_envelope() {
+        let versions = versions();
+        let listed = listed(&versions);
+        let json = serde_json::to_string(&JsonListing {
+            schema_version: SCHEMA_VERSION,
+            default: Some("v2.0"),
+            versions: Some(&listed[1..]),
+            groups: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(json, @r#"{"schema_version":1,"default":"v2.0","versions":[{"version":"v1.0","title":"v1.0","aliases":[],"files":1,"bytes":10}]}"#);
+    }
+
+    #[test]
+    fn json_passes_unknown_fields_through() {
+        let versions: Versions = serde_json::from_str(
+            r#"[{"version": "v1.0", "title": "One", "aliases": [], "lts": true}]"#,
+        )
+        .unwrap();
+        let (version, _) = versions.into_iter().next().unwrap();
+        let listed = ListedVersion::new(version, &[]);
+        insta::assert_snapshot!(serde_json::to_string(&listed).unwrap(), @r#"{"version":"v1.0","title":"One","aliases":[],"extra":{"lts":true}}"#);
+        insta::assert_snapshot!(to_yaml(&[listed]), @r#"
+        - version: "v1.0"
+          title: "One"
+          aliases: []
+          extra: {"lts":true}
+        "#);
+    }
+
+    #[test]
+    fn hidden_versions_are_listed_with_all() {
+        let versions: Versions = serde_json::from_str(
+            r#"[{"version": "v1.0", "title": null, "aliases": []}, {"version": "pr-1", "title": null, "aliases": [], "hidden": true}]"#,
+        )
+        .unwrap();
+        let tags = |all| {
+            let query = Query {
+                all,
+                ..Default::default()
+            };
+            let mut versions = versions.clone();
+            query.filter(&mut versions);
+            (&versions)
+                .into_iter()
+                .map(|(version, _)| version.tag.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tags(false), ["v1.0"]);
+        assert_eq!(tags(true), ["pr-1", "v1.0"]);
+    }
+
+    #[test]
+    fn relative_dates_read_like_git() {
+        let dates = [
+            0,
+            1,
+            89,
+            90,
+            3600,
+            86_400 * 2,
+            86_400 * 20,
+            86_400 * 100,
+            86_400 * 400,
+        ]
+        .map(relative_date);
+        insta::assert_debug_snapshot!(dates, @r#"
+        [
+            "0 seconds ago",
+            "1 second ago",
+            "89 seconds ago",
+            "2 minutes ago",
+            "60 minutes ago",
+            "2 days ago",
+            "3 weeks ago",
+            "3 months ago",
+            "1 year, 1 month ago",
+        ]
+        "#);
+        assert_eq!(relative_date(86_400 * 365 * 6), "6 years ago");
+        assert_eq!(relative_date(-5), "in the future");
+    }
+
+    #[test]
+    fn recorded_deploys_skip_the_history() {
+        let versions: Versions = serde_json::from_str(
+            r#"[{"version": "v1.0", "title": null, "aliases": [], "updated_at": "2024-05-01T12:00:00+02:00", "source_commit": "0123456789abcdef"}]"#,
+        )
+        .unwrap();
+        // The revision doesn't exist, so reading the history would fail
+        let deploys = deploys(&commands::defaults(), "no-such-rev", &versions).unwrap();
+        let deploy = &deploys["v1.0"];
+        assert_eq!(deploy.time, 1714557600);
+        assert_eq!(deploy.iso, "2024-05-01T12:00:00+02:00");
+        assert_eq!(deploy.source.as_deref(), Some("0123456789abcdef"));
+    }
+
+    #[test]
+    fn output_is_replaced_atomically() {
+        let dir = Scratch::dir("list-output");
+        let path = dir.join("nested/list.json");
+        write_atomically(&path, "old").unwrap();
+        write_atomically(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
 }
@@ -1,23 +1,47 @@
-use clap::Subcommand;
+use clap::{Args, FromArgMatches, Subcommand};
 
 use crate::GitArgs;
 
-mod deploy;
-mod list;
+mod add_external;
+pub(crate) mod deploy;
+mod inject_banner;
+pub(crate) mod list;
+mod retention;
+mod verify;
 
 #[derive(Subcommand)]
 pub enum Command {
-    Deploy(deploy::DeployArgs),
-    List(list::ListArgs),
+    Deploy(Box<deploy::DeployArgs>),
+    List(Box<list::ListArgs>),
+    InjectBanner(inject_banner::InjectBannerArgs),
+    Verify(verify::VerifyArgs),
+    AddExternal(add_external::AddExternalArgs),
+    Retention(retention::RetentionArgs),
 }
 
 impl Command {
     pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
+        // Listing a versions file from disk is the only command that works outside a repository
+        let git_args = match self {
+            Command::List(args) if args.reads_file() => git_args,
+            _ => git_args.discover()?,
+        };
         match self {
             Command::Deploy(args) => args.execute(git_args)?,
             Command::List(args) => args.execute(git_args)?,
+            Command::InjectBanner(args) => args.execute(git_args)?,
+            Command::Verify(args) => args.execute(git_args)?,
+            Command::AddExternal(args) => args.execute(git_args)?,
+            Command::Retention(args) => args.execute(git_args)?,
         }
 
         Ok(())
     }
 }
+
+/// The arguments `T` parses to when none are given on the command line
+pub(crate) fn defaults<T: Args + FromArgMatches>() -> T {
+    let name = env!("CARGO_PKG_NAME");
+    let matches = T::augment_args(clap::Command::new(name)).get_matches_from([name]);
+    T::from_arg_matches(&matches).expect("arguments without values parse to their defaults")
+}
@@ -0,0 +1,121 @@
+use anyhow::bail;
+use clap::{Args, Subcommand};
+
+use crate::{
+    GitArgs,
+    versions::{Compat, RetentionRule, Versions},
+};
+
+use super::deploy::{
+    Status, add_versions_files, has_schema_copies, has_versions_js, push, push_mirrors,
+};
+
+#[derive(Debug, Args)]
+/// Manage the retention policy in versions.json, which every deploy applies to prune old
+/// versions (unless run with --no-retention)
+pub struct RetentionArgs {
+    #[command(subcommand)]
+    action: RetentionAction,
+
+    /// Write versions.json in another tool's format, as with `deploy --compat`
+    #[arg(long, value_enum, value_name = "TOOL", global = true)]
+    compat: Option<Compat>,
+}
+
+#[derive(Debug, Subcommand)]
+enum RetentionAction {
+    /// Add a rule, or replace the rule for the same pattern. Aliased versions are always kept.
+    #[command(group(clap::ArgGroup::new("limit").required(true).multiple(true)))]
+    Set {
+        /// Glob the rule applies to (e.g. "pr-*")
+        pattern: String,
+
+        /// Keep only the newest this many matching versions
+        #[arg(long, group = "limit")]
+        keep: Option<usize>,
+
+        /// Drop matching versions last deployed more than this many days ago
+        #[arg(long, group = "limit", value_name = "DAYS")]
+        max_age_days: Option<u64>,
+    },
+    /// Print the rules
+    List,
+    /// Remove the rule for a pattern
+    Rm {
+        /// Glob of the rule to remove
+        pattern: String,
+    },
+}
+
+impl RetentionArgs {
+    pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
+        git_args.fetch()?;
+        let remote_rev = git_args.remote_rev();
+        let remote_tip = git_args.backend().rev_parse(&remote_rev);
+        let parent = remote_tip
+            .clone()
+            .or_else(|| git_args.backend().rev_parse(&git_args.branch));
+
+        let mut versions = match &parent {
+            Some(parent) => Versions::from_git(&*git_args.backend(), parent)?,
+            None => Versions::default(),
+        };
+        let summary = match &self.action {
+            RetentionAction::List => {
+                if versions.retention().is_empty() {
+                    println!("No retention rules.");
+                }
+                for rule in versions.retention() {
+                    println!("{}", rule);
+                }
+                return Ok(());
+            }
+            RetentionAction::Set {
+                pattern,
+                keep,
+                max_age_days,
+            } => {
+                let rule = RetentionRule {
+                    pattern: pattern.clone(),
+                    keep: *keep,
+                    max_age_days: *max_age_days,
+                };
+                let summary = format!("Set retention rule {}", rule);
+                versions.set_retention(rule);
+                summary
+            }
+            RetentionAction::Rm { pattern } => {
+                if !versions.remove_retention(pattern) {
+                    bail!("there is no retention rule for {}", pattern);
+                }
+                format!("Removed retention rule for {}", pattern)
+            }
+        };
+
+        let commit = git_args.commit(&format!(
+            "{} with {} {}",
+            summary,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ));
+        let mut commit = add_versions_files(
+            commit,
+            &git_args,
+            &versions,
+            self.compat,
+            has_versions_js(&git_args, parent.as_deref()),
+            has_schema_copies(&git_args, parent.as_deref(), &versions),
+        )?;
+        if let Some(parent) = &parent {
+            commit = commit.parent(parent);
+        }
+        git_args.backend().commit(&commit, None)?;
+        println!("{} on {}.", summary, git_args.branch);
+
+        if git_args.push {
+            let pushed = push(&git_args, git_args.remote(), remote_tip.as_deref());
+            push_mirrors(&git_args, pushed.map_err(Into::into), Status::new(false))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, bail};
+use clap::Args;
+use git_cmd::git_in_dir;
+
+use crate::{
+    GitArgs, checksums, git,
+    versions::{VERSIONS_FILE, Versions},
+};
+
+use super::deploy::has_versions_js;
+
+#[derive(Debug, Args)]
+/// Check the deployed versions on the publish branch
+pub struct VerifyArgs {
+    /// Versions to check (default: all deployed versions)
+    versions: Vec<String>,
+
+    /// Validate each version's files against its SHA256SUMS manifest, and the manifests
+    /// against manifests.json
+    #[arg(long)]
+    checksums: bool,
+}
+
+impl VerifyArgs {
+    pub fn execute(&self, git_args: GitArgs) -> anyhow::Result<()> {
+        git_args.fetch()?;
+        let remote_rev = git_args.read_rev();
+
+        let versions_json = git_in_dir(
+            git_args.repo_dir().into(),
+            &["show", &format!("{}:{}", remote_rev, VERSIONS_FILE)],
+        )
+        .with_context(|| format!("{} has no {}", remote_rev, VERSIONS_FILE))?;
+        let versions: Versions = serde_json::from_str(&versions_json)
+            .with_context(|| format!("failed to parse {}", VERSIONS_FILE))?;
+
+        let tags = if self.versions.is_empty() {
+            (&versions)
+                .into_iter()
+                .map(|(version, _)| version.tag.clone())
+                .collect()
+        } else {
+            self.versions.clone()
+        };
+
+        let deploy_prefix = git_args.deploy_prefix.clone().unwrap_or_default();
+        let versions_copies = has_versions_js(&git_args, Some(&remote_rev));
+        let manifests = if self.checksums {
+            let path = deploy_prefix.join(checksums::MANIFESTS_INDEX);
+            let json = git_in_dir(
+                git_args.repo_dir().into(),
+                &[
+                    "show",
+                    &format!("{}:{}", remote_rev, path.to_string_lossy()),
+                ],
+            )
+            .with_context(|| format!("{} has no {}", remote_rev, path.display()))?;
+            serde_json::from_str::<BTreeMap<String, String>>(&json)
+                .with_context(|| format!("failed to parse {}", checksums::MANIFESTS_INDEX))?
+        } else {
+            BTreeMap::new()
+        };
+
+        let name_errors = versions.validate();
+        if !name_errors.is_empty() {
+            println!("{}: {} problems", VERSIONS_FILE, name_errors.len());
+            for error in &name_errors {
+                println!("  {}", error);
+            }
+        }
+
+        let mut failed = 0;
+        for tag in &tags {
+            let problems = if let Some(url) = versions.by_tag(tag).and_then(|v| v.url.as_ref()) {
+                // External versions only have a problem if they were also deployed
+                let version_dir = deploy_prefix.join(tag).to_string_lossy().replace('\\', "/");
+                match git_args
+                    .backend()
+                    .ls_tree(&remote_rev, &version_dir)?
+                    .is_empty()
+                {
+                    true => Vec::new(),
+                    false => vec![format!(
+                        "links to {} but {} has files too",
+                        url, version_dir
+                    )],
+                }
+            } else if versions.by_tag(tag).is_none() {
+                vec![format!("not listed in {}", VERSIONS_FILE)]
+            } else {
+                let version_dir = deploy_prefix.join(tag).to_string_lossy().replace('\\', "/");
+                self.check(
+                    &git_args,
+                    &remote_rev,
+                    &version_dir,
+                    manifests.get(tag),
+                    versions_copies,
+                )?
+            };
+
+            if problems.is_empty() {
+                println!("{}: ok", tag);
+            } else {
+                failed += 1;
+                println!("{}: {} problems", tag, problems.len());
+                for problem in problems {
+                    println!("  {}", problem);
+                }
+            }
+        }
+
+        if failed > 0 {
+            bail!("{} of {} versions failed verification", failed, tags.len());
+        }
+        if !name_errors.is_empty() {
+            bail!("{} has invalid version or alias names", VERSIONS_FILE);
+        }
+        Ok(())
+    }
+
+    /// Check the files deployed to `version_dir`, against its manifest with --checksums
+    fn check(
+        &self,
+        git_args: &GitArgs,
+        remote_rev: &str,
+        version_dir: &str,
+        manifest_digest: Option<&String>,
+        versions_copies: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let entries = git_args.backend().ls_tree(remote_rev, version_dir)?;
+        if entries.is_empty() {
+            return Ok(vec![format!("{} has no files", version_dir)]);
+        }
+        if !self.checksums {
+            return Ok(Vec::new());
+        }
+
+        let mut problems = Vec::new();
+        let manifest_path = format!("{}/{}", version_dir, checksums::MANIFEST_FILE);
+        let Some(manifest_entry) = entries.get(&manifest_path) else {
+            return Ok(vec![format!("missing {}", manifest_path)]);
+        };
+        let manifest = git::read_blobs(
+            git_args.repo_dir(),
+            std::slice::from_ref(&manifest_entry.sha),
+        )?
+        .pop()
+        .unwrap_or_default();
+        match manifest_digest {
+            None => problems.push(format!("not listed in {}", checksums::MANIFESTS_INDEX)),
+            Some(digest) if *digest != checksums::hex_digest(&manifest) => problems.push(format!(
+                "{} does not match the hash recorded in {}",
+                checksums::MANIFEST_FILE,
+                checksums::MANIFESTS_INDEX
+            )),
+            Some(_) => {}
+        }
+        let manifest = checksums::parse(&String::from_utf8_lossy(&manifest))?;
+
+        let files = entries
+            .iter()
+            .map(|(path, entry)| (&path[version_dir.len() + 1..], entry.sha.clone()))
+            .filter(|(relative, _)| !checksums::is_generated(relative, versions_copies))
+            .collect::<Vec<_>>();
+        let shas = files.iter().map(|(_, sha)| sha.clone()).collect::<Vec<_>>();
+        let mut actual = BTreeMap::new();
+        git::for_each_blob(git_args.repo_dir(), &shas, |i, contents| {
+            actual.insert(files[i].0.to_string(), checksums::hex_digest(&contents));
+            Ok(())
+        })?;
+        problems.extend(checksums::compare(&manifest, &actual));
+        Ok(problems)
+    }
+}
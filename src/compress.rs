@@ -0,0 +1,76 @@
+//! Precompression of deployed files for hosts that serve `.gz` and `.br` siblings
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use flate2::{Compression, write::GzEncoder};
+
+/// Encodings a precompressed sibling can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Encoding {
+    Gzip,
+    Br,
+}
+
+impl Encoding {
+    /// File extension of the sibling, appended to the original path
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Br => "br",
+        }
+    }
+
+    /// Compress `data` at the highest level. The output only depends on the input, so
+    /// unchanged files produce unchanged siblings.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                encoder
+                    .write_all(data)
+                    .expect("writing to a Vec cannot fail");
+                encoder.finish().expect("writing to a Vec cannot fail")
+            }
+            Encoding::Br => {
+                let mut output = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 11, 22);
+                    encoder
+                        .write_all(data)
+                        .expect("writing to a Vec cannot fail");
+                }
+                output
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let data = "<p>compressible</p>\n".repeat(100).into_bytes();
+
+        let gzip = Encoding::Gzip.compress(&data);
+        assert!(gzip.len() < data.len());
+        assert_eq!(gzip, Encoding::Gzip.compress(&data));
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(gzip.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let br = Encoding::Br.compress(&data);
+        assert!(br.len() < data.len());
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(br.as_slice(), 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,295 @@
+//! The failures of reading and writing the publish branch, so library users and the binary's
+//! exit code can tell them apart
+
+use std::{fmt, io};
+
+use crate::{
+    git::{ImportError, ImportFailure, NonFastForward},
+    versions::{ArgumentError, NameError, VERSIONS_FILE},
+};
+
+pub(crate) type Result<T, E = DocverError> = std::result::Result<T, E>;
+
+/// Why an operation on the publish branch or its versions failed
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DocverError {
+    /// `path` is not inside a git repository
+    NotARepository { path: String, message: String },
+    /// The publish branch couldn't be fetched from the remote
+    Fetch {
+        remote: String,
+        branch: String,
+        message: String,
+    },
+    /// A branch or revision doesn't exist; `no_fetch` when it was never fetched and --no-fetch
+    /// asked not to
+    MissingBranch { rev: String, no_fetch: bool },
+    /// The versions file at `location` is missing, or can't be parsed when there is a `source`
+    Manifest {
+        location: String,
+        source: Option<serde_json::Error>,
+    },
+    /// A version is not deployed
+    VersionNotFound(String),
+    /// A version is already deployed under the tag
+    VersionExists(String),
+    /// No version has the alias
+    AliasNotFound(String),
+    /// The alias already points at the version `tag`
+    AliasConflict { alias: String, tag: String },
+    /// A version tag or alias can't be used
+    Name(NameError),
+    /// A value doesn't have the expected form
+    Argument(ArgumentError),
+    /// The branch can't be updated without losing history
+    NonFastForward(NonFastForward),
+    /// git fast-import failed for a recognized reason
+    Import(ImportError),
+    /// The remote refused the push, e.g. because the branch moved since it was fetched
+    PushRejected { remote: String, message: String },
+    /// A git command failed
+    Git { command: String, message: String },
+    /// Running git or reading a file failed
+    Io { action: String, source: io::Error },
+}
+
+impl DocverError {
+    /// Wrap an I/O error with what was being done, e.g. "failed to spawn git ls-tree"
+    pub(crate) fn io(action: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let action = action.into();
+        move |source| DocverError::Io { action, source }
+    }
+
+    /// A failure of `git <command>`, described by its stderr
+    pub(crate) fn git(command: impl Into<String>, stderr: &[u8]) -> Self {
+        DocverError::Git {
+            command: command.into(),
+            message: String::from_utf8_lossy(stderr).trim().to_string(),
+        }
+    }
+
+    /// The exit code of the binary: 2 for unusable names and values (like clap's usage errors),
+    /// 3 when a version, alias or branch doesn't exist, 4 when versions.json can't be read, 5 for
+    /// name clashes, 6 when the branch moved or the remote refused the push, 7 when fetching
+    /// failed, 8 when the import names an object that doesn't exist, 9 when objects couldn't be
+    /// written, 10 for a stream git couldn't parse, 11 when the ref is locked and 1 otherwise
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            DocverError::Name(
+                NameError::AliasIsVersion(_)
+                | NameError::VersionIsAlias(_)
+                | NameError::CaseClash(..),
+            )
+            | DocverError::VersionExists(_)
+            | DocverError::AliasConflict { .. } => 5,
+            DocverError::Name(_) | DocverError::Argument(_) => 2,
+            DocverError::MissingBranch { .. }
+            | DocverError::VersionNotFound(_)
+            | DocverError::AliasNotFound(_) => 3,
+            DocverError::Manifest { .. } => 4,
+            DocverError::NonFastForward(_) | DocverError::PushRejected { .. } => 6,
+            DocverError::Fetch { .. } => 7,
+            DocverError::Import(err) => match err.failure {
+                ImportFailure::MissingObject => 8,
+                ImportFailure::WriteFailed => 9,
+                ImportFailure::MalformedStream => 10,
+                ImportFailure::RefLocked => 11,
+                ImportFailure::NonFastForward => 6,
+            },
+            DocverError::NotARepository { .. }
+            | DocverError::Git { .. }
+            | DocverError::Io { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for DocverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocverError::NotARepository { path, message } => write!(
+                f,
+                "{} is not in a git repository ({}); run inside one or pass -C/--repo",
+                path, message
+            ),
+            DocverError::Fetch {
+                remote,
+                branch,
+                message,
+            } => write!(f, "failed to fetch {} from {}: {}", branch, remote, message),
+            DocverError::MissingBranch {
+                rev,
+                no_fetch: true,
+            } => write!(
+                f,
+                "{} has never been fetched, so --no-fetch has nothing to read; run once without --no-fetch",
+                rev
+            ),
+            DocverError::MissingBranch { rev, .. } => write!(f, "unknown revision {:?}", rev),
+            DocverError::Manifest {
+                location,
+                source: None,
+            } => write!(f, "there is no {} at {}", VERSIONS_FILE, location),
+            DocverError::Manifest {
+                location,
+                source: Some(source),
+            } => write!(
+                f,
+                "failed to parse {} at {} (line {}, column {})",
+                VERSIONS_FILE,
+                location,
+                source.line(),
+                source.column()
+            ),
+            DocverError::VersionNotFound(tag) => write!(f, "version {} is not deployed", tag),
+            DocverError::VersionExists(tag) => write!(f, "version {} already exists", tag),
+            DocverError::AliasNotFound(alias) => write!(f, "no version has the alias {}", alias),
+            DocverError::AliasConflict { alias, tag } => {
+                write!(f, "{} is already an alias of {}", alias, tag)
+            }
+            DocverError::Name(err) => err.fmt(f),
+            DocverError::Argument(err) => err.fmt(f),
+            DocverError::NonFastForward(err) => err.fmt(f),
+            DocverError::Import(err) => err.fmt(f),
+            DocverError::PushRejected { remote, message } => {
+                write!(f, "{} rejected the push: {}", remote, message)
+            }
+            DocverError::Git { command, message } => {
+                write!(f, "git {} failed: {}", command, message)
+            }
+            DocverError::Io { action, source } => write!(f, "{}: {}", action, source),
+        }
+    }
+}
+
+impl std::error::Error for DocverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DocverError::Manifest {
+                source: Some(source),
+                ..
+            } => Some(source),
+            // The hint is already the message, so the chain continues with git's own words
+            DocverError::Import(err) => Some(&err.stderr),
+            DocverError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<NameError> for DocverError {
+    fn from(err: NameError) -> Self {
+        DocverError::Name(err)
+    }
+}
+
+impl From<ArgumentError> for DocverError {
+    fn from(err: ArgumentError) -> Self {
+        DocverError::Argument(err)
+    }
+}
+
+impl From<NonFastForward> for DocverError {
+    fn from(err: NonFastForward) -> Self {
+        DocverError::NonFastForward(err)
+    }
+}
+
+impl From<ImportError> for DocverError {
+    fn from(err: ImportError) -> Self {
+        DocverError::Import(err)
+    }
+}
+
+impl From<io::Error> for DocverError {
+    fn from(source: io::Error) -> Self {
+        DocverError::Io {
+            action: "failed to talk to git".to_string(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::GitStderr;
+
+    #[test]
+    fn exit_codes_tell_failures_apart() {
+        let codes = [
+            DocverError::Name(NameError::Empty),
+            DocverError::Name(NameError::CaseClash("V1".into(), "v1".into())),
+            DocverError::VersionNotFound("v1".into()),
+            DocverError::Manifest {
+                location: "origin/gh-pages".into(),
+                source: None,
+            },
+            DocverError::PushRejected {
+                remote: "origin".into(),
+                message: "[rejected] gh-pages -> gh-pages (fetch first)".into(),
+            },
+            DocverError::Fetch {
+                remote: "origin".into(),
+                branch: "gh-pages".into(),
+                message: "could not read from remote repository".into(),
+            },
+            DocverError::git("fast-import", b"fatal: bad input\n"),
+            DocverError::Import(ImportError {
+                failure: ImportFailure::MalformedStream,
+                refname: "refs/heads/gh-pages".into(),
+                stderr: GitStderr("fatal: Unsupported command: bogus".into()),
+            }),
+        ]
+        .iter()
+        .map(|err| (err.exit_code(), err.to_string()))
+        .collect::<Vec<_>>();
+        insta::assert_debug_snapshot!(codes, @r#"
+        [
+            (
+                2,
+                "version tags and aliases can't be empty",
+            ),
+            (
+                5,
+                "V1 differs from v1 only by case, so they would share a directory on case-insensitive filesystems",
+            ),
+            (
+                3,
+                "version v1 is not deployed",
+            ),
+            (
+                4,
+                "there is no versions.json at origin/gh-pages",
+            ),
+            (
+                6,
+                "origin rejected the push: [rejected] gh-pages -> gh-pages (fetch first)",
+            ),
+            (
+                7,
+                "failed to fetch gh-pages from origin: could not read from remote repository",
+            ),
+            (
+                1,
+                "git fast-import failed: fatal: bad input",
+            ),
+            (
+                10,
+                "git fast-import couldn't parse the stream for refs/heads/gh-pages, which is a bug. Please report it at https://github.com/ConnerPetzold/versite/issues with the error below",
+            ),
+        ]
+        "#);
+
+        let locked = DocverError::from(ImportError {
+            failure: ImportFailure::RefLocked,
+            refname: "refs/heads/gh-pages".into(),
+            stderr: GitStderr("error: cannot lock ref 'refs/heads/gh-pages'".into()),
+        });
+        let source = std::error::Error::source(&locked).map(ToString::to_string);
+        assert_eq!(
+            source.as_deref(),
+            Some("error: cannot lock ref 'refs/heads/gh-pages'")
+        );
+    }
+}
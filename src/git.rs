@@ -1,16 +1,38 @@
 use std::{
-    collections::BTreeMap,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
     fs,
     io::{self, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local, TimeZone, Timelike};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    checksums,
+    error::{DocverError, Result},
+    versions::ArgumentError,
+};
 
 const DEFAULT_AUTHOR_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "[bot]");
 const DEFAULT_AUTHOR_EMAIL: &str = concat!(env!("CARGO_PKG_NAME"), "[bot]@users.noreply.github.io");
+const NOTES_REF: &str = "refs/notes/commits";
+
+/// Told about the files of a commit as they are written, e.g. to draw a progress bar
+pub trait CommitProgress {
+    /// Writing the commit's `files` files starts
+    fn start(&self, files: u64);
+
+    /// A file of `bytes` bytes was written
+    fn file(&self, bytes: u64);
+
+    /// The commit is written
+    fn finish(&self);
+}
 
 #[derive(Debug, Clone)]
 pub struct Commit {
@@ -20,14 +42,376 @@ pub struct Commit {
     committer: Option<(String, String, String)>,
     message: String,
     from: Option<String>,
+    /// Move the ref before the commit: to nothing (`Some(None)`) or to a revision
+    reset: Option<Option<String>>,
     delete_all: bool,
+    force: bool,
+    sign: bool,
+    date: Option<String>,
+    marks: Option<Marks>,
     deletes: BTreeMap<String, ()>,
     files: BTreeMap<String, FileEntry>,
+    /// The exact bytes of file names that aren't UTF-8, by their name in `files`
+    raw_paths: BTreeMap<String, Vec<u8>>,
+    /// Annotated tags of the new commit, by name
+    tags: BTreeMap<String, String>,
+    /// A note to attach to the new commit in refs/notes/commits
+    note: Option<String>,
+}
+
+/// Returned by [`Commit::run`] when the ref cannot be updated without losing history
+#[derive(Debug)]
+pub struct NonFastForward {
+    pub refname: String,
+    pub stderr: String,
+}
+
+impl std::fmt::Display for NonFastForward {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "git fast-import refused to update {} (non-fast-forward). The new commit must descend from the current branch tip. Hint: base the import on the tip (set a parent) or recreate/reset the branch.\nFull error: {}",
+            self.refname, self.stderr
+        )
+    }
+}
+
+impl std::error::Error for NonFastForward {}
+
+/// Why git fast-import failed, told apart by what it printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImportFailure {
+    /// The ref can't be updated without losing history
+    NonFastForward,
+    /// The parent or another object the stream names doesn't exist, usually because the branch
+    /// moved after it was fetched
+    MissingObject,
+    /// The pack or loose objects couldn't be written, e.g. because the disk is full
+    WriteFailed,
+    /// fast-import couldn't parse the stream, which is a bug in the stream writer
+    MalformedStream,
+    /// Another git process holds the lock on the ref
+    RefLocked,
+}
+
+impl ImportFailure {
+    /// Recognize the failure from fast-import's stderr; `None` for anything else
+    pub fn classify(stderr: &str) -> Option<Self> {
+        let any = |needles: &[&str]| needles.iter().any(|needle| stderr.contains(needle));
+        if stderr.contains("Not updating") && any(&["does not contain", "non-fast-forward"]) {
+            Some(ImportFailure::NonFastForward)
+        } else if any(&["cannot lock ref", ".lock': File exists"]) {
+            Some(ImportFailure::RefLocked)
+        } else if any(&[
+            "No space left on device",
+            "Disk quota exceeded",
+            "unable to create temporary file",
+            "cannot create packfile",
+            "failed to write",
+            "cannot write",
+        ]) {
+            Some(ImportFailure::WriteFailed)
+        } else if any(&[
+            "Not a valid commit",
+            "Invalid ref name or SHA1 expression",
+            "not a valid object",
+            "missing object",
+        ]) {
+            Some(ImportFailure::MissingObject)
+        } else if any(&[
+            "Unsupported command",
+            "Expected ",
+            "Missing space",
+            "Garbage after",
+            "Unknown mark",
+            "Invalid dataref",
+            "Invalid path",
+            "Invalid raw date",
+            "Unsupported file mode",
+            "Unquoted",
+            "Bad format",
+        ]) {
+            Some(ImportFailure::MalformedStream)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returned by [`Commit::run`] when git fast-import fails in a way [`ImportFailure`]
+/// recognizes; its stderr is the source of the error
+#[derive(Debug)]
+pub struct ImportError {
+    pub failure: ImportFailure,
+    pub refname: String,
+    pub stderr: GitStderr,
+}
+
+/// What a git command printed to stderr
+#[derive(Debug)]
+pub struct GitStderr(pub String);
+
+impl std::fmt::Display for GitStderr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for GitStderr {}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.failure {
+            ImportFailure::NonFastForward => write!(
+                f,
+                "git fast-import refused to update {} (non-fast-forward). Hint: base the import on the tip or recreate the branch",
+                self.refname
+            ),
+            ImportFailure::MissingObject => write!(
+                f,
+                "git fast-import couldn't find the parent of the new {} commit; the branch probably moved since it was fetched. Hint: fetch again (without --no-fetch) and retry",
+                self.refname
+            ),
+            ImportFailure::WriteFailed => write!(
+                f,
+                "git fast-import couldn't write the objects for {}. Hint: check the free space and quota of the repository's filesystem",
+                self.refname
+            ),
+            ImportFailure::MalformedStream => write!(
+                f,
+                "git fast-import couldn't parse the stream for {}, which is a bug. Please report it at {}/issues with the error below",
+                self.refname,
+                env!("CARGO_PKG_REPOSITORY")
+            ),
+            ImportFailure::RefLocked => write!(
+                f,
+                "{} is locked by another git process. Hint: wait for it to finish, or delete the stale .lock file if none is running",
+                self.refname
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.stderr)
+    }
+}
+
+/// The objects git fast-import wrote, from the statistics it prints with `--stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub blobs: ObjectCount,
+    pub trees: ObjectCount,
+    pub commits: ObjectCount,
+    pub tags: ObjectCount,
+    /// Growth of the object database, loose objects and packs together
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_written: Option<u64>,
+    /// The statistics as printed, kept instead of the counts when they couldn't be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unparsed: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ObjectCount {
+    pub new: u64,
+    pub duplicates: u64,
+}
+
+impl ImportReport {
+    /// Read the per-type lines of the statistics, e.g.
+    /// `blobs  :     12 (     3 duplicates      0 deltas of      0 attempts)`
+    fn parse(stats: &str) -> Self {
+        let count = |kind: &str| {
+            stats.lines().find_map(|line| {
+                let (name, rest) = line.split_once(':')?;
+                if name.trim() != kind {
+                    return None;
+                }
+                let (new, rest) = rest.split_once('(')?;
+                let duplicates = rest.trim_start().strip_suffix(')')?;
+                let (duplicates, _) = duplicates.split_once(" duplicates")?;
+                Some(ObjectCount {
+                    new: new.trim().parse().ok()?,
+                    duplicates: duplicates.trim().parse().ok()?,
+                })
+            })
+        };
+        match (
+            count("blobs"),
+            count("trees"),
+            count("commits"),
+            count("tags"),
+        ) {
+            (Some(blobs), Some(trees), Some(commits), Some(tags)) => ImportReport {
+                blobs,
+                trees,
+                commits,
+                tags,
+                ..Default::default()
+            },
+            _ => ImportReport {
+                unparsed: Some(stats.trim().to_string()),
+                ..Default::default()
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 enum FileEntry {
-    Inline { mode: u32, data: Vec<u8> },
+    Inline {
+        mode: u32,
+        data: Vec<u8>,
+    },
+    Blob {
+        mode: u32,
+        sha: String,
+    },
+    /// A symbolic link, stored as a blob holding the link target
+    Symlink {
+        target: String,
+    },
+    /// A file on disk, copied into the import in chunks so its contents are never held in
+    /// memory
+    External {
+        mode: u32,
+        path: PathBuf,
+    },
+}
+
+impl FileEntry {
+    /// The mode and contents of entries held in memory
+    fn inline(&self) -> Option<(u32, &[u8])> {
+        match self {
+            FileEntry::Inline { mode, data } => Some((*mode, data)),
+            FileEntry::Symlink { target } => Some((0o120000, target.as_bytes())),
+            FileEntry::Blob { .. } | FileEntry::External { .. } => None,
+        }
+    }
+
+    fn mode(&self) -> u32 {
+        match self {
+            FileEntry::Inline { mode, .. }
+            | FileEntry::Blob { mode, .. }
+            | FileEntry::External { mode, .. } => *mode,
+            FileEntry::Symlink { .. } => 0o120000,
+        }
+    }
+}
+
+/// Copy the `len` bytes of the file at `path` to `w`, failing if the file has another size by
+/// the time it is read
+fn copy_file<W: Write>(path: &Path, len: u64, w: &mut W) -> io::Result<()> {
+    let mut file = fs::File::open(path)?;
+    let copied = io::copy(&mut io::Read::take(&mut file, len), w)?;
+    let grew = io::Read::read(&mut file, &mut [0])? > 0;
+    if copied != len || grew {
+        return Err(io::Error::other(format!(
+            "{} changed size while being imported (expected {} bytes)",
+            path.display(),
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// A blob entry from `git ls-tree`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub sha: String,
+}
+
+/// Marks kept between imports, so contents imported by an earlier run are referenced by mark
+/// instead of being streamed again. fast-import reads and writes the marks file itself
+/// (":<mark> <sha>" lines); the SHA-256 of each blob's contents is kept beside it, in the same
+/// path with ".hashes" appended.
+#[derive(Debug, Clone)]
+pub struct Marks {
+    path: PathBuf,
+    /// Marks of earlier imports, by the SHA-256 of their contents in hex
+    by_hash: HashMap<String, usize>,
+    /// The first mark free for new blobs
+    next: usize,
+}
+
+impl Marks {
+    /// Load the marks at `path`, which need not exist yet. Hashes whose mark is missing from the
+    /// marks file (e.g. because it was deleted) are dropped, so they are imported again.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        // fast-import runs in the repository, so relative paths must not reach it
+        let path = std::path::absolute(path.as_ref())
+            .map_err(DocverError::io("failed to resolve the marks file"))?;
+        let read = |path: &Path| match fs::read_to_string(path) {
+            Ok(text) => Ok(text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            Err(source) => Err(DocverError::Io {
+                action: format!("failed to read {}", path.display()),
+                source,
+            }),
+        };
+        let mark = |field: &str| field.strip_prefix(':')?.parse::<usize>().ok();
+
+        let known = read(&path)?
+            .lines()
+            .filter_map(|line| mark(line.split(' ').next()?))
+            .collect::<BTreeSet<_>>();
+        let mut marks = Marks {
+            next: known.last().map_or(1, |last| last + 1),
+            by_hash: HashMap::new(),
+            path,
+        };
+        for line in read(&marks.hashes_path())?.lines() {
+            if let Some((hash, field)) = line.split_once(' ')
+                && let Some(mark) = mark(field)
+                && known.contains(&mark)
+            {
+                marks.by_hash.insert(hash.to_string(), mark);
+            }
+        }
+        Ok(marks)
+    }
+
+    fn hashes_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".hashes");
+        path.into()
+    }
+
+    /// Record the contents of blobs fast-import has just exported marks for
+    fn save(&self, blobs: &[(usize, [u8; 32], &[u8])]) -> Result<()> {
+        let path = self.hashes_path();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(DocverError::io(format!(
+                "failed to open {}",
+                path.display()
+            )))?;
+        let lines = blobs
+            .iter()
+            .map(|(mark, hash, _)| format!("{} :{}\n", checksums::hex(hash), mark))
+            .collect::<String>();
+        file.write_all(lines.as_bytes())
+            .map_err(DocverError::io(format!(
+                "failed to write {}",
+                path.display()
+            )))
+    }
+}
+
+/// Contents emitted as standalone blobs so paths can refer to them by mark
+struct SharedBlobs<'a> {
+    /// Blob data to emit before the commit, in mark order
+    blobs: Vec<(usize, [u8; 32], &'a [u8])>,
+    /// The mark each shared path refers to
+    marks: HashMap<&'a str, usize>,
+    /// The mark of the commit itself, for tags and notes to refer to
+    commit_mark: usize,
 }
 
 impl Commit {
@@ -39,9 +423,17 @@ impl Commit {
             committer: None,
             message: String::new(),
             from: None,
+            reset: None,
             delete_all: false,
+            force: false,
+            sign: false,
+            date: None,
+            marks: None,
             deletes: BTreeMap::new(),
             files: BTreeMap::new(),
+            raw_paths: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            note: None,
         }
     }
 
@@ -50,12 +442,50 @@ impl Commit {
         self
     }
 
+    /// The current time in git's raw date format with the local UTC offset, or
+    /// `SOURCE_DATE_EPOCH` in UTC when it is set
     pub fn now_when() -> String {
-        let secs: i64 = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_secs() as i64)
-            .unwrap_or(0);
-        format!("{} +0000", secs)
+        if let Some(epoch) = std::env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        {
+            return format!("{} +0000", epoch);
+        }
+        Local::now().format(RAW_DATE).to_string()
+    }
+
+    /// Set the author identity instead of resolving it from the environment or git config
+    pub fn author(mut self, name: impl AsRef<str>, email: impl AsRef<str>) -> Self {
+        self.author = Some((
+            sanitize_identity_part(name.as_ref()),
+            sanitize_identity_part(email.as_ref()),
+            Self::now_when(),
+        ));
+        self
+    }
+
+    /// Set the committer identity instead of resolving it from the environment or the author
+    pub fn committer(mut self, name: impl AsRef<str>, email: impl AsRef<str>) -> Self {
+        self.committer = Some((
+            sanitize_identity_part(name.as_ref()),
+            sanitize_identity_part(email.as_ref()),
+            Self::now_when(),
+        ));
+        self
+    }
+
+    /// Reuse and extend `marks`: contents an earlier import already has are referenced by mark,
+    /// and every other inline file becomes a marked blob. Files streamed from disk and signed
+    /// commits don't use marks.
+    pub fn marks(mut self, marks: Marks) -> Self {
+        self.marks = Some(marks);
+        self
+    }
+
+    /// Override both the author and committer timestamps (in git's raw date format)
+    pub fn date(mut self, when: impl Into<String>) -> Self {
+        self.date = Some(when.into());
+        self
     }
 
     pub fn parent(mut self, commit: impl Into<String>) -> Self {
@@ -63,6 +493,224 @@ impl Commit {
         self
     }
 
+    /// Remove every file inherited from the parent before applying this commit's changes
+    pub fn delete_all(mut self) -> Self {
+        self.delete_all = true;
+        self
+    }
+
+    /// Reset the ref to nothing at the start of the stream, so a commit without a parent
+    /// starts a new history. Updating the ref still needs [`Commit::force`].
+    pub fn reset(mut self) -> Self {
+        self.reset = Some(None);
+        self
+    }
+
+    /// Reset the ref to `rev` at the start of the stream; the commit builds on it unless it has
+    /// a parent of its own
+    pub fn reset_to(mut self, rev: impl Into<String>) -> Self {
+        self.reset = Some(Some(rev.into()));
+        self
+    }
+
+    /// Create (or move) the annotated tag `name` pointing at the new commit, tagged by the
+    /// committer
+    pub fn tag(mut self, name: impl Into<String>, message: impl Into<String>) -> Self {
+        self.tags.insert(name.into(), message.into());
+        self
+    }
+
+    /// Attach `text` to the new commit as a note in refs/notes/commits, replacing any note it
+    /// would have
+    pub fn note(mut self, text: impl Into<String>) -> Self {
+        self.note = Some(text.into());
+        self
+    }
+
+    /// The current tip of refs/notes/commits, which a new notes commit must build on
+    fn notes_tip(&self) -> io::Result<Option<String>> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["rev-parse", "--verify", "--quiet", NOTES_REF])
+            .output()?;
+        let tip = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok((!tip.is_empty()).then_some(tip))
+    }
+
+    /// The commit the new commit builds on, if any
+    fn base(&self) -> Option<&str> {
+        self.from
+            .as_deref()
+            .or(self.reset.as_ref().and_then(|rev| rev.as_deref()))
+    }
+
+    /// Allow the ref to be updated even when the new commit does not descend from it; only
+    /// then is fast-import run with `--force`
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Create a signed commit using the plumbing backend, since fast-import cannot sign
+    pub fn sign(mut self, sign: bool) -> Self {
+        self.sign = sign;
+        self
+    }
+
+    /// The file paths of the tree this commit produces from the parent's file paths `base`
+    pub fn paths(&self, base: impl IntoIterator<Item = String>) -> BTreeSet<String> {
+        base.into_iter()
+            .filter(|path| !self.is_deleted(path))
+            .chain(self.files.keys().cloned())
+            .collect()
+    }
+
+    /// Whether a file at `path` in the parent tree is removed by this commit
+    fn is_deleted(&self, path: &str) -> bool {
+        self.delete_all
+            || self.deletes.keys().any(|d| {
+                path == d || (path.starts_with(d.as_str()) && path[d.len()..].starts_with('/'))
+            })
+    }
+
+    /// Turn this into a commit without history whose tree is the one it would produce on top
+    /// of `base` (as listed by [`ls_tree`]), carrying the untouched files over by blob. The ref
+    /// is reset first; replacing an existing branch needs [`Commit::force`].
+    pub fn squash(self, base: BTreeMap<String, TreeEntry>) -> Self {
+        let mut squashed = self.clone();
+        for (path, entry) in base {
+            if !self.is_deleted(&path) && !self.files.contains_key(&path) {
+                squashed = squashed.add_blob(path, entry.mode, entry.sha);
+            }
+        }
+        squashed.from = None;
+        squashed.deletes.clear();
+        squashed.delete_all().reset()
+    }
+
+    /// Whether this commit adds a file at `path`
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// The contents of the file this commit adds at `path`, if any
+    pub fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self.files.get(path) {
+            None => Ok(None),
+            Some(FileEntry::Blob { sha, .. }) => {
+                Ok(read_blobs(&self.repo_dir, std::slice::from_ref(sha))?.pop())
+            }
+            Some(FileEntry::External { path, .. }) => Ok(Some(read_external(path)?)),
+            Some(entry) => Ok(entry.inline().map(|(_, data)| data.to_vec())),
+        }
+    }
+
+    /// The bytes of file contents this commit holds in memory until it is written, as opposed
+    /// to files streamed from disk or reused by SHA
+    pub fn buffered_bytes(&self) -> u64 {
+        self.files
+            .values()
+            .filter_map(FileEntry::inline)
+            .map(|(_, data)| data.len() as u64)
+            .sum()
+    }
+
+    /// Hand the path and contents of every file this commit adds under `dir` to `f`
+    pub fn for_each_file(
+        &self,
+        dir: &str,
+        mut f: impl FnMut(&str, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let prefix = format!("{}/", dir);
+        let mut blobs = Vec::new();
+        for (path, entry) in self.files.range(prefix.clone()..) {
+            if !path.starts_with(&prefix) {
+                break;
+            }
+            match entry {
+                FileEntry::Blob { sha, .. } => blobs.push((path, sha.clone())),
+                FileEntry::External { path: src, .. } => f(path, &read_external(src)?)?,
+                entry => f(path, entry.inline().map_or(&[], |(_, data)| data))?,
+            }
+        }
+        let shas = blobs.iter().map(|(_, sha)| sha.clone()).collect::<Vec<_>>();
+        for_each_blob(&self.repo_dir, &shas, |i, contents| {
+            f(blobs[i].0, &contents)
+        })
+    }
+
+    /// Compare the blobs committed under `dir` (as listed by [`ls_tree`]) with the files this
+    /// commit adds there, describing every missing, unexpected or different file
+    pub fn compare_tree(
+        &self,
+        committed: &BTreeMap<String, TreeEntry>,
+        dir: &str,
+    ) -> Result<Vec<String>> {
+        let prefix = format!("{}/", dir);
+        let mut problems = Vec::new();
+        let mut inline = Vec::new();
+        let mut external = Vec::new();
+        for (path, entry) in self.files.range(prefix.clone()..) {
+            if !path.starts_with(&prefix) {
+                break;
+            }
+            let mode = entry.mode();
+            let sha = match entry {
+                FileEntry::Blob { sha, .. } => Some(sha),
+                _ => None,
+            };
+            match committed.get(path) {
+                None => problems.push(format!("missing {}", path)),
+                Some(found) if found.mode != mode => problems.push(format!(
+                    "{} has mode {:o} instead of {:o}",
+                    path, found.mode, mode
+                )),
+                Some(found) => match (entry.inline(), sha) {
+                    (_, Some(sha)) if *sha != found.sha => problems.push(format!(
+                        "{} has blob {} instead of {}",
+                        path, found.sha, sha
+                    )),
+                    (Some((_, data)), _) => inline.push((path, data, &found.sha)),
+                    _ => {
+                        if let FileEntry::External { path: src, .. } = entry {
+                            external.push((path, src.clone(), &found.sha));
+                        }
+                    }
+                },
+            }
+        }
+
+        // Files streamed from disk are compared by hashing them again
+        let paths = external
+            .iter()
+            .map(|(_, src, _)| src.clone())
+            .collect::<Vec<_>>();
+        for ((path, _, sha), hashed) in external.iter().zip(hash_objects(&self.repo_dir, &paths)?) {
+            if hashed != **sha {
+                problems.push(format!("{} has blob {} with different contents", path, sha));
+            }
+        }
+
+        // Inline contents are compared byte for byte with the committed blobs
+        let shas = inline
+            .iter()
+            .map(|(_, _, sha)| (*sha).clone())
+            .collect::<Vec<_>>();
+        for ((path, data, sha), blob) in inline.iter().zip(read_blobs(&self.repo_dir, &shas)?) {
+            if blob != *data {
+                problems.push(format!("{} has blob {} with different contents", path, sha));
+            }
+        }
+
+        for path in committed.keys() {
+            if path.starts_with(&prefix) && !self.files.contains_key(path) {
+                problems.push(format!("unexpected file {}", path));
+            }
+        }
+        Ok(problems)
+    }
+
     pub fn delete_path(mut self, path: impl AsRef<str>) -> Self {
         self.deletes.insert(path.as_ref().to_string(), ());
         self
@@ -84,27 +732,146 @@ impl Commit {
         self
     }
 
+    /// Add a symbolic link pointing at `target`
+    pub fn add_symlink(mut self, path: impl AsRef<str>, target: impl AsRef<Path>) -> Self {
+        // Git stores link targets with forward slashes regardless of platform
+        let target = target.as_ref().to_string_lossy().replace('\\', "/");
+        self.files
+            .insert(path.as_ref().to_string(), FileEntry::Symlink { target });
+        self
+    }
+
+    /// Add a file that reuses a blob already present in the repository
+    pub fn add_blob(mut self, path: impl AsRef<str>, mode: u32, sha: impl Into<String>) -> Self {
+        self.files.insert(
+            path.as_ref().to_string(),
+            FileEntry::Blob {
+                mode,
+                sha: sha.into(),
+            },
+        );
+        self
+    }
+
+    /// Add a file that reuses the blob `sha`, checking that it is a full object id; unlike
+    /// [`Commit::add_blob`], which trusts SHAs read back from git
+    pub fn add_blob_ref(self, path: impl AsRef<str>, mode: u32, sha: &str) -> Result<Self> {
+        if !is_object_id(sha) {
+            return Err(ArgumentError::Format {
+                input: sha.to_string(),
+                expected: "a full hexadecimal object id",
+            }
+            .into());
+        }
+        Ok(self.add_blob(path, mode, sha))
+    }
+
+    /// Add every file under `src_path` in `rev` at the same place under `dest_path`, reusing
+    /// their blobs. An empty `src_path` copies the whole tree; one that doesn't exist in `rev`
+    /// adds nothing.
+    pub fn add_tree_from(mut self, rev: &str, src_path: &str, dest_path: &str) -> Result<Self> {
+        let src = src_path.trim_end_matches('/');
+        let dest = dest_path.trim_end_matches('/');
+        for (path, entry) in ls_tree(&self.repo_dir, rev, src)? {
+            let rel = if src.is_empty() {
+                Some(path.as_str())
+            } else if path == src {
+                Some("")
+            } else {
+                path.strip_prefix(src)
+                    .and_then(|rest| rest.strip_prefix('/'))
+            };
+            let Some(rel) = rel else {
+                continue;
+            };
+            let to = match (dest.is_empty(), rel.is_empty()) {
+                (_, true) => dest.to_string(),
+                (true, false) => rel.to_string(),
+                (false, false) => format!("{}/{}", dest, rel),
+            };
+            self = self.add_blob(to, entry.mode, entry.sha);
+        }
+        Ok(self)
+    }
+
+    /// Write the file added at `path` under the byte string `raw` instead, for names that aren't
+    /// UTF-8. Only fast-import can write such names, so signed commits refuse them.
+    pub fn raw_path(mut self, path: impl AsRef<str>, raw: impl Into<Vec<u8>>) -> Self {
+        self.raw_paths.insert(path.as_ref().to_string(), raw.into());
+        self
+    }
+
+    /// A path of this commit as written in the stream
+    fn stream_path<'a>(&'a self, path: &'a str) -> Cow<'a, str> {
+        quote_path(
+            self.raw_paths
+                .get(path)
+                .map_or(path.as_bytes(), Vec::as_slice),
+        )
+    }
+
+    /// Add the file at `src` with its mode on disk. Its contents are streamed from disk when
+    /// the commit is written, so the file must not change until then.
     pub fn add_file(self, dest_path: impl AsRef<str>, src: impl AsRef<Path>) -> Result<Self> {
-        let data = fs::read(src.as_ref()).with_context(|| {
-            format!(
-                "failed to read file for fast-import: {}",
-                src.as_ref().display()
-            )
+        let mode = file_mode(src.as_ref());
+        self.add_file_with_mode(dest_path, mode, src)
+    }
+
+    /// Add the file at `src` with `mode`, streamed like [`Commit::add_file`]
+    pub fn add_file_with_mode(
+        mut self,
+        dest_path: impl AsRef<str>,
+        mode: u32,
+        src: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let path = src.as_ref().to_path_buf();
+        fs::metadata(&path).map_err(|source| DocverError::Io {
+            action: format!("failed to read file for fast-import: {}", path.display()),
+            source,
         })?;
-        Ok(self.add_bytes(dest_path, 0o100644, data))
+        self.files.insert(
+            dest_path.as_ref().to_string(),
+            FileEntry::External { mode, path },
+        );
+        Ok(self)
+    }
+
+    /// Refuse the GIT_AUTHOR_DATE and GIT_COMMITTER_DATE this commit would use when they can't
+    /// be read, like git does, instead of committing with the current time
+    fn check_env_dates(&self) -> Result<()> {
+        if self.date.is_some() {
+            return Ok(());
+        }
+        if self.author.is_none() {
+            env_date("AUTHOR")?;
+        }
+        if self.committer.is_none() {
+            env_date("COMMITTER")?;
+        }
+        Ok(())
     }
 
     fn resolve_author(&self) -> (String, String, String) {
+        let (name, email, when) = self.resolve_author_identity();
+        (name, email, self.date.clone().unwrap_or(when))
+    }
+
+    fn resolve_author_identity(&self) -> (String, String, String) {
         if let Some((n, e, t)) = &self.author {
             return (n.clone(), e.clone(), t.clone());
         }
         let name = get_env_value("AUTHOR", "NAME")
             .or_else(|| get_env_value("COMMITTER", "NAME"))
+            .or_else(|| get_config_value(&self.repo_dir, "user.name"))
             .unwrap_or_else(|| DEFAULT_AUTHOR_NAME.to_string());
         let email = get_env_value("AUTHOR", "EMAIL")
             .or_else(|| get_env_value("COMMITTER", "EMAIL"))
+            .or_else(|| get_config_value(&self.repo_dir, "user.email"))
             .unwrap_or_else(|| DEFAULT_AUTHOR_EMAIL.to_string());
-        let when = get_env_value("AUTHOR", "DATE").unwrap_or_else(Self::now_when);
+        let when = env_date("AUTHOR")
+            .ok()
+            .flatten()
+            .unwrap_or_else(Self::now_when);
         (name, email, when)
     }
 
@@ -113,6 +880,17 @@ impl Commit {
         default_name: &str,
         default_email: &str,
         default_when: &str,
+    ) -> (String, String, String) {
+        let (name, email, when) =
+            self.resolve_committer_identity(default_name, default_email, default_when);
+        (name, email, self.date.clone().unwrap_or(when))
+    }
+
+    fn resolve_committer_identity(
+        &self,
+        default_name: &str,
+        default_email: &str,
+        default_when: &str,
     ) -> (String, String, String) {
         if let Some((n, e, t)) = &self.committer {
             return (n.clone(), e.clone(), t.clone());
@@ -120,12 +898,93 @@ impl Commit {
         let name = get_env_value("COMMITTER", "NAME").unwrap_or_else(|| default_name.to_string());
         let email =
             get_env_value("COMMITTER", "EMAIL").unwrap_or_else(|| default_email.to_string());
-        let when = get_env_value("COMMITTER", "DATE").unwrap_or_else(|| default_when.to_string());
+        let when = env_date("COMMITTER")
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| default_when.to_string());
         (name, email, when)
     }
 
-    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+    /// Assign marks to inline contents that appear under more than one path (or to all of them
+    /// when keeping marks), keyed by the SHA-256 of the data, so each duplicate is only
+    /// streamed once. Contents with a mark from an earlier import are not streamed at all.
+    fn shared_blobs(&self) -> SharedBlobs<'_> {
+        let hashed = self
+            .files
+            .iter()
+            .filter_map(|(path, entry)| {
+                let (_, data) = entry.inline()?;
+                Some((path.as_str(), Sha256::digest(data).into(), data))
+            })
+            .collect::<Vec<_>>();
+
+        let mut counts: HashMap<[u8; 32], usize> = HashMap::new();
+        for (_, hash, _) in &hashed {
+            *counts.entry(*hash).or_default() += 1;
+        }
+
+        let next = self.marks.as_ref().map_or(1, |marks| marks.next);
+        let mut blobs = Vec::new();
+        let mut marks_by_hash: HashMap<[u8; 32], usize> = HashMap::new();
+        let mut marks_by_path = HashMap::new();
+        for (path, hash, data) in hashed {
+            if let Some(marks) = &self.marks
+                && let Some(mark) = marks.by_hash.get(&checksums::hex(&hash))
+            {
+                marks_by_path.insert(path, *mark);
+                continue;
+            }
+            if self.marks.is_none() && counts[&hash] < 2 {
+                continue;
+            }
+            let mark = *marks_by_hash.entry(hash).or_insert_with(|| {
+                blobs.push((next + blobs.len(), hash, data));
+                next + blobs.len() - 1
+            });
+            marks_by_path.insert(path, mark);
+        }
+        SharedBlobs {
+            commit_mark: next + blobs.len(),
+            blobs,
+            marks: marks_by_path,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: W) -> io::Result<()> {
+        self.write_with(w, &self.shared_blobs(), None)
+    }
+
+    fn write_with<W: Write>(
+        &self,
+        mut w: W,
+        shared: &SharedBlobs,
+        progress: Option<&dyn CommitProgress>,
+    ) -> io::Result<()> {
+        let SharedBlobs {
+            blobs,
+            marks,
+            commit_mark,
+        } = shared;
+        if let Some(to) = &self.reset {
+            writeln!(w, "reset {}", self.refname)?;
+            if let Some(rev) = to {
+                writeln!(w, "from {}", rev)?;
+            }
+            writeln!(w)?;
+        }
+        for (mark, _, data) in blobs {
+            writeln!(w, "blob")?;
+            writeln!(w, "mark :{}", mark)?;
+            writeln!(w, "data {}", data.len())?;
+            w.write_all(data)?;
+            writeln!(w)?;
+        }
+
         writeln!(w, "commit {}", self.refname)?;
+        let marked = !self.tags.is_empty() || self.note.is_some();
+        if marked {
+            writeln!(w, "mark :{}", commit_mark)?;
+        }
 
         let (an, ae, at_when) = self.resolve_author();
         let (cn, ce, ct_when) = self.resolve_committer(&an, &ae, &at_when);
@@ -143,80 +1002,1744 @@ impl Commit {
             writeln!(w, "deleteall")?;
         }
 
-        for (path, _) in &self.deletes {
-            writeln!(w, "D {}", path)?;
+        for path in self.deletes.keys() {
+            writeln!(w, "D {}", quote_path(path.as_bytes()))?;
         }
 
+        if let Some(progress) = progress {
+            progress.start(self.files.len() as u64);
+        }
         for (path, entry) in &self.files {
-            match entry {
-                FileEntry::Inline { mode, data } => {
-                    writeln!(w, "M {:06o} inline {}", mode, path)?;
+            if let FileEntry::External { mode, path: src } = entry {
+                let len = fs::metadata(src)?.len();
+                if let Some(progress) = progress {
+                    progress.file(len);
+                }
+                writeln!(w, "M {:06o} inline {}", mode, self.stream_path(path))?;
+                writeln!(w, "data {}", len)?;
+                copy_file(src, len, &mut w)?;
+                writeln!(w)?;
+                continue;
+            }
+            if let Some(progress) = progress {
+                progress.file(entry.inline().map_or(0, |(_, data)| data.len() as u64));
+            }
+            match (entry, entry.inline()) {
+                (FileEntry::Blob { mode, sha }, _) => {
+                    writeln!(w, "M {:06o} {} {}", mode, sha, self.stream_path(path))?;
+                }
+                (_, Some((mode, data))) => {
+                    if let Some(mark) = marks.get(path.as_str()) {
+                        writeln!(w, "M {:06o} :{} {}", mode, mark, self.stream_path(path))?;
+                        continue;
+                    }
+                    writeln!(w, "M {:06o} inline {}", mode, self.stream_path(path))?;
                     writeln!(w, "data {}", data.len())?;
                     w.write_all(data)?;
                     writeln!(w)?;
                 }
+                (_, None) => unreachable!("only blobs and external files lack inline contents"),
+            }
+        }
+
+        for (name, message) in &self.tags {
+            writeln!(w, "tag {}", name)?;
+            writeln!(w, "from :{}", commit_mark)?;
+            writeln!(w, "tagger {}<{}> {}", name_field(&cn), ce, ct_when)?;
+            writeln!(w, "data {}", message.len())?;
+            writeln!(w, "{}", message)?;
+        }
+        if let Some(note) = &self.note {
+            let message = format!("Notes added by {}", env!("CARGO_PKG_NAME"));
+            writeln!(w, "commit {}", NOTES_REF)?;
+            writeln!(w, "committer {}<{}> {}", name_field(&cn), ce, ct_when)?;
+            writeln!(w, "data {}", message.len())?;
+            writeln!(w, "{}", message)?;
+            if let Some(tip) = self.notes_tip()? {
+                writeln!(w, "from {}", tip)?;
             }
+            // End the note with a newline, as `git notes add` does
+            let note = format!("{}\n", note.trim_end_matches('\n'));
+            writeln!(w, "N inline :{}", commit_mark)?;
+            writeln!(w, "data {}", note.len())?;
+            w.write_all(note.as_bytes())?;
         }
 
         writeln!(w, "done")?;
+        if let Some(progress) = progress {
+            progress.finish();
+        }
         Ok(())
     }
 
-    pub fn run(&self) -> Result<()> {
+    /// Import the commit, returning what fast-import wrote; signed commits don't go through
+    /// fast-import and report nothing
+    pub fn run(&self) -> Result<Option<ImportReport>> {
+        self.run_with(None)
+    }
+
+    /// [`Commit::run`], telling `progress` about each file as it is streamed into fast-import
+    pub fn run_with(&self, progress: Option<&dyn CommitProgress>) -> Result<Option<ImportReport>> {
+        self.check_env_dates()?;
+        if self.sign {
+            return self.run_signed().map(|()| None);
+        }
+
+        let marks_args = self.marks.iter().flat_map(|marks| {
+            [
+                format!("--import-marks-if-exists={}", marks.path.display()),
+                format!("--export-marks={}", marks.path.display()),
+            ]
+        });
+        let shared = self.shared_blobs();
+        let bytes_before = self.object_bytes();
         let mut child = Command::new("git")
             .arg("-C")
             .arg(&self.repo_dir)
-            .arg("fast-import")
+            .args(["fast-import", "--stats"])
+            .args(self.force.then_some("--force"))
+            .args(marks_args)
+            // Failures and statistics are recognized by git's untranslated messages
+            .env("LC_ALL", "C")
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
             .spawn()
-            .context("failed to spawn git fast-import")?;
+            .map_err(DocverError::io("failed to spawn git fast-import"))?;
 
         {
             let stdin = child.stdin.take().expect("stdin should be piped");
             let mut bufw = io::BufWriter::new(stdin);
-            self.write_to(&mut bufw)?;
+            if let Err(source) = self
+                .write_with(&mut bufw, &shared, progress)
+                .and_then(|()| bufw.flush())
+            {
+                // Stop git before the stream ends, so it can't import the partial commit
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(DocverError::Io {
+                    action: "failed to stream the commit into git fast-import".to_string(),
+                    source,
+                });
+            }
         }
 
         let output = child
             .wait_with_output()
-            .context("failed to wait on git fast-import")?;
+            .map_err(DocverError::io("failed to wait on git fast-import"))?;
         if !output.status.success() {
+            // The statistics --stats asked for follow the error; they don't explain it
             let stderr = String::from_utf8_lossy(&output.stderr);
-            let stderr_trimmed = stderr.trim();
-
-            // Provide a more readable hint for common non-fast-forward failures
-            if stderr_trimmed.contains("Not updating")
-                && (stderr_trimmed.contains("does not contain")
-                    || stderr_trimmed.contains("non-fast-forward"))
-            {
-                anyhow::bail!(
-                    "git fast-import refused to update {} (non-fast-forward). The new commit must descend from the current branch tip. Hint: base the import on the tip (set a parent) or recreate/reset the branch.\nFull error: {}",
-                    self.refname,
-                    stderr_trimmed
-                );
-            }
-
-            anyhow::bail!("git fast-import failed: {}", stderr_trimmed);
+            let stderr = match stderr.split_once("fast-import statistics:") {
+                Some((error, _)) => error.trim().to_string(),
+                None => stderr.trim().to_string(),
+            };
+            return Err(match ImportFailure::classify(&stderr) {
+                Some(ImportFailure::NonFastForward) => NonFastForward {
+                    refname: self.refname.clone(),
+                    stderr,
+                }
+                .into(),
+                Some(failure) => ImportError {
+                    failure,
+                    refname: self.refname.clone(),
+                    stderr: GitStderr(stderr),
+                }
+                .into(),
+                None => DocverError::git("fast-import", stderr.as_bytes()),
+            });
         }
-        Ok(())
+        if let Some(marks) = &self.marks {
+            marks.save(&shared.blobs)?;
+        }
+        let mut report = ImportReport::parse(&String::from_utf8_lossy(&output.stderr));
+        report.bytes_written = bytes_before
+            .zip(self.object_bytes())
+            .map(|(before, after)| after.saturating_sub(before));
+        Ok(Some(report))
     }
-}
 
-fn name_field(name: &str) -> String {
-    if name.is_empty() {
-        String::new()
-    } else {
-        format!("{} ", name)
+    /// Bytes used by loose and packed objects, per `git count-objects`
+    fn object_bytes(&self) -> Option<u64> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["count-objects", "-v"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let kib = stdout
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .filter(|(key, _)| matches!(*key, "size" | "size-pack"))
+            .map(|(_, value)| value.trim().parse::<u64>().ok())
+            .sum::<Option<u64>>()?;
+        Some(kib * 1024)
     }
-}
 
-fn sanitize_identity_part(s: &str) -> String {
-    s.replace(['<', '>', '\n'], "")
-}
+    /// Create the commit with `hash-object`, `mktree` and `commit-tree -S`, then update the ref
+    /// with the same fast-forward rules fast-import applies
+    fn run_signed(&self) -> Result<()> {
+        if let Some(path) = self.raw_paths.keys().next() {
+            return Err(DocverError::Io {
+                action: "failed to build the signed commit".to_string(),
+                source: io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} isn't UTF-8, which only unsigned commits can store",
+                        path
+                    ),
+                ),
+            });
+        }
+        let mut entries = match (self.base(), self.delete_all) {
+            (Some(from), false) => ls_tree(&self.repo_dir, from, "")?,
+            _ => BTreeMap::new(),
+        };
+        for path in self.deletes.keys() {
+            let dir = format!("{}/", path);
+            entries.retain(|p, _| p != path && !p.starts_with(&dir));
+        }
+
+        let inline = self
+            .files
+            .iter()
+            .filter_map(|(path, entry)| {
+                let (mode, data) = entry.inline()?;
+                Some((path, mode, data))
+            })
+            .collect::<Vec<_>>();
+        let shas = write_blobs(&self.repo_dir, inline.iter().map(|(_, _, data)| *data))?;
+        for ((path, mode, _), sha) in inline.into_iter().zip(shas) {
+            entries.insert(path.clone(), TreeEntry { mode, sha });
+        }
+        let external = self
+            .files
+            .iter()
+            .filter_map(|(path, entry)| match entry {
+                FileEntry::External { mode, path: src } => Some((path, *mode, src.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let paths = external
+            .iter()
+            .map(|(_, _, src)| src.clone())
+            .collect::<Vec<_>>();
+        let shas = hash_objects_with(&self.repo_dir, &paths, true)?;
+        for ((path, mode, _), sha) in external.into_iter().zip(shas) {
+            entries.insert(path.clone(), TreeEntry { mode, sha });
+        }
+        for (path, entry) in &self.files {
+            if let FileEntry::Blob { mode, sha } = entry {
+                entries.insert(
+                    path.clone(),
+                    TreeEntry {
+                        mode: *mode,
+                        sha: sha.clone(),
+                    },
+                );
+            }
+        }
+
+        let tree = make_tree(&self.repo_dir, &entries)?;
+
+        let (an, ae, at_when) = self.resolve_author();
+        let (cn, ce, ct_when) = self.resolve_committer(&an, &ae, &at_when);
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["commit-tree", "-S", tree.as_str(), "-F", "-"])
+            .envs([
+                ("GIT_AUTHOR_NAME", an),
+                ("GIT_AUTHOR_EMAIL", ae),
+                ("GIT_AUTHOR_DATE", at_when),
+                ("GIT_COMMITTER_NAME", cn),
+                ("GIT_COMMITTER_EMAIL", ce),
+                ("GIT_COMMITTER_DATE", ct_when),
+            ]);
+        if let Some(from) = self.base() {
+            command.args(["-p", from]);
+        }
+        let output = run_with_stdin(&mut command, self.message.as_bytes())
+            .map_err(DocverError::io("failed to run git commit-tree"))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(DocverError::Git {
+                command: "commit-tree -S".to_string(),
+                message: format!(
+                    "could not sign the deploy commit: {}\nHint: set user.signingkey (and gpg.format=ssh for SSH keys), and make sure the gpg or ssh agent is running and unlocked.",
+                    stderr.trim()
+                ),
+            });
+        }
+        let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        let current = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["rev-parse", "--verify", "--quiet", self.refname.as_str()])
+            .output()
+            .map_err(DocverError::io("failed to run git rev-parse"))?;
+        let current = String::from_utf8_lossy(&current.stdout).trim().to_string();
+        if !self.force && !current.is_empty() {
+            let descends = Command::new("git")
+                .arg("-C")
+                .arg(&self.repo_dir)
+                .args([
+                    "merge-base",
+                    "--is-ancestor",
+                    current.as_str(),
+                    commit.as_str(),
+                ])
+                .status()
+                .map_err(DocverError::io("failed to run git merge-base"))?
+                .success();
+            if !descends {
+                return Err(NonFastForward {
+                    refname: self.refname.clone(),
+                    stderr: format!("new tip {} does not contain {}", commit, current),
+                }
+                .into());
+            }
+        }
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo_dir)
+            .args(["update-ref", self.refname.as_str(), commit.as_str()])
+            .output()
+            .map_err(DocverError::io("failed to run git update-ref"))?;
+        if !output.status.success() {
+            return Err(DocverError::git("update-ref", &output.stderr));
+        }
+
+        for (name, message) in &self.tags {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&self.repo_dir)
+                .args(["tag", "--force", "-a", name, "-m", message, commit.as_str()])
+                .output()
+                .map_err(DocverError::io("failed to run git tag"))?;
+            if !output.status.success() {
+                return Err(DocverError::git("tag", &output.stderr));
+            }
+        }
+        if let Some(note) = &self.note {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&self.repo_dir)
+                .args(["notes", "add", "--force", "-m", note, commit.as_str()])
+                .output()
+                .map_err(DocverError::io("failed to run git notes"))?;
+            if !output.status.success() {
+                return Err(DocverError::git("notes add", &output.stderr));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a path must be quoted to survive in a fast-import `M` or `D` line: fast-import
+/// reads a path starting with a double quote as quoted, and a line can't hold control
+/// characters. Paths starting or ending with a space are quoted too, so they stay visible.
+fn needs_quoting(path: &str) -> bool {
+    path.starts_with('"')
+        || path.starts_with(' ')
+        || path.ends_with(' ')
+        || path.chars().any(|c| c.is_ascii_control())
+}
+
+/// The path as written in the fast-import stream, C-style quoted when it needs to be. Bytes
+/// that aren't UTF-8 are written as octal escapes, which fast-import turns back into bytes.
+fn quote_path(path: &[u8]) -> Cow<'_, str> {
+    if let Ok(path) = std::str::from_utf8(path)
+        && !needs_quoting(path)
+    {
+        return Cow::Borrowed(path);
+    }
+    let mut quoted = String::with_capacity(path.len() + 2);
+    quoted.push('"');
+    for chunk in path.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03o}", c as u8)),
+                c => quoted.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            quoted.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Whether `sha` is a full SHA-1 or SHA-256 object id in git's lowercase hex
+fn is_object_id(sha: &str) -> bool {
+    matches!(sha.len(), 40 | 64)
+        && sha
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Read a file added with [`Commit::add_file`]
+fn read_external(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|source| DocverError::Io {
+        action: format!("failed to read {}", path.display()),
+        source,
+    })
+}
+
+/// The git mode for the file at `path`: `0o100755` when it is executable (only detectable
+/// on Unix), `0o100644` otherwise
+pub fn file_mode(path: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0) {
+            return 0o100755;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    0o100644
+}
+
+/// List the blobs under `path` (or the whole tree when empty) in `rev`, keyed by their full path in the tree
+pub fn ls_tree(
+    repo_dir: impl AsRef<Path>,
+    rev: &str,
+    path: impl AsRef<str>,
+) -> Result<BTreeMap<String, TreeEntry>> {
+    let output = run_ls_tree(repo_dir.as_ref(), rev, path.as_ref(), false)?;
+    let mut entries = BTreeMap::new();
+    for record in output.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+        // Each record is "<mode> SP <type> SP <sha> TAB <path>"
+        let record = String::from_utf8_lossy(record);
+        let Some((meta, path)) = record.split_once('\t') else {
+            continue;
+        };
+        let mut fields = meta.split(' ');
+        let (Some(mode), Some("blob"), Some(sha)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Ok(mode) = u32::from_str_radix(mode, 8) else {
+            continue;
+        };
+        entries.insert(
+            path.to_string(),
+            TreeEntry {
+                mode,
+                sha: sha.to_string(),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+/// The size of every blob under `path` (or the whole tree when empty) in `rev`, keyed by their
+/// full path in the tree
+pub fn blob_sizes(
+    repo_dir: impl AsRef<Path>,
+    rev: &str,
+    path: impl AsRef<str>,
+) -> Result<BTreeMap<String, u64>> {
+    let output = run_ls_tree(repo_dir.as_ref(), rev, path.as_ref(), true)?;
+    let mut sizes = BTreeMap::new();
+    for record in output.split(|b| *b == 0).filter(|r| !r.is_empty()) {
+        // Each record is "<mode> SP <type> SP <sha> SP+ <size> TAB <path>"
+        let record = String::from_utf8_lossy(record);
+        let Some((meta, path)) = record.split_once('\t') else {
+            continue;
+        };
+        let fields = meta.split_whitespace().collect::<Vec<_>>();
+        if let [_, "blob", _, size] = fields[..]
+            && let Ok(size) = size.parse()
+        {
+            sizes.insert(path.to_string(), size);
+        }
+    }
+    Ok(sizes)
+}
+
+/// Run `git ls-tree -r` (with object sizes when `long`), returning its NUL-separated records
+fn run_ls_tree(repo_dir: &Path, rev: &str, path: &str, long: bool) -> Result<Vec<u8>> {
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["ls-tree", "-r", "-z", rev]);
+    if long {
+        command.arg("-l");
+    }
+    if !path.is_empty() {
+        command.args(["--", path]);
+    }
+    let output = command
+        .output()
+        .map_err(DocverError::io("failed to spawn git ls-tree"))?;
+    if !output.status.success() {
+        return Err(DocverError::git("ls-tree", &output.stderr));
+    }
+    Ok(output.stdout)
+}
+
+/// Read the contents of the given blobs, in order
+pub fn read_blobs(repo_dir: impl AsRef<Path>, shas: &[String]) -> Result<Vec<Vec<u8>>> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir.as_ref())
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(DocverError::io("failed to spawn git cat-file"))?;
+
+    // Feed the requests from another thread so a full stdout pipe cannot deadlock us
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let input = shas
+        .iter()
+        .map(|sha| format!("{}\n", sha))
+        .collect::<String>();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer.join().expect("stdin writer panicked")?;
+    if !output.status.success() {
+        return Err(DocverError::git("cat-file", &output.stderr));
+    }
+
+    // Each response is "<sha> SP <type> SP <size> LF <contents> LF"
+    let truncated = || DocverError::Git {
+        command: "cat-file".to_string(),
+        message: "truncated output".to_string(),
+    };
+    let mut blobs = Vec::with_capacity(shas.len());
+    let mut rest = output.stdout.as_slice();
+    for sha in shas {
+        let header_end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(truncated)?;
+        let header = String::from_utf8_lossy(&rest[..header_end]).to_string();
+        let size = match header.split(' ').collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>().map_err(|_| truncated())?,
+            _ => {
+                return Err(DocverError::Git {
+                    command: "cat-file".to_string(),
+                    message: format!("cannot read blob {}: {}", sha, header),
+                });
+            }
+        };
+        let start = header_end + 1;
+        let contents = rest.get(start..start + size).ok_or_else(truncated)?;
+        blobs.push(contents.to_vec());
+        rest = rest.get(start + size + 1..).unwrap_or_default();
+    }
+    Ok(blobs)
+}
+
+/// Hand the contents of each blob to `f` with its index in `shas`, reading them in batches so
+/// large trees are not held in memory at once
+pub fn for_each_blob(
+    repo_dir: impl AsRef<Path>,
+    shas: &[String],
+    mut f: impl FnMut(usize, Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    const BATCH: usize = 256;
+    for (batch, chunk) in shas.chunks(BATCH).enumerate() {
+        for (i, contents) in read_blobs(repo_dir.as_ref(), chunk)?
+            .into_iter()
+            .enumerate()
+        {
+            f(batch * BATCH + i, contents)?;
+        }
+    }
+    Ok(())
+}
+
+/// Compute the blob SHAs of the given files without writing them to the object database
+pub fn hash_objects(repo_dir: impl AsRef<Path>, paths: &[PathBuf]) -> Result<Vec<String>> {
+    hash_objects_with(repo_dir, paths, false)
+}
+
+fn hash_objects_with(
+    repo_dir: impl AsRef<Path>,
+    paths: &[PathBuf],
+    write: bool,
+) -> Result<Vec<String>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir.as_ref())
+        .args(["hash-object", "--no-filters", "--stdin-paths"])
+        .args(write.then_some("-w"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(DocverError::io("failed to spawn git hash-object"))?;
+
+    {
+        let stdin = child.stdin.take().expect("stdin should be piped");
+        let mut bufw = io::BufWriter::new(stdin);
+        for path in paths {
+            let absolute = fs::canonicalize(path).map_err(|source| DocverError::Io {
+                action: format!("failed to resolve {}", path.display()),
+                source,
+            })?;
+            writeln!(bufw, "{}", absolute.display())?;
+        }
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(DocverError::io("failed to wait on git hash-object"))?;
+    if !output.status.success() {
+        return Err(DocverError::git("hash-object", &output.stderr));
+    }
+
+    let shas = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if shas.len() != paths.len() {
+        return Err(DocverError::Git {
+            command: "hash-object".to_string(),
+            message: format!("returned {} hashes for {} files", shas.len(), paths.len()),
+        });
+    }
+    Ok(shas)
+}
+
+/// Write each blob into the object database, returning their SHAs in order
+fn write_blobs<'a>(
+    repo_dir: impl AsRef<Path>,
+    blobs: impl Iterator<Item = &'a [u8]>,
+) -> Result<Vec<String>> {
+    // hash-object only reads paths in batch mode, so stage the contents in a scratch directory,
+    // one per call so concurrent commits don't share it
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let scratch = std::env::temp_dir().join(format!(
+        "{}-blobs-{}-{}",
+        env!("CARGO_PKG_NAME"),
+        std::process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&scratch).map_err(DocverError::io("failed to create scratch directory"))?;
+    let result = (|| {
+        let mut paths = Vec::new();
+        for (i, data) in blobs.enumerate() {
+            let path = scratch.join(i.to_string());
+            fs::write(&path, data).map_err(|source| DocverError::Io {
+                action: format!("failed to write {}", path.display()),
+                source,
+            })?;
+            paths.push(path);
+        }
+        hash_objects_with(repo_dir, &paths, true)
+    })();
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+/// Streams blobs into the object database through one `git fast-import` as they are produced,
+/// so their contents don't have to be held in memory until the commit referring to them is
+/// written
+pub struct BlobWriter {
+    child: std::process::Child,
+    stdin: Option<io::BufWriter<std::process::ChildStdin>>,
+    marks: PathBuf,
+    count: usize,
+}
+
+impl BlobWriter {
+    pub fn new(repo_dir: impl AsRef<Path>) -> Result<Self> {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let marks = std::env::temp_dir().join(format!(
+            "{}-blob-marks-{}-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id(),
+            CALLS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(repo_dir.as_ref())
+            .arg("fast-import")
+            .arg(format!("--export-marks={}", marks.display()))
+            .env("LC_ALL", "C")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(DocverError::io("failed to spawn git fast-import"))?;
+        let stdin = child.stdin.take().map(io::BufWriter::new);
+        Ok(Self {
+            child,
+            stdin,
+            marks,
+            count: 0,
+        })
+    }
+
+    /// Write `data` as the next blob, returning its index in what [`BlobWriter::finish`]
+    /// returns
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let w = self.stdin.as_mut().expect("stdin is open until finish");
+        self.count += 1;
+        writeln!(w, "blob")?;
+        writeln!(w, "mark :{}", self.count)?;
+        writeln!(w, "data {}", data.len())?;
+        w.write_all(data)?;
+        writeln!(w)?;
+        Ok(self.count - 1)
+    }
+
+    /// Wait for fast-import to store every blob written, returning their SHAs in order
+    pub fn finish(mut self) -> Result<Vec<String>> {
+        let mut stdin = self.stdin.take().expect("stdin is open until finish");
+        writeln!(stdin, "done")?;
+        stdin.flush()?;
+        drop(stdin);
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            io::Read::read_to_end(&mut pipe, &mut stderr)?;
+        }
+        let status = self
+            .child
+            .wait()
+            .map_err(DocverError::io("failed to wait on git fast-import"))?;
+        if !status.success() {
+            return Err(DocverError::git("fast-import", &stderr));
+        }
+
+        let marks = fs::read_to_string(&self.marks).unwrap_or_default();
+        let mut shas = vec![String::new(); self.count];
+        for (mark, sha) in marks.lines().filter_map(|line| line.split_once(' ')) {
+            if let Some(index) = mark
+                .strip_prefix(':')
+                .and_then(|mark| mark.parse::<usize>().ok())
+                .and_then(|mark| mark.checked_sub(1))
+                && index < shas.len()
+            {
+                shas[index] = sha.to_string();
+            }
+        }
+        if shas.iter().any(String::is_empty) {
+            return Err(DocverError::Git {
+                command: "fast-import".to_string(),
+                message: format!("exported marks for fewer than {} blobs", self.count),
+            });
+        }
+        Ok(shas)
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        // Stop a writer that wasn't finished before its stream ends
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+        let _ = fs::remove_file(&self.marks);
+    }
+}
+
+/// Build the tree objects for `entries` bottom-up with `git mktree`, returning the root tree
+fn make_tree(repo_dir: impl AsRef<Path>, entries: &BTreeMap<String, TreeEntry>) -> Result<String> {
+    // Group entries by directory; every ancestor directory gets a (possibly empty) listing
+    let mut dirs: BTreeMap<String, Vec<(String, u32, String)>> = BTreeMap::new();
+    dirs.insert(String::new(), Vec::new());
+    for (path, entry) in entries {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        dirs.entry(dir.to_string()).or_default().push((
+            name.to_string(),
+            entry.mode,
+            entry.sha.clone(),
+        ));
+        let mut ancestor = dir;
+        while !ancestor.is_empty() {
+            ancestor = ancestor.rsplit_once('/').map_or("", |(parent, _)| parent);
+            dirs.entry(ancestor.to_string()).or_default();
+        }
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir.as_ref())
+        .args(["mktree", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(DocverError::io("failed to spawn git mktree"))?;
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let mut stdout = io::BufReader::new(child.stdout.take().expect("stdout should be piped"));
+
+    // Deepest directories first so each subtree's SHA is known before its parent is written
+    let mut order = dirs.keys().cloned().collect::<Vec<_>>();
+    order.sort_by_key(|dir| std::cmp::Reverse(dir.split('/').filter(|c| !c.is_empty()).count()));
+
+    let mut root = String::new();
+    for dir in order {
+        let listing = &dirs[&dir];
+        for (name, mode, sha) in listing {
+            let kind = if *mode == 0o040000 { "tree" } else { "blob" };
+            writeln!(stdin, "{:06o} {} {}\t{}", mode, kind, sha, name)?;
+        }
+        writeln!(stdin)?;
+        stdin.flush()?;
+
+        let mut sha = String::new();
+        io::BufRead::read_line(&mut stdout, &mut sha)
+            .map_err(DocverError::io("failed to read from git mktree"))?;
+        let sha = sha.trim().to_string();
+        if sha.is_empty() {
+            let _ = child.kill();
+            return Err(DocverError::Git {
+                command: "mktree".to_string(),
+                message: format!("no tree was written for {:?}", dir),
+            });
+        }
+
+        if dir.is_empty() {
+            root = sha;
+            continue;
+        }
+        let (parent, name) = dir.rsplit_once('/').unwrap_or(("", &dir));
+        dirs.get_mut(parent)
+            .expect("ancestors are registered")
+            .push((name.to_string(), 0o040000, sha));
+    }
+    drop(stdin);
+    child
+        .wait()
+        .map_err(DocverError::io("failed to wait on git mktree"))?;
+    Ok(root)
+}
+
+fn run_with_stdin(command: &mut Command, input: &[u8]) -> io::Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin should be piped")
+        .write_all(input)?;
+    child.wait_with_output()
+}
+
+/// Parse a `--commit-date` value (RFC 3339 or unix seconds) into git's raw date format
+pub fn parse_date(s: &str) -> anyhow::Result<String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<i64>() {
+        return Ok(format!("{} +0000", secs));
+    }
+    let invalid = || anyhow::anyhow!("invalid date {:?}: expected RFC 3339 or unix seconds", s);
+    let date = DateTime::parse_from_rfc3339(s).map_err(|_| invalid())?;
+    // chrono reads a leap second ("23:59:60") as an overlong nanosecond field
+    if date.nanosecond() >= 1_000_000_000 {
+        return Err(invalid());
+    }
+    Ok(date.format(RAW_DATE).to_string())
+}
+
+/// git's raw date format, e.g. "1700000000 +0200"
+const RAW_DATE: &str = "%s %z";
+
+/// A `GIT_AUTHOR_DATE` or `GIT_COMMITTER_DATE` value in git's raw date format. Values with an
+/// offset are kept as they are; bare unix seconds (optionally "@"-prefixed) get the local
+/// offset, and RFC 3339 dates are converted.
+fn raw_date(value: &str) -> Option<String> {
+    if local_time(value).is_some() {
+        return Some(value.to_string());
+    }
+    match value.trim().trim_start_matches('@').parse::<i64>() {
+        Ok(secs) => Some(Local.timestamp_opt(secs, 0).single().map_or_else(
+            || format!("{} +0000", secs),
+            |date| date.format(RAW_DATE).to_string(),
+        )),
+        Err(_) => parse_date(value).ok(),
+    }
+}
+
+/// The date in GIT_<scope>_DATE, if set, as fast-import reads it
+fn env_date(scope: &str) -> Result<Option<String>> {
+    get_env_value(scope, "DATE")
+        .map(|value| checked_date(scope, &value))
+        .transpose()
+}
+
+fn checked_date(scope: &str, value: &str) -> Result<String> {
+    raw_date(value).ok_or_else(|| {
+        ArgumentError::Format {
+            input: value.to_string(),
+            expected: match scope {
+                "AUTHOR" => "a GIT_AUTHOR_DATE of unix seconds with an offset, or RFC 3339",
+                _ => "a GIT_COMMITTER_DATE of unix seconds with an offset, or RFC 3339",
+            },
+        }
+        .into()
+    })
+}
+
+/// Whether `descendant` contains `ancestor` (or is it). Commits missing from the repository are
+/// an error.
+pub fn is_ancestor(repo_dir: impl AsRef<Path>, ancestor: &str, descendant: &str) -> Result<bool> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir.as_ref())
+        .args(["merge-base", "--is-ancestor", ancestor, descendant])
+        .output()
+        .map_err(DocverError::io("failed to run git merge-base"))?;
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(DocverError::git(
+            format!("merge-base --is-ancestor {} {}", ancestor, descendant),
+            &output.stderr,
+        )),
+    }
+}
+
+/// The unix seconds of a timestamp in git's raw date format
+pub fn seconds_of(when: &str) -> Option<i64> {
+    when.trim().split_once(' ')?.0.parse().ok()
+}
+
+/// The calendar date (YYYY-MM-DD) of a timestamp in git's raw date format, in its own offset
+pub fn date_of(when: &str) -> Option<String> {
+    Some(local_time(when)?.format("%Y-%m-%d").to_string())
+}
+
+/// A timestamp in git's raw date format as strict ISO 8601 in its own offset, like git's `%cI`
+/// (e.g. "2023-11-15T00:13:20+02:00")
+pub fn iso_of(when: &str) -> Option<String> {
+    Some(local_time(when)?.format("%Y-%m-%dT%H:%M:%S%:z").to_string())
+}
+
+/// A timestamp in git's raw date format in its own offset
+fn local_time(when: &str) -> Option<DateTime<FixedOffset>> {
+    let (secs, offset) = when.trim().split_once(' ')?;
+    let secs: i64 = secs.parse().ok()?;
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let digits = offset.trim_start_matches(['+', '-']);
+    let hours: i32 = digits.get(0..2)?.parse().ok()?;
+    let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+
+    let offset = FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))?;
+    Some(DateTime::from_timestamp(secs, 0)?.with_timezone(&offset))
+}
+
+fn name_field(name: &str) -> String {
+    if name.is_empty() {
+        String::new()
+    } else {
+        format!("{} ", name)
+    }
+}
+
+fn sanitize_identity_part(s: &str) -> String {
+    s.replace(['<', '>', '\n'], "")
+}
 
 fn get_env_value(scope: &str, field: &str) -> Option<String> {
     let key = format!("GIT_{}_{}", scope, field);
     std::env::var(key).ok().map(|s| sanitize_identity_part(&s))
 }
+
+fn get_config_value(repo_dir: &Path, key: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["config", "--get", key])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!value.is_empty()).then(|| sanitize_identity_part(&value))
+}
+
+/// Parse an identity in the `Name <email>` format
+pub fn parse_identity(s: &str) -> anyhow::Result<(String, String)> {
+    let invalid = || anyhow::anyhow!("invalid identity {:?}: expected \"Name <email>\"", s);
+    let (name, rest) = s.trim().split_once('<').ok_or_else(invalid)?;
+    let email = rest.strip_suffix('>').ok_or_else(invalid)?;
+    let name = name.trim();
+    if name.is_empty() || email.trim().is_empty() || email.contains(['<', '>']) {
+        return Err(invalid());
+    }
+    Ok((
+        sanitize_identity_part(name),
+        sanitize_identity_part(email.trim()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Scratch, scratch_repo};
+
+    fn commit() -> Commit {
+        let mut commit = Commit::new(".", "refs/heads/gh-pages").message("deploy");
+        let identity = (
+            "Test".to_string(),
+            "test@example.com".to_string(),
+            "1700000000 +0000".to_string(),
+        );
+        commit.author = Some(identity.clone());
+        commit.committer = Some(identity);
+        commit
+    }
+
+    fn stream(commit: &Commit) -> String {
+        let mut out = Vec::new();
+        commit.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn commits_can_move_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Commit>();
+    }
+
+    #[test]
+    fn duplicate_contents_share_a_mark() {
+        let commit = commit()
+            .add_bytes("v1/index.html", 0o100644, "same")
+            .add_bytes("v1/style.css", 0o100644, "unique")
+            .add_bytes("latest/index.html", 0o100644, "same")
+            .add_blob(
+                "v1/logo.png",
+                0o100644,
+                "0123456789abcdef0123456789abcdef01234567",
+            );
+
+        insta::assert_snapshot!(stream(&commit), @r"
+        blob
+        mark :1
+        data 4
+        same
+        commit refs/heads/gh-pages
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        M 100644 :1 latest/index.html
+        M 100644 :1 v1/index.html
+        M 100644 0123456789abcdef0123456789abcdef01234567 v1/logo.png
+        M 100644 inline v1/style.css
+        data 6
+        unique
+        done
+        ");
+    }
+
+    #[test]
+    fn same_input_produces_identical_streams() {
+        let build = || {
+            Commit::new(".", "refs/heads/gh-pages")
+                .message("deploy")
+                .date("1700000000 +0000")
+                .add_bytes("v1/index.html", 0o100644, "index")
+                .add_bytes("versions.json", 0o100644, "[]")
+                .delete_path("v1")
+        };
+
+        assert_eq!(stream(&build()), stream(&build()));
+        assert!(stream(&build()).contains("> 1700000000 +0000\n"));
+    }
+
+    #[test]
+    fn parse_commit_dates() {
+        assert_eq!(parse_date("1700000000").unwrap(), "1700000000 +0000");
+        assert_eq!(
+            parse_date("2023-11-14T22:13:20Z").unwrap(),
+            "1700000000 +0000"
+        );
+        assert_eq!(
+            parse_date("2023-11-15T00:13:20.123+02:00").unwrap(),
+            "1700000000 +0200"
+        );
+        assert_eq!(parse_date("1969-12-31T19:00:00-05:00").unwrap(), "0 -0500");
+        for impossible in [
+            "2023-13-01T00:00:00Z",
+            "2023-00-10T00:00:00Z",
+            "2023-01-00T00:00:00Z",
+            "2023-02-29T00:00:00Z",
+            "2023-02-31T00:00:00Z",
+            "2023-04-31T00:00:00Z",
+            "2023-01-01T24:00:00Z",
+            "2023-01-01T00:60:00Z",
+            "2023-01-01T00:00:60Z",
+            "2016-12-31T23:59:60Z",
+            "2023-01-01T00:00:99Z",
+            "2023-01-01T00:00:00+24:00",
+        ] {
+            assert!(
+                parse_date(impossible).is_err(),
+                "{} was accepted",
+                impossible
+            );
+        }
+        assert_eq!(
+            parse_date("2024-02-29T00:00:00Z").unwrap(),
+            "1709164800 +0000"
+        );
+        assert_eq!(date_of("1700000000 +0000").as_deref(), Some("2023-11-14"));
+        assert_eq!(date_of("1700000000 +0200").as_deref(), Some("2023-11-15"));
+        assert_eq!(date_of("0 -0500").as_deref(), Some("1969-12-31"));
+        assert_eq!(
+            iso_of("1700000000 +0200").as_deref(),
+            Some("2023-11-15T00:13:20+02:00")
+        );
+        assert_eq!(
+            iso_of("0 -0530").as_deref(),
+            Some("1969-12-31T18:30:00-05:30")
+        );
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn dates_carry_a_utc_offset() {
+        assert!(local_time(&Commit::now_when()).is_some());
+
+        let local = Local
+            .timestamp_opt(1_700_000_000, 0)
+            .unwrap()
+            .format("%z")
+            .to_string();
+        assert_eq!(
+            raw_date("1700000000 -0500").as_deref(),
+            Some("1700000000 -0500")
+        );
+        assert_eq!(
+            raw_date("@1700000000"),
+            Some(format!("1700000000 {}", local))
+        );
+        assert_eq!(
+            raw_date("2023-11-15T00:13:20+02:00").as_deref(),
+            Some("1700000000 +0200")
+        );
+        assert_eq!(raw_date("yesterday"), None);
+        insta::assert_snapshot!(
+            checked_date("AUTHOR", "yesterday").unwrap_err(),
+            @r#"invalid value "yesterday": expected a GIT_AUTHOR_DATE of unix seconds with an offset, or RFC 3339"#
+        );
+    }
+
+    #[test]
+    fn paths_apply_deletes_to_base() {
+        let commit = commit()
+            .delete_path("v1")
+            .add_bytes("v1/index.html", 0o100644, "new");
+        let base = ["v1/old.html", "v10/index.html", "versions.json"].map(String::from);
+        assert_eq!(
+            commit.paths(base.clone()),
+            BTreeSet::from(["v1/index.html", "v10/index.html", "versions.json"].map(String::from))
+        );
+        assert_eq!(
+            commit.delete_all().paths(base),
+            BTreeSet::from(["v1/index.html".to_string()])
+        );
+    }
+
+    #[test]
+    fn parse_identities() {
+        assert_eq!(
+            parse_identity("Jane Doe <jane@example.com>").unwrap(),
+            ("Jane Doe".to_string(), "jane@example.com".to_string())
+        );
+        assert!(parse_identity("Jane Doe").is_err());
+        assert!(parse_identity("<jane@example.com>").is_err());
+        assert!(parse_identity("Jane <jane<@example.com>").is_err());
+        assert!(parse_identity("Jane <>").is_err());
+    }
+
+    #[test]
+    fn symlinks_store_their_target() {
+        let commit = commit()
+            .add_symlink("v1/current", "releases/1.0")
+            .add_bytes("v1/index.html", 0o100644, "releases/1.0");
+
+        let stream = stream(&commit);
+        assert!(stream.contains("M 120000 :1 v1/current\n"));
+        assert!(stream.contains("M 100644 :1 v1/index.html\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executable_files_keep_their_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = Scratch::dir("git-mode");
+        let script = dir.join("install.sh");
+        let page = dir.join("index.html");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::write(&page, "<html>").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&page, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let build = || {
+            commit()
+                .add_file("v1/install.sh", &script)
+                .unwrap()
+                .add_file("v1/index.html", &page)
+                .unwrap()
+        };
+        let first = stream(&build());
+        let second = stream(&build());
+
+        assert!(first.contains("M 100755 inline v1/install.sh\n"));
+        assert!(first.contains("M 100644 inline v1/index.html\n"));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn blob_refs_need_full_object_ids() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let commit = commit()
+            .add_bytes("v1/index.html", 0o100644, "<html>")
+            .add_blob_ref("v1/logo.png", 0o100644, sha)
+            .unwrap();
+        insta::assert_snapshot!(stream(&commit), @r"
+        commit refs/heads/gh-pages
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        M 100644 inline v1/index.html
+        data 6
+        <html>
+        M 100644 0123456789abcdef0123456789abcdef01234567 v1/logo.png
+        done
+        ");
+
+        for bad in [
+            "0123abc",
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+            "HEAD",
+        ] {
+            let err = commit
+                .clone()
+                .add_blob_ref("v1/a.png", 0o100644, bad)
+                .unwrap_err();
+            assert!(matches!(err, DocverError::Argument(_)), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn trees_are_copied_by_blob() {
+        let dir = scratch_repo("git-tree");
+        let mut source = commit()
+            .add_bytes("v1/index.html", 0o100644, "<html>")
+            .add_bytes("v1/api/search.js", 0o100755, "search")
+            .add_bytes("v10/index.html", 0o100644, "other");
+        source.repo_dir = dir.to_path_buf();
+        source.run().unwrap();
+
+        let copy = |src, dest| {
+            let mut commit = commit();
+            commit.repo_dir = dir.to_path_buf();
+            let commit = commit.add_tree_from("gh-pages", src, dest).unwrap();
+            commit
+                .files
+                .iter()
+                .map(|(path, entry)| format!("{:o} {}", entry.mode(), path))
+                .collect::<Vec<_>>()
+        };
+        let copies = [
+            copy("v1", "latest"),
+            copy("v1/", ""),
+            copy("v1/index.html", "index.html"),
+        ];
+        insta::assert_debug_snapshot!(copies, @r#"
+        [
+            [
+                "100755 latest/api/search.js",
+                "100644 latest/index.html",
+            ],
+            [
+                "100755 api/search.js",
+                "100644 index.html",
+            ],
+            [
+                "100644 index.html",
+            ],
+        ]
+        "#);
+    }
+
+    #[test]
+    fn marks_skip_contents_imported_before() {
+        let dir = scratch_repo("git-marks");
+        let marks_file = dir.join("marks");
+        let import = |files: &[(&str, &str)], parent: Option<&str>| {
+            let mut commit = commit().marks(Marks::load(&marks_file).unwrap());
+            commit.repo_dir = dir.to_path_buf();
+            for (path, data) in files {
+                commit = commit.add_bytes(*path, 0o100644, *data);
+            }
+            if let Some(parent) = parent {
+                commit = commit.parent(parent);
+            }
+            let text = stream(&commit);
+            commit.run().unwrap();
+            text
+        };
+
+        import(&[("dev/index.html", "<html>"), ("dev/app.js", "v1")], None);
+        let second = import(
+            &[("dev/index.html", "<html>"), ("dev/app.js", "v2")],
+            Some("refs/heads/gh-pages^0"),
+        );
+        let tree = dir.git(&["show", "gh-pages:dev/index.html"]);
+
+        assert_eq!(tree, "<html>");
+        insta::assert_snapshot!(second, @r"
+        blob
+        mark :3
+        data 2
+        v2
+        commit refs/heads/gh-pages
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        from refs/heads/gh-pages^0
+        M 100644 :3 dev/app.js
+        M 100644 :2 dev/index.html
+        done
+        ");
+    }
+
+    #[test]
+    fn files_stream_from_disk() {
+        let dir = Scratch::dir("git-stream");
+        let page = dir.join("index.html");
+        fs::write(&page, "<html>").unwrap();
+
+        let streamed = commit()
+            .add_file_with_mode("v1/index.html", 0o100644, &page)
+            .unwrap();
+        let inline = commit().add_bytes("v1/index.html", 0o100644, b"<html>".to_vec());
+        assert_eq!(streamed.buffered_bytes(), 0);
+        assert_eq!(inline.buffered_bytes(), 6);
+        assert_eq!(stream(&streamed), stream(&inline));
+
+        // The length announced in the data header must be what gets copied
+        let mut out = Vec::new();
+        let err = copy_file(&page, 7, &mut out).unwrap_err();
+        assert!(err.to_string().contains("changed size"));
+        let err = copy_file(&page, 5, &mut out).unwrap_err();
+        assert!(err.to_string().contains("changed size"));
+    }
+
+    #[test]
+    fn blob_writers_store_blobs_in_order() {
+        let repo = scratch_repo("git-blob-writer");
+        let mut writer = BlobWriter::new(&*repo).unwrap();
+        assert_eq!(writer.write(b"first").unwrap(), 0);
+        assert_eq!(writer.write(b"second").unwrap(), 1);
+        let shas = writer.finish().unwrap();
+        let contents = read_blobs(&*repo, &shas).unwrap();
+        assert_eq!(contents, [b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[test]
+    fn resets_come_before_blobs_and_the_commit() {
+        let commit = commit()
+            .reset_to("1".repeat(40))
+            .delete_all()
+            .delete_path("v1")
+            .add_bytes("v2/index.html", 0o100644, "same")
+            .add_bytes("latest/index.html", 0o100644, "same");
+        assert!(!commit.force);
+        insta::assert_snapshot!(stream(&commit), @r"
+        reset refs/heads/gh-pages
+        from 1111111111111111111111111111111111111111
+
+        blob
+        mark :1
+        data 4
+        same
+        commit refs/heads/gh-pages
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        deleteall
+        D v1
+        M 100644 :1 latest/index.html
+        M 100644 :1 v2/index.html
+        done
+        ");
+    }
+
+    #[test]
+    fn tags_and_notes_refer_to_the_commit_mark() {
+        let dir = scratch_repo("git-notes");
+        let deploy = |note: &str, parent: Option<&str>| {
+            let mut commit = commit()
+                .add_bytes("v1/index.html", 0o100644, "same")
+                .add_bytes("latest/index.html", 0o100644, "same")
+                .tag("docs-v1", "Deployed v1")
+                .note(note);
+            commit.repo_dir = dir.to_path_buf();
+            if let Some(parent) = parent {
+                commit = commit.parent(parent);
+            }
+            let text = stream(&commit);
+            commit.run().unwrap();
+            text
+        };
+        let git = |args: &[&str]| dir.git(args);
+
+        let first = deploy("deployed from main", None);
+        let notes_tip = git(&["rev-parse", "refs/notes/commits"]);
+        let second = deploy("deployed again", Some("refs/heads/gh-pages^0"));
+        let tagged = git(&["rev-parse", "docs-v1^{commit}", "gh-pages"]);
+        let notes = [
+            git(&["notes", "show", "gh-pages"]),
+            git(&["notes", "show", "gh-pages^"]),
+        ];
+
+        insta::assert_snapshot!(first, @r"
+        blob
+        mark :1
+        data 4
+        same
+        commit refs/heads/gh-pages
+        mark :2
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        M 100644 :1 latest/index.html
+        M 100644 :1 v1/index.html
+        tag docs-v1
+        from :2
+        tagger Test <test@example.com> 1700000000 +0000
+        data 11
+        Deployed v1
+        commit refs/notes/commits
+        committer Test <test@example.com> 1700000000 +0000
+        data 22
+        Notes added by versite
+        N inline :2
+        data 19
+        deployed from main
+        done
+        ");
+        assert!(second.contains(&format!("from {}", notes_tip)));
+        let tagged = tagged.lines().collect::<Vec<_>>();
+        assert_eq!(tagged[0], tagged[1]);
+        assert_eq!(notes, ["deployed again\n", "deployed from main\n"]);
+    }
+
+    #[test]
+    fn awkward_paths_are_quoted() {
+        let dir = scratch_repo("git-quote");
+        let paths = [
+            "v1/a\"b.html",
+            "v1/\"quoted\".html",
+            "v1/new\nline.txt",
+            "v1/back\\slash\ttab\u{7}.txt",
+            "v1/trailing space .css",
+            " v1/leading.css",
+        ];
+        let mut commit = paths
+            .iter()
+            .fold(commit(), |commit, path| {
+                commit.add_bytes(*path, 0o100644, "x")
+            })
+            .delete_path("old\nname");
+        commit.repo_dir = dir.to_path_buf();
+        let text = stream(&commit);
+        commit.run().unwrap();
+        let tree = ls_tree(&dir, "gh-pages", "").unwrap();
+
+        let lines = text.lines().filter(|line| line.starts_with(['M', 'D']));
+        insta::assert_snapshot!(lines.collect::<Vec<_>>().join("\n"), @r#"
+        D "old\nname"
+        M 100644 :1 " v1/leading.css"
+        M 100644 :1 v1/"quoted".html
+        M 100644 :1 v1/a"b.html
+        M 100644 :1 "v1/back\\slash\ttab\007.txt"
+        M 100644 :1 "v1/new\nline.txt"
+        M 100644 :1 v1/trailing space .css
+        "#);
+        assert_eq!(
+            tree.keys().collect::<BTreeSet<_>>(),
+            paths
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<BTreeSet<_>>()
+                .iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn raw_paths_keep_their_bytes() {
+        let dir = scratch_repo("git-raw");
+        let mut commit = commit()
+            .add_bytes("v1/caf\u{fffd}.html", 0o100644, "x")
+            .raw_path("v1/caf\u{fffd}.html", b"v1/caf\xe9.html".to_vec())
+            .add_bytes("v1/café.html", 0o100644, "y");
+        commit.repo_dir = dir.to_path_buf();
+        let text = stream(&commit);
+        commit.run().unwrap();
+        let names = Command::new("git")
+            .arg("-C")
+            .arg(&*dir)
+            .args(["ls-tree", "-r", "-z", "--name-only", "gh-pages"])
+            .output()
+            .unwrap()
+            .stdout;
+
+        let lines = text.lines().filter(|line| line.starts_with('M'));
+        insta::assert_snapshot!(lines.collect::<Vec<_>>().join("\n"), @r#"
+        M 100644 inline v1/café.html
+        M 100644 inline "v1/caf\351.html"
+        "#);
+        assert_eq!(names, b"v1/caf\xc3\xa9.html\0v1/caf\xe9.html\0");
+    }
+
+    #[test]
+    fn squash_keeps_the_untouched_tree() {
+        let entry = |sha: &str| TreeEntry {
+            mode: 0o100644,
+            sha: sha.repeat(40),
+        };
+        let base = BTreeMap::from([
+            ("v1/old.html".to_string(), entry("1")),
+            ("v2/index.html".to_string(), entry("2")),
+            ("v3/index.html".to_string(), entry("3")),
+            ("versions.json".to_string(), entry("4")),
+        ]);
+        let commit = commit()
+            .parent("5".repeat(40))
+            .delete_path("v1")
+            .delete_path("v3")
+            .add_bytes("v1/index.html", 0o100644, "new")
+            .add_bytes("versions.json", 0o100644, "[]");
+
+        insta::assert_snapshot!(stream(&commit.squash(base)), @r"
+        reset refs/heads/gh-pages
+
+        commit refs/heads/gh-pages
+        author Test <test@example.com> 1700000000 +0000
+        committer Test <test@example.com> 1700000000 +0000
+        data 6
+        deploy
+        deleteall
+        M 100644 inline v1/index.html
+        data 3
+        new
+        M 100644 2222222222222222222222222222222222222222 v2/index.html
+        M 100644 inline versions.json
+        data 2
+        []
+        done
+        ");
+    }
+
+    #[test]
+    fn unique_contents_stay_inline() {
+        let commit = commit()
+            .add_bytes("a.txt", 0o100644, "a")
+            .add_bytes("b.txt", 0o100644, "b");
+
+        let stream = stream(&commit);
+        assert!(!stream.contains("mark"));
+        assert_eq!(stream.matches(" inline ").count(), 2);
+    }
+
+    #[test]
+    fn import_statistics_are_parsed() {
+        let dir = scratch_repo("git-stats");
+        let mut commit = commit()
+            .add_bytes("v1/index.html", 0o100644, "<html>")
+            .add_bytes("v1/app.js", 0o100644, "app")
+            .add_bytes("latest/index.html", 0o100644, "<html>");
+        commit.repo_dir = dir.to_path_buf();
+        let report = commit.run().unwrap().unwrap();
+
+        assert!(report.bytes_written.is_some_and(|bytes| bytes > 0));
+        insta::assert_debug_snapshot!(
+            (report.blobs, report.trees, report.commits, report.unparsed),
+            @r"
+        (
+            ObjectCount {
+                new: 2,
+                duplicates: 0,
+            },
+            ObjectCount {
+                new: 3,
+                duplicates: 0,
+            },
+            ObjectCount {
+                new: 1,
+                duplicates: 0,
+            },
+            None,
+        )
+        "
+        );
+        let garbled = ImportReport::parse("fast-import statistics:\nTotal objects: lots\n");
+        assert_eq!(garbled.blobs, ObjectCount::default());
+        insta::assert_debug_snapshot!(garbled.unparsed, @r#"
+        Some(
+            "fast-import statistics:\nTotal objects: lots",
+        )
+        "#);
+    }
+
+    #[test]
+    fn import_failures_are_classified() {
+        let samples = [
+            "error: Not updating refs/heads/gh-pages (new tip abc does not contain def)",
+            "fatal: Not a valid commit: 1234567890123456789012345678901234567890\nfast-import: dumping crash report to .git/fast_import_crash_1",
+            "fatal: Invalid ref name or SHA1 expression: origin/gh-pages",
+            "fatal: cannot create packfile: No space left on device",
+            "error: unable to write file .git/objects/pack/tmp_pack_x: Disk quota exceeded",
+            "fatal: Unsupported command: bogus\nfast-import: dumping crash report to .git/fast_import_crash_2",
+            "fatal: Missing space after source: M 100644 :1",
+            "error: cannot lock ref 'refs/heads/gh-pages': Unable to create '/repo/.git/refs/heads/gh-pages.lock': File exists.",
+            "fatal: something else entirely",
+        ]
+        .map(ImportFailure::classify);
+        insta::assert_debug_snapshot!(samples, @r"
+        [
+            Some(
+                NonFastForward,
+            ),
+            Some(
+                MissingObject,
+            ),
+            Some(
+                MissingObject,
+            ),
+            Some(
+                WriteFailed,
+            ),
+            Some(
+                WriteFailed,
+            ),
+            Some(
+                MalformedStream,
+            ),
+            Some(
+                MalformedStream,
+            ),
+            Some(
+                RefLocked,
+            ),
+            None,
+        ]
+        ");
+    }
+
+    #[test]
+    fn trees_are_built_bottom_up() {
+        let dir = scratch_repo("git-mktree");
+        let shas = write_blobs(&*dir, [&b"<html>"[..], b"search", b""].into_iter()).unwrap();
+        let entries = ["v1/index.html", "v1/api/deep/search.js", "empty"]
+            .into_iter()
+            .zip(shas)
+            .map(|(path, sha)| {
+                (
+                    path.to_string(),
+                    TreeEntry {
+                        mode: 0o100644,
+                        sha,
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        let tree = make_tree(&*dir, &entries).unwrap();
+
+        let mut imported = commit()
+            .add_bytes("v1/index.html", 0o100644, "<html>")
+            .add_bytes("v1/api/deep/search.js", 0o100644, "search")
+            .add_bytes("empty", 0o100644, "");
+        imported.repo_dir = dir.to_path_buf();
+        imported.run().unwrap();
+
+        assert_eq!(tree, dir.git(&["rev-parse", "gh-pages^{tree}"]).trim());
+        insta::assert_snapshot!(dir.git(&["ls-tree", "-r", "-t", "--name-only", &tree]), @r"
+        empty
+        v1
+        v1/api
+        v1/api/deep
+        v1/api/deep/search.js
+        v1/index.html
+        ");
+    }
+
+    #[test]
+    fn signed_commits_carry_a_signature() {
+        let dir = scratch_repo("git-signed");
+        dir.git(&["config", "gpg.format", "ssh"]);
+        let signed = |commit: Commit| {
+            let mut commit = commit.sign(true);
+            commit.repo_dir = dir.to_path_buf();
+            commit.run()
+        };
+
+        let unsigned = signed(commit().add_bytes("v1/index.html", 0o100644, "<html>"));
+        let key = dir.join("key");
+        let keygen = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(keygen.success());
+        dir.git(&["config", "user.signingkey", key.to_str().unwrap()]);
+        signed(
+            commit()
+                .add_bytes("v1/index.html", 0o100644, "<html>")
+                .add_bytes("v1/app.js", 0o100644, "app")
+                .add_bytes("v2/index.html", 0o100644, "<html>"),
+        )
+        .unwrap();
+        signed(
+            commit()
+                .parent("refs/heads/gh-pages")
+                .delete_path("v1")
+                .add_bytes("v3/index.html", 0o100644, "new"),
+        )
+        .unwrap();
+        let diverged = signed(commit().add_bytes("v4/index.html", 0o100644, "other"));
+
+        let raw = dir.git(&["cat-file", "commit", "gh-pages"]);
+        assert!(raw.contains("\ngpgsig -----BEGIN SSH SIGNATURE-----"));
+        assert_eq!(dir.git(&["rev-list", "--count", "gh-pages"]), "2\n");
+        insta::assert_snapshot!(dir.git(&["ls-tree", "-r", "--name-only", "gh-pages"]), @r"
+        v2/index.html
+        v3/index.html
+        ");
+        let unsigned = unsigned.unwrap_err().to_string();
+        assert!(unsigned.contains("could not sign the deploy commit"));
+        assert!(unsigned.contains("Hint: set user.signingkey"));
+        assert!(matches!(diverged, Err(DocverError::NonFastForward(_))));
+    }
+}
@@ -0,0 +1,308 @@
+//! Rewrites of deployed HTML pages, made with lol_html's streaming rewriter
+
+use std::{cell::Cell, rc::Rc};
+
+use lol_html::{HtmlRewriter, Settings, element, end_tag, html_content::ContentType};
+
+use crate::template;
+
+/// Banner injected into the pages of outdated versions unless a template is given
+pub const DEFAULT_BANNER: &str = concat!(
+    "<div class=\"versite-banner\" style=\"padding:0.75em 1em;background:#fff3cd;color:#664d03;",
+    "border-bottom:1px solid #ffe69c;text-align:center\">",
+    "You are viewing the documentation for {version}. ",
+    "The latest version is <a href=\"{latest_url}\">{latest}</a>.</div>"
+);
+
+/// Expand the `{version}`, `{latest}` and `{latest_url}` placeholders of a banner template
+pub fn render_banner(
+    banner_template: &str,
+    version: &str,
+    latest: &str,
+    latest_url: &str,
+) -> anyhow::Result<String> {
+    template::render(banner_template, |name| match name {
+        "version" => Some(version.to_string()),
+        "latest" => Some(latest.to_string()),
+        "latest_url" => Some(latest_url.to_string()),
+        _ => None,
+    })
+}
+
+/// Rewrites applied to the HTML pages of a version as they are deployed
+#[derive(Debug, Default)]
+pub struct Rewrites {
+    /// URL of the directory holding the canonical copies of the pages
+    pub canonical_root: Option<String>,
+    pub keep_existing_canonical: bool,
+    /// Rendered banner to inject after `<body>`
+    pub banner: Option<String>,
+}
+
+impl Rewrites {
+    /// Whether any rewrite is enabled, in which case pages never match their source files
+    pub fn is_active(&self) -> bool {
+        self.canonical_root.is_some() || self.banner.is_some()
+    }
+
+    /// Rewrite the page at `page` (relative to the version directory) if it is HTML
+    pub fn apply(&self, page: &str, mut data: Vec<u8>) -> Vec<u8> {
+        if !is_html(page) {
+            return data;
+        }
+        if let Some(root) = &self.canonical_root {
+            let href = format!("{}/{}", root, page);
+            data = set_canonical(&data, &href, self.keep_existing_canonical);
+        }
+        if let Some(banner) = &self.banner {
+            data = set_banner(&data, banner);
+        }
+        data
+    }
+}
+
+/// Whether a deployed file is an HTML page
+pub fn is_html(path: &str) -> bool {
+    path.ends_with(".html") || path.ends_with(".htm")
+}
+
+/// Point the page's canonical link at `href`, replacing any existing canonical links. With
+/// `keep_existing`, a page whose canonical link points elsewhere is left untouched.
+pub fn set_canonical(html: &[u8], href: &str, keep_existing: bool) -> Vec<u8> {
+    let foreign = Cell::new(false);
+    let removed = rewrite(
+        html,
+        Settings::new().append_element_content_handler(element!("link[rel]", |el| {
+            let canonical = el.get_attribute("rel").is_some_and(|rel| {
+                rel.split_ascii_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("canonical"))
+            });
+            if canonical {
+                if keep_existing && el.get_attribute("href").is_some_and(|h| h != href) {
+                    foreign.set(true);
+                }
+                el.remove();
+            }
+            Ok(())
+        })),
+    );
+    let Some(removed) = removed.filter(|_| !foreign.get()) else {
+        return html.to_vec();
+    };
+
+    let link = format!(
+        "<link rel=\"canonical\" href=\"{}\">",
+        escape_attribute(href)
+    );
+    // The link goes at the end of the head, or at the start of the document without one
+    insert(
+        &removed,
+        &link,
+        &[
+            Place::BeforeEnd("head"),
+            Place::BeforeStart("body"),
+            Place::AfterStart("head"),
+        ],
+    )
+}
+
+/// Comments delimiting an injected banner, so re-injecting replaces it
+const BANNER_START: &str = "<!-- versite-banner -->";
+const BANNER_END: &str = "<!-- /versite-banner -->";
+
+/// Insert `banner` right after the page's `<body>` tag, replacing a previously injected banner
+pub fn set_banner(html: &[u8], banner: &str) -> Vec<u8> {
+    let html = remove_banner(html);
+    let banner = format!("{}{}{}", BANNER_START, banner, BANNER_END);
+    // Pages without a body tag get the banner after the head, or at the start
+    insert(
+        &html,
+        &banner,
+        &[Place::AfterStart("body"), Place::AfterEnd("head")],
+    )
+}
+
+fn remove_banner(html: &[u8]) -> Vec<u8> {
+    let find = |needle: &str, from: usize| {
+        html[from..]
+            .windows(needle.len())
+            .position(|w| w == needle.as_bytes())
+            .map(|i| from + i)
+    };
+    match find(BANNER_START, 0).and_then(|start| Some((start, find(BANNER_END, start)?))) {
+        Some((start, end)) => [&html[..start], &html[end + BANNER_END.len()..]].concat(),
+        None => html.to_vec(),
+    }
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+/// Where [`insert`] can put content, relative to the start or end tag of the first element
+/// with the given name
+#[derive(Debug, Clone, Copy)]
+enum Place {
+    BeforeStart(&'static str),
+    AfterStart(&'static str),
+    BeforeEnd(&'static str),
+    AfterEnd(&'static str),
+}
+
+/// Insert the markup `content` at the first of `places` the page has, or at its start when it
+/// has none of them
+fn insert(html: &[u8], content: &str, places: &[Place]) -> Vec<u8> {
+    for &place in places {
+        let (Place::BeforeStart(name)
+        | Place::AfterStart(name)
+        | Place::BeforeEnd(name)
+        | Place::AfterEnd(name)) = place;
+        let inserted = Rc::new(Cell::new(false));
+        let output = rewrite(
+            html,
+            Settings::new().append_element_content_handler(element!(name, |el| {
+                if inserted.get() {
+                    return Ok(());
+                }
+                match place {
+                    Place::BeforeStart(_) => el.before(content, ContentType::Html),
+                    Place::AfterStart(_) => el.prepend(content, ContentType::Html),
+                    Place::BeforeEnd(_) | Place::AfterEnd(_) => {
+                        // Only called when the end tag is actually in the page
+                        let (content, inserted) = (content.to_string(), inserted.clone());
+                        if let Some(handlers) = el.end_tag_handlers() {
+                            handlers.push(end_tag!(move |end| {
+                                if !inserted.replace(true) {
+                                    match place {
+                                        Place::BeforeEnd(_) => {
+                                            end.before(&content, ContentType::Html)
+                                        }
+                                        _ => end.after(&content, ContentType::Html),
+                                    }
+                                }
+                                Ok(())
+                            }));
+                        }
+                        return Ok(());
+                    }
+                }
+                inserted.set(true);
+                Ok(())
+            })),
+        );
+        match output {
+            Some(output) if inserted.get() => return output,
+            Some(_) => {}
+            // Leave pages the rewriter can't parse as they are
+            None => return html.to_vec(),
+        }
+    }
+    [content.as_bytes(), html].concat()
+}
+
+/// Run `html` through a streaming rewriter with the handlers of `settings`. The rewriter
+/// follows the HTML syntax, so markup in comments, raw text elements such as `<script>` and
+/// attribute values is never mistaken for tags. None when the page can't be rewritten.
+fn rewrite(html: &[u8], settings: Settings) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(html.len() + 256);
+    let mut rewriter = HtmlRewriter::new(settings.with_strict(false), |chunk: &[u8]| {
+        output.extend_from_slice(chunk)
+    });
+    rewriter.write(html).ok()?;
+    rewriter.end().ok()?;
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canonical(html: &str, keep_existing: bool) -> String {
+        let result = set_canonical(
+            html.as_bytes(),
+            "https://d.example/latest/a.html",
+            keep_existing,
+        );
+        String::from_utf8(result).unwrap()
+    }
+
+    #[test]
+    fn inserts_canonical_link() {
+        assert_eq!(
+            canonical(
+                "<html><head><title>A</head></title></head><body></body></html>",
+                false
+            ),
+            "<html><head><title>A</head></title><link rel=\"canonical\" href=\"https://d.example/latest/a.html\"></head><body></body></html>"
+        );
+        // Without a closing head tag the link goes before the body
+        assert_eq!(
+            canonical(
+                "<!doctype html><!-- <head> --><HEAD><meta charset=utf-8><BODY>x",
+                false
+            ),
+            "<!doctype html><!-- <head> --><HEAD><meta charset=utf-8><link rel=\"canonical\" href=\"https://d.example/latest/a.html\"><BODY>x"
+        );
+        assert_eq!(
+            canonical("plain", false),
+            "<link rel=\"canonical\" href=\"https://d.example/latest/a.html\">plain"
+        );
+    }
+
+    #[test]
+    fn injects_banner_once() {
+        let page = b"<html><head><title><body></title></head><body class=\"x\">hi</body></html>";
+        let once = set_banner(page, "<div>old</div>");
+        assert_eq!(
+            String::from_utf8_lossy(&once),
+            "<html><head><title><body></title></head><body class=\"x\"><!-- versite-banner --><div>old</div><!-- /versite-banner -->hi</body></html>"
+        );
+        assert_eq!(set_banner(&once, "<div>old</div>"), once);
+        assert_eq!(
+            String::from_utf8_lossy(&set_banner(&once, "<p>new</p>")),
+            "<html><head><title><body></title></head><body class=\"x\"><!-- versite-banner --><p>new</p><!-- /versite-banner -->hi</body></html>"
+        );
+    }
+
+    #[test]
+    fn ignores_markup_in_comments_scripts_and_attributes() {
+        let page = concat!(
+            "<head><script>document.write('<body>')</script>",
+            "<!-- <link rel=\"canonical\" href=\"https://other.example/\"> --></head>",
+            "<body data-note=\"<body>\">hi</body>"
+        );
+        assert_eq!(
+            canonical(page, true),
+            concat!(
+                "<head><script>document.write('<body>')</script>",
+                "<!-- <link rel=\"canonical\" href=\"https://other.example/\"> -->",
+                "<link rel=\"canonical\" href=\"https://d.example/latest/a.html\"></head>",
+                "<body data-note=\"<body>\">hi</body>"
+            )
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&set_banner(page.as_bytes(), "<p>b</p>")),
+            concat!(
+                "<head><script>document.write('<body>')</script>",
+                "<!-- <link rel=\"canonical\" href=\"https://other.example/\"> --></head>",
+                "<body data-note=\"<body>\"><!-- versite-banner --><p>b</p><!-- /versite-banner -->hi</body>"
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_or_keeps_existing_canonical() {
+        let page = "<head><link href='https://other.example/?a=1&b=2' REL=\"Canonical\" /></head>";
+        assert_eq!(
+            canonical(page, false),
+            "<head><link rel=\"canonical\" href=\"https://d.example/latest/a.html\"></head>"
+        );
+        assert_eq!(canonical(page, true), page);
+
+        let ours = "<head><link rel=canonical href=https://d.example/latest/a.html></head>";
+        assert_eq!(
+            canonical(ours, true),
+            "<head><link rel=\"canonical\" href=\"https://d.example/latest/a.html\"></head>"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Versite: versioned static site deployments to a Git branch
+//!
+//! The `versite` binary is a thin command line interface over this library. Tools that drive
+//! deployments themselves can call [`deploy`] and [`list`] instead of running the binary and
+//! parsing its output.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::Args;
+use git_cmd::git_in_dir;
+
+mod api;
+mod backend;
+mod checksums;
+mod cli;
+mod commands;
+mod compress;
+mod error;
+mod git;
+mod html;
+mod progress;
+mod project;
+mod reader;
+mod search;
+mod sitemap;
+mod template;
+#[cfg(test)]
+mod test_support;
+pub mod versions;
+
+pub use api::{BranchOptions, DeployOptions, DeployReport, Error, ListOptions, deploy, list};
+pub use backend::Backend;
+pub use cli::run;
+pub use error::DocverError;
+pub use git::{Commit, GitStderr, ImportError, ImportFailure, ImportReport, Marks, ObjectCount};
+pub use versions::{Version, Versions};
+
+#[derive(Debug, Args)]
+#[command(next_help_heading = "Global Options")]
+pub struct GitArgs {
+    /// Git remote (name or URL) to publish to; repeat to also push to mirrors, the first
+    /// one is fetched from
+    #[arg(short, long = "remote", default_value = "origin", global = true)]
+    remotes: Vec<String>,
+
+    /// Git branch to publish to (e.g. "gh-pages")
+    #[arg(short, long, default_value = "gh-pages", global = true)]
+    branch: String,
+
+    /// Commit message to use for the deployment (defaults to an auto-generated message)
+    #[arg(short, long, global = true)]
+    message: Option<String>,
+
+    /// Push the commit after creating it
+    #[arg(short, long, global = true)]
+    push: bool,
+
+    /// Force-push the branch, refusing to overwrite commits that landed after it was fetched
+    #[arg(long, global = true)]
+    force_push: bool,
+
+    /// Sign the commit with the configured GPG or SSH key (uses `git commit-tree -S`)
+    #[arg(long, global = true)]
+    sign: bool,
+
+    /// Author of the commit as "Name <email>" (defaults to GIT_AUTHOR_*, then git config user.*)
+    #[arg(long, global = true, value_parser = git::parse_identity, value_name = "NAME <EMAIL>")]
+    author: Option<(String, String)>,
+
+    /// Committer of the commit as "Name <email>" (defaults to GIT_COMMITTER_*, then the author)
+    #[arg(long, global = true, value_parser = git::parse_identity, value_name = "NAME <EMAIL>")]
+    committer: Option<(String, String)>,
+
+    /// Timestamp for the commit's author and committer, as RFC 3339 or unix seconds
+    /// (defaults to SOURCE_DATE_EPOCH when set, otherwise the current time)
+    #[arg(long, global = true, value_parser = git::parse_date)]
+    commit_date: Option<String>,
+
+    /// Use the publish branch as last fetched instead of fetching it (works offline, but may be
+    /// stale)
+    #[arg(long, alias = "offline", global = true)]
+    no_fetch: bool,
+
+    /// Do not show progress bars
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Optional prefix directory under which to place deployed files
+    #[arg(long, global = true, value_parser = parse_deploy_prefix)]
+    deploy_prefix: Option<PathBuf>,
+
+    /// Repository to publish from (defaults to the one containing the current directory)
+    #[arg(short = 'C', long = "repo", value_name = "PATH", global = true)]
+    repo: Option<PathBuf>,
+
+    /// Git directory to publish from, e.g. a bare mirror; nothing is read from a worktree
+    #[arg(long, value_name = "PATH", global = true, conflicts_with = "repo")]
+    git_dir: Option<PathBuf>,
+
+    /// The toplevel of the repository, or its git directory when it has no worktree, found by
+    /// [`GitArgs::discover`]
+    #[arg(skip)]
+    repo_dir: String,
+
+    /// Whether the repository is used without a worktree
+    #[arg(skip)]
+    bare: bool,
+}
+
+/// Normalize a deploy prefix into a relative tree path, rejecting anything that escapes the root
+fn parse_deploy_prefix(s: &str) -> anyhow::Result<PathBuf> {
+    let normalized = s.replace('\\', "/");
+    if normalized.as_bytes().get(1) == Some(&b':') {
+        anyhow::bail!(
+            "invalid deploy prefix {:?}: must be a path inside the branch",
+            s
+        );
+    }
+
+    if normalized.chars().any(char::is_control) {
+        anyhow::bail!(
+            "invalid deploy prefix {:?}: control characters are not allowed",
+            s
+        );
+    }
+
+    let mut prefix = PathBuf::new();
+    for component in normalized.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => anyhow::bail!("invalid deploy prefix {:?}: '..' is not allowed", s),
+            part if part.len() > versions::MAX_NAME_LEN => anyhow::bail!(
+                "invalid deploy prefix {:?}: directory names can't be longer than {} bytes",
+                s,
+                versions::MAX_NAME_LEN
+            ),
+            part => prefix.push(part),
+        }
+    }
+    Ok(prefix)
+}
+
+impl GitArgs {
+    /// Find the repository for every git command to run in: the toplevel of the one given with
+    /// --repo or containing the current directory, or the git directory of a bare repository
+    /// or of --git-dir
+    pub fn discover(mut self) -> Result<Self, DocverError> {
+        let start = self
+            .git_dir
+            .clone()
+            .or_else(|| self.repo.clone())
+            .unwrap_or_else(|| PathBuf::from("."));
+        let not_a_repository = |message: String| DocverError::NotARepository {
+            path: start.display().to_string(),
+            message,
+        };
+        let rev_parse = |args: &[&str]| {
+            let mut command = Command::new("git");
+            match &self.git_dir {
+                Some(git_dir) => command.arg("--git-dir").arg(git_dir),
+                None => command.arg("-C").arg(&start),
+            };
+            let output = command
+                .arg("rev-parse")
+                .args(args)
+                .output()
+                .map_err(DocverError::io("failed to run git rev-parse"))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(not_a_repository(stderr.trim().to_string()));
+            }
+            String::from_utf8(output.stdout)
+                .map_err(|_| not_a_repository("its path isn't valid UTF-8".to_string()))
+        };
+
+        let found = rev_parse(&["--is-bare-repository", "--absolute-git-dir"])?;
+        let (is_bare, git_dir) = found.split_once('\n').unwrap_or_default();
+        self.bare = self.git_dir.is_some() || is_bare == "true";
+        self.repo_dir = if self.bare {
+            git_dir.trim_end_matches('\n').to_string()
+        } else {
+            rev_parse(&["--show-toplevel"])?
+                .trim_end_matches('\n')
+                .to_string()
+        };
+        Ok(self)
+    }
+
+    /// The repository git commands run in: its toplevel (or git directory, without a worktree)
+    /// once discovered, otherwise the current directory
+    pub fn repo_dir(&self) -> &str {
+        if self.repo_dir.is_empty() {
+            "."
+        } else {
+            &self.repo_dir
+        }
+    }
+
+    /// The repository, read and written through the backend chosen with --backend
+    pub fn backend(&self) -> Box<dyn Backend> {
+        backend::open(self.repo_dir())
+    }
+
+    /// The worktree of the repository, unless it is bare or given with --git-dir
+    pub fn worktree(&self) -> Option<&Path> {
+        (!self.bare).then(|| Path::new(self.repo_dir()))
+    }
+
+    /// The remote that is fetched from and whose branch the deploy is based on
+    pub fn remote(&self) -> &str {
+        &self.remotes[0]
+    }
+
+    /// The remote-tracking ref for the publish branch of the primary remote
+    pub fn remote_rev(&self) -> String {
+        if self.is_named_remote() {
+            format!("{}/{}", self.remote(), self.branch)
+        } else {
+            // URLs have no remote-tracking refs of their own, so fetch into a private one
+            format!("refs/remotes/{}/{}", env!("CARGO_PKG_NAME"), self.branch)
+        }
+    }
+
+    /// Fetch the publish branch from the primary remote into [`GitArgs::remote_rev`]. With
+    /// --no-fetch the existing ref is used as is.
+    pub fn fetch(&self) -> Result<(), DocverError> {
+        if self.no_fetch {
+            let rev = self.remote_rev();
+            if git_in_dir(
+                self.repo_dir().into(),
+                &["rev-parse", "--verify", "--quiet", rev.as_str()],
+            )
+            .is_err()
+            {
+                return Err(DocverError::MissingBranch {
+                    rev,
+                    no_fetch: true,
+                });
+            }
+            eprintln!(
+                "warning: --no-fetch: using {} as last fetched; it may be stale",
+                rev
+            );
+            return Ok(());
+        }
+        let fetched = if self.is_named_remote() {
+            git_in_dir(
+                self.repo_dir().into(),
+                &["fetch", self.remote(), self.branch.as_str()],
+            )
+        } else {
+            let refspec = format!("+refs/heads/{}:{}", self.branch, self.remote_rev());
+            git_in_dir(
+                self.repo_dir().into(),
+                &["fetch", self.remote(), refspec.as_str()],
+            )
+        };
+        let Err(err) = fetched else {
+            return Ok(());
+        };
+
+        // A branch that was never pushed is read from the local branch instead; so is an
+        // unreachable remote, unless the deploy has to be pushed there
+        let local = format!("refs/heads/{}", self.branch);
+        let has_local = git_in_dir(
+            self.repo_dir().into(),
+            &["rev-parse", "--verify", "--quiet", &local],
+        )
+        .is_ok();
+        if err.to_string().contains("couldn't find remote ref") {
+            if has_local {
+                eprintln!(
+                    "note: {} has no branch {}; using the local branch",
+                    self.remote(),
+                    self.branch
+                );
+            }
+            return Ok(());
+        }
+        if !self.push && has_local {
+            eprintln!(
+                "note: could not fetch {} from {}; using the local branch",
+                self.branch,
+                self.remote()
+            );
+            return Ok(());
+        }
+        Err(DocverError::Fetch {
+            remote: self.remote().to_string(),
+            branch: self.branch.clone(),
+            message: err.to_string(),
+        })
+    }
+
+    /// The ref to read the published site from: the fetched remote branch, or the local branch
+    /// when the remote has none
+    pub fn read_rev(&self) -> String {
+        let remote_rev = self.remote_rev();
+        if git_in_dir(
+            self.repo_dir().into(),
+            &["rev-parse", "--verify", "--quiet", &remote_rev],
+        )
+        .is_ok()
+        {
+            remote_rev
+        } else {
+            format!("refs/heads/{}", self.branch)
+        }
+    }
+
+    fn is_named_remote(&self) -> bool {
+        let key = format!("remote.{}.url", self.remote());
+        git_in_dir(self.repo_dir().into(), &["config", "--get", key.as_str()]).is_ok()
+    }
+
+    /// Start a commit on the publish branch with the configured signing, identities and date
+    pub fn commit(&self, message: &str) -> Commit {
+        let mut commit = Commit::new(self.repo_dir(), format!("refs/heads/{}", self.branch))
+            .message(message)
+            .sign(self.sign);
+        if let Some((name, email)) = &self.author {
+            commit = commit.author(name, email);
+        }
+        if let Some((name, email)) = &self.committer {
+            commit = commit.committer(name, email);
+        }
+        if let Some(date) = &self.commit_date {
+            commit = commit.date(date);
+        }
+        commit
+    }
+
+    /// The deploy prefix as a tree path ("" when deploying to the branch root)
+    pub fn prefix(&self) -> String {
+        self.deploy_prefix
+            .as_ref()
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Scratch;
+
+    #[test]
+    fn normalize_deploy_prefix() {
+        assert_eq!(
+            parse_deploy_prefix("/docs/").unwrap(),
+            PathBuf::from("docs")
+        );
+        assert_eq!(
+            parse_deploy_prefix("./docs/./api").unwrap(),
+            PathBuf::from("docs/api")
+        );
+        assert_eq!(
+            parse_deploy_prefix("docs\\api").unwrap(),
+            PathBuf::from("docs/api")
+        );
+        assert!(parse_deploy_prefix("../x").is_err());
+        assert!(parse_deploy_prefix("docs/../../x").is_err());
+        assert!(parse_deploy_prefix("docs\\..\\..\\x").is_err());
+        assert!(parse_deploy_prefix("docs\n").is_err());
+        assert!(parse_deploy_prefix(&"d".repeat(256)).is_err());
+        assert!(parse_deploy_prefix("C:\\docs").is_err());
+    }
+
+    #[test]
+    fn discover_finds_the_toplevel() {
+        let dir = Scratch::dir("discover");
+        let nested = dir.join("repo/docs/build");
+        std::fs::create_dir_all(&nested).unwrap();
+        dir.git(&["init", "-q", "repo"]);
+        let discover = |repo: PathBuf| {
+            GitArgs {
+                repo: Some(repo),
+                ..commands::defaults()
+            }
+            .discover()
+        };
+
+        let found = discover(nested).unwrap();
+        let outside = discover(dir.to_path_buf()).unwrap_err();
+        let toplevel = std::fs::canonicalize(dir.join("repo")).unwrap();
+
+        assert_eq!(PathBuf::from(found.repo_dir()), toplevel);
+        assert!(matches!(outside, DocverError::NotARepository { .. }));
+    }
+}
@@ -0,0 +1,186 @@
+//! A minimal terminal progress bar for long-running deploys
+
+use std::{
+    cell::Cell,
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use crate::git::CommitProgress;
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tracks files and bytes processed, drawing a bar on stderr when it is a terminal
+#[derive(Debug)]
+pub struct Progress {
+    label: Cell<&'static str>,
+    visible: bool,
+    total: Cell<Option<u64>>,
+    files: Cell<u64>,
+    bytes: Cell<u64>,
+    started: Instant,
+    last_draw: Cell<Option<Instant>>,
+}
+
+impl Progress {
+    /// Create a tracker; nothing is drawn when `quiet` is set or stderr is not a terminal
+    pub fn new(label: &'static str, quiet: bool) -> Self {
+        Self {
+            label: Cell::new(label),
+            visible: !quiet && std::io::stderr().is_terminal(),
+            total: Cell::new(None),
+            files: Cell::new(0),
+            bytes: Cell::new(0),
+            started: Instant::now(),
+            last_draw: Cell::new(None),
+        }
+    }
+
+    /// Start a new phase with a known number of files, resetting the counters
+    pub fn phase(&self, label: &'static str, total: u64) {
+        self.label.set(label);
+        self.total.set(Some(total));
+        self.files.set(0);
+        self.bytes.set(0);
+        self.last_draw.set(None);
+        self.draw();
+    }
+
+    /// Record one processed file of `bytes` bytes
+    pub fn inc(&self, bytes: u64) {
+        self.files.set(self.files.get() + 1);
+        self.bytes.set(self.bytes.get() + bytes);
+        self.draw();
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.get()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Erase the bar so regular output can be printed on a clean line
+    pub fn clear(&self) {
+        if self.visible {
+            eprint!("\r\x1b[2K");
+            let _ = std::io::stderr().flush();
+        }
+    }
+
+    fn draw(&self) {
+        if !self.visible {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .last_draw
+            .get()
+            .is_some_and(|last| now.duration_since(last) < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw.set(Some(now));
+
+        let files = self.files.get();
+        let line = match self.total.get() {
+            Some(total) => {
+                let filled = (files * BAR_WIDTH as u64)
+                    .checked_div(total)
+                    .unwrap_or(BAR_WIDTH as u64)
+                    .min(BAR_WIDTH as u64) as usize;
+                format!(
+                    "{} [{}{}] {}/{} files, {}",
+                    self.label.get(),
+                    "=".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    files,
+                    total,
+                    format_bytes(self.bytes.get())
+                )
+            }
+            None => format!("{} {} files", self.label.get(), files),
+        };
+        eprint!("\r\x1b[2K{}", line);
+        let _ = std::io::stderr().flush();
+    }
+}
+
+impl CommitProgress for Progress {
+    fn start(&self, files: u64) {
+        self.phase("Importing", files);
+    }
+
+    fn file(&self, bytes: u64) {
+        self.inc(bytes);
+    }
+
+    fn finish(&self) {
+        self.clear();
+    }
+}
+
+/// Format a byte count with a binary unit (e.g. "1.5 MiB")
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Parse a byte count given as a plain number or with a decimal ("50MB") or binary ("1.5 GiB")
+/// unit, case-insensitively
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => anyhow::bail!("invalid size {:?}: unknown unit {:?}", s, unit.trim()),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid size {:?}: expected a number with a unit", s))?;
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_byte_counts() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(2 * 1024 * 1024 * 1024), "2.0 GiB");
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("50MB").unwrap(), 50_000_000);
+        assert_eq!(parse_size("1.5 GiB").unwrap(), 3 << 29);
+        assert_eq!(parse_size("10kib").unwrap(), 10240);
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("5 parsecs").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Version detection from project metadata
+
+use std::fs;
+
+use anyhow::{Context, bail};
+use clap::ValueEnum;
+use git_cmd::git_in_dir;
+
+/// Where to read the version identifier from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VersionSource {
+    /// `[package].version` in Cargo.toml
+    Cargo,
+    /// `version` in package.json
+    Npm,
+    /// The tag pointing at HEAD
+    GitTag,
+    /// The output of `git describe --tags`
+    GitDescribe,
+}
+
+impl VersionSource {
+    /// A short description of the source, for messages
+    pub fn describe(&self) -> &'static str {
+        match self {
+            VersionSource::Cargo => "Cargo.toml",
+            VersionSource::Npm => "package.json",
+            VersionSource::GitTag => "the tag at HEAD",
+            VersionSource::GitDescribe => "git describe",
+        }
+    }
+
+    /// Read the version identifier from the project manifest in the current directory, or from
+    /// the tags of the repository at `repo_dir`
+    pub fn detect(&self, repo_dir: &str) -> anyhow::Result<String> {
+        let version = match self {
+            VersionSource::Cargo => {
+                let manifest =
+                    fs::read_to_string("Cargo.toml").context("failed to read Cargo.toml")?;
+                cargo_package_version(&manifest)?
+            }
+            VersionSource::Npm => {
+                let manifest =
+                    fs::read_to_string("package.json").context("failed to read package.json")?;
+                let json: serde_json::Value =
+                    serde_json::from_str(&manifest).context("failed to parse package.json")?;
+                json.get("version")
+                    .and_then(|v| v.as_str())
+                    .context("package.json has no \"version\" field")?
+                    .to_string()
+            }
+            VersionSource::GitTag => git_in_dir(
+                repo_dir.into(),
+                &["describe", "--tags", "--exact-match", "HEAD"],
+            )
+            .context("no tag points at HEAD")?,
+            VersionSource::GitDescribe => git_in_dir(repo_dir.into(), &["describe", "--tags"])
+                .context("git describe found no tags")?,
+        };
+
+        let version = version.trim().to_string();
+        if version.is_empty() {
+            bail!("{} does not contain a version", self.describe());
+        }
+        Ok(version)
+    }
+}
+
+/// Extract `[package].version` from a Cargo manifest
+fn cargo_package_version(manifest: &str) -> anyhow::Result<String> {
+    let mut in_package = false;
+    for line in manifest.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        if !in_package {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key.trim() {
+            "version" => {
+                return value
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .map(str::to_string)
+                    .context("[package].version in Cargo.toml is not a string");
+            }
+            "version.workspace" => {
+                bail!("Cargo.toml inherits its version from the workspace, which is not supported")
+            }
+            _ => {}
+        }
+    }
+    bail!("Cargo.toml has no [package].version")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cargo_package_version() {
+        let manifest = r#"
+[workspace]
+version = "0.0.0"
+
+[package]
+name = "site"
+version = "1.2.3" # released
+
+[dependencies]
+version = "9.9.9"
+"#;
+        assert_eq!(cargo_package_version(manifest).unwrap(), "1.2.3");
+        assert!(cargo_package_version("[package]\nversion.workspace = true\n").is_err());
+        assert!(cargo_package_version("[package]\nname = \"site\"\n").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Concurrent file reading with a bound on the bytes held in memory at once
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use anyhow::Context;
+
+/// Default cap on bytes read but not yet consumed
+pub const MAX_IN_FLIGHT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Read `paths` on a pool of worker threads, handing each file's contents to `consume` as soon
+/// as it is available, after `process` has turned them into the value to consume on the worker
+/// thread that read them. A file counts against `max_in_flight` from before it is read until
+/// `consume` returns, so `consume` should write the value out rather than keep it (a single
+/// larger file is still read on its own). The first read, process or consume error stops the
+/// workers and is returned.
+pub fn read_files<T: Send>(
+    paths: &[PathBuf],
+    max_in_flight: u64,
+    process: impl Fn(usize, Vec<u8>) -> anyhow::Result<T> + Sync,
+    mut consume: impl FnMut(usize, T) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(16)
+        .min(paths.len().max(1));
+
+    let budget = Budget::new(max_in_flight);
+    let next = AtomicUsize::new(0);
+    let abort = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel::<(usize, u64, anyhow::Result<T>)>();
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (budget, next, abort, process) = (&budget, &next, &abort, &process);
+            scope.spawn(move || {
+                while !abort.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(index) else {
+                        break;
+                    };
+                    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                    budget.acquire(size);
+                    let data = fs::read(path)
+                        .with_context(|| {
+                            format!("failed to read file for fast-import: {}", path.display())
+                        })
+                        .and_then(|data| process(index, data));
+                    if tx.send((index, size, data)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let result = rx.iter().try_for_each(|(index, size, data)| {
+            consume(index, data?)?;
+            budget.release(size);
+            Ok(())
+        });
+        if result.is_err() {
+            abort.store(true, Ordering::Relaxed);
+            budget.close();
+        }
+        result
+    })
+}
+
+/// A byte-counting semaphore
+struct Budget {
+    capacity: u64,
+    state: Mutex<(u64, bool)>,
+    available: Condvar,
+}
+
+impl Budget {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new((0, false)),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Wait until `bytes` fit in the budget; always succeeds when nothing else is in flight
+    fn acquire(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && state.0 > 0 && state.0 + bytes > self.capacity {
+            state = self.available.wait(state).unwrap();
+        }
+        state.0 += bytes;
+    }
+
+    fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(bytes);
+        self.available.notify_all();
+    }
+
+    /// Wake every waiter for good, used when aborting
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.available.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Scratch;
+
+    #[test]
+    fn reads_every_file_and_reports_errors() {
+        let dir = Scratch::dir("reader");
+        let paths = (0..50)
+            .map(|i| {
+                let path = dir.join(format!("{}.txt", i));
+                fs::write(&path, i.to_string()).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        let mut contents = vec![String::new(); paths.len()];
+        read_files(
+            &paths,
+            8,
+            |_, data| Ok(data),
+            |index, data| {
+                contents[index] = String::from_utf8(data)?;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(
+            contents
+                .iter()
+                .enumerate()
+                .all(|(i, c)| *c == i.to_string())
+        );
+
+        let mut missing = paths.clone();
+        missing.push(dir.join("missing.txt"));
+        assert!(read_files(&missing, 8, |_, data| Ok(data), |_, _| Ok(())).is_err());
+    }
+
+    #[test]
+    fn files_count_against_the_budget_until_consumed() {
+        let dir = Scratch::dir("reader-budget");
+        let paths = (0..20)
+            .map(|i| {
+                let path = dir.join(format!("{}.txt", i));
+                fs::write(&path, "0123456789").unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        // Each file fills the budget, so none may be read while another awaits its consumer
+        let (in_flight, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        read_files(
+            &paths,
+            10,
+            |_, data| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                Ok(data)
+            },
+            |_, _| {
+                thread::sleep(std::time::Duration::from_millis(1));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,162 @@
+//! Merging the per-version search indexes into one cross-version index
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Search index formats that can be merged across versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SearchIndexFormat {
+    /// lunr-style JSON (`{"docs": [{"location", "title", "text"}]}`) at search/search_index.json,
+    /// as written by MkDocs
+    Lunr,
+}
+
+impl SearchIndexFormat {
+    /// Path of a version's index, relative to the version directory
+    pub fn version_index(&self) -> &'static str {
+        match self {
+            SearchIndexFormat::Lunr => "search/search_index.json",
+        }
+    }
+
+    /// Path of the merged index, relative to the deploy prefix
+    pub fn merged_index(&self) -> &'static str {
+        match self {
+            SearchIndexFormat::Lunr => "search_index.json",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LunrIndex {
+    docs: Vec<LunrDoc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LunrDoc {
+    location: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MergedIndex {
+    docs: Vec<MergedDoc>,
+}
+
+#[derive(Debug, Serialize)]
+struct MergedDoc {
+    version: String,
+    /// Location relative to the deploy prefix, i.e. prefixed with the version directory
+    location: String,
+    title: String,
+    text: String,
+}
+
+/// The merged index and the number of documents left out to stay within the size limit
+pub struct Merged {
+    pub json: String,
+    pub dropped: usize,
+}
+
+/// Merge the indexes of several versions, given as `(tag, index contents)` in the order their
+/// documents should be listed. Documents are kept in order until the merged index would exceed
+/// `max_size` bytes.
+pub fn merge(indexes: &[(String, Vec<u8>)], max_size: Option<u64>) -> anyhow::Result<Merged> {
+    let mut docs = Vec::new();
+    let mut dropped = 0;
+    // Size of `{"docs":[]}` plus each serialized document and its separating comma
+    let mut size = 11;
+    for (tag, contents) in indexes {
+        let index: LunrIndex = serde_json::from_slice(contents)
+            .with_context(|| format!("failed to parse the search index of {}", tag))?;
+        for doc in index.docs {
+            let doc = MergedDoc {
+                version: tag.clone(),
+                location: format!("{}/{}", tag, doc.location),
+                title: doc.title,
+                text: doc.text,
+            };
+            let doc_size = serde_json::to_string(&doc)?.len() as u64 + 1;
+            if max_size.is_some_and(|max| size + doc_size > max) {
+                dropped += 1;
+                continue;
+            }
+            size += doc_size;
+            docs.push(doc);
+        }
+    }
+
+    Ok(Merged {
+        json: serde_json::to_string(&MergedIndex { docs })?,
+        dropped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_INDEX: &str = r#"{
+        "config": {"lang": ["en"]},
+        "docs": [
+            {"location": "", "title": "Home", "text": "Welcome"},
+            {"location": "guide/#install", "title": "Install", "text": "cargo install"}
+        ]
+    }"#;
+    const V2_INDEX: &str = r#"{"docs": [{"location": "api/", "title": "API"}]}"#;
+
+    fn fixtures() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("v2".to_string(), V2_INDEX.as_bytes().to_vec()),
+            ("v1".to_string(), V1_INDEX.as_bytes().to_vec()),
+        ]
+    }
+
+    #[test]
+    fn merges_in_order_with_versions() {
+        let merged = merge(&fixtures(), None).unwrap();
+        assert_eq!(merged.dropped, 0);
+        let value: serde_json::Value = serde_json::from_str(&merged.json).unwrap();
+        insta::assert_json_snapshot!(value, @r#"
+        {
+          "docs": [
+            {
+              "location": "v2/api/",
+              "text": "",
+              "title": "API",
+              "version": "v2"
+            },
+            {
+              "location": "v1/",
+              "text": "Welcome",
+              "title": "Home",
+              "version": "v1"
+            },
+            {
+              "location": "v1/guide/#install",
+              "text": "cargo install",
+              "title": "Install",
+              "version": "v1"
+            }
+          ]
+        }
+        "#);
+        assert_eq!(merge(&fixtures(), None).unwrap().json, merged.json);
+    }
+
+    #[test]
+    fn size_limit_drops_documents() {
+        let full = merge(&fixtures(), None).unwrap();
+        let limited = merge(&fixtures(), Some(full.json.len() as u64 - 1)).unwrap();
+        assert_eq!(limited.dropped, 1);
+        assert!(limited.json.len() < full.json.len());
+        assert!(!limited.json.contains("cargo install"));
+
+        let invalid = vec![("v1".to_string(), b"[]".to_vec())];
+        assert!(merge(&invalid, None).is_err());
+    }
+}
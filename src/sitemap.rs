@@ -0,0 +1,236 @@
+//! sitemap.xml generation following <https://www.sitemaps.org/protocol.html>
+
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::versions::Versions;
+
+/// Priority of pages of the default version
+const DEFAULT_PRIORITY: f32 = 1.0;
+/// Priority of pages of every other version
+const OTHER_PRIORITY: f32 = 0.3;
+/// Marks sitemaps listing every version, which can't be told from their URLs while there is
+/// only one
+const ALL_VERSIONS: &str = "<!-- all versions -->";
+
+/// Render a sitemap of the HTML pages among `paths` (the files of the branch tree). Pages of the
+/// version the default alias points at are listed under the alias; with `all_versions`, pages of
+/// the other versions follow under their tag with a lower priority.
+pub fn render(
+    base_url: &str,
+    prefix: &str,
+    versions: &Versions,
+    paths: &BTreeSet<String>,
+    all_versions: bool,
+) -> String {
+    let default_tag = versions.default_version().map(|version| &version.tag);
+    // External versions aren't on the branch, so the sitemap can't list their pages
+    let mut tags = versions
+        .versions()
+        .values()
+        .filter(|version| !version.is_external())
+        .map(|version| &version.tag)
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.sort_by_key(|tag| Some(*tag) != default_tag);
+
+    let mut result = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n"
+    ));
+    if all_versions {
+        writeln!(result, "  {}", ALL_VERSIONS).expect("Failed to write to sitemap string");
+    }
+    for tag in tags {
+        let (segment, priority) = if Some(tag) == default_tag {
+            (versions.default_alias(), DEFAULT_PRIORITY)
+        } else if all_versions {
+            (tag.as_str(), OTHER_PRIORITY)
+        } else {
+            continue;
+        };
+
+        let version_dir = match prefix {
+            "" => format!("{}/", tag),
+            prefix => format!("{}/{}/", prefix, tag),
+        };
+        let url_dir = match prefix {
+            "" => format!("{}/", segment),
+            prefix => format!("{}/{}/", prefix, segment),
+        };
+        for page in paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(&version_dir))
+            .filter(|page| page.ends_with(".html") || page.ends_with(".htm"))
+        {
+            let page = match page.strip_suffix("index.html") {
+                Some(dir) if dir.is_empty() || dir.ends_with('/') => dir,
+                _ => page,
+            };
+            writeln!(
+                result,
+                "  <url><loc>{}/{}{}</loc><priority>{:.1}</priority></url>",
+                escape(base_url.trim_end_matches('/')),
+                escape(&url_dir),
+                escape(page),
+                priority
+            )
+            .expect("Failed to write to sitemap string");
+        }
+    }
+    result.push_str("</urlset>\n");
+    result
+}
+
+/// What an existing sitemap was rendered with, so later deploys can keep it up to date
+#[derive(Debug, PartialEq)]
+pub struct Settings {
+    pub base_url: String,
+    pub all_versions: bool,
+}
+
+/// Recover the settings of a sitemap [`render`] wrote from its URLs: the base URL is what comes
+/// before the first version or alias directory. None when no URL has one.
+pub fn settings(existing: &str, prefix: &str, versions: &Versions) -> Option<Settings> {
+    let segments = versions
+        .versions()
+        .values()
+        .map(|version| version.tag.as_str())
+        .chain(versions.aliases().keys().map(String::as_str))
+        .chain([versions.default_alias()])
+        .map(|segment| match prefix {
+            "" => format!("/{}/", segment),
+            prefix => format!("/{}/{}/", prefix, segment),
+        })
+        .collect::<Vec<_>>();
+    let base_url = existing
+        .split("<loc>")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("</loc>"))
+        .map(|(loc, _)| unescape(loc))
+        .find_map(|loc| {
+            let start = segments
+                .iter()
+                .filter_map(|segment| loc.find(segment.as_str()))
+                .min()?;
+            Some(loc[..start].to_string())
+        })?;
+    Some(Settings {
+        base_url,
+        all_versions: existing.contains(ALL_VERSIONS)
+            || existing.contains(&format!("<priority>{:.1}</priority>", OTHER_PRIORITY)),
+    })
+}
+
+/// Escape text for use in XML character data
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use quick_xml::{Reader, events::Event};
+
+    use super::*;
+
+    fn assert_well_formed(xml: &str) {
+        let mut reader = Reader::from_str(xml);
+        reader.config_mut().check_end_names = true;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Eof) => break,
+                Ok(_) => {}
+                Err(err) => panic!("malformed sitemap: {}\n{}", err, xml),
+            }
+        }
+    }
+
+    #[test]
+    fn lists_default_version_pages() {
+        let mut versions = Versions::default();
+        versions.add("v1".into(), None, HashSet::new()).unwrap();
+        versions
+            .add("v2".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        let paths = BTreeSet::from([
+            "docs/v1/index.html".to_string(),
+            "docs/v2/index.html".to_string(),
+            "docs/v2/a&b/page.html".to_string(),
+            "docs/v2/style.css".to_string(),
+            "versions.json".to_string(),
+        ]);
+
+        let sitemap = render(
+            "https://docs.example.com/",
+            "docs",
+            &versions,
+            &paths,
+            false,
+        );
+        assert_well_formed(&sitemap);
+        insta::assert_snapshot!(sitemap, @r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+          <url><loc>https://docs.example.com/docs/latest/a&amp;b/page.html</loc><priority>1.0</priority></url>
+          <url><loc>https://docs.example.com/docs/latest/</loc><priority>1.0</priority></url>
+        </urlset>
+        "#);
+
+        let sitemap = render("https://docs.example.com", "docs", &versions, &paths, true);
+        assert_well_formed(&sitemap);
+        assert!(sitemap.contains(
+            "<url><loc>https://docs.example.com/docs/v1/</loc><priority>0.3</priority></url>"
+        ));
+    }
+
+    #[test]
+    fn settings_are_recovered_from_a_rendered_sitemap() {
+        let mut versions = Versions::default();
+        versions.add("v1".into(), None, HashSet::new()).unwrap();
+        versions
+            .add("v2".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        let paths = BTreeSet::from([
+            "docs/v1/index.html".to_string(),
+            "docs/v2/index.html".to_string(),
+        ]);
+
+        for all_versions in [false, true] {
+            let sitemap = render(
+                "https://example.com/a&b",
+                "docs",
+                &versions,
+                &paths,
+                all_versions,
+            );
+            assert_eq!(
+                settings(&sitemap, "docs", &versions),
+                Some(Settings {
+                    base_url: "https://example.com/a&b".to_string(),
+                    all_versions,
+                })
+            );
+        }
+        let empty = render(
+            "https://example.com",
+            "docs",
+            &versions,
+            &BTreeSet::new(),
+            false,
+        );
+        assert_eq!(settings(&empty, "docs", &versions), None);
+    }
+}
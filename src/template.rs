@@ -0,0 +1,80 @@
+//! `{placeholder}` templates with `{{` and `}}` escapes
+
+use anyhow::bail;
+
+/// Expand every `{name}` in `template` using `lookup`. Unknown placeholders and unbalanced braces
+/// are errors.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    bail!("unclosed '{{' in template {:?}", template);
+                };
+                let name = &rest[..end];
+                match lookup(name) {
+                    Some(value) => result.push_str(&value),
+                    None => bail!(
+                        "unknown placeholder {{{}}} in template {:?}",
+                        name,
+                        template
+                    ),
+                }
+                chars = rest[end + 1..].chars();
+            }
+            '}' => bail!(
+                "unmatched '}}' in template {:?} (use '}}}}' for a literal)",
+                template
+            ),
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "version" => Some("v1.2.3".into()),
+            "date" => Some("2024-05-01".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_placeholders_and_escapes() {
+        assert_eq!(
+            render("MyProject {version} ({date})", lookup).unwrap(),
+            "MyProject v1.2.3 (2024-05-01)"
+        );
+        assert_eq!(
+            render("{{literal}} {version}", lookup).unwrap(),
+            "{literal} v1.2.3"
+        );
+        assert_eq!(
+            render("no placeholders", lookup).unwrap(),
+            "no placeholders"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_unbalanced() {
+        assert!(render("{unknown}", lookup).is_err());
+        assert!(render("{version", lookup).is_err());
+        assert!(render("version}", lookup).is_err());
+    }
+}
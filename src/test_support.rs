@@ -0,0 +1,104 @@
+//! Fixtures shared by the unit tests
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temp directory, removed when dropped so fixtures are cleaned
+/// up even when an assertion fails
+pub struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    /// A new empty directory, named after `name` and unique to this call
+    pub fn dir(name: &str) -> Scratch {
+        let path = std::env::temp_dir().join(format!(
+            "versite-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Scratch { path }
+    }
+
+    /// Run git in the directory with a fixed identity, panicking when it fails, and return
+    /// what it printed
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&self.path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A scratch directory holding a new, empty git repository
+pub fn scratch_repo(name: &str) -> Scratch {
+    let repo = Scratch::dir(name);
+    repo.git(&["init", "-q"]);
+    repo
+}
+
+/// A scratch directory with a site to deploy in `site`, and a `work` repository holding an
+/// empty commit
+fn site_and_work_repo(name: &str) -> Scratch {
+    let dir = Scratch::dir(name);
+    fs::create_dir_all(dir.join("site")).unwrap();
+    fs::write(dir.join("site/index.html"), "<h1>Docs</h1>").unwrap();
+    dir.git(&["init", "-q", "work"]);
+    dir.git(&["-C", "work", "commit", "-q", "--allow-empty", "-m", "init"]);
+    dir
+}
+
+/// A work repository with an empty bare "origin" next to it, and a site to deploy
+pub fn publishing_setup(name: &str) -> Scratch {
+    let dir = site_and_work_repo(name);
+    dir.git(&["init", "-q", "--bare", "origin.git"]);
+    dir.git(&["-C", "work", "remote", "add", "origin", "../origin.git"]);
+    dir
+}
+
+/// Like [`publishing_setup`], with the work repository also cloned to the bare `bare.git` to
+/// publish from directly
+pub fn bare_publishing_setup(name: &str) -> Scratch {
+    let dir = site_and_work_repo(name);
+    dir.git(&["clone", "-q", "--bare", "work", "bare.git"]);
+    dir
+}
@@ -1,28 +1,245 @@
 use core::fmt;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use anyhow::Context;
-use git_cmd::git_in_dir;
-use serde::ser::SerializeSeq;
+use clap::ValueEnum;
+use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
 
+use crate::backend::Backend;
+use crate::error::{DocverError, Result};
+
 pub const VERSIONS_FILE: &str = "versions.json";
 
+/// The script written for client-side version switchers, with a copy of versions.json in every
+/// version directory
+pub const VERSIONS_JS: &str = "versions.js";
+
+/// Version of the versions.json format, bumped on incompatible changes. Files without a
+/// `schema` are the bare list of versions of schema 1, and are upgraded when next written.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// The alias the site root is served from
+pub const DEFAULT_ALIAS: &str = "latest";
+
+/// Files deploys write next to the versions, which a version or alias of the same name would
+/// clash with
+const RESERVED_NAMES: &[&str] = &[
+    VERSIONS_FILE,
+    VERSIONS_JS,
+    "_redirects",
+    ".nojekyll",
+    ".gitignore",
+    "robots.txt",
+    "sitemap.xml",
+    crate::checksums::MANIFESTS_INDEX,
+];
+
+/// The longest version tag or alias, in bytes: the longest file name most filesystems allow
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Why a version tag or alias can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NameError {
+    /// The name is empty
+    Empty,
+    /// The name is not a single path segment, e.g. "a/b" or ".."
+    NotASegment(String),
+    /// The name is a file deploys write to the branch, e.g. "_redirects"
+    Reserved(String),
+    /// The name has a control character, such as a newline or tab
+    ControlCharacter(String),
+    /// The name is longer than [`MAX_NAME_LEN`] bytes
+    TooLong(String),
+    /// An alias has the tag of a version
+    AliasIsVersion(String),
+    /// A version has the name of an alias
+    VersionIsAlias(String),
+    /// A name differs from an existing version or alias (the second name) only by case
+    CaseClash(String, String),
+}
+
+impl Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "version tags and aliases can't be empty"),
+            NameError::NotASegment(name) => write!(
+                f,
+                "{:?} can't be used as a version or alias: it must be a single path segment, without / or \\ and other than . and ..",
+                name
+            ),
+            NameError::Reserved(name) => write!(
+                f,
+                "{:?} can't be used as a version or alias: deploys write a file of that name",
+                name
+            ),
+            NameError::ControlCharacter(name) => write!(
+                f,
+                "{:?} can't be used as a version or alias: it has a control character",
+                name
+            ),
+            NameError::TooLong(name) => write!(
+                f,
+                "{:?} can't be used as a version or alias: it is longer than {} bytes",
+                name, MAX_NAME_LEN
+            ),
+            NameError::AliasIsVersion(name) => {
+                write!(f, "alias {} has the same name as a version", name)
+            }
+            NameError::VersionIsAlias(name) => {
+                write!(f, "version {} has the same name as an alias", name)
+            }
+            NameError::CaseClash(name, existing) => write!(
+                f,
+                "{} differs from {} only by case, so they would share a directory on case-insensitive filesystems",
+                name, existing
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// Check that `name` can be used as a version tag or alias, i.e. as a directory of the branch
+pub fn validate_name(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        Err(NameError::Empty)
+    } else if name == "." || name == ".." || name.contains(['/', '\\']) {
+        Err(NameError::NotASegment(name.to_string()))
+    } else if RESERVED_NAMES.contains(&name) {
+        Err(NameError::Reserved(name.to_string()))
+    } else if name.chars().any(char::is_control) {
+        Err(NameError::ControlCharacter(name.to_string()))
+    } else if name.len() > MAX_NAME_LEN {
+        Err(NameError::TooLong(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// `name` with its slashes and backslashes replaced by dashes, e.g. "feature-login" for a preview
+/// of the branch "feature/login", so it can be deployed as a single directory
+pub fn sanitize_name(name: &str) -> String {
+    name.replace(['/', '\\'], "-")
+}
+
+/// Whether `name` matches the shell-style `pattern`, e.g. "pr-*"; patterns that aren't valid
+/// globs match nothing
+pub(crate) fn glob_matches(pattern: &str, name: &str) -> bool {
+    glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(name))
+}
+
+/// The key identifiers are looked up by when no name matches exactly: lowercase, sanitized, and
+/// without the leading "v" of a version number, so "V1.2.3" finds "v1.2.3" or "1.2.3", "Latest"
+/// finds "latest" and "feature/login" finds "feature-login"
+fn lookup_key(name: &str) -> String {
+    let name = sanitize_name(&name.to_lowercase());
+    match name.strip_prefix('v') {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest.to_string(),
+        _ => name,
+    }
+}
+
+/// Formats of the rules that serve aliases (and the site root) from their versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewriteFormat {
+    /// A `_redirects` file, as read by Netlify and Cloudflare Pages
+    Netlify,
+}
+
+/// Options of [`Versions::rewrites`]
+#[derive(Debug, Clone)]
+pub struct RewriteOptions {
+    /// The directory the versions are deployed under ("" for the branch root)
+    pub prefix: String,
+    /// HTTP status of the alias rules: 200 rewrites keep the alias in the URL, 301 and 302
+    /// redirect to the version
+    pub alias_status: u16,
+    /// HTTP status of the site root rule
+    pub root_status: u16,
+}
+
+impl Default for RewriteOptions {
+    fn default() -> Self {
+        RewriteOptions {
+            prefix: String::new(),
+            alias_status: 200,
+            root_status: 200,
+        }
+    }
+}
+
+/// Parse the HTTP status of rewrite rules: 200 (rewrite), 301 or 302 (redirect)
+pub(crate) fn parse_redirect_status(s: &str) -> anyhow::Result<u16> {
+    match s.parse() {
+        Ok(status @ (200 | 301 | 302)) => Ok(status),
+        _ => anyhow::bail!("expected 200 (rewrite), 301 or 302 (redirect), got {:?}", s),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Version {
     #[serde(rename = "version")]
     pub tag: String,
     pub title: Option<String>,
+    /// An absolute URL the version lives at instead of a directory of the branch, e.g. docs
+    /// moved to an archive host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Left out of listings and version selectors, though still served
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// When the version was first deployed, in ISO 8601
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// When the version was last deployed, in ISO 8601
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    /// The full SHA of the source commit the version was last deployed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_commit: Option<String>,
+    /// Position in the [`OrderingStrategy::Explicit`] order, heaviest first (0 when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i64>,
+    /// Fields of the versions.json entry this version of versite doesn't know, written back
+    /// unchanged
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Version {
     pub fn new(tag: String, title: Option<String>) -> Self {
-        Self { tag, title }
+        Self {
+            tag,
+            title,
+            url: None,
+            hidden: false,
+            created_at: None,
+            updated_at: None,
+            source_commit: None,
+            weight: None,
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    /// Whether the version links to [`Version::url`] rather than being deployed to the branch
+    pub fn is_external(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// The unix seconds of the last deploy, falling back to the first
+    fn deployed_at(&self) -> Option<i64> {
+        let at = self.updated_at.as_ref().or(self.created_at.as_ref())?;
+        crate::git::seconds_of(&crate::git::parse_date(at).ok()?)
     }
 }
 
-fn parse_semver_like(tag: &str) -> Option<semver::Version> {
+pub(crate) fn parse_semver_like(tag: &str) -> Option<semver::Version> {
     let trimmed = tag.trim_start_matches(['v', 'V']);
     if let Ok(v) = semver::Version::parse(trimmed) {
         return Some(v);
@@ -32,6 +249,7 @@ fn parse_semver_like(tag: &str) -> Option<semver::Version> {
         .chars()
         .take_while(|c| c.is_ascii_digit() || *c == '.')
         .collect();
+    let rest = &trimmed[numeric_prefix.len()..];
 
     if !numeric_prefix.is_empty() {
         // Try to coerce incomplete versions like "1" or "1.2" into full MAJOR.MINOR.PATCH
@@ -44,6 +262,12 @@ fn parse_semver_like(tag: &str) -> Option<semver::Version> {
                 parts.push("0");
             }
             let coerced = parts.join(".");
+            // Keep prerelease and build suffixes, so "2.0-rc.1" stays a prerelease of 2.0.0
+            if rest.starts_with(['-', '+'])
+                && let Ok(v) = semver::Version::parse(&format!("{}{}", coerced, rest))
+            {
+                return Some(v);
+            }
             return semver::Version::parse(&coerced).ok();
         }
     }
@@ -51,20 +275,183 @@ fn parse_semver_like(tag: &str) -> Option<semver::Version> {
     None
 }
 
+/// The major version group of a tag, e.g. "1.x" for "v1.2", or "other" for tags that are not
+/// semver-like
+pub fn major_group(tag: &str) -> String {
+    match parse_semver_like(tag) {
+        Some(version) => format!("{}.x", version.major),
+        None => "other".to_string(),
+    }
+}
+
+/// The release a tag belongs to, e.g. "2.0.0" for both "v2.0" and "2.0.0-rc.1", or "other" for
+/// tags that are not semver-like
+pub fn release_group(tag: &str) -> String {
+    match parse_semver_like(tag) {
+        Some(version) => format!("{}.{}.{}", version.major, version.minor, version.patch),
+        None => "other".to_string(),
+    }
+}
+
+/// Whether a tag is a semver prerelease, like "2.0.0-rc.1". Build metadata alone doesn't make
+/// one.
+pub fn is_prerelease(tag: &str) -> bool {
+    parse_semver_like(tag).is_some_and(|version| !version.pre.is_empty())
+}
+
+/// The release channel of a semver-like tag: "stable" for releases, otherwise the leading
+/// prerelease identifier (e.g. "beta" for "2.0.0-beta.3"). Non-semver tags have no channel.
+pub fn channel_of(tag: &str) -> Option<String> {
+    let version = parse_semver_like(tag)?;
+    if version.pre.is_empty() {
+        return Some(STABLE_CHANNEL.to_string());
+    }
+    let channel = version
+        .pre
+        .as_str()
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(|c: char| c.is_ascii_digit());
+    (!channel.is_empty()).then(|| channel.to_ascii_lowercase())
+}
+
+pub const STABLE_CHANNEL: &str = "stable";
+
+/// Which alias tracks the newest version of each release channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channels(BTreeMap<String, String>);
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            (STABLE_CHANNEL.to_string(), "stable".to_string()),
+            ("beta".to_string(), "beta".to_string()),
+            ("rc".to_string(), "rc".to_string()),
+        ]))
+    }
+}
+
+impl Channels {
+    /// Track `channel` with `alias`, replacing any existing mapping for the channel
+    pub fn set(&mut self, channel: impl Into<String>, alias: impl Into<String>) {
+        self.0.insert(channel.into(), alias.into());
+    }
+
+    pub fn alias(&self, channel: &str) -> Option<&str> {
+        self.0.get(channel).map(String::as_str)
+    }
+}
+
+/// How versions are ordered in versions.json, listings and version selectors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrderingStrategy {
+    /// Non-semver versions first, then semver versions newest first
+    #[default]
+    SemverDesc,
+    /// Non-semver versions first, then semver versions oldest first
+    SemverAsc,
+    /// By tag, comparing runs of digits as numbers (e.g. "v1.9" before "v1.10")
+    Natural,
+    /// By the weight of each version, heaviest first, then like semver-desc
+    Explicit,
+    /// In the order the versions were first deployed, as kept in versions.json
+    Manual,
+}
+
+/// How versions.json is laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ManifestFormat {
+    /// Indented, one field per line
+    #[default]
+    Pretty,
+    /// On a single line
+    Compact,
+}
+
+impl OrderingStrategy {
+    pub fn compare(self, a: &Version, b: &Version) -> std::cmp::Ordering {
+        match self {
+            OrderingStrategy::SemverDesc => compare_semver(a, b, true),
+            OrderingStrategy::SemverAsc => compare_semver(a, b, false),
+            OrderingStrategy::Natural => compare_natural(&a.tag, &b.tag),
+            OrderingStrategy::Explicit => b
+                .weight
+                .unwrap_or_default()
+                .cmp(&a.weight.unwrap_or_default())
+                .then_with(|| compare_semver(a, b, true)),
+            // Versions are sorted from insertion order, which the stable sort then keeps
+            OrderingStrategy::Manual => std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+/// Non-semver versions first in reverse lexicographic order, then semver versions newest first
+/// (or oldest first unless `descending`)
+fn compare_semver(a: &Version, b: &Version, descending: bool) -> std::cmp::Ordering {
+    use std::cmp::Ordering::*;
+    match (parse_semver_like(&a.tag), parse_semver_like(&b.tag)) {
+        (Some(va), Some(vb)) if descending => vb.cmp(&va),
+        (Some(va), Some(vb)) => va.cmp(&vb),
+        (Some(_), None) => Greater,
+        (None, Some(_)) => Less,
+        (None, None) => b.tag.cmp(&a.tag),
+    }
+}
+
+/// The order versions are pruned and retained in: the most recently deployed first, then those
+/// without a deploy date by version number, and the rest by tag compared naturally so "pr-10"
+/// is newer than "pr-9"
+fn newest_first(a: &Version, b: &Version) -> std::cmp::Ordering {
+    b.deployed_at()
+        .cmp(&a.deployed_at())
+        .then_with(|| parse_semver_like(&b.tag).cmp(&parse_semver_like(&a.tag)))
+        .then_with(|| compare_natural(&b.tag, &a.tag))
+}
+
+/// Compare tags chunk by chunk, runs of digits by their value and the rest as text
+fn compare_natural(a: &str, b: &str) -> std::cmp::Ordering {
+    fn chunks(s: &str) -> Vec<(bool, &str)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.char_indices().skip(1) {
+            let previous = s[..i].chars().next_back().expect("not the first char");
+            if c.is_ascii_digit() != previous.is_ascii_digit() {
+                chunks.push(&s[start..i]);
+                start = i;
+            }
+        }
+        if !s.is_empty() {
+            chunks.push(&s[start..]);
+        }
+        chunks
+            .into_iter()
+            .map(|chunk| (chunk.starts_with(|c: char| c.is_ascii_digit()), chunk))
+            .collect()
+    }
+
+    let (a_chunks, b_chunks) = (chunks(a), chunks(b));
+    for (x, y) in a_chunks.iter().zip(&b_chunks) {
+        let ordering = match (x, y) {
+            ((true, x), (true, y)) => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            ((_, x), (_, y)) => x.cmp(y),
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    a_chunks.len().cmp(&b_chunks.len()).then_with(|| a.cmp(b))
+}
+
+/// The default [`OrderingStrategy::SemverDesc`] order
 impl Ord for Version {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        use std::cmp::Ordering::*;
-        let a = parse_semver_like(&self.tag);
-        let b = parse_semver_like(&other.tag);
-        match (a, b) {
-            // reverse semver order: higher versions come first
-            (Some(va), Some(vb)) => vb.cmp(&va),
-            // reverse the semver vs non-semver ordering so non-semver comes first
-            (Some(_), None) => Greater,
-            (None, Some(_)) => Less,
-            // reverse lexicographic for non-semver vs non-semver
-            (None, None) => other.tag.cmp(&self.tag),
-        }
+        OrderingStrategy::SemverDesc.compare(self, other)
     }
 }
 
@@ -74,237 +461,1498 @@ impl PartialOrd for Version {
     }
 }
 
+/// The `TAG[:TITLE]` form [`Version`]'s `FromStr` reads, with the colons of the tag escaped
 impl Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.tag)?;
+        write!(f, "{}", self.tag.replace(':', "\\:"))?;
         if let Some(title) = &self.title {
-            write!(f, " ({})", title)?;
+            write!(f, ":{}", title)?;
         }
         Ok(())
     }
 }
 
-#[derive(Default, Debug, Clone)]
-pub struct Versions {
-    pub versions: HashMap<String, Version>,
-    pub aliases: HashMap<String, String>,
+/// Why a version or alias argument can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArgumentError {
+    /// The argument isn't in the expected form, e.g. "ALIAS=TAG"
+    Format {
+        input: String,
+        expected: &'static str,
+    },
+    /// A name in the argument can't be used as a version or alias
+    Name(NameError),
 }
 
-impl Versions {
-    pub fn from_git(remote_rev: &str) -> Self {
-        git_in_dir(
-            ".".into(),
-            &["show", format!("{}:{}", remote_rev, VERSIONS_FILE).as_str()],
-        )
-        .and_then(|s| {
-            serde_json::from_str(&s).context(format!("Failed to parse {}", VERSIONS_FILE))
-        })
-        .unwrap_or_default()
+impl Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgumentError::Format { input, expected } => {
+                write!(f, "invalid value {:?}: expected {}", input, expected)
+            }
+            ArgumentError::Name(err) => write!(f, "{}", err),
+        }
     }
+}
 
-    pub fn by_alias(&self, alias: &str) -> Option<&Version> {
-        self.aliases.get(alias).and_then(|v| self.versions.get(v))
-    }
+impl std::error::Error for ArgumentError {}
 
-    pub fn by_tag(&self, tag: &str) -> Option<&Version> {
-        self.versions.get(tag)
+impl From<NameError> for ArgumentError {
+    fn from(err: NameError) -> Self {
+        ArgumentError::Name(err)
     }
+}
 
-    pub fn search(&self, tag_or_alias: &str) -> Vec<&Version> {
-        self.versions
-            .values()
-            .filter(|v| {
-                v.tag == tag_or_alias
-                    || self
-                        .aliases
-                        .get(tag_or_alias)
-                        .map(|av| av == &v.tag)
-                        .is_some()
-            })
-            .collect()
+/// Reads `TAG` or `TAG:TITLE`, e.g. "v2.0:Version 2 (beta)". The title is everything after the
+/// first colon; a colon in the tag is escaped as `\:`.
+impl FromStr for Version {
+    type Err = ArgumentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tag = String::new();
+        let mut title = None;
+        let mut chars = s.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' if chars.peek().is_some_and(|(_, next)| *next == ':') => {
+                    tag.push(':');
+                    chars.next();
+                }
+                ':' => {
+                    title = Some(s[i + 1..].to_string());
+                    break;
+                }
+                c => tag.push(c),
+            }
+        }
+        validate_name(&tag)?;
+        Ok(Version::new(tag, title))
     }
+}
 
-    pub fn add(
-        &mut self,
-        version_tag: String,
-        title: Option<String>,
-        aliases: HashSet<String>,
-    ) -> Option<&Version> {
-        let version = Version::new(version_tag.clone(), title);
+/// An alias and the version it should point at, read from `ALIAS=TAG`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasAssignment {
+    pub alias: String,
+    pub tag: String,
+}
 
-        self.versions.insert(version_tag.clone(), version);
-        for alias in aliases {
-            self.aliases.insert(alias, version_tag.clone());
-        }
+impl FromStr for AliasAssignment {
+    type Err = ArgumentError;
 
-        self.versions.get(&version_tag)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((alias, tag)) = s.split_once('=') else {
+            return Err(ArgumentError::Format {
+                input: s.to_string(),
+                expected: "ALIAS=TAG",
+            });
+        };
+        validate_name(alias)?;
+        validate_name(tag)?;
+        Ok(AliasAssignment {
+            alias: alias.to_string(),
+            tag: tag.to_string(),
+        })
     }
+}
 
-    pub fn netlify_rewrites(&self, default_alias: String) -> String {
-        let mut result = String::new();
-        let mut default_tag: Option<String> = None;
+impl Display for AliasAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.alias, self.tag)
+    }
+}
 
-        for (alias, tag) in &self.aliases {
-            writeln!(result, "/{}/* /{}/:splat 200", alias, tag)
-                .expect("Failed to write to netlify redirects string");
+/// Which side wins when [`Versions::merge`] finds a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the title or alias target of the versions merged into
+    Ours,
+    /// Take the title or alias target of the versions merged in
+    Theirs,
+    /// Fail, leaving the versions unchanged
+    Error,
+}
 
-            if *alias == default_alias {
-                default_tag = Some(tag.clone());
-            }
-        }
+/// A version or alias both sides of a [`Versions::merge`] have, with different values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// The version has a different title on each side
+    Title {
+        tag: String,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+    /// The alias points at a different version on each side
+    Alias {
+        alias: String,
+        ours: String,
+        theirs: String,
+    },
+}
 
-        if let Some(default_tag) = default_tag {
-            writeln!(result, "/* /{}/:splat 200", default_tag)
-                .expect("Failed to write to netlify redirects string");
+impl Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = |title: &Option<String>| match title {
+            Some(title) => format!("{:?}", title),
+            None => "no title".to_string(),
+        };
+        match self {
+            MergeConflict::Title { tag, ours, theirs } => write!(
+                f,
+                "version {} is titled {} here and {} in the other versions",
+                tag,
+                title(ours),
+                title(theirs)
+            ),
+            MergeConflict::Alias {
+                alias,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "alias {} points at {} here and at {} in the other versions",
+                alias, ours, theirs
+            ),
         }
-
-        result
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct VersionWithAliases {
-    version: String,
-    title: Option<String>,
-    aliases: HashSet<String>,
+/// What [`Versions::merge`] changed, with names in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Tags of the versions added
+    pub added: Vec<String>,
+    /// Aliases added or repointed, with the tag they now point at
+    pub aliases: Vec<(String, String)>,
+    /// The conflicts found, whichever side won
+    pub conflicts: Vec<MergeConflict>,
 }
 
-impl Serialize for Versions {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut seq = serializer.serialize_seq(Some(self.versions.len()))?;
-        let mut versions = self.versions.values().collect::<Vec<_>>();
-        versions.sort();
-        for version in versions {
-            let title = version.title.clone().unwrap_or_else(|| version.tag.clone());
-            seq.serialize_element(&VersionWithAliases {
-                version: version.tag.clone(),
-                title: Some(title),
-                aliases: self
-                    .aliases
-                    .iter()
-                    .filter(|(_, v)| **v == version.tag)
-                    .map(|(a, _)| a.clone())
-                    .collect(),
-            })?;
-        }
-        seq.end()
-    }
+/// Why [`Versions::merge`] failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MergeError {
+    /// The sides conflict and the strategy is [`MergeStrategy::Error`]
+    Conflicts(Vec<MergeConflict>),
+    /// A name of the versions merged in can't be used alongside ours, e.g. it is an alias here
+    Name(NameError),
 }
 
-impl<'de> Deserialize<'de> for Versions {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let items = Vec::<VersionWithAliases>::deserialize(deserializer)?;
-        let mut versions: HashMap<String, Version> = HashMap::with_capacity(items.len());
-        let mut aliases: HashMap<String, String> = HashMap::new();
-        for v in items {
-            if versions
-                .insert(v.version.clone(), Version::new(v.version.clone(), v.title))
-                .is_some()
-            {
-                return Err(de::Error::custom("duplicate version tag"));
-            }
-            for alias in v.aliases {
-                aliases.insert(alias, v.version.clone());
+impl Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::Conflicts(conflicts) => {
+                write!(f, "{} conflicts merging versions", conflicts.len())?;
+                for conflict in conflicts {
+                    write!(f, "\n  {}", conflict)?;
+                }
+                Ok(())
             }
+            MergeError::Name(err) => write!(f, "can't merge versions: {}", err),
         }
-        Ok(Self { versions, aliases })
     }
 }
 
-pub struct VersionsIter<'a> {
-    versions_sorted: Vec<&'a Version>,
-    index: usize,
-    aliases: &'a HashMap<String, String>,
+impl std::error::Error for MergeError {}
+
+impl From<NameError> for MergeError {
+    fn from(err: NameError) -> Self {
+        MergeError::Name(err)
+    }
 }
 
-impl<'a> Iterator for VersionsIter<'a> {
-    type Item = (&'a Version, Vec<&'a str>);
+/// A version whose title changed, as listed by [`Versions::diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetitledVersion {
+    pub tag: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= self.versions_sorted.len() {
-            return None;
-        }
-        let version = self.versions_sorted[self.index];
-        self.index += 1;
+/// An alias and the version it points at, as listed by [`Versions::diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AliasTarget {
+    pub alias: String,
+    pub tag: String,
+}
 
-        let aliases = self
-            .aliases
-            .iter()
-            .filter_map(|(alias, tag)| {
-                if tag == &version.tag {
-                    Some(alias.as_str())
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+/// An alias that points at another version, as listed by [`Versions::diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepointedAlias {
+    pub alias: String,
+    pub from: String,
+    pub to: String,
+}
 
-        Some((version, aliases))
+/// A change of the default alias, as listed by [`Versions::diff`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultAliasChange {
+    pub from: String,
+    pub to: String,
+}
+
+/// The changes from one set of versions to another, made by [`Versions::diff`], with names in
+/// order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionsDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub retitled: Vec<RetitledVersion>,
+    pub aliases_added: Vec<AliasTarget>,
+    pub aliases_removed: Vec<AliasTarget>,
+    pub aliases_repointed: Vec<RepointedAlias>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_alias: Option<DefaultAliasChange>,
+}
+
+impl VersionsDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == VersionsDiff::default()
     }
 }
 
-impl<'a> IntoIterator for &'a Versions {
-    type Item = (&'a Version, Vec<&'a str>);
-    type IntoIter = VersionsIter<'a>;
+impl Display for VersionsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        let title = |title: &Option<String>| match title {
+            Some(title) => format!("{:?}", title),
+            None => "no title".to_string(),
+        };
+        let mut lines = Vec::new();
+        lines.extend(self.added.iter().map(|tag| format!("added {}", tag)));
+        lines.extend(self.removed.iter().map(|tag| format!("removed {}", tag)));
+        lines.extend(self.retitled.iter().map(|retitled| {
+            format!(
+                "retitled {} from {} to {}",
+                retitled.tag,
+                title(&retitled.from),
+                title(&retitled.to)
+            )
+        }));
+        lines.extend(
+            self.aliases_added
+                .iter()
+                .map(|added| format!("added alias {} -> {}", added.alias, added.tag)),
+        );
+        lines.extend(
+            self.aliases_removed
+                .iter()
+                .map(|removed| format!("removed alias {} -> {}", removed.alias, removed.tag)),
+        );
+        lines.extend(self.aliases_repointed.iter().map(|repointed| {
+            format!(
+                "repointed alias {} from {} to {}",
+                repointed.alias, repointed.from, repointed.to
+            )
+        }));
+        lines.extend(self.default_alias.iter().map(|change| {
+            format!(
+                "changed the default alias from {} to {}",
+                change.from, change.to
+            )
+        }));
+        write!(f, "{}", lines.join("\n"))
+    }
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        let mut versions_sorted = self.versions.values().collect::<Vec<_>>();
-        versions_sorted.sort();
-        VersionsIter {
-            versions_sorted,
-            index: 0,
-            aliases: &self.aliases,
+/// A rule of the retention policy kept in versions.json, which deploys apply after adding a
+/// version: of the versions whose tag matches the glob, only the newest `keep` and those
+/// deployed in the last `max_age_days` days are kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRule {
+    #[serde(rename = "match")]
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u64>,
+}
+
+impl Display for RetentionRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.pattern)?;
+        if let Some(keep) = self.keep {
+            write!(f, " keep the newest {}", keep)?;
+        }
+        if let Some(days) = self.max_age_days {
+            let joiner = if self.keep.is_some() { "," } else { "" };
+            write!(f, "{} drop after {} days", joiner, days)?;
         }
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use insta::assert_json_snapshot;
+/// The deployed versions and the aliases pointing at them. Mutations go through [`Versions::add`],
+/// [`Versions::remove`] and the alias methods, which keep every alias pointing at a version.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Versions {
+    versions: HashMap<String, Version>,
+    /// The tags of `versions` in the order they were added (or read from versions.json)
+    order: Vec<String>,
+    aliases: HashMap<String, String>,
+    ordering: OrderingStrategy,
+    format: ManifestFormat,
+    /// The alias the site root is served from, when it isn't [`DEFAULT_ALIAS`]
+    default_alias: Option<String>,
+    retention: Vec<RetentionRule>,
+    /// Top-level fields of versions.json this version of versite doesn't know, written back
+    /// unchanged
+    extra: serde_json::Map<String, serde_json::Value>,
+}
 
-    use super::*;
+impl Versions {
+    /// The deployed versions by tag
+    pub fn versions(&self) -> &HashMap<String, Version> {
+        &self.versions
+    }
 
-    #[test]
-    fn order_semver_and_dev_versions() {
-        let mut versions = vec![
-            Version {
-                tag: "1.2.3".into(),
-                title: None,
-            },
-            Version {
-                tag: "dev".into(),
-                title: None,
-            },
-            Version {
-                tag: "v1.10.0".into(),
-                title: None,
-            },
-            Version {
-                tag: "1.2.10".into(),
-                title: None,
-            },
-            Version {
-                tag: "main".into(),
-                title: None,
-            },
-            Version {
-                tag: "v0.8_or_older".into(),
-                title: None,
-            },
-        ];
-        versions.sort();
+    /// The tag each alias points at
+    pub fn aliases(&self) -> &HashMap<String, String> {
+        &self.aliases
+    }
 
-        assert_json_snapshot!(versions, @r#"
-        [
-          {
-            "version": "main",
-            "title": null
+    /// The order of the versions when iterating, in versions.json and in listings
+    pub fn ordering(&self) -> OrderingStrategy {
+        self.ordering
+    }
+
+    pub fn set_ordering(&mut self, ordering: OrderingStrategy) {
+        self.ordering = ordering;
+    }
+
+    /// How versions.json is laid out when written
+    pub fn manifest_format(&self) -> ManifestFormat {
+        self.format
+    }
+
+    pub fn set_manifest_format(&mut self, format: ManifestFormat) {
+        self.format = format;
+    }
+
+    /// The alias the site root is served from, which version selectors treat as canonical
+    pub fn default_alias(&self) -> &str {
+        self.default_alias.as_deref().unwrap_or(DEFAULT_ALIAS)
+    }
+
+    /// The version the default alias points at, if any
+    pub fn default_version(&self) -> Option<&Version> {
+        self.by_alias(self.default_alias())
+    }
+
+    /// Serve the site root from `alias`, which must point at a version
+    pub fn set_default(&mut self, alias: &str) -> Result<()> {
+        if !self.aliases.contains_key(alias) {
+            return Err(DocverError::AliasNotFound(alias.to_string()));
+        }
+        self.default_alias = (alias != DEFAULT_ALIAS).then(|| alias.to_string());
+        Ok(())
+    }
+
+    /// The versions at `remote_rev` of the repository at `repo_dir`, or none if it has no
+    /// versions file (e.g. on a new branch). A file that can't be read, such as one written by a
+    /// newer versite or a bad manual edit, is an error rather than being replaced on the next
+    /// write.
+    pub fn from_git(backend: &dyn Backend, remote_rev: &str) -> Result<Self> {
+        let Ok(Some(contents)) = backend.read_file(remote_rev, VERSIONS_FILE) else {
+            return Ok(Self::default());
+        };
+        serde_json::from_slice(&contents).map_err(|source| DocverError::Manifest {
+            location: remote_rev.to_string(),
+            source: Some(source),
+        })
+    }
+
+    /// The versions as of `rev`. Unlike [`Versions::from_git`], a revision that doesn't exist or
+    /// has no readable versions file is an error.
+    pub fn at(backend: &dyn Backend, rev: &str) -> Result<Self> {
+        if backend.rev_parse(&format!("{}^{{commit}}", rev)).is_none() {
+            return Err(DocverError::MissingBranch {
+                rev: rev.to_string(),
+                no_fetch: false,
+            });
+        }
+        let manifest = |source| DocverError::Manifest {
+            location: rev.to_string(),
+            source,
+        };
+        let contents = backend
+            .read_file(rev, VERSIONS_FILE)
+            .ok()
+            .flatten()
+            .ok_or_else(|| manifest(None))?;
+        serde_json::from_slice(&contents).map_err(|source| manifest(Some(source)))
+    }
+
+    /// Read a versions file from disk, e.g. from a checkout of the branch. Unlike
+    /// [`Versions::from_git`], a file that can't be parsed is an error.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(DocverError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        serde_json::from_str(&contents).map_err(|source| DocverError::Manifest {
+            location: path.display().to_string(),
+            source: Some(source),
+        })
+    }
+
+    pub fn by_alias(&self, alias: &str) -> Option<&Version> {
+        self.aliases.get(alias).and_then(|v| self.versions.get(v))
+    }
+
+    pub fn by_tag(&self, tag: &str) -> Option<&Version> {
+        self.versions.get(tag)
+    }
+
+    /// The version `identifier` names: the version or alias of that name, or else the one whose
+    /// name matches it ignoring case and a leading "v". `None` when nothing matches, or more than
+    /// one version does.
+    pub fn resolve(&self, identifier: &str) -> Option<&Version> {
+        if let Some(version) = self
+            .by_tag(identifier)
+            .or_else(|| self.by_alias(identifier))
+        {
+            return Some(version);
+        }
+        let key = lookup_key(identifier);
+        match self.matching(|name| lookup_key(name) == key).as_slice() {
+            [version] => Some(version),
+            _ => None,
+        }
+    }
+
+    /// The versions whose tag or one of whose aliases equals `identifier`, or matches it as a
+    /// glob when it contains `*` or `?`, or starts with it when it ends with "." or "-" (e.g.
+    /// "v1." for every v1.x). When nothing equals it, names that match it ignoring case and a
+    /// leading "v" are found instead.
+    pub fn search(&self, identifier: &str) -> Vec<&Version> {
+        if identifier.contains(['*', '?']) {
+            return self.matching(|name| glob_matches(identifier, name));
+        }
+        let key = lookup_key(identifier);
+        if identifier.ends_with(['.', '-']) {
+            return self.matching(|name| lookup_key(name).starts_with(&key));
+        }
+        let found = self.matching(|name| name == identifier);
+        if !found.is_empty() {
+            return found;
+        }
+        self.matching(|name| lookup_key(name) == key)
+    }
+
+    /// The versions whose tag or one of whose aliases matches `regex`
+    pub fn search_regex(&self, regex: &Regex) -> Vec<&Version> {
+        self.matching(|name| regex.is_match(name))
+    }
+
+    fn matching(&self, matches: impl Fn(&str) -> bool) -> Vec<&Version> {
+        let mut found = self
+            .insertion_order()
+            .filter(|v| {
+                matches(&v.tag)
+                    || self
+                        .aliases
+                        .iter()
+                        .any(|(alias, tag)| *tag == v.tag && matches(alias))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| self.ordering.compare(a, b));
+        found
+    }
+
+    /// Add a version, replacing it if the tag exists, and point `aliases` at it. Returns the
+    /// aliases that were repointed from another version, with that version's tag, in alias order.
+    /// Nothing is changed when a name is invalid or an alias would share a version's name. A
+    /// replaced version keeps its deploy details and stays hidden if it was.
+    pub fn add(
+        &mut self,
+        version_tag: String,
+        title: Option<String>,
+        aliases: HashSet<String>,
+    ) -> Result<Vec<(String, String)>, NameError> {
+        validate_name(&version_tag)?;
+        if self.aliases.contains_key(&version_tag) {
+            return Err(NameError::VersionIsAlias(version_tag));
+        }
+        self.check_case(&version_tag)?;
+        let mut sorted = aliases.iter().collect::<Vec<_>>();
+        sorted.sort();
+        for alias in sorted {
+            validate_name(alias)?;
+            if *alias == version_tag || self.versions.contains_key(alias) {
+                return Err(NameError::AliasIsVersion(alias.clone()));
+            }
+            if alias.to_lowercase() == version_tag.to_lowercase() {
+                return Err(NameError::CaseClash(alias.clone(), version_tag));
+            }
+            self.check_case(alias)?;
+        }
+
+        let mut version = Version::new(version_tag.clone(), title);
+        if let Some(existing) = self.versions.get_mut(&version_tag) {
+            version.hidden = existing.hidden;
+            version.created_at = existing.created_at.take();
+            version.updated_at = existing.updated_at.take();
+            version.source_commit = existing.source_commit.take();
+            version.weight = existing.weight;
+            version.extra = std::mem::take(&mut existing.extra);
+        }
+
+        if self.versions.insert(version_tag.clone(), version).is_none() {
+            self.order.push(version_tag.clone());
+        }
+        let mut repointed = aliases
+            .into_iter()
+            .filter_map(|alias| {
+                let previous = self.aliases.insert(alias.clone(), version_tag.clone())?;
+                (previous != version_tag).then_some((alias, previous))
+            })
+            .collect::<Vec<_>>();
+        repointed.sort();
+        Ok(repointed)
+    }
+
+    /// Fail when `name` differs from a version or alias only by case
+    fn check_case(&self, name: &str) -> Result<(), NameError> {
+        let lower = name.to_lowercase();
+        let mut names = self
+            .versions
+            .keys()
+            .chain(self.aliases.keys())
+            .collect::<Vec<_>>();
+        names.sort();
+        match names
+            .into_iter()
+            .find(|existing| *existing != name && existing.to_lowercase() == lower)
+        {
+            Some(existing) => Err(NameError::CaseClash(name.to_string(), existing.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove a version along with the aliases pointing at it, which are returned in alias order
+    pub fn remove(&mut self, tag: &str) -> Option<(Version, Vec<String>)> {
+        let version = self.versions.remove(tag)?;
+        self.order.retain(|t| t != tag);
+        let mut aliases = self
+            .aliases
+            .iter()
+            .filter(|(_, target)| *target == tag)
+            .map(|(alias, _)| alias.clone())
+            .collect::<Vec<_>>();
+        aliases.sort();
+        for alias in &aliases {
+            self.aliases.remove(alias);
+        }
+        Some((version, aliases))
+    }
+
+    /// Rename the version `old` to `new`, repointing its aliases, which are returned in alias
+    /// order. Fails when `old` is not deployed or `new` is already a version or an alias.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<Vec<String>> {
+        if !self.versions.contains_key(old) {
+            return Err(DocverError::VersionNotFound(old.to_string()));
+        }
+        validate_name(new)?;
+        if self.versions.contains_key(new) {
+            return Err(DocverError::VersionExists(new.to_string()));
+        }
+        if let Some(tag) = self.aliases.get(new) {
+            return Err(DocverError::AliasConflict {
+                alias: new.to_string(),
+                tag: tag.clone(),
+            });
+        }
+        if let Err(NameError::CaseClash(_, existing)) = self.check_case(new)
+            && existing != old
+        {
+            return Err(NameError::CaseClash(new.to_string(), existing).into());
+        }
+
+        let mut version = self.versions.remove(old).expect("checked above");
+        version.tag = new.to_string();
+        self.versions.insert(new.to_string(), version);
+        for tag in &mut self.order {
+            if tag == old {
+                *tag = new.to_string();
+            }
+        }
+        let mut aliases = Vec::new();
+        for (alias, tag) in &mut self.aliases {
+            if tag == old {
+                *tag = new.to_string();
+                aliases.push(alias.clone());
+            }
+        }
+        aliases.sort();
+        Ok(aliases)
+    }
+
+    /// Point `alias` at the deployed version `tag`, returning the tag it pointed at before
+    pub fn set_alias(&mut self, alias: String, tag: &str) -> Result<Option<String>> {
+        if !self.versions.contains_key(tag) {
+            return Err(DocverError::VersionNotFound(tag.to_string()));
+        }
+        validate_name(&alias)?;
+        if self.versions.contains_key(&alias) {
+            return Err(NameError::AliasIsVersion(alias).into());
+        }
+        self.check_case(&alias)?;
+        Ok(self.aliases.insert(alias, tag.to_string()))
+    }
+
+    /// The invalid names and name clashes of versions read from the branch, which deserializing
+    /// lets through so they can be reported and fixed rather than losing the file
+    pub fn validate(&self) -> Vec<NameError> {
+        let mut tags = self.versions.keys().collect::<Vec<_>>();
+        tags.sort();
+        let mut aliases = self.aliases.keys().collect::<Vec<_>>();
+        aliases.sort();
+
+        let mut errors = Vec::new();
+        for name in tags.iter().chain(&aliases) {
+            if let Err(err) = validate_name(name) {
+                errors.push(err);
+            }
+        }
+        for alias in aliases {
+            if self.versions.contains_key(alias) {
+                errors.push(NameError::AliasIsVersion(alias.clone()));
+            }
+        }
+        errors
+    }
+
+    /// Record that a version was deployed at `at` (ISO 8601) from `source_commit`: it was last
+    /// deployed then, and first deployed then unless that is already known
+    pub fn record_deploy(
+        &mut self,
+        tag: &str,
+        at: &str,
+        source_commit: Option<&str>,
+    ) -> Result<()> {
+        let Some(version) = self.versions.get_mut(tag) else {
+            return Err(DocverError::VersionNotFound(tag.to_string()));
+        };
+        version.created_at.get_or_insert_with(|| at.to_string());
+        version.updated_at = Some(at.to_string());
+        version.source_commit = source_commit.map(str::to_string);
+        Ok(())
+    }
+
+    /// Set the weight of a version in the [`OrderingStrategy::Explicit`] order
+    pub fn set_weight(&mut self, tag: &str, weight: Option<i64>) -> Result<()> {
+        let Some(version) = self.versions.get_mut(tag) else {
+            return Err(DocverError::VersionNotFound(tag.to_string()));
+        };
+        version.weight = weight;
+        Ok(())
+    }
+
+    /// Add a version that links to the absolute `url` instead of being deployed, replacing the
+    /// version if the tag exists
+    pub fn add_external(&mut self, tag: String, url: String, title: Option<String>) -> Result<()> {
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            return Err(ArgumentError::Format {
+                input: url,
+                expected: "an absolute http(s) URL",
+            }
+            .into());
+        }
+        self.add(tag.clone(), title, HashSet::new())?;
+        self.versions.get_mut(&tag).expect("just added").url = Some(url);
+        Ok(())
+    }
+
+    /// Hide a version from listings and version selectors, or show it again
+    pub fn set_hidden(&mut self, tag: &str, hidden: bool) -> Result<()> {
+        let Some(version) = self.versions.get_mut(tag) else {
+            return Err(DocverError::VersionNotFound(tag.to_string()));
+        };
+        version.hidden = hidden;
+        Ok(())
+    }
+
+    /// Remove an alias, returning the tag it pointed at
+    pub fn remove_alias(&mut self, alias: &str) -> Option<String> {
+        self.aliases.remove(alias)
+    }
+
+    /// The version with the highest semver-like tag, ignoring prereleases unless
+    /// `include_prereleases` is set. Tags that parse to the same version are tie-broken by the
+    /// lexicographically greatest tag so the result is deterministic.
+    pub fn latest_semver(&self, include_prereleases: bool) -> Option<&Version> {
+        self.versions
+            .values()
+            .filter_map(|v| parse_semver_like(&v.tag).map(|semver| (semver, v)))
+            .filter(|(semver, _)| include_prereleases || semver.pre.is_empty())
+            .max_by(|(a, va), (b, vb)| a.cmp(b).then_with(|| va.tag.cmp(&vb.tag)))
+            .map(|(_, v)| v)
+    }
+
+    /// Point the alias of `tag`'s release channel at the newest version in that channel, which
+    /// may leave it unchanged. Returns the alias and the tag it now points at, or `None` when the
+    /// tag is not semver-like or its channel has no alias.
+    pub fn update_channel(&mut self, tag: &str, channels: &Channels) -> Option<(String, String)> {
+        let channel = channel_of(tag)?;
+        let alias = channels.alias(&channel)?.to_string();
+
+        let newest = self
+            .versions
+            .values()
+            .filter(|v| channel_of(&v.tag).as_deref() == Some(channel.as_str()))
+            .filter_map(|v| parse_semver_like(&v.tag).map(|semver| (semver, v)))
+            .max_by(|(a, va), (b, vb)| a.cmp(b).then_with(|| va.tag.cmp(&vb.tag)))
+            .map(|(_, v)| v.tag.clone())?;
+
+        self.aliases.insert(alias.clone(), newest.clone());
+        Some((alias, newest))
+    }
+
+    /// Remove all but the newest `keep` versions whose tag matches the glob `pattern`, returning
+    /// the removed tags. Versions are ordered as in [`newest_first`]. Aliased versions and the
+    /// `except` tag are never removed.
+    pub fn prune(&mut self, pattern: &str, keep: usize, except: &str) -> Vec<String> {
+        let aliased = self.aliases.values().collect::<HashSet<_>>();
+        let mut candidates = self
+            .versions
+            .values()
+            .filter(|v| glob_matches(pattern, &v.tag))
+            .filter(|v| !aliased.contains(&v.tag))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| newest_first(a, b));
+
+        let removed = candidates
+            .into_iter()
+            .skip(keep)
+            .map(|v| v.tag.clone())
+            .filter(|tag| tag != except)
+            .collect::<Vec<_>>();
+        for tag in &removed {
+            self.remove(tag);
+        }
+        removed
+    }
+
+    /// The retention policy, applied by [`Versions::apply_retention`]
+    pub fn retention(&self) -> &[RetentionRule] {
+        &self.retention
+    }
+
+    /// Add a retention rule, replacing the rule with the same pattern
+    pub fn set_retention(&mut self, rule: RetentionRule) {
+        match self
+            .retention
+            .iter_mut()
+            .find(|r| r.pattern == rule.pattern)
+        {
+            Some(existing) => *existing = rule,
+            None => self.retention.push(rule),
+        }
+    }
+
+    /// Remove the retention rule for `pattern`, returning whether there was one
+    pub fn remove_retention(&mut self, pattern: &str) -> bool {
+        let before = self.retention.len();
+        self.retention.retain(|rule| rule.pattern != pattern);
+        self.retention.len() < before
+    }
+
+    /// Remove the versions the retention policy no longer keeps as of `now` (unix seconds),
+    /// returning the removed tags. Like [`Versions::prune`], aliased versions (and so the
+    /// default version) and the `except` tag are never removed, and versions without a deploy
+    /// date never expire.
+    pub fn apply_retention(&mut self, now: i64, except: &str) -> Vec<String> {
+        let aliased = self.aliases.values().collect::<HashSet<_>>();
+        let mut removed = Vec::new();
+        for rule in &self.retention {
+            let mut candidates = self
+                .versions
+                .values()
+                .filter(|v| glob_matches(&rule.pattern, &v.tag))
+                .filter(|v| !aliased.contains(&v.tag))
+                .collect::<Vec<_>>();
+            candidates.sort_by(|a, b| newest_first(a, b));
+
+            for (newer, version) in candidates.into_iter().enumerate() {
+                let too_many = rule.keep.is_some_and(|keep| newer >= keep);
+                let too_old = rule.max_age_days.is_some_and(|days| {
+                    version
+                        .deployed_at()
+                        .is_some_and(|at| now - at > days as i64 * 86_400)
+                });
+                if (too_many || too_old) && version.tag != except && !removed.contains(&version.tag)
+                {
+                    removed.push(version.tag.clone());
+                }
+            }
+        }
+        for tag in &removed {
+            self.remove(tag);
+        }
+        removed
+    }
+
+    /// Keep only the versions `f` accepts, dropping the aliases of the others
+    pub fn retain(&mut self, mut f: impl FnMut(&Version) -> bool) {
+        self.versions.retain(|_, version| f(version));
+        let versions = &self.versions;
+        self.order.retain(|tag| versions.contains_key(tag));
+        self.aliases.retain(|_, tag| versions.contains_key(tag));
+    }
+
+    /// The changes that turn these versions into `other`
+    pub fn diff(&self, other: &Versions) -> VersionsDiff {
+        let mut diff = VersionsDiff::default();
+        for (tag, version) in &other.versions {
+            match self.versions.get(tag) {
+                None => diff.added.push(tag.clone()),
+                Some(ours) if ours.title != version.title => diff.retitled.push(RetitledVersion {
+                    tag: tag.clone(),
+                    from: ours.title.clone(),
+                    to: version.title.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.removed = self
+            .versions
+            .keys()
+            .filter(|tag| !other.versions.contains_key(*tag))
+            .cloned()
+            .collect();
+        for (alias, tag) in &other.aliases {
+            match self.aliases.get(alias) {
+                None => diff.aliases_added.push(AliasTarget {
+                    alias: alias.clone(),
+                    tag: tag.clone(),
+                }),
+                Some(ours) if ours != tag => diff.aliases_repointed.push(RepointedAlias {
+                    alias: alias.clone(),
+                    from: ours.clone(),
+                    to: tag.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        for (alias, tag) in &self.aliases {
+            if !other.aliases.contains_key(alias) {
+                diff.aliases_removed.push(AliasTarget {
+                    alias: alias.clone(),
+                    tag: tag.clone(),
+                });
+            }
+        }
+
+        if self.default_alias() != other.default_alias() {
+            diff.default_alias = Some(DefaultAliasChange {
+                from: self.default_alias().to_string(),
+                to: other.default_alias().to_string(),
+            });
+        }
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.retitled.sort_by(|a, b| a.tag.cmp(&b.tag));
+        diff.aliases_added.sort_by(|a, b| a.alias.cmp(&b.alias));
+        diff.aliases_removed.sort_by(|a, b| a.alias.cmp(&b.alias));
+        diff.aliases_repointed.sort_by(|a, b| a.alias.cmp(&b.alias));
+        diff
+    }
+
+    /// Add the versions and aliases of `other`, e.g. ones another pipeline deployed to the same
+    /// branch. A version both sides have stays ours unless the titles differ and `strategy` is
+    /// [`MergeStrategy::Theirs`], and likewise for an alias pointing at different versions.
+    /// Aliases of `other` that point at none of its versions are skipped. Nothing is changed when
+    /// this fails.
+    pub fn merge(
+        &mut self,
+        other: Versions,
+        strategy: MergeStrategy,
+    ) -> Result<MergeReport, MergeError> {
+        let mut merged = self.clone();
+        let mut report = MergeReport::default();
+
+        let mut theirs = other.versions.into_values().collect::<Vec<_>>();
+        theirs.sort_by(|a, b| a.tag.cmp(&b.tag));
+        for version in theirs {
+            let Some(ours) = merged.versions.get_mut(&version.tag) else {
+                validate_name(&version.tag)?;
+                if merged.aliases.contains_key(&version.tag) {
+                    return Err(NameError::VersionIsAlias(version.tag).into());
+                }
+                merged.check_case(&version.tag)?;
+                report.added.push(version.tag.clone());
+                merged.order.push(version.tag.clone());
+                merged.versions.insert(version.tag.clone(), version);
+                continue;
+            };
+            if ours.title != version.title {
+                report.conflicts.push(MergeConflict::Title {
+                    tag: version.tag.clone(),
+                    ours: ours.title.clone(),
+                    theirs: version.title.clone(),
+                });
+                if strategy == MergeStrategy::Theirs {
+                    *ours = version;
+                }
+            }
+        }
+
+        let mut aliases = other.aliases.into_iter().collect::<Vec<_>>();
+        aliases.sort();
+        for (alias, tag) in aliases {
+            if !merged.versions.contains_key(&tag) {
+                continue;
+            }
+            match merged.aliases.get(&alias) {
+                Some(ours) if *ours == tag => continue,
+                Some(ours) => {
+                    report.conflicts.push(MergeConflict::Alias {
+                        alias: alias.clone(),
+                        ours: ours.clone(),
+                        theirs: tag.clone(),
+                    });
+                    if strategy != MergeStrategy::Theirs {
+                        continue;
+                    }
+                }
+                None => {
+                    validate_name(&alias)?;
+                    if merged.versions.contains_key(&alias) {
+                        return Err(NameError::AliasIsVersion(alias).into());
+                    }
+                    merged.check_case(&alias)?;
+                }
+            }
+            merged.aliases.insert(alias.clone(), tag.clone());
+            report.aliases.push((alias, tag));
+        }
+
+        for (key, value) in other.extra {
+            merged.extra.entry(key).or_insert(value);
+        }
+
+        if strategy == MergeStrategy::Error && !report.conflicts.is_empty() {
+            return Err(MergeError::Conflicts(report.conflicts));
+        }
+        *self = merged;
+        Ok(report)
+    }
+
+    /// The files (relative to the branch root) with the rules serving every alias from its
+    /// version, in alias order, and the site root from the default alias
+    pub fn rewrites(
+        &self,
+        format: RewriteFormat,
+        options: &RewriteOptions,
+    ) -> Vec<(PathBuf, String)> {
+        match format {
+            RewriteFormat::Netlify => {
+                vec![(PathBuf::from("_redirects"), self.netlify_rewrites(options))]
+            }
+        }
+    }
+
+    /// The aliases of versions that still exist on the branch, with their tags, in alias order
+    fn live_aliases(&self) -> Vec<(&String, &String)> {
+        let mut aliases = self
+            .aliases
+            .iter()
+            .filter(|(_, tag)| self.versions.get(*tag).is_some_and(|v| !v.is_external()))
+            .collect::<Vec<_>>();
+        aliases.sort();
+        aliases
+    }
+
+    /// Netlify rewrite rules for every alias, plus the site root pointing at the default alias
+    /// last
+    fn netlify_rewrites(&self, options: &RewriteOptions) -> String {
+        let mut result = String::new();
+        let base = match options.prefix.trim_matches('/') {
+            "" => "/".to_string(),
+            prefix => format!("/{}/", prefix),
+        };
+        for (alias, tag) in self.live_aliases() {
+            let status = options.alias_status;
+            writeln!(result, "{base}{}/* {base}{}/:splat {status}", alias, tag)
+                .expect("Failed to write to netlify redirects string");
+        }
+
+        if let Some(default_tag) = self
+            .default_version()
+            .filter(|version| !version.is_external())
+            .map(|version| &version.tag)
+        {
+            let status = options.root_status;
+            writeln!(result, "{base}* {base}{}/:splat {status}", default_tag)
+                .expect("Failed to write to netlify redirects string");
+        }
+
+        result
+    }
+
+    /// A versions.js script exposing the versions (the entries of versions.json, without the
+    /// hidden ones) and the default alias to client-side version switchers
+    pub fn versions_js(&self) -> serde_json::Result<String> {
+        Ok(format!(
+            "window.DOCVER_VERSIONS = {};\nwindow.DOCVER_DEFAULT_ALIAS = {};\n",
+            serde_json::to_string(&self.visible_entries())?,
+            serde_json::to_string(self.default_alias())?
+        ))
+    }
+
+    /// The bare list of entries versions.js exposes, for the copies of versions.json in each
+    /// version directory that switchers fetch relative to the page
+    pub fn entries_json(&self) -> serde_json::Result<String> {
+        let mut json = serde_json::to_string(&self.visible_entries())?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    /// The entries of version switchers: titled, and without the hidden versions
+    fn visible_entries(&self) -> Vec<VersionWithAliases> {
+        let mut entries = self.entries(true);
+        entries.retain(|entry| !entry.hidden);
+        entries
+    }
+
+    /// A robots.txt that lets crawlers index the version the default alias points at (and the
+    /// versions in `allow`) while disallowing every other version and its aliases
+    pub fn robots_txt(&self, prefix: &str, allow: &[String]) -> String {
+        let base = match prefix.trim_matches('/') {
+            "" => "/".to_string(),
+            prefix => format!("/{}/", prefix),
+        };
+        let default_tag = self.aliases.get(self.default_alias());
+
+        let mut result = String::new();
+        if !allow.is_empty() {
+            writeln!(result, "{} {}", ROBOTS_ALLOW_COMMENT, allow.join(" "))
+                .expect("Failed to write to robots.txt string");
+        }
+        result.push_str("User-agent: *\n");
+        for (version, mut aliases) in self {
+            if version.is_external() {
+                continue;
+            }
+            aliases.sort();
+            let allowed = Some(&version.tag) == default_tag || allow.contains(&version.tag);
+            let rule = if allowed { "Allow" } else { "Disallow" };
+            for path in std::iter::once(version.tag.as_str()).chain(aliases) {
+                writeln!(result, "{}: {base}{}/", rule, path)
+                    .expect("Failed to write to robots.txt string");
+            }
+        }
+        result
+    }
+}
+
+/// Comment in a generated robots.txt recording the `--robots-allow` versions, so redeploys
+/// can regenerate it with the same allow-list
+const ROBOTS_ALLOW_COMMENT: &str = "# Allowed versions:";
+
+/// The versions recorded as allowed in a robots.txt generated by [`Versions::robots_txt`]
+pub fn robots_allow_list(robots_txt: &str) -> Vec<String> {
+    robots_txt
+        .lines()
+        .find_map(|line| line.strip_prefix(ROBOTS_ALLOW_COMMENT))
+        .map(|tags| tags.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct VersionWithAliases {
+    version: String,
+    title: Option<String>,
+    aliases: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<i64>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Formats of other tools' versions.json that versite can write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compat {
+    /// The bare list of versions on one line, as mike writes it for mkdocs-material's version
+    /// selector
+    Mike,
+}
+
+/// Formats JSON like Python's `json.dumps` defaults: ", " and ": " separators, and characters
+/// outside of printable ASCII escaped
+struct PythonFormatter;
+
+impl serde_json::ser::Formatter for PythonFormatter {
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        writer.write_all(b": ")
+    }
+
+    fn write_string_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + io::Write,
+    {
+        for c in fragment.chars() {
+            if c.is_ascii() && c != '\x7f' {
+                writer.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    writer.write_all(format!("\\u{:04x}", unit).as_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The versions.json object of schema 2 and up
+#[derive(Serialize)]
+struct VersionsFile<'a> {
+    schema: u32,
+    #[serde(skip_serializing_if = "is_default_ordering")]
+    ordering: OrderingStrategy,
+    #[serde(skip_serializing_if = "is_default_format")]
+    format: ManifestFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    retention: &'a [RetentionRule],
+    versions: Vec<VersionWithAliases>,
+    #[serde(flatten)]
+    extra: &'a serde_json::Map<String, serde_json::Value>,
+}
+
+fn is_default_ordering(ordering: &OrderingStrategy) -> bool {
+    *ordering == OrderingStrategy::default()
+}
+
+fn is_default_format(format: &ManifestFormat) -> bool {
+    *format == ManifestFormat::default()
+}
+
+/// The contents of a versions.json of any schema
+#[derive(Default)]
+struct ReadVersionsFile {
+    items: Vec<VersionWithAliases>,
+    ordering: OrderingStrategy,
+    format: ManifestFormat,
+    default: Option<String>,
+    retention: Vec<RetentionRule>,
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Versions {
+    /// The contents of versions.json, or of the file `compat`'s tool writes. versions.json ends
+    /// with a newline, and its fields are always in the same order, so rewriting an unchanged
+    /// file gives the same bytes.
+    pub fn to_json(&self, compat: Option<Compat>) -> serde_json::Result<String> {
+        match compat {
+            None => {
+                let mut json = match self.format {
+                    ManifestFormat::Pretty => serde_json::to_string_pretty(self)?,
+                    ManifestFormat::Compact => serde_json::to_string(self)?,
+                };
+                json.push('\n');
+                Ok(json)
+            }
+            Some(Compat::Mike) => {
+                let mut json = Vec::new();
+                let mut serializer =
+                    serde_json::Serializer::with_formatter(&mut json, PythonFormatter);
+                // mike always writes a title, falling back to the version
+                self.entries(true).serialize(&mut serializer)?;
+                Ok(String::from_utf8(json).expect("serde_json writes UTF-8"))
+            }
+        }
+    }
+
+    /// The versions in the order they were added, or read from versions.json
+    fn insertion_order(&self) -> impl Iterator<Item = &Version> {
+        self.order.iter().map(|tag| &self.versions[tag])
+    }
+
+    /// The versions sorted by the ordering strategy. Ties keep insertion order, so the result
+    /// doesn't depend on hashing.
+    fn sorted(&self) -> Vec<&Version> {
+        let mut versions = self.insertion_order().collect::<Vec<_>>();
+        versions.sort_by(|a, b| self.ordering.compare(a, b));
+        versions
+    }
+
+    /// Iterate over the versions and their aliases in the order the versions were added, or read
+    /// from versions.json, regardless of the ordering strategy
+    pub fn iter_insertion_order(&self) -> VersionsIter<'_> {
+        VersionsIter {
+            versions_sorted: self.insertion_order().collect(),
+            index: 0,
+            aliases: &self.aliases,
+        }
+    }
+
+    /// The versions.json entries, newest first. Versions without a title are titled by their tag
+    /// with `fallback_titles`, for version selectors, and otherwise have a null title.
+    fn entries(&self, fallback_titles: bool) -> Vec<VersionWithAliases> {
+        self.sorted()
+            .into_iter()
+            .map(|version| VersionWithAliases {
+                version: version.tag.clone(),
+                title: match fallback_titles {
+                    true => Some(version.title.clone().unwrap_or_else(|| version.tag.clone())),
+                    false => version.title.clone(),
+                },
+                aliases: self
+                    .aliases
+                    .iter()
+                    .filter(|(_, v)| **v == version.tag)
+                    .map(|(a, _)| a.clone())
+                    .collect(),
+                url: version.url.clone(),
+                hidden: version.hidden,
+                created_at: version.created_at.clone(),
+                updated_at: version.updated_at.clone(),
+                source_commit: version.source_commit.clone(),
+                weight: version.weight,
+                extra: version.extra.clone(),
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Versions {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        VersionsFile {
+            schema: SCHEMA_VERSION,
+            ordering: self.ordering,
+            format: self.format,
+            default: self.default_alias.as_deref(),
+            retention: &self.retention,
+            versions: self.entries(false),
+            extra: &self.extra,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Reads both the versions.json object and the bare list of schema 1
+struct VersionsFileVisitor;
+
+impl<'de> de::Visitor<'de> for VersionsFileVisitor {
+    type Value = ReadVersionsFile;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a list of versions or an object with a schema and versions"
+        )
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let items = Vec::deserialize(de::value::SeqAccessDeserializer::new(seq))?;
+        Ok(ReadVersionsFile {
+            items,
+            ..Default::default()
+        })
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut schema = None;
+        let mut file = ReadVersionsFile::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "schema" => {
+                    let value = map.next_value::<u32>()?;
+                    if value > SCHEMA_VERSION {
+                        return Err(de::Error::custom(format!(
+                            "{} has schema {}, but this versite only reads schemas up to {}; please upgrade versite",
+                            VERSIONS_FILE, value, SCHEMA_VERSION
+                        )));
+                    }
+                    schema = Some(value);
+                }
+                "ordering" => file.ordering = map.next_value()?,
+                "format" => file.format = map.next_value()?,
+                "default" => file.default = map.next_value()?,
+                "retention" => file.retention = map.next_value()?,
+                "versions" => file.items = map.next_value()?,
+                _ => {
+                    file.extra.insert(key, map.next_value()?);
+                }
+            }
+        }
+        if schema.is_none() {
+            return Err(de::Error::missing_field("schema"));
+        }
+        Ok(file)
+    }
+}
+
+impl<'de> Deserialize<'de> for Versions {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let file = deserializer.deserialize_any(VersionsFileVisitor)?;
+        let mut versions: HashMap<String, Version> = HashMap::with_capacity(file.items.len());
+        let mut order = Vec::with_capacity(file.items.len());
+        let mut aliases: HashMap<String, String> = HashMap::new();
+        // Tags that differ only by case would share a directory on case-insensitive filesystems
+        let mut lowercase_tags: HashMap<String, String> = HashMap::new();
+        for v in file.items {
+            if let Some(existing) =
+                lowercase_tags.insert(v.version.to_lowercase(), v.version.clone())
+                && existing != v.version
+            {
+                return Err(de::Error::custom(NameError::CaseClash(v.version, existing)));
+            }
+            let version = Version {
+                tag: v.version.clone(),
+                title: v.title,
+                url: v.url,
+                hidden: v.hidden,
+                created_at: v.created_at,
+                updated_at: v.updated_at,
+                source_commit: v.source_commit,
+                weight: v.weight,
+                extra: v.extra,
+            };
+            if versions.insert(v.version.clone(), version).is_some() {
+                return Err(de::Error::custom("duplicate version tag"));
+            }
+            order.push(v.version.clone());
+            for alias in v.aliases {
+                aliases.insert(alias, v.version.clone());
+            }
+        }
+        Ok(Self {
+            versions,
+            order,
+            aliases,
+            ordering: file.ordering,
+            format: file.format,
+            default_alias: file.default,
+            retention: file.retention,
+            extra: file.extra,
+        })
+    }
+}
+
+pub struct VersionsIter<'a> {
+    versions_sorted: Vec<&'a Version>,
+    index: usize,
+    aliases: &'a HashMap<String, String>,
+}
+
+impl<'a> Iterator for VersionsIter<'a> {
+    type Item = (&'a Version, Vec<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.versions_sorted.len() {
+            return None;
+        }
+        let version = self.versions_sorted[self.index];
+        self.index += 1;
+
+        let aliases = self
+            .aliases
+            .iter()
+            .filter_map(|(alias, tag)| {
+                if tag == &version.tag {
+                    Some(alias.as_str())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Some((version, aliases))
+    }
+}
+
+impl<'a> IntoIterator for &'a Versions {
+    type Item = (&'a Version, Vec<&'a str>);
+    type IntoIter = VersionsIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        VersionsIter {
+            versions_sorted: self.sorted(),
+            index: 0,
+            aliases: &self.aliases,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use insta::assert_json_snapshot;
+
+    use super::*;
+    use crate::test_support::Scratch;
+
+    #[test]
+    fn order_semver_and_dev_versions() {
+        let mut versions = vec![
+            Version::new("1.2.3".into(), None),
+            Version::new("dev".into(), None),
+            Version::new("v1.10.0".into(), None),
+            Version::new("1.2.10".into(), None),
+            Version::new("main".into(), None),
+            Version::new("v0.8_or_older".into(), None),
+        ];
+        versions.sort();
+
+        assert_json_snapshot!(versions, @r#"
+        [
+          {
+            "version": "main",
+            "title": null
           },
           {
             "version": "dev",
@@ -331,78 +1979,1547 @@ mod tests {
     }
 
     #[test]
-    fn serialize_versions_sorted() {
+    fn serialize_versions_sorted() {
+        let mut versions = Versions::default();
+        versions
+            .add("1.0.0".into(), Some("1.0.0 title".into()), HashSet::new())
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        versions
+            .add("alpha".into(), Some("alpha title".into()), HashSet::new())
+            .unwrap();
+
+        assert_json_snapshot!(versions, @r#"
+        {
+          "schema": 2,
+          "versions": [
+            {
+              "version": "alpha",
+              "title": "alpha title",
+              "aliases": []
+            },
+            {
+              "version": "v2.0.0",
+              "title": null,
+              "aliases": [
+                "stable"
+              ]
+            },
+            {
+              "version": "1.0.0",
+              "title": "1.0.0 title",
+              "aliases": []
+            }
+          ]
+        }
+        "#);
+    }
+
+    #[test]
+    fn ordering_strategies() {
+        let mut versions = Versions::default();
+        for tag in [
+            "1.2.3",
+            "dev",
+            "v1.10.0",
+            "1.2.10",
+            "main",
+            "v1.9.0",
+            "v2.0.0-rc.1",
+        ] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+        versions.set_weight("main", Some(-1)).unwrap();
+        versions.set_weight("1.2.3", Some(10)).unwrap();
+
+        let orders = [
+            OrderingStrategy::SemverDesc,
+            OrderingStrategy::SemverAsc,
+            OrderingStrategy::Natural,
+            OrderingStrategy::Explicit,
+        ]
+        .map(|ordering| {
+            versions.set_ordering(ordering);
+            let tags = (&versions)
+                .into_iter()
+                .map(|(version, _)| version.tag.as_str())
+                .collect::<Vec<_>>();
+            format!("{:?}: {}", ordering, tags.join(" "))
+        });
+        insta::assert_snapshot!(orders.join("\n"), @r"
+        SemverDesc: main dev v2.0.0-rc.1 v1.10.0 v1.9.0 1.2.10 1.2.3
+        SemverAsc: main dev 1.2.3 1.2.10 v1.9.0 v1.10.0 v2.0.0-rc.1
+        Natural: 1.2.3 1.2.10 dev main v1.9.0 v1.10.0 v2.0.0-rc.1
+        Explicit: 1.2.3 dev v2.0.0-rc.1 v1.10.0 v1.9.0 1.2.10 main
+        ");
+    }
+
+    #[test]
+    fn version_arguments_round_trip() {
+        let parse = |s: &str| s.parse::<Version>().map(|v| (v.tag, v.title));
+        assert_eq!(parse("v1.0"), Ok(("v1.0".into(), None)));
+        assert_eq!(
+            parse("v2.0:Version 2: the sequel"),
+            Ok(("v2.0".into(), Some("Version 2: the sequel".into())))
+        );
+        assert_eq!(parse("v3:"), Ok(("v3".into(), Some("".into()))));
+        assert_eq!(
+            parse("release\\:3:Three"),
+            Ok(("release:3".into(), Some("Three".into())))
+        );
+        assert_eq!(
+            parse(":Untagged"),
+            Err(ArgumentError::Name(NameError::Empty))
+        );
+        assert!(parse("../x:Title").is_err());
+
+        for s in [
+            "v1.0",
+            "v2.0:Version 2: the sequel",
+            "v3:",
+            "release\\:3:Three",
+        ] {
+            assert_eq!(s.parse::<Version>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn alias_assignments() {
+        let assignment = "latest=v2.0".parse::<AliasAssignment>().unwrap();
+        assert_eq!(
+            assignment,
+            AliasAssignment {
+                alias: "latest".into(),
+                tag: "v2.0".into()
+            }
+        );
+        assert_eq!(assignment.to_string(), "latest=v2.0");
+        assert_eq!(
+            "latest".parse::<AliasAssignment>().unwrap_err().to_string(),
+            r#"invalid value "latest": expected ALIAS=TAG"#
+        );
+        assert_eq!(
+            "=v2.0".parse::<AliasAssignment>(),
+            Err(ArgumentError::Name(NameError::Empty))
+        );
+        assert!("latest=a/b".parse::<AliasAssignment>().is_err());
+    }
+
+    #[test]
+    fn unchanged_versions_write_the_same_bytes() {
+        // Unknown fields in any order, which are written back sorted
+        let json = r#"{"zeta": 1, "schema": 2, "alpha": true, "versions": [
+            {"weight": 3, "zz": 0, "version": "v2.0", "aliases": ["stable", "latest"], "title": "Two", "aa": 1},
+            {"title": null, "version": "v1.0", "aliases": []}
+        ]}"#;
+        let versions: Versions = serde_json::from_str(json).unwrap();
+        let written = versions.to_json(None).unwrap();
+        assert!(written.ends_with("}\n"));
+        for _ in 0..3 {
+            let reread: Versions = serde_json::from_str(&written).unwrap();
+            assert_eq!(reread.to_json(None).unwrap(), written);
+            assert_eq!(versions.clone().to_json(None).unwrap(), written);
+        }
+
+        // Redeploying one version only changes its lines, including a comma for the new fields
+        let mut redeployed = versions.clone();
+        redeployed
+            .record_deploy("v1.0", "2024-01-01T00:00:00Z", None)
+            .unwrap();
+        let after = redeployed.to_json(None).unwrap();
+        let changed = after
+            .lines()
+            .filter(|line| !written.lines().any(|before| before == *line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changed,
+            [
+                r#"      "aliases": [],"#,
+                r#"      "created_at": "2024-01-01T00:00:00Z","#,
+                r#"      "updated_at": "2024-01-01T00:00:00Z""#
+            ]
+        );
+
+        let mut compact = versions.clone();
+        compact.set_manifest_format(ManifestFormat::Compact);
+        let written = compact.to_json(None).unwrap();
+        insta::assert_snapshot!(written, @r#"{"schema":2,"format":"compact","versions":[{"version":"v2.0","title":"Two","aliases":["latest","stable"],"weight":3,"aa":1,"zz":0},{"version":"v1.0","title":null,"aliases":[]}],"alpha":true,"zeta":1}"#);
+        assert!(written.ends_with("}\n"));
+        let reread: Versions = serde_json::from_str(&written).unwrap();
+        assert_eq!(reread.manifest_format(), ManifestFormat::Compact);
+        assert_eq!(reread.to_json(None).unwrap(), written);
+    }
+
+    #[test]
+    fn missing_titles_stay_missing() {
+        let mut versions = Versions::default();
+        versions.add("v1.0".into(), None, HashSet::new()).unwrap();
+
+        let json = versions.to_json(None).unwrap();
+        assert!(json.contains(r#""title": null"#));
+        let read: Versions = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.by_tag("v1.0").unwrap().title, None);
+
+        // Version selectors and mike show the tag instead
+        assert!(
+            versions
+                .versions_js()
+                .unwrap()
+                .contains(r#""title":"v1.0""#)
+        );
+        assert_eq!(
+            versions.to_json(Some(Compat::Mike)).unwrap(),
+            r#"[{"version": "v1.0", "title": "v1.0", "aliases": []}]"#
+        );
+    }
+
+    #[test]
+    fn external_versions_link_elsewhere() {
+        let mut versions = Versions::default();
+        versions
+            .add(
+                "v1.0".into(),
+                Some("1.0".into()),
+                HashSet::from(["latest".into()]),
+            )
+            .unwrap();
+        versions
+            .add_external(
+                "v0.9".into(),
+                "https://archive.example.com/v0.9/".into(),
+                Some("0.9".into()),
+            )
+            .unwrap();
+        versions.set_alias("old".into(), "v0.9").unwrap();
+        assert!(
+            versions
+                .add_external("v0.8".into(), "archive/v0.8".into(), None)
+                .is_err()
+        );
+
+        let read: Versions = serde_json::from_str(&versions.to_json(None).unwrap()).unwrap();
+        assert_eq!(read, versions);
+        assert!(versions.versions_js().unwrap().contains(
+            r#"{"version":"v0.9","title":"0.9","aliases":["old"],"url":"https://archive.example.com/v0.9/"}"#
+        ));
+
+        // Nothing is served from the branch for the external version or its aliases
+        assert_eq!(
+            netlify(&versions, &RewriteOptions::default()),
+            "/latest/* /v1.0/:splat 200\n/* /v1.0/:splat 200\n"
+        );
+        assert!(!versions.robots_txt("", &[]).contains("v0.9"));
+
+        // Deploying the version replaces the link
+        versions.add("v0.9".into(), None, HashSet::new()).unwrap();
+        assert!(!versions.by_tag("v0.9").unwrap().is_external());
+    }
+
+    #[test]
+    fn default_alias_is_kept_and_served() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        assert_eq!(versions.default_alias(), "latest");
+        assert!(versions.set_default("next").is_err());
+
+        let before = versions.clone();
+        versions.set_default("stable").unwrap();
+        assert_eq!(versions.default_version().unwrap().tag, "v1.0.0");
+        assert_eq!(
+            before.diff(&versions).to_string(),
+            "changed the default alias from latest to stable"
+        );
+        assert_eq!(
+            netlify(&versions, &RewriteOptions::default()),
+            "/latest/* /v2.0.0/:splat 200\n/stable/* /v1.0.0/:splat 200\n/* /v1.0.0/:splat 200\n"
+        );
+        assert!(
+            versions
+                .versions_js()
+                .unwrap()
+                .ends_with("window.DOCVER_DEFAULT_ALIAS = \"stable\";\n")
+        );
+
+        let json = versions.to_json(None).unwrap();
+        assert!(json.contains("\"default\": \"stable\""));
+        let read: Versions = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.default_alias(), "stable");
+
+        // The built-in default isn't written
+        versions.set_default("latest").unwrap();
+        assert!(!versions.to_json(None).unwrap().contains("\"default\""));
+    }
+
+    #[test]
+    fn insertion_order_is_kept() {
+        let mut versions = Versions::default();
+        for tag in ["v2.0", "dev", "v1.0", "v3.0"] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+        versions
+            .add("v1.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        versions.remove("dev");
+        versions.rename("v2.0", "v2.1").unwrap();
+        let in_order = |versions: &Versions| {
+            versions
+                .iter_insertion_order()
+                .map(|(version, _)| version.tag.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(in_order(&versions), ["v2.1", "v1.0", "v3.0"]);
+
+        let sorted = |versions: &Versions| {
+            versions
+                .into_iter()
+                .map(|(version, _)| version.tag.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(&versions), ["v3.0", "v2.1", "v1.0"]);
+        versions.set_ordering(OrderingStrategy::Manual);
+        assert_eq!(sorted(&versions), ["v2.1", "v1.0", "v3.0"]);
+
+        // Manual ordering is what versions.json keeps, so reading it back keeps the order
+        let json = versions.to_json(None).unwrap();
+        let read: Versions = serde_json::from_str(&json).unwrap();
+        assert_eq!(in_order(&read), ["v2.1", "v1.0", "v3.0"]);
+        assert_eq!(sorted(&read), ["v2.1", "v1.0", "v3.0"]);
+    }
+
+    #[test]
+    fn ordering_is_kept_in_the_envelope() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), Some("One".into()), HashSet::new())
+            .unwrap();
+        versions.set_weight("v1.0.0", Some(2)).unwrap();
+        versions.set_ordering(OrderingStrategy::Explicit);
+        let json = serde_json::to_string(&versions).unwrap();
+        insta::assert_snapshot!(json, @r#"{"schema":2,"ordering":"explicit","versions":[{"version":"v1.0.0","title":"One","aliases":[],"weight":2}]}"#);
+        assert_eq!(serde_json::from_str::<Versions>(&json).unwrap(), versions);
+    }
+
+    #[test]
+    fn deserialize_versions_with_aliases() {
+        let json = r#"[
+            {"version":"dev","title":"Development","aliases":["latest"]},
+            {"version":"1.0.0","title":"1.0.0","aliases":["stable"]}
+        ]"#;
+        let versions: Versions = serde_json::from_str(json).unwrap();
+        dbg!(&versions);
+        assert_json_snapshot!(versions, @r#"
+        {
+          "schema": 2,
+          "versions": [
+            {
+              "version": "dev",
+              "title": "Development",
+              "aliases": [
+                "latest"
+              ]
+            },
+            {
+              "version": "1.0.0",
+              "title": "1.0.0",
+              "aliases": [
+                "stable"
+              ]
+            }
+          ]
+        }
+        "#);
+    }
+
+    #[test]
+    fn latest_semver_skips_prereleases_and_non_semver() {
+        let mut versions = Versions::default();
+        for tag in ["dev", "v1.8.5", "v2.1.0", "v3.0.0-beta.1", "main"] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+
+        assert_eq!(versions.latest_semver(false).unwrap().tag, "v2.1.0");
+        assert_eq!(versions.latest_semver(true).unwrap().tag, "v3.0.0-beta.1");
+    }
+
+    #[test]
+    fn prerelease_and_build_suffixes() {
+        let parse = |tag| parse_semver_like(tag).map(|version| version.to_string());
+        assert_eq!(parse("2.0-rc.1").as_deref(), Some("2.0.0-rc.1"));
+        assert_eq!(parse("v3-alpha").as_deref(), Some("3.0.0-alpha"));
+        assert_eq!(parse("1.2+build.5").as_deref(), Some("1.2.0+build.5"));
+        assert_eq!(parse("0.8_or_older").as_deref(), Some("0.8.0"));
+
+        assert!(is_prerelease("2.0-rc.1"));
+        assert!(is_prerelease("v3.0.0-alpha"));
+        assert!(!is_prerelease("1.2.0+build.5"));
+        assert!(!is_prerelease("main"));
+
+        assert_eq!(release_group("2.0-rc.1"), "2.0.0");
+        assert_eq!(release_group("v2.0.0"), "2.0.0");
+        assert_eq!(release_group("1.2.0+build.5"), "1.2.0");
+        assert_eq!(release_group("main"), "other");
+
+        let mut versions = Versions::default();
+        for tag in [
+            "v1.9.5",
+            "v2.0-rc.1",
+            "v2.0-rc.2",
+            "v2.0.0-alpha",
+            "1.9.6+build.7",
+        ] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+        assert_eq!(versions.latest_semver(false).unwrap().tag, "1.9.6+build.7");
+        assert_eq!(versions.latest_semver(true).unwrap().tag, "v2.0-rc.2");
+        assert_eq!(channel_of("v2.0-rc.2").as_deref(), Some("rc"));
+        let tags: Vec<_> = versions
+            .entries(false)
+            .into_iter()
+            .map(|e| e.version)
+            .collect();
+        assert_eq!(
+            tags,
+            [
+                "v2.0-rc.2",
+                "v2.0-rc.1",
+                "v2.0.0-alpha",
+                "1.9.6+build.7",
+                "v1.9.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn latest_semver_ties_and_empty() {
+        let mut versions = Versions::default();
+        versions.add("dev".into(), None, HashSet::new()).unwrap();
+        assert!(versions.latest_semver(true).is_none());
+
+        versions.add("2.0".into(), None, HashSet::new()).unwrap();
+        versions.add("v2.0.0".into(), None, HashSet::new()).unwrap();
+        assert_eq!(versions.latest_semver(false).unwrap().tag, "v2.0.0");
+    }
+
+    #[test]
+    fn channels_from_prerelease() {
+        assert_eq!(channel_of("1.9.2").as_deref(), Some("stable"));
+        assert_eq!(channel_of("v2.0.0-beta.3").as_deref(), Some("beta"));
+        assert_eq!(channel_of("2.0.0-rc1").as_deref(), Some("rc"));
+        assert_eq!(channel_of("dev"), None);
+    }
+
+    #[test]
+    fn update_channel_tracks_newest_in_channel() {
+        let mut versions = Versions::default();
+        let mut channels = Channels::default();
+        channels.set("beta", "next");
+        for tag in ["1.9.2", "2.0.0-beta.3", "2.0.0-beta.2", "1.9.1", "dev"] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+
+        assert_eq!(
+            versions.update_channel("1.9.1", &channels),
+            Some(("stable".into(), "1.9.2".into()))
+        );
+        assert_eq!(
+            versions.update_channel("2.0.0-beta.2", &channels),
+            Some(("next".into(), "2.0.0-beta.3".into()))
+        );
+        assert_eq!(versions.update_channel("dev", &channels), None);
+        assert_eq!(versions.by_alias("stable").unwrap().tag, "1.9.2");
+        assert_eq!(versions.by_alias("next").unwrap().tag, "2.0.0-beta.3");
+    }
+
+    #[test]
+    fn prune_keeps_newest_unaliased_matches() {
+        let mut versions = Versions::default();
+        for tag in ["pr-1", "pr-2", "pr-3", "pr-9", "pr-10", "pr-15", "v1.0.0"] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+        versions
+            .add("pr-1".into(), None, HashSet::from(["preview".into()]))
+            .unwrap();
+
+        // Undated versions go by tag, with numbers compared by value
+        let mut undated = versions.clone();
+        assert_eq!(undated.prune("pr-*", 2, "pr-2"), ["pr-9", "pr-3"]);
+        let mut remaining = undated.versions.keys().cloned().collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, ["pr-1", "pr-10", "pr-15", "pr-2", "v1.0.0"]);
+
+        // The latest deploy comes first
+        versions
+            .record_deploy("pr-9", "2024-05-02T00:00:00Z", None)
+            .unwrap();
+        versions
+            .record_deploy("pr-3", "2024-05-01T00:00:00Z", None)
+            .unwrap();
+        assert_eq!(versions.prune("pr-*", 2, "pr-2"), ["pr-15", "pr-10"]);
+    }
+
+    #[test]
+    fn retention_rules_are_stored_with_the_versions() {
+        let mut versions = Versions::default();
+        versions.add("v1.0".into(), None, HashSet::new()).unwrap();
+        let rule = |pattern: &str, keep, max_age_days| RetentionRule {
+            pattern: pattern.into(),
+            keep,
+            max_age_days,
+        };
+        versions.set_retention(rule("pr-*", Some(10), Some(30)));
+        versions.set_retention(rule("nightly-*", None, Some(7)));
+        versions.set_retention(rule("pr-*", Some(5), None));
+        assert_eq!(
+            versions
+                .retention()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["pr-*: keep the newest 5", "nightly-*: drop after 7 days"]
+        );
+
+        let json = serde_json::to_string(&versions).unwrap();
+        insta::assert_snapshot!(json, @r#"{"schema":2,"retention":[{"match":"pr-*","keep":5},{"match":"nightly-*","max_age_days":7}],"versions":[{"version":"v1.0","title":null,"aliases":[]}]}"#);
+        assert_eq!(serde_json::from_str::<Versions>(&json).unwrap(), versions);
+        // mike has no retention policy
+        assert!(
+            !versions
+                .to_json(Some(Compat::Mike))
+                .unwrap()
+                .contains("retention")
+        );
+
+        assert!(versions.remove_retention("pr-*"));
+        assert!(!versions.remove_retention("pr-*"));
+        assert_eq!(versions.retention(), [rule("nightly-*", None, Some(7))]);
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_aliased_and_recent_versions() {
+        const DAY: i64 = 86_400;
+        // 2023-11-14T22:13:20Z
+        let now = 1_700_000_000;
+        let iso =
+            |days_ago: i64| crate::git::iso_of(&format!("{} +0000", now - days_ago * DAY)).unwrap();
+
+        let mut versions = Versions::default();
+        for (tag, days_ago) in [
+            ("pr-1", Some(40)),
+            ("pr-2", Some(31)),
+            ("pr-3", Some(29)),
+            ("pr-4", None),
+            ("pr-5", Some(1)),
+            ("nightly-1", Some(9)),
+            ("nightly-2", Some(2)),
+            ("v1.0", Some(400)),
+        ] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+            if let Some(days_ago) = days_ago {
+                versions.record_deploy(tag, &iso(days_ago), None).unwrap();
+            }
+        }
+        versions.set_alias("preview".into(), "pr-1").unwrap();
+        // Only the last deploy counts, falling back to the first
+        versions.versions.get_mut("pr-3").unwrap().created_at = Some(iso(100));
+        versions.versions.get_mut("pr-2").unwrap().updated_at = None;
+
+        let apply = |rules: &[(&str, Option<usize>, Option<u64>)], except: &str| {
+            let mut versions = versions.clone();
+            for (pattern, keep, max_age_days) in rules {
+                versions.set_retention(RetentionRule {
+                    pattern: pattern.to_string(),
+                    keep: *keep,
+                    max_age_days: *max_age_days,
+                });
+            }
+            let removed = versions.apply_retention(now, except);
+            for tag in &removed {
+                assert!(versions.by_tag(tag).is_none());
+            }
+            removed
+        };
+
+        // pr-1 is aliased, and the deployed version never goes. The rest are counted from
+        // the latest deploy, with the undated pr-4 as the oldest.
+        assert_eq!(apply(&[("pr-*", Some(2), None)], "pr-5"), ["pr-2", "pr-4"]);
+        assert_eq!(
+            apply(&[("pr-*", Some(0), None)], "pr-5"),
+            ["pr-3", "pr-2", "pr-4"]
+        );
+        // pr-2's first deploy is 31 days ago; pr-4 was never dated so never expires
+        assert_eq!(apply(&[("pr-*", None, Some(30))], "v1.0"), ["pr-2"]);
+        assert!(apply(&[("pr-*", None, Some(31))], "v1.0").is_empty());
+        // Either limit removes a version, and a version matched by two rules goes once
+        assert_eq!(
+            apply(
+                &[
+                    ("pr-*", Some(3), Some(30)),
+                    ("*-*", None, Some(7)),
+                    ("v*", Some(1), None)
+                ],
+                "nightly-2"
+            ),
+            ["pr-2", "pr-4", "nightly-1", "pr-3"]
+        );
+        assert!(apply(&[], "v1.0").is_empty());
+        assert_eq!(versions.versions().len(), 8);
+    }
+
+    #[test]
+    fn from_file_reports_the_parse_error() {
+        let dir = Scratch::dir("versions-file");
+        let path = dir.join(VERSIONS_FILE);
+        fs::write(
+            &path,
+            "[\n  {\"version\": \"v1\", \"aliases\": []},\n  {\"version\": }\n]",
+        )
+        .unwrap();
+        let err = Versions::from_file(&path).unwrap_err();
+        fs::write(
+            &path,
+            r#"[{"version": "v1", "title": null, "aliases": ["latest"]}]"#,
+        )
+        .unwrap();
+        let versions = Versions::from_file(&path).unwrap();
+
+        assert!(err.to_string().ends_with("(line 3, column 15)"));
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "expected value at line 3 column 15"
+        );
+        assert_eq!(versions.by_alias("latest").unwrap().tag, "v1");
+    }
+
+    #[test]
+    fn major_groups() {
+        assert_eq!(major_group("v1.2.3"), "1.x");
+        assert_eq!(major_group("1.2"), "1.x");
+        assert_eq!(major_group("2"), "2.x");
+        assert_eq!(major_group("0.8_or_older"), "0.x");
+        assert_eq!(major_group("3.0.0-rc.1"), "3.x");
+        assert_eq!(major_group("main"), "other");
+    }
+
+    #[test]
+    fn search_exact_glob_and_regex() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        versions.add("v1.1.0".into(), None, HashSet::new()).unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        versions.add("dev".into(), None, HashSet::new()).unwrap();
+        let tags = |found: Vec<&Version>| found.iter().map(|v| v.tag.clone()).collect::<Vec<_>>();
+
+        assert_eq!(tags(versions.search("v1.1.0")), ["v1.1.0"]);
+        // An alias only finds the version it points at
+        assert_eq!(tags(versions.search("latest")), ["v2.0.0"]);
+        assert_eq!(tags(versions.search("stable")), ["v1.0.0"]);
+        assert!(versions.search("v1").is_empty());
+        assert!(versions.search("v3.0.0").is_empty());
+        assert_eq!(tags(versions.search("v1.*")), ["v1.1.0", "v1.0.0"]);
+        assert_eq!(tags(versions.search("v1.")), ["v1.1.0", "v1.0.0"]);
+        assert_eq!(tags(versions.search("1.")), ["v1.1.0", "v1.0.0"]);
+        assert_eq!(tags(versions.search("v2.0.0-")), Vec::<String>::new());
+        assert_eq!(tags(versions.search("sta*")), ["v1.0.0"]);
+        let regex = Regex::new(r"^v\d\.0\.0$|^lat").unwrap();
+        assert_eq!(tags(versions.search_regex(&regex)), ["v2.0.0", "v1.0.0"]);
+    }
+
+    #[test]
+    fn diff_lists_each_change() {
+        let mut before = Versions::default();
+        before
+            .add("v1.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        before
+            .add("v2.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        assert!(before.diff(&before).is_empty());
+        assert_eq!(before.diff(&before).to_string(), "no changes");
+
+        let mut after = before.clone();
+        after.remove("v1.0");
+        after
+            .add("v2.0".into(), Some("Two".into()), HashSet::new())
+            .unwrap();
+        after
+            .add(
+                "v3.0".into(),
+                None,
+                HashSet::from(["latest".into(), "next".into()]),
+            )
+            .unwrap();
+        after.set_alias("old".into(), "v2.0").unwrap();
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.to_string(),
+            "added v3.0
+removed v1.0
+retitled v2.0 from no title to \"Two\"
+added alias next -> v3.0
+added alias old -> v2.0
+removed alias stable -> v1.0
+repointed alias latest from v2.0 to v3.0"
+        );
+        assert_json_snapshot!(diff, @r#"
+        {
+          "added": [
+            "v3.0"
+          ],
+          "removed": [
+            "v1.0"
+          ],
+          "retitled": [
+            {
+              "tag": "v2.0",
+              "from": null,
+              "to": "Two"
+            }
+          ],
+          "aliases_added": [
+            {
+              "alias": "next",
+              "tag": "v3.0"
+            },
+            {
+              "alias": "old",
+              "tag": "v2.0"
+            }
+          ],
+          "aliases_removed": [
+            {
+              "alias": "stable",
+              "tag": "v1.0"
+            }
+          ],
+          "aliases_repointed": [
+            {
+              "alias": "latest",
+              "from": "v2.0",
+              "to": "v3.0"
+            }
+          ]
+        }
+        "#);
+    }
+
+    #[test]
+    fn merge_strategies() {
+        let mut ours = Versions::default();
+        ours.add("v1.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        ours.add("v2.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        let mut theirs = Versions::default();
+        theirs
+            .add("v2.0".into(), Some("Two".into()), HashSet::new())
+            .unwrap();
+        theirs
+            .add(
+                "v3.0".into(),
+                None,
+                HashSet::from(["latest".into(), "next".into()]),
+            )
+            .unwrap();
+
+        let conflicts = vec![
+            MergeConflict::Title {
+                tag: "v2.0".into(),
+                ours: None,
+                theirs: Some("Two".into()),
+            },
+            MergeConflict::Alias {
+                alias: "latest".into(),
+                ours: "v2.0".into(),
+                theirs: "v3.0".into(),
+            },
+        ];
+        let mut merged = ours.clone();
+        assert_eq!(
+            merged.merge(theirs.clone(), MergeStrategy::Error),
+            Err(MergeError::Conflicts(conflicts.clone()))
+        );
+        assert_eq!(merged, ours);
+
+        let report = merged.merge(theirs.clone(), MergeStrategy::Ours).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                added: vec!["v3.0".into()],
+                aliases: vec![("next".into(), "v3.0".into())],
+                conflicts: conflicts.clone(),
+            }
+        );
+        assert_eq!(merged.by_alias("latest").unwrap().tag, "v2.0");
+        assert_eq!(merged.by_tag("v2.0").unwrap().title, None);
+
+        let mut merged = ours.clone();
+        let report = merged.merge(theirs, MergeStrategy::Theirs).unwrap();
+        assert_eq!(
+            report.aliases,
+            [
+                ("latest".to_string(), "v3.0".to_string()),
+                ("next".to_string(), "v3.0".to_string())
+            ]
+        );
+        assert_eq!(merged.by_alias("latest").unwrap().tag, "v3.0");
+        assert_eq!(merged.by_tag("v2.0").unwrap().title.as_deref(), Some("Two"));
+
+        let mut clashing = Versions::default();
+        clashing.add("stable".into(), None, HashSet::new()).unwrap();
+        assert_eq!(
+            ours.clone().merge(clashing, MergeStrategy::Theirs),
+            Err(MergeError::Name(NameError::VersionIsAlias("stable".into())))
+        );
+    }
+
+    #[test]
+    fn merge_is_idempotent_and_keeps_aliases_pointing_at_versions() {
+        // A small linear congruential generator, so the cases are the same on every run
+        let mut seed = 0x2545_f491_u64;
+        let mut next = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+        let tags = ["v1.0", "v1.1", "v2.0", "v3.0-rc.1", "dev"];
+        let aliases = ["latest", "stable", "next", "lts"];
+        let titles = [None, Some("One"), Some("Two")];
+        let mut random_versions = || {
+            let mut versions = Versions::default();
+            for tag in tags {
+                if next(2) == 0 {
+                    let title = titles[next(titles.len())].map(String::from);
+                    versions.add(tag.into(), title, HashSet::new()).unwrap();
+                }
+            }
+            let deployed = versions.versions.keys().cloned().collect::<Vec<_>>();
+            if !deployed.is_empty() {
+                for alias in aliases {
+                    if next(2) == 0 {
+                        let tag = &deployed[next(deployed.len())];
+                        versions.set_alias(alias.into(), tag).unwrap();
+                    }
+                }
+            }
+            versions
+        };
+
+        for _ in 0..200 {
+            let ours = random_versions();
+            let theirs = random_versions();
+            for strategy in [MergeStrategy::Ours, MergeStrategy::Theirs] {
+                let mut merged = ours.clone();
+                merged.merge(theirs.clone(), strategy).unwrap();
+                assert!(
+                    merged
+                        .aliases
+                        .values()
+                        .all(|tag| merged.versions.contains_key(tag))
+                );
+
+                let mut again = merged.clone();
+                let report = again.merge(theirs.clone(), strategy).unwrap();
+                assert_eq!(again, merged);
+                assert!(report.added.is_empty() && report.aliases.is_empty());
+
+                let mut itself = merged.clone();
+                assert_eq!(
+                    itself.merge(merged.clone(), MergeStrategy::Error),
+                    Ok(MergeReport::default())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn retain_drops_dangling_aliases() {
         let mut versions = Versions::default();
-        versions.add("1.0.0".into(), Some("1.0.0 title".into()), HashSet::new());
-        versions.add("v2.0.0".into(), None, HashSet::from(["stable".into()]));
-        versions.add("alpha".into(), Some("alpha title".into()), HashSet::new());
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["stable".into()]))
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
 
+        let range = semver::VersionReq::parse(">=1.0, <2").unwrap();
+        versions.retain(|v| parse_semver_like(&v.tag).is_some_and(|semver| range.matches(&semver)));
         assert_json_snapshot!(versions, @r#"
-        [
-          {
-            "version": "alpha",
-            "title": "alpha title",
-            "aliases": []
-          },
-          {
-            "version": "v2.0.0",
-            "title": "v2.0.0",
-            "aliases": [
-              "stable"
+        {
+          "schema": 2,
+          "versions": [
+            {
+              "version": "v1.0.0",
+              "title": null,
+              "aliases": [
+                "stable"
+              ]
+            }
+          ]
+        }
+        "#);
+    }
+
+    #[test]
+    fn add_reports_repointed_aliases() {
+        let mut versions = Versions::default();
+        let repointed = versions
+            .add(
+                "v1.0.0".into(),
+                None,
+                HashSet::from(["latest".into(), "stable".into()]),
+            )
+            .unwrap();
+        assert!(repointed.is_empty());
+
+        // Re-adding keeps aliases that already point at the version out of the report
+        let repointed = versions
+            .add(
+                "v1.0.0".into(),
+                Some("One".into()),
+                HashSet::from(["latest".into()]),
+            )
+            .unwrap();
+        assert!(repointed.is_empty());
+        assert_eq!(
+            versions.by_alias("latest").unwrap().title.as_deref(),
+            Some("One")
+        );
+
+        let repointed = versions
+            .add(
+                "v2.0.0".into(),
+                None,
+                HashSet::from(["stable".into(), "latest".into(), "next".into()]),
+            )
+            .unwrap();
+        assert_eq!(
+            repointed,
+            [
+                ("latest".to_string(), "v1.0.0".to_string()),
+                ("stable".to_string(), "v1.0.0".to_string())
             ]
-          },
-          {
-            "version": "1.0.0",
-            "title": "1.0.0 title",
-            "aliases": []
-          }
-        ]
+        );
+    }
+
+    #[test]
+    fn remove_drops_the_aliases_of_the_version() {
+        let mut versions = Versions::default();
+        versions
+            .add(
+                "v1.0.0".into(),
+                None,
+                HashSet::from(["old".into(), "lts".into()]),
+            )
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        let (removed, aliases) = versions.remove("v1.0.0").unwrap();
+        assert_eq!(removed.tag, "v1.0.0");
+        assert_eq!(aliases, ["lts", "old"]);
+        assert!(versions.remove("v1.0.0").is_none());
+        assert_eq!(
+            versions.aliases(),
+            &HashMap::from([("latest".to_string(), "v2.0.0".to_string())])
+        );
+
+        // An unaliased version has nothing to report
+        versions.add("v3.0.0".into(), None, HashSet::new()).unwrap();
+        assert_eq!(versions.remove("v3.0.0").unwrap().1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn alias_methods_only_point_at_deployed_versions() {
+        let mut versions = Versions::default();
+        versions.add("v1.0.0".into(), None, HashSet::new()).unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        assert_eq!(versions.set_alias("stable".into(), "v1.0.0").unwrap(), None);
+        assert_eq!(
+            versions
+                .set_alias("latest".into(), "v1.0.0")
+                .unwrap()
+                .as_deref(),
+            Some("v2.0.0")
+        );
+        assert!(versions.set_alias("next".into(), "v3.0.0").is_err());
+        assert!(!versions.aliases().contains_key("next"));
+
+        assert_eq!(versions.remove_alias("stable").as_deref(), Some("v1.0.0"));
+        assert_eq!(versions.remove_alias("stable"), None);
+        assert_eq!(versions.by_alias("latest").unwrap().tag, "v1.0.0");
+    }
+
+    #[test]
+    fn names_must_be_single_unreserved_segments() {
+        assert_eq!(validate_name("v1.0.0"), Ok(()));
+        assert_eq!(validate_name(".well-known"), Ok(()));
+        assert_eq!(validate_name(""), Err(NameError::Empty));
+        for name in [".", "..", "a/b", "a\\b", "/abs"] {
+            assert_eq!(
+                validate_name(name),
+                Err(NameError::NotASegment(name.into()))
+            );
+        }
+        for name in ["versions.json", "_redirects", "sitemap.xml"] {
+            assert_eq!(validate_name(name), Err(NameError::Reserved(name.into())));
+        }
+        for name in ["v1\n", "a\tb", "\u{0}", "v1\u{7f}"] {
+            assert_eq!(
+                validate_name(name),
+                Err(NameError::ControlCharacter(name.into()))
+            );
+        }
+        assert_eq!(sanitize_name("feature/login\\x"), "feature-login-x");
+        assert_eq!(validate_name(&sanitize_name("../evil")), Ok(()));
+        assert_eq!(validate_name(&"v".repeat(MAX_NAME_LEN)), Ok(()));
+        let long = "v".repeat(MAX_NAME_LEN + 1);
+        assert_eq!(validate_name(&long), Err(NameError::TooLong(long)));
+    }
+
+    #[test]
+    fn malicious_names_never_reach_the_tree() {
+        let mut versions = Versions::default();
+        for name in ["../evil", "..", "/etc", "a/../../b", "..\\evil", "v1\n../x"] {
+            assert!(versions.add(name.into(), None, HashSet::new()).is_err());
+            assert!(
+                versions
+                    .add("v1".into(), None, HashSet::from([name.into()]))
+                    .is_err()
+            );
+        }
+        assert!(versions.versions().is_empty());
+        assert!(versions.aliases().is_empty());
+    }
+
+    #[test]
+    fn add_rejects_names_that_clash() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        let before = versions.clone();
+
+        assert_eq!(
+            versions.add("v2.0.0".into(), None, HashSet::from(["v1.0.0".into()])),
+            Err(NameError::AliasIsVersion("v1.0.0".into()))
+        );
+        assert_eq!(
+            versions.add("v2.0.0".into(), None, HashSet::from(["v2.0.0".into()])),
+            Err(NameError::AliasIsVersion("v2.0.0".into()))
+        );
+        assert_eq!(
+            versions.add("latest".into(), None, HashSet::new()),
+            Err(NameError::VersionIsAlias("latest".into()))
+        );
+        assert_eq!(
+            versions.add("v2.0.0".into(), None, HashSet::from(["../x".into()])),
+            Err(NameError::NotASegment("../x".into()))
+        );
+        assert_eq!(versions, before);
+
+        assert!(versions.set_alias("v1.0.0".into(), "v1.0.0").is_err());
+        assert!(versions.rename("v1.0.0", "_redirects").is_err());
+        assert_eq!(versions, before);
+    }
+
+    #[test]
+    fn lookup_ignores_case_and_leading_v() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.2.3".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        versions
+            .add("Nightly".into(), None, HashSet::new())
+            .unwrap();
+        versions.add("vnext".into(), None, HashSet::new()).unwrap();
+
+        let resolve =
+            |versions: &Versions, identifier| versions.resolve(identifier).map(|v| v.tag.clone());
+        assert_eq!(resolve(&versions, "v1.2.3").as_deref(), Some("v1.2.3"));
+        assert_eq!(resolve(&versions, "V1.2.3").as_deref(), Some("v1.2.3"));
+        assert_eq!(resolve(&versions, "1.2.3").as_deref(), Some("v1.2.3"));
+        assert_eq!(resolve(&versions, "Latest").as_deref(), Some("v1.2.3"));
+        assert_eq!(resolve(&versions, "nightly").as_deref(), Some("Nightly"));
+        assert_eq!(resolve(&versions, "next").as_deref(), None);
+        versions
+            .add(sanitize_name("feature/login"), None, HashSet::new())
+            .unwrap();
+        assert_eq!(
+            resolve(&versions, "feature/login").as_deref(),
+            Some("feature-login")
+        );
+
+        // An exact match wins over the others, and several other matches are ambiguous
+        versions.add("1.2.3".into(), None, HashSet::new()).unwrap();
+        assert_eq!(resolve(&versions, "1.2.3").as_deref(), Some("1.2.3"));
+        assert_eq!(resolve(&versions, "V1.2.3").as_deref(), None);
+        assert_eq!(versions.search("V1.2.3").len(), 2);
+    }
+
+    #[test]
+    fn names_differing_only_by_case_are_rejected() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        assert_eq!(
+            versions.add("V1.0".into(), None, HashSet::new()),
+            Err(NameError::CaseClash("V1.0".into(), "v1.0".into()))
+        );
+        assert_eq!(
+            versions.add("v2.0".into(), None, HashSet::from(["Latest".into()])),
+            Err(NameError::CaseClash("Latest".into(), "latest".into()))
+        );
+        assert!(versions.set_alias("LATEST".into(), "v1.0").is_err());
+        versions.rename("v1.0", "V1.0").unwrap();
+
+        let err = serde_json::from_str::<Versions>(
+            r#"[{"version": "v1.0", "title": null, "aliases": []}, {"version": "V1.0", "title": null, "aliases": []}]"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "V1.0 differs from v1.0 only by case, so they would share a directory on case-insensitive filesystems"
+        );
+    }
+
+    #[test]
+    fn validate_reports_names_read_from_the_branch() {
+        let versions: Versions = serde_json::from_str(
+            r#"[
+                {"version": "v1.0.0", "title": null, "aliases": ["v2.0.0"]},
+                {"version": "v2.0.0", "title": null, "aliases": []},
+                {"version": "..", "title": null, "aliases": ["a/b"]}
+            ]"#,
+        )
+        .unwrap();
+        let errors = versions
+            .validate()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(errors, @r#"
+        ".." can't be used as a version or alias: it must be a single path segment, without / or \ and other than . and ..
+        "a/b" can't be used as a version or alias: it must be a single path segment, without / or \ and other than . and ..
+        alias v2.0.0 has the same name as a version
         "#);
     }
 
     #[test]
-    fn deserialize_versions_with_aliases() {
+    fn unknown_fields_survive_a_round_trip() {
         let json = r#"[
-            {"version":"dev","title":"Development","aliases":["latest"]},
-            {"version":"1.0.0","title":"1.0.0","aliases":["stable"]}
+            {"version": "v2.0.0", "title": "Two", "aliases": [], "draft": true, "meta": {"lts": false}},
+            {"version": "v1.0.0", "title": "One", "aliases": []}
         ]"#;
+        let mut versions: Versions = serde_json::from_str(json).unwrap();
+        insta::assert_snapshot!(serde_json::to_string(&versions).unwrap(), @r#"{"schema":2,"versions":[{"version":"v2.0.0","title":"Two","aliases":[],"draft":true,"meta":{"lts":false}},{"version":"v1.0.0","title":"One","aliases":[]}]}"#);
+
+        // Redeploying a version keeps its fields
+        versions
+            .add("v2.0.0".into(), Some("Two again".into()), HashSet::new())
+            .unwrap();
+        assert_eq!(
+            versions.by_tag("v2.0.0").unwrap().extra["meta"],
+            serde_json::json!({"lts": false})
+        );
+    }
+
+    #[test]
+    fn schema_1_files_are_upgraded() {
+        let legacy = r#"[{"version": "v1.0.0", "title": "One", "aliases": ["latest"]}]"#;
+        let versions: Versions = serde_json::from_str(legacy).unwrap();
+        let upgraded = serde_json::to_string(&versions).unwrap();
+        insta::assert_snapshot!(upgraded, @r#"{"schema":2,"versions":[{"version":"v1.0.0","title":"One","aliases":["latest"]}]}"#);
+        assert_eq!(
+            serde_json::from_str::<Versions>(&upgraded).unwrap(),
+            versions
+        );
+    }
+
+    #[test]
+    fn envelope_fields_survive_a_round_trip() {
+        let json = r#"{"schema": 2, "generator": {"name": "other"}, "versions": []}"#;
         let versions: Versions = serde_json::from_str(json).unwrap();
-        dbg!(&versions);
-        assert_json_snapshot!(versions, @r#"
-        [
-          {
-            "version": "dev",
-            "title": "Development",
-            "aliases": [
-              "latest"
-            ]
-          },
-          {
-            "version": "1.0.0",
-            "title": "1.0.0",
-            "aliases": [
-              "stable"
-            ]
-          }
-        ]
-        "#);
+        insta::assert_snapshot!(serde_json::to_string(&versions).unwrap(), @r#"{"schema":2,"versions":[],"generator":{"name":"other"}}"#);
     }
 
     #[test]
-    fn iterate_versions_with_aliases_pairs() {
-        use std::collections::HashSet;
+    fn parse_errors_point_at_the_problem() {
+        let source =
+            serde_json::from_str::<Versions>("{\"schema\": 2, \"versions\": [\n  {\"version\"")
+                .unwrap_err();
+        let err = DocverError::Manifest {
+            location: "origin/gh-pages".into(),
+            source: Some(source),
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to parse versions.json at origin/gh-pages (line 2, column 12)"
+        );
+    }
+
+    #[test]
+    fn unknown_schemas_are_rejected() {
+        let err = serde_json::from_str::<Versions>(r#"{"schema": 3, "versions": []}"#).unwrap_err();
+        insta::assert_snapshot!(err, @"versions.json has schema 3, but this versite only reads schemas up to 2; please upgrade versite at line 1 column 12");
+        let err = serde_json::from_str::<Versions>(r#"{"versions": []}"#).unwrap_err();
+        insta::assert_snapshot!(err, @"missing field `schema` at line 1 column 16");
+    }
+
+    /// versions.json as mike writes it, with `json.dumps` of its entries
+    const MIKE_VERSIONS: &str = r#"[{"version": "2.0", "title": "2.0", "aliases": ["latest", "stable"]}, {"version": "1.1", "title": "1.1 \u00e9t\u00e9 \ud83d\ude80", "aliases": [], "properties": {"hidden": true}}, {"version": "1.0", "title": "Tab\there \"quoted\"", "aliases": []}]"#;
+
+    #[test]
+    fn mike_files_round_trip_byte_for_byte() {
+        let versions: Versions = serde_json::from_str(MIKE_VERSIONS).unwrap();
+        assert_eq!(
+            versions.by_tag("1.1").unwrap().title.as_deref(),
+            Some("1.1 été 🚀")
+        );
+        assert_eq!(
+            versions.versions()["1.1"].extra["properties"],
+            serde_json::json!({"hidden": true})
+        );
+        assert_eq!(versions.to_json(Some(Compat::Mike)).unwrap(), MIKE_VERSIONS);
+
+        let upgraded = versions.to_json(None).unwrap();
+        let versions: Versions = serde_json::from_str(&upgraded).unwrap();
+        assert_eq!(versions.to_json(Some(Compat::Mike)).unwrap(), MIKE_VERSIONS);
+    }
+
+    #[test]
+    fn mike_output_of_versite_versions() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+        versions
+            .add("v0.9.0".into(), Some("Old".into()), HashSet::new())
+            .unwrap();
+        insta::assert_snapshot!(versions.to_json(Some(Compat::Mike)).unwrap(), @r#"[{"version": "v1.0.0", "title": "v1.0.0", "aliases": ["latest"]}, {"version": "v0.9.0", "title": "Old", "aliases": []}]"#);
+        assert_eq!(
+            Versions::default().to_json(Some(Compat::Mike)).unwrap(),
+            "[]"
+        );
+    }
+
+    #[test]
+    fn rename_repoints_aliases() {
+        let mut versions = Versions::default();
+        versions
+            .add(
+                "v1.0.0".into(),
+                Some("One".into()),
+                HashSet::from(["old".into(), "lts".into()]),
+            )
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        assert_eq!(versions.rename("v1.0.0", "v1.0.1").unwrap(), ["lts", "old"]);
+        assert!(versions.by_tag("v1.0.0").is_none());
+        assert_eq!(
+            versions.by_alias("lts"),
+            Some(&Version::new("v1.0.1".into(), Some("One".into())))
+        );
+        assert_eq!(versions.by_alias("old").unwrap().tag, "v1.0.1");
+        assert_eq!(versions.by_alias("latest").unwrap().tag, "v2.0.0");
+
+        let err = |result: Result<Vec<String>>| result.unwrap_err().to_string();
+        let before = versions.clone();
+        assert_eq!(
+            err(versions.rename("v1.0.0", "v3")),
+            "version v1.0.0 is not deployed"
+        );
+        assert_eq!(
+            err(versions.rename("v1.0.1", "v2.0.0")),
+            "version v2.0.0 already exists"
+        );
+        assert_eq!(
+            err(versions.rename("v1.0.1", "latest")),
+            "latest is already an alias of v2.0.0"
+        );
+        assert_eq!(versions, before);
+    }
+
+    #[test]
+    fn rename_keeps_every_alias_pointing_at_a_version() {
+        let tags = ["v0.9", "v1.0.0", "v1.2", "v2.0.0-rc.1", "main", "pr-7"];
+        let aliases = ["latest", "stable", "next"];
+        // Every rename of every version in every alias assignment
+        for assignment in 0..tags.len().pow(aliases.len() as u32) {
+            let mut versions = Versions::default();
+            for tag in tags {
+                versions.add(tag.into(), None, HashSet::new()).unwrap();
+            }
+            let mut rest = assignment;
+            for alias in aliases {
+                versions
+                    .set_alias(alias.into(), tags[rest % tags.len()])
+                    .unwrap();
+                rest /= tags.len();
+            }
+
+            for old in tags {
+                for new in ["v1.1", "v3.0.0", "alpha"] {
+                    let mut renamed = versions.clone();
+                    let moved = renamed.rename(old, new).unwrap();
+
+                    assert!(
+                        renamed
+                            .aliases()
+                            .values()
+                            .all(|tag| renamed.by_tag(tag).is_some())
+                    );
+                    assert_eq!(renamed.by_tag(new).unwrap().tag, new);
+                    assert!(renamed.by_tag(old).is_none());
+                    assert!(moved.iter().all(|alias| renamed.aliases()[alias] == new));
+
+                    let mut expected = versions.versions().values().cloned().collect::<Vec<_>>();
+                    expected.retain(|version| version.tag != old);
+                    expected.push(Version::new(new.into(), None));
+                    expected.sort();
+                    let order = (&renamed)
+                        .into_iter()
+                        .map(|(version, _)| version.clone())
+                        .collect::<Vec<_>>();
+                    assert_eq!(order, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prune_removes_through_remove() {
+        let mut versions = Versions::default();
+        for tag in ["pr-1", "pr-2", "pr-3"] {
+            versions.add(tag.into(), None, HashSet::new()).unwrap();
+        }
+        versions
+            .add("pr-4".into(), None, HashSet::from(["preview".into()]))
+            .unwrap();
+
+        assert_eq!(versions.prune("pr-*", 1, "pr-1"), ["pr-2"]);
+        let mut tags = versions.versions().keys().cloned().collect::<Vec<_>>();
+        tags.sort();
+        assert_eq!(tags, ["pr-1", "pr-3", "pr-4"]);
+    }
+
+    fn netlify(versions: &Versions, options: &RewriteOptions) -> String {
+        let mut files = versions.rewrites(RewriteFormat::Netlify, options);
+        assert_eq!(files.len(), 1);
+        let (path, contents) = files.remove(0);
+        assert_eq!(path, Path::new("_redirects"));
+        contents
+    }
+
+    #[test]
+    fn netlify_rewrites_with_prefix() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        assert_eq!(
+            netlify(&versions, &RewriteOptions::default()),
+            "/latest/* /v1.0.0/:splat 200\n/* /v1.0.0/:splat 200\n"
+        );
+        let options = RewriteOptions {
+            prefix: "docs".into(),
+            ..Default::default()
+        };
+        insta::assert_snapshot!(netlify(&versions, &options), @r"
+        /docs/latest/* /docs/v1.0.0/:splat 200
+        /docs/* /docs/v1.0.0/:splat 200
+        ");
+    }
+
+    #[test]
+    fn netlify_redirect_statuses() {
+        let mut versions = Versions::default();
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["old".into()]))
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
 
+        let options = RewriteOptions {
+            alias_status: 200,
+            root_status: 301,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(netlify(&versions, &options), @r"
+        /latest/* /v2.0.0/:splat 200
+        /old/* /v1.0.0/:splat 200
+        /* /v2.0.0/:splat 301
+        ");
+        let options = RewriteOptions {
+            alias_status: 302,
+            root_status: 302,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(netlify(&versions, &options), @r"
+        /latest/* /v2.0.0/:splat 302
+        /old/* /v1.0.0/:splat 302
+        /* /v2.0.0/:splat 302
+        ");
+
+        assert_eq!(parse_redirect_status("301").unwrap(), 301);
+        assert!(parse_redirect_status("404").is_err());
+        assert!(parse_redirect_status("rewrite").is_err());
+    }
+
+    #[test]
+    fn netlify_rewrites_are_sorted() {
+        let mut versions = Versions::default();
+        versions
+            .add(
+                "v1.0.0".into(),
+                None,
+                HashSet::from(["old".into(), "lts".into()]),
+            )
+            .unwrap();
+        versions
+            .add(
+                "v2.0.0".into(),
+                None,
+                HashSet::from(["stable".into(), "latest".into(), "current".into()]),
+            )
+            .unwrap();
+        versions.aliases.insert("gone".into(), "v0.1.0".into());
+
+        insta::assert_snapshot!(netlify(&versions, &RewriteOptions::default()), @r"
+        /current/* /v2.0.0/:splat 200
+        /latest/* /v2.0.0/:splat 200
+        /lts/* /v1.0.0/:splat 200
+        /old/* /v1.0.0/:splat 200
+        /stable/* /v2.0.0/:splat 200
+        /* /v2.0.0/:splat 200
+        ");
+    }
+
+    #[test]
+    fn versions_js_assigns_globals() {
         let mut versions = Versions::default();
-        versions.add(
-            "1.0.0".into(),
-            Some("1.0.0".into()),
-            HashSet::from(["stable".into()]),
+        versions
+            .add("v1.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        assert_eq!(
+            versions.versions_js().unwrap(),
+            "window.DOCVER_VERSIONS = [{\"version\":\"v1.0.0\",\"title\":\"v1.0.0\",\"aliases\":[\"latest\"]}];\nwindow.DOCVER_DEFAULT_ALIAS = \"latest\";\n"
+        );
+    }
+
+    #[test]
+    fn hidden_versions() {
+        let mut versions: Versions = serde_json::from_str(
+            r#"[{"version": "v1.0.0", "title": null, "aliases": ["latest"]}]"#,
+        )
+        .unwrap();
+        versions
+            .add("pr-123".into(), None, HashSet::from(["preview".into()]))
+            .unwrap();
+        versions.set_hidden("pr-123", true).unwrap();
+        assert!(versions.set_hidden("pr-456", true).is_err());
+
+        // Redeploying keeps the version hidden
+        versions.add("pr-123".into(), None, HashSet::new()).unwrap();
+        assert!(versions.by_tag("pr-123").unwrap().hidden);
+
+        insta::assert_snapshot!(serde_json::to_string(&versions).unwrap(), @r#"{"schema":2,"versions":[{"version":"pr-123","title":null,"aliases":["preview"],"hidden":true},{"version":"v1.0.0","title":null,"aliases":["latest"]}]}"#);
+        assert_eq!(
+            versions.versions_js().unwrap(),
+            "window.DOCVER_VERSIONS = [{\"version\":\"v1.0.0\",\"title\":\"v1.0.0\",\"aliases\":[\"latest\"]}];\nwindow.DOCVER_DEFAULT_ALIAS = \"latest\";\n"
         );
-        versions.add(
-            "dev".into(),
-            Some("Development".into()),
-            HashSet::from(["latest".into()]),
+        assert!(netlify(&versions, &RewriteOptions::default()).contains("/preview/*"));
+    }
+
+    #[test]
+    fn record_deploy_keeps_the_first_deploy_time() {
+        let mut versions: Versions =
+            serde_json::from_str(r#"[{"version": "v1.0.0", "title": null, "aliases": []}]"#)
+                .unwrap();
+        versions
+            .record_deploy("v1.0.0", "2024-01-01T00:00:00+00:00", Some("aaaa"))
+            .unwrap();
+        versions.add("v1.0.0".into(), None, HashSet::new()).unwrap();
+        versions
+            .record_deploy("v1.0.0", "2024-02-01T00:00:00+00:00", Some("bbbb"))
+            .unwrap();
+        assert!(
+            versions
+                .record_deploy("v2.0.0", "2024-02-01T00:00:00+00:00", None)
+                .is_err()
         );
+        insta::assert_snapshot!(serde_json::to_string(&versions).unwrap(), @r#"{"schema":2,"versions":[{"version":"v1.0.0","title":null,"aliases":[],"created_at":"2024-01-01T00:00:00+00:00","updated_at":"2024-02-01T00:00:00+00:00","source_commit":"bbbb"}]}"#);
+    }
+
+    #[test]
+    fn robots_txt_allows_default_version() {
+        let mut versions = Versions::default();
+        versions.add("v1.0.0".into(), None, HashSet::new()).unwrap();
+        versions
+            .add("v1.1.0".into(), None, HashSet::from(["old".into()]))
+            .unwrap();
+        versions
+            .add("v2.0.0".into(), None, HashSet::from(["latest".into()]))
+            .unwrap();
+
+        let robots = versions.robots_txt("docs", &["v1.0.0".into()]);
+        insta::assert_snapshot!(robots, @r"
+        # Allowed versions: v1.0.0
+        User-agent: *
+        Allow: /docs/v2.0.0/
+        Allow: /docs/latest/
+        Disallow: /docs/v1.1.0/
+        Disallow: /docs/old/
+        Allow: /docs/v1.0.0/
+        ");
+        assert_eq!(robots_allow_list(&robots), vec!["v1.0.0".to_string()]);
+        assert!(robots_allow_list(&versions.robots_txt("", &[])).is_empty());
+    }
+
+    #[test]
+    fn iterate_versions_with_aliases_pairs() {
+        use std::collections::HashSet;
+
+        let mut versions = Versions::default();
+        versions
+            .add(
+                "1.0.0".into(),
+                Some("1.0.0".into()),
+                HashSet::from(["stable".into()]),
+            )
+            .unwrap();
+        versions
+            .add(
+                "dev".into(),
+                Some("Development".into()),
+                HashSet::from(["latest".into()]),
+            )
+            .unwrap();
 
         let view: Vec<_> = (&versions)
             .into_iter()